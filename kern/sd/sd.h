@@ -29,3 +29,4 @@
 
 int sd_init();
 int sd_readblock(unsigned int lba, unsigned char *buffer, unsigned int num);
+int sd_writeblock(unsigned char *buffer, unsigned int lba, unsigned int num);
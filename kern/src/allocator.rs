@@ -6,9 +6,12 @@ mod bump;
 
 type AllocatorImpl = bin::Allocator;
 
+pub use self::bin::BinStats;
+
 #[cfg(test)]
 mod tests;
 
+use alloc::vec::Vec;
 use core::alloc::{GlobalAlloc, Layout};
 use core::fmt;
 
@@ -46,6 +49,22 @@ impl Allocator {
         info!("heap beg: {:x}, end: {:x}", start, end);
         *self.0.lock() = Some(AllocatorImpl::new(start, end));
     }
+
+    /// Returns the number of bytes of free memory, or `None` if the allocator
+    /// is not initialized.
+    pub fn free(&self) -> Option<usize> {
+        self.0.lock().as_ref().map(|alloc| alloc.free())
+    }
+
+    /// Returns the size and the numbers of free and used blocks of every bin
+    /// of the allocator, smallest first, or `None` if the allocator is not
+    /// initialized.
+    pub fn bins(&self) -> Option<Vec<BinStats>> {
+        // The stats are copied out first: allocating the vector while the
+        // allocator is locked would deadlock.
+        let bins = self.0.lock().as_ref().map(|alloc| alloc.bins())?;
+        Some(bins.to_vec())
+    }
 }
 
 unsafe impl GlobalAlloc for Allocator {
@@ -58,6 +58,20 @@ pub struct Allocator {
     /// `bins` is a array of `num_bins` LinkedLists. `bins[k]`
     /// contains allocations of size 2^(k+3)
     bins: [LinkedList; NUM_BINS],
+    /// `allocated[k]` is the number of blocks of bin `k` that are allocated
+    /// and not yet freed.
+    allocated: [usize; NUM_BINS],
+}
+
+/// The blocks of a bin, as reported by `Allocator::bins()`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BinStats {
+    /// Size of the bin's blocks in bytes.
+    pub size: usize,
+    /// Number of free blocks in the bin.
+    pub free: usize,
+    /// Number of blocks of the bin's size that are allocated.
+    pub used: usize,
 }
 
 impl Allocator {
@@ -88,7 +102,7 @@ impl Allocator {
             }
         }
 
-        Allocator { bins }
+        Allocator { bins, allocated: [0; NUM_BINS] }
     }
 
     /// return the bin index with the smalles size class that `size` would
@@ -113,6 +127,25 @@ impl Allocator {
         (2 as usize).pow((BIN_SMALLEST_K + bin_idx) as u32)
     }
 
+    /// Returns the number of bytes in the free blocks of every bin.
+    pub fn free(&self) -> usize {
+        (0..NUM_BINS)
+            .map(|bin_idx| self.bins[bin_idx].iter().count() * self.map_to_bin_class_size(bin_idx))
+            .sum()
+    }
+
+    /// Returns the size and the numbers of free and used blocks of every
+    /// bin, smallest first.
+    pub fn bins(&self) -> [BinStats; NUM_BINS] {
+        let mut stats = [BinStats::default(); NUM_BINS];
+        for (bin_idx, bin) in stats.iter_mut().enumerate() {
+            bin.size = self.map_to_bin_class_size(bin_idx);
+            bin.free = self.bins[bin_idx].iter().count();
+            bin.used = self.allocated[bin_idx];
+        }
+        stats
+    }
+
     /// When ptr is being allocated into a bin that is not the smallest possible
     /// class size, this function will split unused memory blocks into free blocks.
     unsafe fn split_memory_block(&mut self, ptr: usize, ptr_size: usize, alloc_size: usize) {
@@ -164,6 +197,7 @@ impl LocalAlloc for Allocator {
         };
 
         let mut bin_class_size = self.map_to_bin_class_size(bin_idx);
+        let original_bin_idx = bin_idx;
         let original_bin_size = bin_class_size;
         loop {
             // Look for a free memory block in this bin.
@@ -171,6 +205,7 @@ impl LocalAlloc for Allocator {
                 if bin.value() as usize % layout.align() == 0 {
                     let ptr = bin.pop();
                     self.split_memory_block(ptr as usize, bin_class_size, original_bin_size);
+                    self.allocated[original_bin_idx] += 1;
                     return ptr as *mut u8;
                 }
             }
@@ -205,5 +240,6 @@ impl LocalAlloc for Allocator {
             None => return,
         };
         self.bins[bin_idx].push(ptr as *mut usize);
+        self.allocated[bin_idx] = self.allocated[bin_idx].saturating_sub(1);
     }
 }
@@ -18,6 +18,12 @@ impl Allocator {
     pub fn new(start: usize, end: usize) -> Allocator {
         Allocator{ current: start, end }
     }
+
+    /// Returns the number of bytes that have not been allocated yet.
+    #[allow(dead_code)]
+    pub fn free(&self) -> usize {
+        self.end - self.current
+    }
 }
 
 impl LocalAlloc for Allocator {
@@ -289,6 +289,34 @@ mod allocator {
             }
         }
     });
+
+    test_allocators!(@bin, bin_stats, 1 << 24, |(_, _, mut a)| {
+        let bins = a.bins();
+        assert_eq!(bins[0].size, 8);
+        assert_eq!(bins[1].size, 16);
+        assert!(bins.iter().all(|bin| bin.used == 0));
+        let free = a.free();
+
+        let small = [a.alloc(layout!(16, 16)), a.alloc(layout!(9, 8)), a.alloc(layout!(16, 256))];
+        let large = a.alloc(layout!(100, 8));
+        assert_eq!(a.bins()[1].used, 3);
+        assert_eq!(a.bins()[4].used, 1);
+        assert_eq!(a.bins().iter().map(|bin| bin.used).sum::<usize>(), 4);
+        assert_eq!(a.free(), free - 3 * 16 - 128);
+
+        let bins = a.bins();
+        a.dealloc(small[0], layout!(16, 16));
+        a.dealloc(large, layout!(100, 8));
+        assert_eq!(a.bins()[1].used, 2);
+        assert_eq!(a.bins()[1].free, bins[1].free + 1);
+        assert_eq!(a.bins()[4].used, 0);
+        assert_eq!(a.bins()[4].free, bins[4].free + 1);
+
+        a.dealloc(small[1], layout!(9, 8));
+        a.dealloc(small[2], layout!(16, 256));
+        assert!(a.bins().iter().all(|bin| bin.used == 0));
+        assert_eq!(a.free(), free);
+    });
 }
 
 mod linked_list {
@@ -36,6 +36,17 @@ impl Console {
         self.inner().read_byte()
     }
 
+    /// Reads a byte from the UART device if one is available, without
+    /// blocking.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        let inner = self.inner();
+        if inner.has_byte() {
+            Some(inner.read_byte())
+        } else {
+            None
+        }
+    }
+
     /// Writes the byte `byte` to the UART device.
     pub fn write_byte(&mut self, byte: u8) {
         self.inner().write_byte(byte);
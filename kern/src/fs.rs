@@ -1,24 +1,44 @@
+// Declared first so that its macros are in scope in the other modules.
+#[macro_use]
+pub mod vfs;
+pub mod devfs;
+pub mod procfs;
 pub mod sd;
+pub mod tmpfs;
+mod volume;
 
-use alloc::rc::Rc;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt::{self, Debug};
 use shim::io;
-use shim::path::{Path};
+use shim::path::{Component, Path, PathBuf};
 
 pub use fat32::traits;
-use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
+use fat32::check::{self, Mode, Problem};
+use fat32::exfat::{self, ExFat, ExFatHandle};
+use fat32::ext2::{self, Ext2, Ext2Handle};
+use fat32::partition::{self, PartitionDevice};
+use fat32::traits::{BlockDevice, Entry as _, FileSystem as _};
+use fat32::vfat::{self, FsStats, VFat, VFatHandle};
 
+use self::devfs::DevFs;
+use self::procfs::ProcFs;
 use self::sd::Sd;
+use self::tmpfs::TmpFs;
+pub use self::vfs::{Dir, Entry, File, Fs};
+use self::vfs::MountedDir;
 use crate::mutex::Mutex;
 
 #[derive(Clone)]
-pub struct PiVFatHandle(Rc<Mutex<VFat<Self>>>);
+pub struct PiVFatHandle(Arc<Mutex<VFat<Self>>>);
 
-// These impls are *unsound*. We should use `Arc` instead of `Rc` to implement
-// `Sync` and `Send` trait for `PiVFatHandle`. However, `Arc` uses atomic memory
-// access, which requires MMU to be initialized on ARM architecture. Since we
-// have enabled only one core of the board, these unsound impls will not cause
-// any immediate harm for now. We will fix this in the future.
+// A `VFat` is only `!Send` because its `CachedPartition` owns the block device
+// as a `Box<dyn BlockDevice>`. Volumes are only created by `mount_volume()`,
+// which takes `Send` devices: the SD card or its partitions, whose requests to
+// the controller are serialized by `sd::CONTROLLER`. The volume itself is
+// only ever reached through the `Mutex`.
 unsafe impl Send for PiVFatHandle {}
 unsafe impl Sync for PiVFatHandle {}
 
@@ -30,14 +50,94 @@ impl Debug for PiVFatHandle {
 
 impl VFatHandle for PiVFatHandle {
     fn new(val: VFat<PiVFatHandle>) -> Self {
-        PiVFatHandle(Rc::new(Mutex::new(val)))
+        PiVFatHandle(Arc::new(Mutex::new(val)))
     }
 
     fn lock<R>(&self, f: impl FnOnce(&mut VFat<PiVFatHandle>) -> R) -> R {
         f(&mut self.0.lock())
     }
 }
-pub struct FileSystem(Mutex<Option<PiVFatHandle>>);
+
+#[derive(Clone)]
+pub struct PiExFatHandle(Arc<Mutex<exfat::Volume>>);
+
+// `exfat::Volume` keeps its device in a `CachedPartition` too: see the impls
+// for `PiVFatHandle`.
+unsafe impl Send for PiExFatHandle {}
+unsafe impl Sync for PiExFatHandle {}
+
+impl Debug for PiExFatHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "PiExFatHandle")
+    }
+}
+
+impl ExFatHandle for PiExFatHandle {
+    fn new(val: exfat::Volume) -> Self {
+        PiExFatHandle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut exfat::Volume) -> R) -> R {
+        f(&mut self.0.lock())
+    }
+}
+
+#[derive(Clone)]
+pub struct PiExt2Handle(Arc<Mutex<ext2::Volume>>);
+
+// Sound for the same reasons as the impls for `PiExFatHandle`.
+unsafe impl Send for PiExt2Handle {}
+unsafe impl Sync for PiExt2Handle {}
+
+impl Debug for PiExt2Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "PiExt2Handle")
+    }
+}
+
+impl Ext2Handle for PiExt2Handle {
+    fn new(val: ext2::Volume) -> Self {
+        PiExt2Handle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut ext2::Volume) -> R) -> R {
+        f(&mut self.0.lock())
+    }
+}
+
+/// The errors of mounting a volume as each of the file systems the kernel
+/// knows, as returned by `mount_volume()`.
+type VolumeErrors = (vfat::Error, exfat::Error, ext2::Error);
+
+/// A file system mounted in the VFS.
+#[derive(Clone)]
+struct Mount {
+    /// Where the file system comes from, like `sd0p1`.
+    source: String,
+    path: PathBuf,
+    fs: Arc<dyn Fs>,
+}
+
+/// A mounted file system, as listed by `FileSystem::mounts()`.
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    pub source: String,
+    pub path: PathBuf,
+    pub fs_type: &'static str,
+}
+
+/// The virtual file system: a table of file systems mounted at paths, which
+/// dispatches every operation on a path to the file system mounted at its
+/// longest prefix.
+pub struct FileSystem {
+    mounts: Mutex<Vec<Mount>>,
+    sd: Mutex<Option<Sd>>,
+}
+
+/// How a volume that was not cleanly unmounted is checked when it is mounted.
+/// Problems are only reported by default: a repair writes to the volume while
+/// the kernel boots, so it is left to `fsck -r`.
+const DIRTY_VOLUME_CHECK: Mode = Mode::ReadOnly;
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
@@ -45,40 +145,390 @@ impl FileSystem {
     /// The file system must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        FileSystem(Mutex::new(None))
+        FileSystem {
+            mounts: Mutex::new(Vec::new()),
+            sd: Mutex::new(None),
+        }
     }
 
     /// Initializes the file system.
     /// The caller should assure that the method is invoked only once during the
-    /// kernel initialization.
-    ///
-    /// # Panics
+    /// kernel initialization, once the MMU is ready: the mounted file systems
+    /// are shared through `Arc`s.
     ///
-    /// Panics if the underlying disk or file sytem failed to initialize.
+    /// Every partition of the SD card that holds a FAT volume, or an exFAT or
+    /// ext2 volume mounted read-only, is mounted: the first one at `/`, partition
+    /// `n` (counting from 1) of the others at `/mnt/p<n>`. An SD card without
+    /// a partition table is mounted whole. If the SD card can't be initialized
+    /// or holds no readable volume, the error is logged and an empty tmpfs is
+    /// mounted at `/` instead. A tmpfs is mounted at `/tmp`, a procfs at
+    /// `/proc` and a devfs at `/dev` in any case.
     pub unsafe fn initialize(&self) {
-        let sd = Sd::new().unwrap();
-        let handle = VFat::<PiVFatHandle>::from(sd).unwrap();
-        *(self.0.lock()) = Some(handle);
+        self.mount_sd();
+        if self.mounts.lock().is_empty() {
+            warn!("fs: mounting an empty tmpfs at /");
+            self.attach("tmpfs", PathBuf::from("/"), Arc::new(TmpFs::new()));
+        }
+        self.attach("tmpfs", PathBuf::from("/tmp"), Arc::new(TmpFs::new()));
+        self.attach("proc", PathBuf::from("/proc"), Arc::new(ProcFs::new()));
+        let devfs = DevFs::new((*self.sd.lock()).clone());
+        self.attach("devfs", PathBuf::from("/dev"), Arc::new(devfs));
+    }
+
+    /// Mounts the volumes of the SD card. See `initialize()`.
+    unsafe fn mount_sd(&self) {
+        let sd = match Sd::new() {
+            Ok(sd) => sd,
+            Err(e) => {
+                warn!("fs: failed to initialize the SD card: {:?}", e);
+                return;
+            }
+        };
+        *self.sd.lock() = Some(sd.clone());
+
+        let partitions = match partition::partitions(sd.clone()) {
+            Ok(partitions) => partitions,
+            Err(e) => {
+                info!("fs: no partition table, mounting the whole SD card: {:?}", e);
+                Vec::new()
+            }
+        };
+
+        for (index, &partition) in partitions.iter().enumerate() {
+            let fs = match mount_volume(PartitionDevice::new(sd.clone(), partition)) {
+                Ok(fs) => fs,
+                Err(errors) => {
+                    info!("fs: partition {} not mounted: {:?}", index + 1, errors);
+                    continue;
+                }
+            };
+            let path = if self.mounts.lock().is_empty() {
+                PathBuf::from("/")
+            } else {
+                PathBuf::from(format!("/mnt/p{}", index + 1))
+            };
+            info!("fs: mounted partition {} at {}", index + 1, path.display());
+            self.attach(&format!("sd0p{}", index + 1), path, fs);
+        }
+
+        if partitions.is_empty() {
+            match mount_volume(sd) {
+                Ok(fs) => self.attach("sd0", PathBuf::from("/"), fs),
+                Err(errors) => {
+                    warn!("fs: failed to mount the SD card: {:?}", errors);
+                }
+            }
+        } else if self.mounts.lock().is_empty() {
+            warn!("fs: no partition of the SD card could be mounted");
+        }
+    }
+
+    /// Adds `fs` to the mount table at `path`.
+    fn attach(&self, source: &str, path: PathBuf, fs: Arc<dyn Fs>) {
+        self.mounts.lock().push(Mount { source: String::from(source), path, fs });
+    }
+
+    /// Mounts the file system of `source` at `path`. Sources are the SD card,
+    /// `sd0`, and its partitions, `sd0p1` to `sd0p<n>`, holding a FAT, exFAT
+    /// or ext2 volume, `tmpfs` for a new, empty tmpfs, `proc` for a procfs and
+    /// `devfs` for a devfs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error kind of `InvalidInput` if `path` is not absolute or
+    /// `source` is unknown, `NotFound` if `source` does not exist,
+    /// `AlreadyExists` if a file system is mounted at `path` or the volume of
+    /// `source` is already mounted, and `Other` if `path` is a file or
+    /// `source` holds no readable volume. `sd0` counts as mounted while any of
+    /// its partitions is, and the other way around.
+    pub fn mount<P: AsRef<Path>>(&self, source: &str, path: P) -> io::Result<()> {
+        let path = normalize(path.as_ref())?;
+        check_mount(&self.mounts.lock(), source, &path)?;
+        if let Ok(entry) = self.open(&path) {
+            if entry.is_file() {
+                return Err(io::Error::new(io::ErrorKind::Other, "can't mount a file system on a file"));
+            }
+        }
+
+        let fs = self.open_source(source)?;
+        // Checked again: the mount table was unlocked while the volume was read.
+        let mut mounts = self.mounts.lock();
+        check_mount(&mounts, source, &path)?;
+        mounts.push(Mount { source: String::from(source), path, fs });
+        Ok(())
+    }
+
+    /// Returns the file system of `source`. See `mount()`.
+    fn open_source(&self, source: &str) -> io::Result<Arc<dyn Fs>> {
+        if source == "tmpfs" {
+            return Ok(Arc::new(TmpFs::new()));
+        }
+        if source == "proc" {
+            return Ok(Arc::new(ProcFs::new()));
+        }
+        if source == "devfs" {
+            return Ok(Arc::new(DevFs::new((*self.sd.lock()).clone())));
+        }
+
+        let sd = match (*self.sd.lock()).clone() {
+            Some(sd) if source.starts_with("sd0") => sd,
+            Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown source")),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "the SD card is not initialized")),
+        };
+        let volume = |result: Result<Arc<dyn Fs>, VolumeErrors>| {
+            result.map_err(|_| io::Error::new(io::ErrorKind::Other, "no FAT, exFAT or ext2 volume found"))
+        };
+
+        if source == "sd0" {
+            return volume(mount_volume(sd));
+        }
+        // Only the canonical name of a partition is accepted, so that a source
+        // names a single entry of the mount table.
+        let number = match source.get(4..).filter(|_| source.starts_with("sd0p")) {
+            Some(number) if number.bytes().all(|b| b.is_ascii_digit()) && !number.starts_with('0') => {
+                number.parse::<usize>().ok()
+            }
+            _ => None,
+        };
+        let index = match number {
+            Some(number) => number - 1,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown source")),
+        };
+        let partitions = partition::partitions(sd.clone())
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "the SD card has no readable partition table"))?;
+        match partitions.get(index) {
+            Some(&partition) => volume(mount_volume(PartitionDevice::new(sd, partition))),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no such partition")),
+        }
+    }
+
+    /// Unmounts the file system mounted at `path`, writing back its modified
+    /// blocks first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error kind of `NotFound` if no file system is mounted at
+    /// `path`, `Other` if file systems are mounted below `path`, or the error
+    /// of writing back the file system, which is then left mounted.
+    pub fn unmount<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = normalize(path.as_ref())?;
+        let mount = match self.mounts.lock().iter().find(|mount| mount.path == path) {
+            Some(mount) => mount.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no file system is mounted there")),
+        };
+        if self.mounts.lock().iter().any(|mount| mount.path != path && mount.path.starts_with(&path)) {
+            return Err(io::Error::new(io::ErrorKind::Other, "file systems are mounted below it"));
+        }
+
+        mount.fs.sync()?;
+        self.mounts.lock().retain(|mount| mount.path != path);
+        Ok(())
+    }
+
+    /// Returns the mounted file systems, in the order they were mounted.
+    pub fn mounts(&self) -> Vec<MountInfo> {
+        self.mounts.lock().iter()
+            .map(|mount| MountInfo {
+                source: mount.source.clone(),
+                path: mount.path.clone(),
+                fs_type: mount.fs.fs_type(),
+            })
+            .collect()
+    }
+
+    /// Returns the mount that holds `path` and the path of the entry within
+    /// its file system: the mount at the longest prefix of `path`.
+    fn resolve(&self, path: &Path) -> io::Result<(Mount, PathBuf)> {
+        let path = normalize(path)?;
+        let mounts = self.mounts.lock();
+        let mount = mounts.iter()
+            .filter(|mount| path.starts_with(&mount.path))
+            .max_by_key(|mount| mount.path.components().count());
+        match mount {
+            Some(mount) => {
+                let rest = path.strip_prefix(&mount.path).expect("mount path is a prefix");
+                Ok((mount.clone(), Path::new("/").join(rest)))
+            }
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no file system is mounted")),
+        }
+    }
+
+    /// Writes back every modified block of every mounted file system, leaving
+    /// them as cleanly unmounted. Every file system is synced even if one
+    /// fails; the first error is returned.
+    pub fn sync(&self) -> io::Result<()> {
+        let mounts = (*self.mounts.lock()).clone();
+        let mut result = Ok(());
+        for mount in mounts {
+            let synced = mount.fs.sync();
+            if result.is_ok() {
+                result = synced;
+            }
+        }
+        result
+    }
+
+    /// Returns the size, usage and identity of the file system that holds
+    /// `path`.
+    pub fn statfs<P: AsRef<Path>>(&self, path: P) -> io::Result<FsStats> {
+        self.resolve(path.as_ref())?.0.fs.statfs()
+    }
+
+    /// Checks the file system that holds `path` for inconsistencies, repairing
+    /// them if `mode` is `Mode::Repair`, and returns the problems found. Only
+    /// FAT volumes can be checked.
+    pub fn check<P: AsRef<Path>>(&self, path: P, mode: Mode) -> io::Result<Vec<Problem>> {
+        self.resolve(path.as_ref())?.0.fs.check(mode)
     }
 }
 
+/// Returns an error kind of `AlreadyExists` if `mounts` has a file system
+/// mounted at `path` or one whose source overlaps `source`. See
+/// `FileSystem::mount()`.
+fn check_mount(mounts: &[Mount], source: &str, path: &Path) -> io::Result<()> {
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "a file system is already mounted there"));
+    }
+    if mounts.iter().any(|mount| overlaps(&mount.source, source)) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the volume is already mounted"));
+    }
+    Ok(())
+}
+
+/// Returns whether the sources `a` and `b` share blocks of the SD card: the
+/// same partition, or the whole card and any of its partitions. Other sources
+/// create a new file system each time and never overlap.
+fn overlaps(a: &str, b: &str) -> bool {
+    a.starts_with("sd0") && b.starts_with("sd0") && (a == b || a == "sd0" || b == "sd0")
+}
+
+/// Returns `path` without `.` and `..` components, or an error kind of
+/// `InvalidInput` if it is not absolute.
+fn normalize(path: &Path) -> io::Result<PathBuf> {
+    if !path.has_root() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+    }
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => (),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Mounts the FAT volume at sector 0 of `device`, or else its exFAT or ext2
+/// volume, read-only. A FAT volume that was not cleanly unmounted is checked
+/// first, as `DIRTY_VOLUME_CHECK` says.
+/// Returns the errors of mounting each.
+fn mount_volume<T>(device: T) -> Result<Arc<dyn Fs>, VolumeErrors>
+where
+    T: BlockDevice + Clone + Send + 'static,
+{
+    let handle = match VFat::<PiVFatHandle>::from_volume(device.clone()) {
+        Ok(handle) => handle,
+        Err(fat_error) => {
+            let exfat_error = match ExFat::<PiExFatHandle>::from(device.clone()) {
+                Ok(exfat) => {
+                    info!("fs: mounted an exFAT volume read-only");
+                    return Ok(Arc::new(exfat));
+                }
+                Err(exfat_error) => exfat_error,
+            };
+            return match Ext2::<PiExt2Handle>::from(device) {
+                Ok(ext2) => {
+                    info!("fs: mounted an ext2 volume read-only");
+                    Ok(Arc::new(ext2))
+                }
+                Err(ext2_error) => Err((fat_error, exfat_error, ext2_error)),
+            };
+        }
+    };
+
+    if handle.lock(|vfat| vfat.needs_check()) {
+        warn!("fs: volume was not cleanly unmounted");
+        match check::check(&handle, DIRTY_VOLUME_CHECK) {
+            Ok(ref problems) if problems.is_empty() => info!("fs: check found no problems"),
+            Ok(problems) => {
+                for problem in problems.iter() {
+                    warn!("fs: {}", problem);
+                }
+                match DIRTY_VOLUME_CHECK {
+                    Mode::ReadOnly => warn!("fs: check found {} problem(s), run `fsck -r` to repair", problems.len()),
+                    Mode::Repair => info!("fs: check repaired {} problem(s)", problems.len()),
+                }
+            }
+            Err(e) => warn!("fs: check failed: {:?}", e),
+        }
+    }
+    Ok(Arc::new(handle))
+}
+
 impl fat32::traits::FileSystem for &FileSystem {
-    type File = File<PiVFatHandle>;
-    type Dir = Dir<PiVFatHandle>;
-    type Entry = Entry<PiVFatHandle>;
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
 
-    fn open_root_dir(self) -> Entry<PiVFatHandle> {
-        match (*self.0.lock()).clone() {
-            Some(handle) => handle.open_root_dir(),
-            None => panic!("Failed to open root dir"),
+    fn open_root_dir(self) -> Entry {
+        match self.open("/") {
+            Ok(entry) => entry,
+            Err(_) => panic!("Failed to open root dir"),
         }
     }
 
+    /// Opens the entry at `path`. File systems mounted on children of a
+    /// directory are listed in place of the entries they are mounted on.
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        match (*self.0.lock()).clone() {
-            Some(handle) => handle.open(path),
-            None => panic!("Failed to open path"),
+        let (mount, rest) = self.resolve(path.as_ref())?;
+        let path = normalize(path.as_ref())?;
+        let mut entry = mount.fs.open(&rest)?;
+        if path == mount.path {
+            if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                entry = entry.renamed(name);
+            }
         }
+
+        let children: Vec<(String, Arc<dyn Fs>)> = self.mounts.lock().iter()
+            .filter(|mount| mount.path.parent() == Some(path.as_path()))
+            .filter_map(|mount| {
+                let name = mount.path.file_name()?.to_str()?;
+                Some((String::from(name), mount.fs.clone()))
+            })
+            .collect();
+        if children.is_empty() {
+            return Ok(entry);
+        }
+        Ok(entry.map_dir(|dir| Dir::new(MountedDir { dir, mounts: children })))
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (mount, path) = self.resolve(path.as_ref())?;
+        mount.fs.create_file(&path)
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        let (mount, path) = self.resolve(path.as_ref())?;
+        mount.fs.create_dir(&path)
     }
-}
 
+    fn remove<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let (mount, rest) = self.resolve(path.as_ref())?;
+        if rest.as_path() == Path::new("/") {
+            return Err(io::Error::new(io::ErrorKind::Other, "a file system is mounted there"));
+        }
+        mount.fs.remove(&rest)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from_mount, from) = self.resolve(from.as_ref())?;
+        let (to_mount, to) = self.resolve(to.as_ref())?;
+        if from_mount.path != to_mount.path {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't rename across file systems"));
+        }
+        from_mount.fs.rename(&from, &to)
+    }
+}
@@ -0,0 +1,372 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::{self, Vec};
+use core::cmp::min;
+
+use shim::io::{self, SeekFrom};
+use shim::path::{Component, Path};
+
+use fat32::partition::{self, PartitionDevice, PartitionInfo};
+use fat32::traits::{self, BlockDevice};
+use fat32::vfat::{Attributes, Metadata, Timestamp};
+
+use super::sd::Sd;
+use super::vfs::{self, read_only, Fs};
+use crate::console::CONSOLE;
+use crate::mutex::Mutex;
+
+/// The state of the generator behind `/dev/random`, or 0 until it is seeded.
+static RANDOM: Mutex<u64> = Mutex::new(0);
+
+/// A file system of device files: `/console`, `/null`, `/zero`, `/random`,
+/// and the SD card, `/sd0`, and its partitions, `/sd0p1` to `/sd0p<n>`, as
+/// raw block devices.
+#[derive(Clone, Debug)]
+pub struct DevFs {
+    sd: Option<Sd>,
+    partitions: Vec<PartitionInfo>,
+}
+
+impl DevFs {
+    /// Returns the device files of the SD card `sd`, if it was initialized,
+    /// and of the other devices.
+    pub fn new(sd: Option<Sd>) -> DevFs {
+        let partitions = match &sd {
+            Some(sd) => partition::partitions(sd.clone()).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        DevFs { sd, partitions }
+    }
+
+    /// Returns the devices, and their names.
+    fn devices(&self) -> Vec<(String, Device)> {
+        let mut devices = vec![
+            (String::from("console"), Device::Console),
+            (String::from("null"), Device::Null),
+            (String::from("zero"), Device::Zero),
+            (String::from("random"), Device::Random),
+        ];
+        if let Some(sd) = &self.sd {
+            devices.push((String::from("sd0"), Device::Sd(sd.clone())));
+            for (index, &partition) in self.partitions.iter().enumerate() {
+                let device = PartitionDevice::new(sd.clone(), partition);
+                devices.push((format!("sd0p{}", index + 1), Device::Partition(device)));
+            }
+        }
+        devices
+    }
+}
+
+/// A device behind a file of a `DevFs`.
+#[derive(Clone, Debug)]
+enum Device {
+    /// The console: reads block until a byte is received.
+    Console,
+    /// Discards writes; reads return nothing.
+    Null,
+    /// Discards writes; reads return zeroes.
+    Zero,
+    /// Reads return pseudo-random bytes; writes are mixed into the
+    /// generator. Not suitable for cryptography.
+    Random,
+    /// The whole SD card.
+    Sd(Sd),
+    /// A partition of the SD card.
+    Partition(PartitionDevice<Sd>),
+}
+
+/// A device file of a `DevFs`.
+#[derive(Clone, Debug)]
+pub struct File {
+    device: Device,
+    pub name: String,
+    pub metadata: Metadata,
+    seek_pos: u64,
+}
+
+/// The root directory of a `DevFs`.
+#[derive(Clone, Debug)]
+pub struct Dir {
+    fs: DevFs,
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// An entry of a `DevFs`.
+#[derive(Clone, Debug)]
+pub enum Entry {
+    File(File),
+    Dir(Dir),
+}
+
+impl File {
+    fn new(name: &str, device: Device) -> File {
+        // Block devices are read-only: raw writes would corrupt the volumes
+        // mounted from them.
+        let mut attributes = Attributes::default();
+        if let Device::Sd(_) | Device::Partition(_) = device {
+            attributes.set(Attributes::READ_ONLY, true);
+        }
+        let timestamp = Timestamp::default();
+        File {
+            device,
+            name: String::from(name),
+            metadata: Metadata::new(attributes, timestamp, timestamp, timestamp),
+            seek_pos: 0,
+        }
+    }
+
+    //  * A method to return the block device behind the file, if it is one.
+    fn block_device(&mut self) -> Option<&mut dyn BlockDevice> {
+        match &mut self.device {
+            Device::Sd(sd) => Some(sd),
+            Device::Partition(partition) => Some(partition),
+            _ => None,
+        }
+    }
+
+    //  * A method to read from a block device at the seek position, a sector
+    //    at a time.
+    fn read_blocks(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = traits::File::size(self);
+        let mut pos = self.seek_pos;
+        let device = self.block_device().expect("file is a block device");
+        let sector_size = device.sector_size();
+        let mut sector = vec![0u8; sector_size as usize];
+
+        let mut read = 0;
+        while read < buf.len() && (size == 0 || pos < size) {
+            device.read_sector(pos / sector_size, &mut sector)?;
+            let start = (pos % sector_size) as usize;
+            let mut len = min(buf.len() - read, sector.len() - start);
+            if size != 0 {
+                len = min(len, (size - pos) as usize);
+            }
+            buf[read..read + len].copy_from_slice(&sector[start..start + len]);
+            read += len;
+            pos += len as u64;
+        }
+        self.seek_pos = pos;
+        Ok(read)
+    }
+}
+
+/// Reads the bytes received by the console into `buf`, blocking until there
+/// is one, and returns the number of bytes read. The console is locked only
+/// to poll it for a byte, so that it can be written to while this waits.
+fn read_console(buf: &mut [u8]) -> usize {
+    let mut read = 0;
+    while read < buf.len() {
+        match CONSOLE.lock().try_read_byte() {
+            Some(byte) => {
+                buf[read] = byte;
+                read += 1;
+            }
+            None if read > 0 => break,
+            None => (),
+        }
+    }
+    read
+}
+
+/// Returns the next number of the generator behind `/dev/random`, seeding
+/// it from the timer on first use. An xorshift64* generator.
+fn next_random(state: &mut u64) -> u64 {
+    if *state == 0 {
+        *state = pi::timer::current_time().as_micros() as u64 | 1;
+    }
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The size of a block device, if known, and 0 for the other devices and
+    /// the whole SD card, whose size the driver does not report.
+    fn size(&self) -> u64 {
+        match &self.device {
+            Device::Partition(partition) => partition.partition().num_sectors * partition.sector_size(),
+            _ => 0,
+        }
+    }
+
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "can't resize a device"))
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.device {
+            Device::Console => Ok(read_console(buf)),
+            Device::Null => Ok(0),
+            Device::Zero => {
+                for byte in buf.iter_mut() {
+                    *byte = 0;
+                }
+                Ok(buf.len())
+            }
+            Device::Random => {
+                let mut state = RANDOM.lock();
+                for chunk in buf.chunks_mut(8) {
+                    let bytes = next_random(&mut state).to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+                Ok(buf.len())
+            }
+            Device::Sd(_) | Device::Partition(_) => self.read_blocks(buf),
+        }
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.device {
+            Device::Console => io::Write::write(&mut *CONSOLE.lock(), buf),
+            Device::Null | Device::Zero => Ok(buf.len()),
+            Device::Random => {
+                let mut state = RANDOM.lock();
+                for chunk in buf.chunks(8) {
+                    let mut bytes = [0u8; 8];
+                    bytes[..chunk.len()].copy_from_slice(chunk);
+                    *state ^= u64::from_le_bytes(bytes);
+                    next_random(&mut state);
+                }
+                Ok(buf.len())
+            }
+            Device::Sd(_) | Device::Partition(_) => {
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, "raw writes would corrupt mounted volumes"))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    /// Seeking a character device does nothing and returns 0. Seeking past
+    /// the end of a block device of known size is an error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if self.block_device().is_none() {
+            return Ok(0);
+        }
+
+        let size = traits::File::size(self) as i128;
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => size + offset as i128,
+            SeekFrom::Current(offset) => self.seek_pos as i128 + offset as i128,
+        };
+
+        if new_pos < 0 || (size != 0 && new_pos > size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek outside of the device"));
+        }
+        self.seek_pos = new_pos as u64;
+        Ok(self.seek_pos)
+    }
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = vec::IntoIter<Entry>;
+
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let entries: Vec<Entry> = self.fs.devices()
+            .into_iter()
+            .map(|(name, device)| Entry::File(File::new(&name, device)))
+            .collect();
+        Ok(entries.into_iter())
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    entry_accessors!();
+
+    read_only_entry_setters!();
+}
+
+impl traits::FileSystem for &DevFs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open_root_dir(self) -> Entry {
+        let mut attributes = Attributes::DIRECTORY;
+        attributes.set(Attributes::READ_ONLY, true);
+        let timestamp = Timestamp::default();
+        Entry::Dir(Dir {
+            fs: self.clone(),
+            name: String::new(),
+            metadata: Metadata::new(attributes, timestamp, timestamp, timestamp),
+        })
+    }
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Entry> {
+        let mut names = Vec::new();
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(name) => names.push(name),
+                Component::ParentDir => {
+                    names.pop();
+                }
+                _ => (),
+            }
+        }
+
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "no such device");
+        match names.as_slice() {
+            [] => Ok(self.open_root_dir()),
+            [name] => {
+                let (name, device) = self.devices()
+                    .into_iter()
+                    .find(|(device, _)| name.to_str() == Some(device.as_str()))
+                    .ok_or_else(not_found)?;
+                Ok(Entry::File(File::new(&name, device)))
+            }
+            _ => Err(not_found()),
+        }
+    }
+
+    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<File> {
+        Err(read_only())
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, _path: P) -> io::Result<Dir> {
+        Err(read_only())
+    }
+
+    fn remove<P: AsRef<Path>>(self, _path: P) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, _from: P, _to: Q) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl From<Entry> for vfs::Entry {
+    fn from(entry: Entry) -> vfs::Entry {
+        vfs::Entry::new(entry)
+    }
+}
+
+impl Fs for DevFs {
+    fn fs_type(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<vfs::Entry> {
+        traits::FileSystem::open(self, path).map(vfs::Entry::from)
+    }
+}
@@ -0,0 +1,337 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::{self, Vec};
+use core::fmt::Write as _;
+
+use shim::io::{self, SeekFrom};
+use shim::path::{Component, Path};
+
+use fat32::traits;
+use fat32::vfat::{Attributes, Metadata, Timestamp};
+use pi::interrupt::Interrupt;
+use pi::local_interrupt::LocalInterrupt;
+
+use super::vfs::{self, read_only, Fs};
+use crate::allocator;
+use crate::param::NCORES;
+use crate::percore;
+use crate::process::Id;
+use crate::traps::irq::IrqHandlerRegistry;
+use crate::{ALLOCATOR, GLOBAL_IRQ, SCHEDULER};
+
+/// A function generating the contents of a file.
+type Contents = fn() -> String;
+
+/// The files of the root directory, and the functions generating their
+/// contents.
+const FILES: [(&str, Contents); 4] = [
+    ("cpuinfo", cpuinfo),
+    ("interrupts", interrupts),
+    ("meminfo", meminfo),
+    ("uptime", uptime),
+];
+
+/// A read-only file system of files describing the kernel, generated when
+/// they are opened: `/meminfo`, `/cpuinfo`, `/uptime`, `/interrupts`, and a
+/// directory per process holding its `status`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcFs;
+
+impl ProcFs {
+    pub fn new() -> ProcFs {
+        ProcFs
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file or directory")
+}
+
+fn metadata(mut attributes: Attributes) -> Metadata {
+    attributes.set(Attributes::READ_ONLY, true);
+    Metadata::new(attributes, Timestamp::default(), Timestamp::default(), Timestamp::default())
+}
+
+/// A file of a `ProcFs`. Its contents are generated once, when it is opened.
+#[derive(Clone, Debug)]
+pub struct File {
+    pub name: String,
+    pub metadata: Metadata,
+    data: Vec<u8>,
+    seek_pos: u64,
+}
+
+/// The directories of a `ProcFs`.
+#[derive(Clone, Copy, Debug)]
+enum Node {
+    Root,
+    Process(Id),
+}
+
+/// A directory of a `ProcFs`. Its entries are generated when it is read.
+#[derive(Clone, Debug)]
+pub struct Dir {
+    node: Node,
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// An entry of a `ProcFs`.
+#[derive(Clone, Debug)]
+pub enum Entry {
+    File(File),
+    Dir(Dir),
+}
+
+impl File {
+    fn new(name: &str, data: String) -> File {
+        File {
+            name: String::from(name),
+            metadata: metadata(Attributes::default()),
+            data: data.into_bytes(),
+            seek_pos: 0,
+        }
+    }
+}
+
+impl Dir {
+    fn new(name: &str, node: Node) -> Dir {
+        Dir { node, name: String::from(name), metadata: metadata(Attributes::DIRECTORY) }
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = vfs::read_at(&self.data, self.seek_pos, buf);
+        self.seek_pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.seek_pos = vfs::seek_within(pos, self.seek_pos, self.data.len() as u64)?;
+        Ok(self.seek_pos)
+    }
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = vec::IntoIter<Entry>;
+
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let mut entries = Vec::new();
+        match self.node {
+            Node::Root => {
+                for &(name, contents) in FILES.iter() {
+                    entries.push(Entry::File(File::new(name, contents())));
+                }
+                for process in SCHEDULER.processes() {
+                    entries.push(Entry::Dir(Dir::new(&format!("{}", process.id), Node::Process(process.id))));
+                }
+            }
+            Node::Process(id) => entries.push(Entry::File(File::new("status", status(id)?))),
+        }
+        Ok(entries.into_iter())
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    entry_accessors!();
+
+    read_only_entry_setters!();
+}
+
+impl traits::FileSystem for &ProcFs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open_root_dir(self) -> Entry {
+        Entry::Dir(Dir::new("", Node::Root))
+    }
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Entry> {
+        let mut names = Vec::new();
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(name) => names.push(name.to_str().ok_or_else(not_found)?),
+                Component::ParentDir => {
+                    names.pop();
+                }
+                _ => (),
+            }
+        }
+
+        match names.as_slice() {
+            [] => Ok(self.open_root_dir()),
+            [name] => {
+                if let Some(&(name, contents)) = FILES.iter().find(|(file, _)| file == name) {
+                    return Ok(Entry::File(File::new(name, contents())));
+                }
+                let id = name.parse::<Id>().map_err(|_| not_found())?;
+                if !SCHEDULER.processes().iter().any(|process| process.id == id) {
+                    return Err(not_found());
+                }
+                Ok(Entry::Dir(Dir::new(name, Node::Process(id))))
+            }
+            [id, "status"] => {
+                let id = id.parse::<Id>().map_err(|_| not_found())?;
+                Ok(Entry::File(File::new("status", status(id)?)))
+            }
+            _ => Err(not_found()),
+        }
+    }
+
+    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<File> {
+        Err(read_only())
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, _path: P) -> io::Result<Dir> {
+        Err(read_only())
+    }
+
+    fn remove<P: AsRef<Path>>(self, _path: P) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, _from: P, _to: Q) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl From<Entry> for vfs::Entry {
+    fn from(entry: Entry) -> vfs::Entry {
+        vfs::Entry::new(entry)
+    }
+}
+
+impl Fs for ProcFs {
+    fn fs_type(&self) -> &'static str {
+        "proc"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<vfs::Entry> {
+        traits::FileSystem::open(self, path).map(vfs::Entry::from)
+    }
+}
+
+/// Returns the contents of `/<id>/status`: the state of the process, its
+/// saved registers and the number of pages mapped for it.
+fn status(id: Id) -> io::Result<String> {
+    let process = SCHEDULER.processes()
+        .into_iter()
+        .find(|process| process.id == id)
+        .ok_or_else(not_found)?;
+    let tf = &process.context;
+
+    let mut status = String::new();
+    let _ = writeln!(status, "Pid:\t{}", process.id);
+    let _ = writeln!(status, "State:\t{}", process.state);
+    let _ = writeln!(status, "Pages:\t{}", process.pages);
+    let _ = writeln!(status, "Pc:\t{:#018x}", tf.link_addr);
+    let _ = writeln!(status, "Sp:\t{:#018x}", tf.sp);
+    let _ = writeln!(status, "Pstate:\t{:#018x}", tf.pstate);
+    let _ = writeln!(status, "Ttbr0:\t{:#018x}", tf.ttbr0);
+    let _ = writeln!(status, "Ttbr1:\t{:#018x}", tf.ttbr1);
+    for (i, reg) in tf.gen_reg.iter().take(31).enumerate() {
+        let _ = writeln!(status, "X{}:\t{:#018x}", i, reg);
+    }
+    Ok(status)
+}
+
+/// Returns the contents of `/meminfo`: the size of the heap, how much of it
+/// is free, and the numbers of free and used blocks of each bin of the
+/// allocator.
+fn meminfo() -> String {
+    let (start, end) = allocator::memory_map().unwrap_or((0, 0));
+    let total = end - start;
+    let free = ALLOCATOR.free().unwrap_or(0);
+
+    let mut meminfo = String::new();
+    let _ = writeln!(meminfo, "MemTotal:\t{} kB", total / 1024);
+    let _ = writeln!(meminfo, "MemFree:\t{} kB", free / 1024);
+    let _ = writeln!(meminfo, "MemUsed:\t{} kB", total.saturating_sub(free) / 1024);
+    let _ = writeln!(meminfo, "HeapStart:\t{:#x}", start);
+    let _ = writeln!(meminfo, "HeapEnd:\t{:#x}", end);
+    let _ = writeln!(meminfo);
+    let _ = writeln!(meminfo, "{:>10} {:>10} {:>10}", "BinSize", "Free", "Used");
+    for bin in ALLOCATOR.bins().unwrap_or_default() {
+        let _ = writeln!(meminfo, "{:>10} {:>10} {:>10}", bin.size, bin.free, bin.used);
+    }
+    meminfo
+}
+
+/// Returns the contents of `/cpuinfo`: the state of each core.
+fn cpuinfo() -> String {
+    let mut cpuinfo = String::new();
+    for cpu in 0..NCORES {
+        let mmu = if percore::is_mmu_ready_on(cpu) { "ready" } else { "off" };
+        let _ = writeln!(cpuinfo, "processor\t: {}", cpu);
+        let _ = writeln!(cpuinfo, "mmu\t\t: {}", mmu);
+        let _ = writeln!(cpuinfo, "preemption\t: {}", percore::get_preemptive_counter_of(cpu));
+        let _ = writeln!(cpuinfo);
+    }
+    cpuinfo
+}
+
+/// Returns the contents of `/uptime`: the seconds since the system started.
+fn uptime() -> String {
+    let uptime = pi::timer::current_time();
+    format!("{}.{:02}\n", uptime.as_secs(), uptime.subsec_millis() / 10)
+}
+
+/// Returns the contents of `/interrupts`: the number of times each interrupt
+/// occurred, per core. Global interrupts are only handled by core 0.
+fn interrupts() -> String {
+    let mut interrupts = format!("{:>16}", "");
+    for cpu in 0..NCORES {
+        let _ = write!(interrupts, " {:>10}", format!("CPU{}", cpu));
+    }
+    let _ = writeln!(interrupts);
+
+    for int in Interrupt::iter() {
+        let _ = write!(interrupts, "{:>15}:", format!("{:?}", int));
+        for cpu in 0..NCORES {
+            let count = if cpu == 0 { GLOBAL_IRQ.count(int) } else { 0 };
+            let _ = write!(interrupts, " {:>10}", count);
+        }
+        let _ = writeln!(interrupts);
+    }
+    for int in LocalInterrupt::iter() {
+        let _ = write!(interrupts, "{:>15}:", format!("{:?}", int));
+        for cpu in 0..NCORES {
+            let _ = write!(interrupts, " {:>10}", percore::local_irq_of(cpu).count(int));
+        }
+        let _ = writeln!(interrupts);
+    }
+    interrupts
+}
@@ -2,6 +2,8 @@ use shim::io;
 
 use fat32::traits::BlockDevice;
 
+use crate::mutex::Mutex;
+
 extern "C" {
     /// A global representing the last SD controller error that occured.
     pub static sd_err: i64;
@@ -31,6 +33,14 @@ extern "C" {
     // External libsd.a equivalent to sd_readsector. This API also allows
     // reading multiple sectors.
     fn sd_readblock(sector_num: u32, buffer: *mut u8, num_sector: u32) -> i32;
+
+    /// Writes `num_sector` sectors (512 bytes each) from `buffer` to the SD
+    /// card, starting at sector `sector_num`. The same requirements on
+    /// `buffer` as for `sd_readblock` apply.
+    ///
+    /// On success, returns the number of bytes written. On error, returns 0
+    /// and stores the error code in `sd_err`.
+    fn sd_writeblock(buffer: *const u8, sector_num: u32, num_sector: u32) -> i32;
 }
 
 // FIXME: Define a `#[no_mangle]` `wait_micros` function for use by `libsd`.
@@ -50,8 +60,14 @@ fn uart_puts(_bytes: *const [u8]) {
 fn uart_hex(_hex: u32) {
 }
 
-/// A handle to an SD card controller.
-#[derive(Debug)]
+/// Held for each read or write request to the SD card controller: `libsd`
+/// keeps the state of a request in globals, and the volumes of the card are
+/// used from every core.
+static CONTROLLER: Mutex<()> = Mutex::new(());
+
+/// A handle to an SD card controller. Clones are handles to the same
+/// controller.
+#[derive(Clone, Debug)]
 pub struct Sd;
 
 impl Sd {
@@ -82,37 +98,99 @@ impl Sd {
     }
 }
 
+/// Returns the number of whole sectors in `len` bytes, checking that sectors
+/// `n` to `n + count - 1` can be addressed by the SD card driver.
+fn sector_count(n: u64, len: usize) -> io::Result<u32> {
+    let count = (len / 512) as u64;
+    if count == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            "buffer size is less than 512"))
+    }
+
+    match n.checked_add(count - 1) {
+        Some(last) if last <= 0xFFFFFFFF => Ok(count as u32),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+            "accessing sector number > 0xFFFFFFFF")),
+    }
+}
+
 impl BlockDevice for Sd {
     /// Reads sector `n` from the SD card into `buf`. On success, the number of
     /// bytes read is returned.
     ///
     /// # Errors
     ///
-    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
-    /// `n > 2^31 - 1` (the maximum value for an `i32`).
-    ///
-    /// An error of kind `TimedOut` is returned if a timeout occurs while
-    /// reading from the SD card.
-    ///
-    /// An error of kind `Other` is returned for all other errors.
+    /// Fails as `read_sectors` does for a single sector: an I/O error of kind
+    /// `InvalidInput` is returned if `buf.len() < 512` or `n > 0xFFFFFFFF`.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         if buf.len() < 512 {
             return Err(io::Error::new(io::ErrorKind::InvalidInput,
                 "buffer size is less than 512"))
-        } else if n > 0xFFFFFFFF {
+        }
+
+        self.read_sectors(n, &mut buf[..512])
+    }
+
+    /// Overwrites sector `n` on the SD card with the first 512 bytes of `buf`.
+    /// On success, the number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// Fails as `write_sectors` does for a single sector.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < 512 {
             return Err(io::Error::new(io::ErrorKind::InvalidInput,
-                "reading from sector number > 0xFFFFFFFF"))
+                "buffer size is less than 512"))
         }
 
+        self.write_sectors(n, &buf[..512])
+    }
+
+    /// Reads `buf.len() / 512` consecutive sectors, starting at sector `n`,
+    /// from the SD card into `buf` with a single `sd_readblock` request. On
+    /// success, the number of bytes read is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
+    /// the last sector read is > 0xFFFFFFFF.
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// reading from the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let count = sector_count(n, buf.len())?;
+        let _controller = CONTROLLER.lock();
         unsafe {
-            match sd_readblock(n as u32, buf.as_mut_ptr(), 1) {
+            match sd_readblock(n as u32, buf.as_mut_ptr(), count) {
                 0 => Err(Sd::err(sd_err)),
-                _ => Ok(512)
+                _ => Ok(count as usize * 512)
             }
         }
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Writes `buf.len() / 512` consecutive sectors, starting at sector `n`,
+    /// from `buf` to the SD card with a single `sd_writeblock` request. On
+    /// success, the number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
+    /// the last sector written is > 0xFFFFFFFF.
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// writing to the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let count = sector_count(n, buf.len())?;
+        let _controller = CONTROLLER.lock();
+        unsafe {
+            match sd_writeblock(buf.as_ptr(), n as u32, count) {
+                0 => Err(Sd::err(sd_err)),
+                _ => Ok(count as usize * 512)
+            }
+        }
     }
 }
@@ -0,0 +1,498 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::{self, Vec};
+use core::cmp::min;
+use core::fmt;
+
+use shim::io::{self, SeekFrom};
+use shim::path::{Component, Path};
+
+use fat32::traits::{self, Entry as _};
+use fat32::vfat::{Attributes, Metadata, Timestamp};
+
+use super::vfs::{self, Fs};
+use crate::mutex::Mutex;
+
+#[cfg(test)]
+mod tests;
+
+/// The inode number of the root directory.
+const ROOT: u64 = 0;
+
+/// The contents of a node.
+enum Data {
+    File(Vec<u8>),
+    /// The names and inode numbers of the children, in creation order.
+    Dir(Vec<(String, u64)>),
+}
+
+/// A file or directory of a `TmpFs`.
+struct Node {
+    data: Data,
+    /// The inode number of the directory holding the node. The root
+    /// directory is its own parent.
+    parent: u64,
+    attributes: Attributes,
+    created: Timestamp,
+    accessed: Timestamp,
+    modified: Timestamp,
+}
+
+impl Node {
+    fn new(data: Data, parent: u64) -> Node {
+        let attributes = match data {
+            Data::File(_) => Attributes::default(),
+            Data::Dir(_) => Attributes::DIRECTORY,
+        };
+        let now = now();
+        Node { data, parent, attributes, created: now, accessed: now, modified: now }
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::new(self.attributes, self.created, self.accessed, self.modified)
+    }
+}
+
+/// The nodes of a `TmpFs`, by inode number. Inode numbers are not reused, so
+/// a handle to a removed node can't reach a node created after it.
+struct Tree {
+    nodes: BTreeMap<u64, Node>,
+    next_inode: u64,
+}
+
+impl Tree {
+    fn node(&self, inode: u64) -> io::Result<&Node> {
+        self.nodes.get(&inode).ok_or_else(removed)
+    }
+
+    fn node_mut(&mut self, inode: u64) -> io::Result<&mut Node> {
+        self.nodes.get_mut(&inode).ok_or_else(removed)
+    }
+
+    fn children(&self, inode: u64) -> io::Result<&Vec<(String, u64)>> {
+        match &self.node(inode)?.data {
+            Data::Dir(children) => Ok(children),
+            Data::File(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "not a directory")),
+        }
+    }
+
+    fn children_mut(&mut self, inode: u64) -> io::Result<&mut Vec<(String, u64)>> {
+        match &mut self.node_mut(inode)?.data {
+            Data::Dir(children) => Ok(children),
+            Data::File(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "not a directory")),
+        }
+    }
+
+    fn child(&self, dir: u64, name: &str) -> io::Result<Option<u64>> {
+        let children = self.children(dir)?;
+        Ok(children.iter().find(|(child, _)| child == name).map(|&(_, inode)| inode))
+    }
+
+    /// Returns the inode number of the entry at `path`.
+    fn lookup(&self, path: &Path) -> io::Result<u64> {
+        if !path.has_root() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+        }
+
+        let mut inode = ROOT;
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => {
+                    let name = name.to_str().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "path is not valid UTF-8")
+                    })?;
+                    inode = match self.child(inode, name)? {
+                        Some(child) => child,
+                        None => return Err(io::Error::new(io::ErrorKind::NotFound, "no such file or directory")),
+                    };
+                }
+                Component::ParentDir => inode = self.node(inode)?.parent,
+                _ => (),
+            }
+        }
+        Ok(inode)
+    }
+
+    /// Returns the inode number of the parent directory of `path` and the
+    /// name of the entry at `path`.
+    fn lookup_parent<'p>(&self, path: &'p Path) -> io::Result<(u64, &'p str)> {
+        let name = path.file_name().and_then(|name| name.to_str());
+        match (path.parent(), name) {
+            (Some(parent), Some(name)) => {
+                let parent = self.lookup(parent)?;
+                self.children(parent)?;
+                Ok((parent, name))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path does not name an entry inside a directory",
+            )),
+        }
+    }
+
+    /// Creates a node with `data` at `path` and returns its inode number and
+    /// name.
+    fn create<'p>(&mut self, path: &'p Path, data: Data) -> io::Result<(u64, &'p str)> {
+        let (parent, name) = self.lookup_parent(path)?;
+        if self.child(parent, name)?.is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists"));
+        }
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.nodes.insert(inode, Node::new(data, parent));
+        self.children_mut(parent)?.push((String::from(name), inode));
+        Ok((inode, name))
+    }
+}
+
+/// Returns the current time, used to stamp nodes that are created or
+/// modified. The Pi has no real-time clock, so this is the FAT epoch, as
+/// `VFatHandle::now()` returns for the FAT volumes.
+fn now() -> Timestamp {
+    Timestamp::new(1980, 1, 1, 0, 0, 0)
+}
+
+/// Returns the error of an operation on a node that was removed.
+fn removed() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "entry was removed")
+}
+
+/// A RAM-backed file system. Its contents are lost when it is unmounted.
+/// Clones are handles to the same file system.
+#[derive(Clone)]
+pub struct TmpFs(Arc<Mutex<Tree>>);
+
+impl fmt::Debug for TmpFs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TmpFs")
+    }
+}
+
+impl TmpFs {
+    /// Returns an empty file system.
+    pub fn new() -> TmpFs {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(ROOT, Node::new(Data::Dir(Vec::new()), ROOT));
+        TmpFs(Arc::new(Mutex::new(Tree { nodes, next_inode: ROOT + 1 })))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut Tree) -> R) -> R {
+        f(&mut self.0.lock())
+    }
+
+    /// Returns the entry for the node `inode`, named `name`.
+    fn entry(&self, inode: u64, name: &str) -> io::Result<Entry> {
+        let (is_dir, metadata) = self.lock(|tree| {
+            tree.node(inode).map(|node| {
+                let is_dir = match node.data {
+                    Data::Dir(_) => true,
+                    Data::File(_) => false,
+                };
+                (is_dir, node.metadata())
+            })
+        })?;
+
+        let name = String::from(name);
+        if is_dir {
+            Ok(Entry::Dir(Dir { fs: self.clone(), inode, name, metadata }))
+        } else {
+            Ok(Entry::File(File { fs: self.clone(), inode, name, metadata, seek_pos: 0 }))
+        }
+    }
+}
+
+/// A file of a `TmpFs`.
+#[derive(Clone, Debug)]
+pub struct File {
+    fs: TmpFs,
+    inode: u64,
+    pub name: String,
+    pub metadata: Metadata,
+    seek_pos: u64,
+}
+
+/// A directory of a `TmpFs`.
+#[derive(Clone, Debug)]
+pub struct Dir {
+    fs: TmpFs,
+    inode: u64,
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// An entry of a `TmpFs`.
+#[derive(Clone, Debug)]
+pub enum Entry {
+    File(File),
+    Dir(Dir),
+}
+
+impl File {
+    //  * A method to run `f` on the contents of the file.
+    fn with_data<R>(&self, f: impl FnOnce(&mut Vec<u8>) -> R) -> io::Result<R> {
+        self.fs.lock(|tree| match &mut tree.node_mut(self.inode)?.data {
+            Data::File(data) => Ok(f(data)),
+            Data::Dir(_) => Err(removed()),
+        })
+    }
+
+    //  * A method to run `f` on the contents of the file, then stamp the file
+    //    as modified and refresh the file's copy of its metadata.
+    fn modify<R>(&mut self, f: impl FnOnce(&mut Vec<u8>) -> R) -> io::Result<R> {
+        let inode = self.inode;
+        let (result, metadata) = self.fs.lock(|tree| {
+            let node = tree.node_mut(inode)?;
+            let result = match &mut node.data {
+                Data::File(data) => f(data),
+                Data::Dir(_) => return Err(removed()),
+            };
+            let now = now();
+            node.modified = now;
+            node.accessed = now;
+            Ok((result, node.metadata()))
+        })?;
+        self.metadata = metadata;
+        Ok(result)
+    }
+}
+
+impl traits::File for File {
+    /// The file is only ever in memory: there is nothing to write.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.with_data(|data| data.len() as u64).unwrap_or(0)
+    }
+
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.modify(|data| data.resize(size as usize, 0))?;
+        self.seek_pos = min(self.seek_pos, size);
+        Ok(())
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.seek_pos;
+        let read = self.with_data(|data| vfs::read_at(data, pos, buf))?;
+        self.seek_pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pos = self.seek_pos as usize;
+        self.modify(|data| {
+            if data.len() < pos + buf.len() {
+                data.resize(pos + buf.len(), 0);
+            }
+            data[pos..pos + buf.len()].copy_from_slice(buf);
+        })?;
+        self.seek_pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.seek_pos = vfs::seek_within(pos, self.seek_pos, traits::File::size(self))?;
+        Ok(self.seek_pos)
+    }
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = vec::IntoIter<Entry>;
+
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let children = self.fs.lock(|tree| tree.children(self.inode).map(|children| children.to_vec()))?;
+        let entries: io::Result<Vec<Entry>> = children.iter()
+            .map(|(name, inode)| self.fs.entry(*inode, name))
+            .collect();
+        Ok(entries?.into_iter())
+    }
+}
+
+impl Entry {
+    fn inode(&self) -> u64 {
+        match self {
+            Entry::File(file) => file.inode,
+            Entry::Dir(dir) => dir.inode,
+        }
+    }
+
+    fn fs(&self) -> &TmpFs {
+        match self {
+            Entry::File(file) => &file.fs,
+            Entry::Dir(dir) => &dir.fs,
+        }
+    }
+
+    //  * A method to run `f` on the node of the entry, then refresh the
+    //    entry's copy of its metadata.
+    fn update(&mut self, f: impl FnOnce(&mut Node)) -> io::Result<()> {
+        let inode = self.inode();
+        let metadata = self.fs().lock(|tree| {
+            tree.node_mut(inode).map(|node| {
+                f(node);
+                node.metadata()
+            })
+        })?;
+        match self {
+            Entry::File(file) => file.metadata = metadata,
+            Entry::Dir(dir) => dir.metadata = metadata,
+        }
+        Ok(())
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    entry_accessors!();
+
+    fn set_read_only(&mut self, read_only: bool) -> io::Result<()> {
+        self.update(|node| node.attributes.set(Attributes::READ_ONLY, read_only))
+    }
+
+    fn set_hidden(&mut self, hidden: bool) -> io::Result<()> {
+        self.update(|node| node.attributes.set(Attributes::HIDDEN, hidden))
+    }
+
+    fn set_system(&mut self, system: bool) -> io::Result<()> {
+        self.update(|node| node.attributes.set(Attributes::SYSTEM, system))
+    }
+
+    fn set_archive(&mut self, archive: bool) -> io::Result<()> {
+        self.update(|node| node.attributes.set(Attributes::ARCHIVE, archive))
+    }
+
+    fn set_created(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.update(|node| node.created = timestamp)
+    }
+
+    fn set_accessed(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.update(|node| node.accessed = timestamp)
+    }
+
+    fn set_modified(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.update(|node| node.modified = timestamp)
+    }
+}
+
+impl traits::FileSystem for &TmpFs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open_root_dir(self) -> Entry {
+        self.entry(ROOT, "").expect("root directory exists")
+    }
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Entry> {
+        let path = path.as_ref();
+        let inode = self.lock(|tree| tree.lookup(path))?;
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        self.entry(inode, name)
+    }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<File> {
+        let path = path.as_ref();
+        let (inode, name) = self.lock(|tree| tree.create(path, Data::File(Vec::new())))?;
+        Ok(self.entry(inode, name)?.into_file().expect("created a file"))
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Dir> {
+        let path = path.as_ref();
+        let (inode, name) = self.lock(|tree| tree.create(path, Data::Dir(Vec::new())))?;
+        Ok(self.entry(inode, name)?.into_dir().expect("created a directory"))
+    }
+
+    /// Open handles to a removed file fail with an error kind of `NotFound`.
+    fn remove<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        self.lock(|tree| {
+            let (parent, name) = tree.lookup_parent(path)?;
+            let inode = tree.child(parent, name)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file or directory"))?;
+            if let Data::Dir(children) = &tree.node(inode)?.data {
+                if !children.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::Other, "directory is not empty"));
+                }
+            }
+
+            tree.children_mut(parent)?.retain(|&(_, child)| child != inode);
+            tree.nodes.remove(&inode);
+            Ok(())
+        })
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        self.lock(|tree| {
+            let (from_parent, from_name) = tree.lookup_parent(from)?;
+            let inode = tree.child(from_parent, from_name)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file or directory"))?;
+            let (to_parent, to_name) = tree.lookup_parent(to)?;
+            if tree.child(to_parent, to_name)?.is_some() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry already exists"));
+            }
+
+            // `to` can't be inside of `from`: walk up from the new parent.
+            let mut ancestor = to_parent;
+            while ancestor != ROOT {
+                if ancestor == inode {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't move a directory into itself"));
+                }
+                ancestor = tree.node(ancestor)?.parent;
+            }
+
+            tree.children_mut(from_parent)?.retain(|&(_, child)| child != inode);
+            tree.children_mut(to_parent)?.push((String::from(to_name), inode));
+            tree.node_mut(inode)?.parent = to_parent;
+            Ok(())
+        })
+    }
+}
+
+impl From<Entry> for vfs::Entry {
+    fn from(entry: Entry) -> vfs::Entry {
+        vfs::Entry::new(entry)
+    }
+}
+
+impl Fs for TmpFs {
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<vfs::Entry> {
+        traits::FileSystem::open(self, path).map(vfs::Entry::from)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<vfs::File> {
+        traits::FileSystem::create_file(self, path).map(vfs::File::new)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<vfs::Dir> {
+        traits::FileSystem::create_dir(self, path).map(vfs::Dir::new)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        traits::FileSystem::remove(self, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        traits::FileSystem::rename(self, from, to)
+    }
+}
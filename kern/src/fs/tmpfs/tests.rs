@@ -0,0 +1,148 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+
+use fat32::traits::{Dir as _, Entry as _, File as _, FileSystem, Metadata as _, Timestamp as _};
+use fat32::vfat::Timestamp;
+
+use super::TmpFs;
+
+fn entry_names(fs: &TmpFs, path: &str) -> Vec<String> {
+    fs.open_dir(path).expect("open dir")
+        .entries().expect("entries")
+        .map(|entry| String::from(entry.name()))
+        .collect()
+}
+
+fn read_to_string(fs: &TmpFs, path: &str) -> String {
+    let mut string = String::new();
+    fs.open_file(path).expect("open file").read_to_string(&mut string).expect("read");
+    string
+}
+
+#[test]
+fn test_create_write_read() {
+    let fs = TmpFs::new();
+    let mut file = fs.create_file("/a.txt").expect("create");
+    file.write_all(b"hello world").expect("write");
+    assert_eq!(file.size(), 11);
+
+    file.seek(SeekFrom::Start(6)).expect("seek");
+    let mut string = String::new();
+    file.read_to_string(&mut string).expect("read");
+    assert_eq!(string, "world");
+    assert_eq!(read_to_string(&fs, "/a.txt"), "hello world");
+
+    file.seek(SeekFrom::Start(6)).expect("seek");
+    file.write_all(b"there, world").expect("overwrite");
+    assert_eq!(read_to_string(&fs, "/a.txt"), "hello there, world");
+    assert!(file.seek(SeekFrom::End(1)).is_err());
+
+    file.set_len(5).expect("truncate");
+    assert_eq!(read_to_string(&fs, "/a.txt"), "hello");
+    file.set_len(7).expect("extend");
+    assert_eq!(read_to_string(&fs, "/a.txt"), "hello\0\0");
+
+    assert_eq!(fs.create_file("/a.txt").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.create_file("/missing/a.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert!(fs.create_file("/a.txt/b.txt").is_err());
+}
+
+#[test]
+fn test_create_dirs() {
+    let fs = TmpFs::new();
+    fs.create_dir("/d").expect("create dir");
+    fs.create_dir("/d/e").expect("create nested dir");
+    fs.create_file("/d/e/f.txt").expect("create file");
+    fs.create_file("/d/b.txt").expect("create file");
+
+    assert_eq!(entry_names(&fs, "/"), vec!["d"]);
+    assert_eq!(entry_names(&fs, "/d"), vec!["e", "b.txt"]);
+    assert!(fs.open("/d").unwrap().is_dir());
+    assert!(fs.open("/d/e/../b.txt").unwrap().is_file());
+    assert_eq!(fs.open("/d/e/f.txt").unwrap().name(), "f.txt");
+    assert_eq!(fs.open("/d/x").unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_rename() {
+    let fs = TmpFs::new();
+    fs.create_dir("/d").unwrap();
+    fs.create_dir("/d/e").unwrap();
+    fs.create_file("/a.txt").unwrap().write_all(b"moved").unwrap();
+
+    fs.rename("/a.txt", "/d/e/b.txt").expect("move file");
+    assert_eq!(entry_names(&fs, "/"), vec!["d"]);
+    assert_eq!(entry_names(&fs, "/d/e"), vec!["b.txt"]);
+    assert_eq!(read_to_string(&fs, "/d/e/b.txt"), "moved");
+
+    fs.rename("/d/e", "/e").expect("move dir");
+    assert_eq!(entry_names(&fs, "/"), vec!["d", "e"]);
+    assert_eq!(read_to_string(&fs, "/e/b.txt"), "moved");
+    assert!(fs.open("/e/..").unwrap().is_dir());
+    assert_eq!(entry_names(&fs, "/e/.."), vec!["d", "e"]);
+
+    fs.create_dir("/e/f").unwrap();
+    assert_eq!(fs.rename("/e", "/e/f/g").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fs.rename("/e", "/e/g").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fs.rename("/d", "/e/b.txt").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs.rename("/x", "/y").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(entry_names(&fs, "/e"), vec!["b.txt", "f"]);
+}
+
+#[test]
+fn test_remove() {
+    let fs = TmpFs::new();
+    fs.create_dir("/d").unwrap();
+    let mut file = fs.create_file("/d/a.txt").unwrap();
+    file.write_all(b"gone").unwrap();
+
+    assert!(fs.remove("/d").is_err());
+    fs.remove("/d/a.txt").expect("remove file");
+    assert_eq!(entry_names(&fs, "/d"), Vec::<String>::new());
+    assert_eq!(fs.open("/d/a.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(file.write(b"x").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.remove("/d/a.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    // A file created in place of a removed one is not reached by handles to
+    // the removed one.
+    fs.create_file("/d/a.txt").unwrap();
+    assert_eq!(file.read(&mut [0u8; 4]).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    fs.remove("/d/a.txt").unwrap();
+    fs.remove("/d").expect("remove empty dir");
+    assert_eq!(entry_names(&fs, "/"), Vec::<String>::new());
+}
+
+#[test]
+fn test_write_and_set_len_stamp_file() {
+    let fs = TmpFs::new();
+    fs.create_file("/a.txt").unwrap();
+    let epoch = fs.open("/a.txt").unwrap().metadata().modified();
+    assert_eq!(epoch.year(), 1980);
+
+    let past = Timestamp::new(2001, 2, 3, 4, 5, 6);
+    let mut entry = fs.open("/a.txt").unwrap();
+    entry.set_modified(past).unwrap();
+    entry.set_accessed(past).unwrap();
+    entry.set_created(past).unwrap();
+    assert_eq!(fs.open("/a.txt").unwrap().metadata().modified(), past);
+
+    let mut file = fs.open_file("/a.txt").unwrap();
+    file.write_all(b"stamp").unwrap();
+    let metadata = fs.open("/a.txt").unwrap().metadata().clone();
+    assert_eq!(metadata.modified(), epoch);
+    assert_eq!(metadata.accessed().year(), 1980);
+    assert_eq!(metadata.created(), past);
+    assert_eq!(file.metadata.modified(), epoch);
+
+    fs.open("/a.txt").unwrap().set_modified(past).unwrap();
+    file.set_len(2).unwrap();
+    assert_eq!(fs.open("/a.txt").unwrap().metadata().modified(), epoch);
+
+    fs.open("/a.txt").unwrap().set_modified(past).unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.read(&mut [0u8; 2]).unwrap();
+    assert_eq!(fs.open("/a.txt").unwrap().metadata().modified(), past);
+}
@@ -0,0 +1,484 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::fmt;
+
+use shim::io::{self, SeekFrom};
+use shim::path::Path;
+
+use fat32::check::{Mode, Problem};
+use fat32::traits;
+use fat32::vfat::{FsStats, Metadata, Timestamp};
+
+/// A file system that can be mounted in the VFS: an object-safe counterpart
+/// of `traits::FileSystem`. Paths are absolute, from the root of the file
+/// system rather than of the VFS. File systems are shared by every core.
+pub trait Fs: Send + Sync {
+    /// The name of the type of file system, like `vfat`.
+    fn fs_type(&self) -> &'static str;
+
+    /// Opens the entry at `path`. See `traits::FileSystem::open()`.
+    fn open(&self, path: &Path) -> io::Result<Entry>;
+
+    /// Creates a new, empty file at `path`. See
+    /// `traits::FileSystem::create_file()`. Refused by default, as by
+    /// read-only file systems.
+    fn create_file(&self, _path: &Path) -> io::Result<File> {
+        Err(read_only())
+    }
+
+    /// Creates a new, empty directory at `path`. See
+    /// `traits::FileSystem::create_dir()`. Refused by default.
+    fn create_dir(&self, _path: &Path) -> io::Result<Dir> {
+        Err(read_only())
+    }
+
+    /// Removes the file or empty directory at `path`. See
+    /// `traits::FileSystem::remove()`. Refused by default.
+    fn remove(&self, _path: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    /// Moves the entry at `from` to `to`. See `traits::FileSystem::rename()`.
+    /// Refused by default.
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    /// Returns the size, usage and identity of the file system.
+    fn statfs(&self) -> io::Result<FsStats> {
+        Err(unsupported("file system statistics"))
+    }
+
+    /// Checks the file system for inconsistencies, repairing them if `mode`
+    /// is `Mode::Repair`, and returns the problems found.
+    fn check(&self, _mode: Mode) -> io::Result<Vec<Problem>> {
+        Err(unsupported("checking"))
+    }
+
+    /// Writes every modified block back to the device, leaving the file
+    /// system consistent on it. Called when the file system is unmounted.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the error of an operation that a file system does not support.
+fn unsupported(operation: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{} is not supported by the file system", operation))
+}
+
+/// Returns the error of an operation that would modify a read-only file
+/// system.
+pub fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "file system is read-only")
+}
+
+/// Reads from `data` at `pos` into `buf`, as a file holding `data` read at
+/// seek position `pos`, and returns the number of bytes read.
+pub fn read_at(data: &[u8], pos: u64, buf: &mut [u8]) -> usize {
+    let start = min(pos, data.len() as u64) as usize;
+    let len = min(buf.len(), data.len() - start);
+    buf[..len].copy_from_slice(&data[start..start + len]);
+    len
+}
+
+/// Returns the seek position `pos` resolves to in a file of `size` bytes at
+/// seek position `current`. Seeking past the end of the file is an error, as
+/// on FAT volumes.
+pub fn seek_within(pos: SeekFrom, current: u64, size: u64) -> io::Result<u64> {
+    let new_pos = match pos {
+        SeekFrom::Start(offset) => offset as i128,
+        SeekFrom::End(offset) => size as i128 + offset as i128,
+        SeekFrom::Current(offset) => current as i128 + offset as i128,
+    };
+
+    if new_pos < 0 || new_pos > size as i128 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek outside of the file"));
+    }
+    Ok(new_pos as u64)
+}
+
+/// Implements the accessors of `traits::Entry` for an enum `Entry` whose
+/// variants hold a `File` and a `Dir`, both with `name` and `metadata`
+/// fields. `File`, `Dir` and `Metadata` must be in scope where it is used.
+macro_rules! entry_accessors {
+    () => {
+        fn name(&self) -> &str {
+            match self {
+                Entry::File(file) => &file.name,
+                Entry::Dir(dir) => &dir.name,
+            }
+        }
+
+        fn metadata(&self) -> &Metadata {
+            match self {
+                Entry::File(file) => &file.metadata,
+                Entry::Dir(dir) => &dir.metadata,
+            }
+        }
+
+        fn as_file(&self) -> Option<&File> {
+            match self {
+                Entry::File(file) => Some(file),
+                Entry::Dir(_) => None,
+            }
+        }
+
+        fn as_dir(&self) -> Option<&Dir> {
+            match self {
+                Entry::File(_) => None,
+                Entry::Dir(dir) => Some(dir),
+            }
+        }
+
+        fn into_file(self) -> Option<File> {
+            match self {
+                Entry::File(file) => Some(file),
+                Entry::Dir(_) => None,
+            }
+        }
+
+        fn into_dir(self) -> Option<Dir> {
+            match self {
+                Entry::File(_) => None,
+                Entry::Dir(dir) => Some(dir),
+            }
+        }
+    };
+}
+
+/// Implements the setters of `traits::Entry` for the entries of a read-only
+/// file system: every change is refused with `read_only()`.
+macro_rules! read_only_entry_setters {
+    () => {
+        fn set_read_only(&mut self, _read_only: bool) -> ::shim::io::Result<()> {
+            Err($crate::fs::vfs::read_only())
+        }
+
+        fn set_hidden(&mut self, _hidden: bool) -> ::shim::io::Result<()> {
+            Err($crate::fs::vfs::read_only())
+        }
+
+        fn set_system(&mut self, _system: bool) -> ::shim::io::Result<()> {
+            Err($crate::fs::vfs::read_only())
+        }
+
+        fn set_archive(&mut self, _archive: bool) -> ::shim::io::Result<()> {
+            Err($crate::fs::vfs::read_only())
+        }
+
+        fn set_created(&mut self, _timestamp: ::fat32::vfat::Timestamp) -> ::shim::io::Result<()> {
+            Err($crate::fs::vfs::read_only())
+        }
+
+        fn set_accessed(&mut self, _timestamp: ::fat32::vfat::Timestamp) -> ::shim::io::Result<()> {
+            Err($crate::fs::vfs::read_only())
+        }
+
+        fn set_modified(&mut self, _timestamp: ::fat32::vfat::Timestamp) -> ::shim::io::Result<()> {
+            Err($crate::fs::vfs::read_only())
+        }
+    };
+}
+
+/// An object-safe counterpart of `traits::File`. Files are `Send` so that
+/// processes can hold them.
+pub trait FileOps: io::Read + io::Write + io::Seek + Send {
+    fn sync(&mut self) -> io::Result<()>;
+    fn size(&self) -> u64;
+    fn set_len(&mut self, size: u64) -> io::Result<()>;
+}
+
+impl<T: traits::File + Send> FileOps for T {
+    fn sync(&mut self) -> io::Result<()> {
+        traits::File::sync(self)
+    }
+
+    fn size(&self) -> u64 {
+        traits::File::size(self)
+    }
+
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        traits::File::set_len(self, size)
+    }
+}
+
+/// An object-safe counterpart of `traits::Dir`.
+pub trait DirOps {
+    fn entries(&self) -> io::Result<Box<dyn Iterator<Item = Entry>>>;
+}
+
+impl<T> DirOps for T
+where
+    T: traits::Dir,
+    T::Entry: Into<Entry> + 'static,
+    T::Iter: 'static,
+{
+    fn entries(&self) -> io::Result<Box<dyn Iterator<Item = Entry>>> {
+        let entries = traits::Dir::entries(self)?;
+        Ok(Box::new(entries.map(Into::into)))
+    }
+}
+
+/// An attribute of an entry, as set by the setters of `traits::Entry`.
+#[derive(Debug, Copy, Clone)]
+pub enum Attribute {
+    ReadOnly(bool),
+    Hidden(bool),
+    System(bool),
+    Archive(bool),
+    Created(Timestamp),
+    Accessed(Timestamp),
+    Modified(Timestamp),
+}
+
+/// The object-safe part of `traits::Entry` that does not depend on whether
+/// the entry is a file or a directory.
+pub trait EntryOps {
+    fn name(&self) -> &str;
+    fn metadata(&self) -> &Metadata;
+    fn set(&mut self, attribute: Attribute) -> io::Result<()>;
+}
+
+impl<T: traits::Entry<Metadata = Metadata>> EntryOps for T {
+    fn name(&self) -> &str {
+        traits::Entry::name(self)
+    }
+
+    fn metadata(&self) -> &Metadata {
+        traits::Entry::metadata(self)
+    }
+
+    fn set(&mut self, attribute: Attribute) -> io::Result<()> {
+        match attribute {
+            Attribute::ReadOnly(read_only) => self.set_read_only(read_only),
+            Attribute::Hidden(hidden) => self.set_hidden(hidden),
+            Attribute::System(system) => self.set_system(system),
+            Attribute::Archive(archive) => self.set_archive(archive),
+            Attribute::Created(timestamp) => self.set_created(timestamp),
+            Attribute::Accessed(timestamp) => self.set_accessed(timestamp),
+            Attribute::Modified(timestamp) => self.set_modified(timestamp),
+        }
+    }
+}
+
+/// A file of a mounted file system.
+pub struct File(Box<dyn FileOps>);
+
+/// A directory of a mounted file system.
+pub struct Dir(Box<dyn DirOps>);
+
+/// An entry of a mounted file system.
+pub struct Entry {
+    /// The name the entry is listed under, if not its own: mount points are
+    /// listed under the name of the directory they are mounted at.
+    name: Option<String>,
+    ops: Box<dyn EntryOps>,
+    node: Node,
+}
+
+enum Node {
+    File(File),
+    Dir(Dir),
+}
+
+impl File {
+    pub fn new<T: FileOps + 'static>(file: T) -> File {
+        File(Box::new(file))
+    }
+}
+
+impl Dir {
+    pub fn new<T: DirOps + 'static>(dir: T) -> Dir {
+        Dir(Box::new(dir))
+    }
+}
+
+impl Entry {
+    /// Returns the VFS entry for the entry `entry` of a file system.
+    pub fn new<T>(entry: T) -> Entry
+    where
+        T: traits::Entry<Metadata = Metadata> + Clone + 'static,
+        T::File: Send + 'static,
+        T::Dir: DirOps + 'static,
+    {
+        let node = match entry.clone().into_file() {
+            Some(file) => Node::File(File::new(file)),
+            None => Node::Dir(Dir::new(entry.clone().into_dir().expect("entry is a file or a directory"))),
+        };
+        Entry { name: None, ops: Box::new(entry), node }
+    }
+
+    /// Returns `self`, listed under `name`.
+    pub fn renamed(self, name: &str) -> Entry {
+        Entry { name: Some(String::from(name)), ..self }
+    }
+
+    /// Returns `self`, with its directory replaced by `f(dir)` if it is one.
+    pub fn map_dir<F: FnOnce(Dir) -> Dir>(self, f: F) -> Entry {
+        let node = match self.node {
+            Node::File(file) => Node::File(file),
+            Node::Dir(dir) => Node::Dir(f(dir)),
+        };
+        Entry { name: self.name, ops: self.ops, node }
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("File").field("size", &self.0.size()).finish()
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("name", &traits::Entry::name(self))
+            .field("is_dir", &traits::Entry::is_dir(self))
+            .finish()
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        match &self.name {
+            Some(name) => name,
+            None => self.ops.name(),
+        }
+    }
+
+    fn metadata(&self) -> &Metadata {
+        self.ops.metadata()
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match &self.node {
+            Node::File(file) => Some(file),
+            Node::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match &self.node {
+            Node::File(_) => None,
+            Node::Dir(dir) => Some(dir),
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self.node {
+            Node::File(file) => Some(file),
+            Node::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self.node {
+            Node::File(_) => None,
+            Node::Dir(dir) => Some(dir),
+        }
+    }
+
+    fn set_read_only(&mut self, read_only: bool) -> io::Result<()> {
+        self.ops.set(Attribute::ReadOnly(read_only))
+    }
+
+    fn set_hidden(&mut self, hidden: bool) -> io::Result<()> {
+        self.ops.set(Attribute::Hidden(hidden))
+    }
+
+    fn set_system(&mut self, system: bool) -> io::Result<()> {
+        self.ops.set(Attribute::System(system))
+    }
+
+    fn set_archive(&mut self, archive: bool) -> io::Result<()> {
+        self.ops.set(Attribute::Archive(archive))
+    }
+
+    fn set_created(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.ops.set(Attribute::Created(timestamp))
+    }
+
+    fn set_accessed(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.ops.set(Attribute::Accessed(timestamp))
+    }
+
+    fn set_modified(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.ops.set(Attribute::Modified(timestamp))
+    }
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = Box<dyn Iterator<Item = Entry>>;
+
+    fn entries(&self) -> io::Result<Self::Iter> {
+        self.0.entries()
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.0.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.0.size()
+    }
+
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.0.set_len(size)
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// A directory with file systems mounted on some of its children: the
+/// entries of `dir`, where the root directories of the mounted file systems
+/// take the place of the entries they are mounted on.
+pub struct MountedDir {
+    pub dir: Dir,
+    /// The names of the children with file systems mounted on them, and the
+    /// file systems.
+    pub mounts: Vec<(String, Arc<dyn Fs>)>,
+}
+
+impl DirOps for MountedDir {
+    fn entries(&self) -> io::Result<Box<dyn Iterator<Item = Entry>>> {
+        let mut entries: Vec<Entry> = traits::Dir::entries(&self.dir)?
+            .filter(|entry| !self.mounts.iter().any(|(name, _)| name == traits::Entry::name(entry)))
+            .collect();
+        for (name, fs) in &self.mounts {
+            entries.push(fs.open(Path::new("/"))?.renamed(name));
+        }
+        Ok(Box::new(entries.into_iter()))
+    }
+}
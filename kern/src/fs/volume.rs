@@ -0,0 +1,199 @@
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::path::Path;
+
+use fat32::check::{self, Mode, Problem};
+use fat32::exfat::{self, ExFat};
+use fat32::ext2::{self, Ext2};
+use fat32::traits::{self, FileSystem, Metadata as _, Timestamp as _};
+use fat32::vfat::{self, Attributes, FsStats, Metadata, Timestamp, VFatHandle};
+
+use super::vfs::{Dir, Entry, File, Fs};
+use super::{PiExFatHandle, PiExt2Handle, PiVFatHandle};
+
+impl From<vfat::Entry<PiVFatHandle>> for Entry {
+    fn from(entry: vfat::Entry<PiVFatHandle>) -> Entry {
+        Entry::new(entry)
+    }
+}
+
+impl From<exfat::Entry<PiExFatHandle>> for Entry {
+    fn from(entry: exfat::Entry<PiExFatHandle>) -> Entry {
+        Entry::new(entry)
+    }
+}
+
+impl From<ext2::Entry<PiExt2Handle>> for Entry {
+    fn from(entry: ext2::Entry<PiExt2Handle>) -> Entry {
+        Entry::new(Ext2Entry::new(entry))
+    }
+}
+
+/// An entry of an ext2 volume, with its metadata in the FAT form the VFS
+/// deals in: mode bits become attributes and times are clamped to the
+/// years FAT can record.
+#[derive(Clone)]
+struct Ext2Entry {
+    entry: ext2::Entry<PiExt2Handle>,
+    metadata: Metadata,
+}
+
+impl Ext2Entry {
+    fn new(entry: ext2::Entry<PiExt2Handle>) -> Ext2Entry {
+        let ext2_metadata = *traits::Entry::metadata(&entry);
+        let mut attributes = Attributes::default();
+        attributes.set(Attributes::READ_ONLY, ext2_metadata.read_only());
+        attributes.set(Attributes::HIDDEN, ext2_metadata.hidden());
+        attributes.set(Attributes::DIRECTORY, traits::Entry::is_dir(&entry));
+        let metadata = Metadata::new(
+            attributes,
+            fat_timestamp(ext2_metadata.created()),
+            fat_timestamp(ext2_metadata.accessed()),
+            fat_timestamp(ext2_metadata.modified()),
+        );
+        Ext2Entry { entry, metadata }
+    }
+}
+
+/// Returns `timestamp` as a FAT timestamp, clamped to the years 1980 to 2107.
+fn fat_timestamp(timestamp: ext2::Timestamp) -> Timestamp {
+    Timestamp::new(
+        timestamp.year(),
+        timestamp.month(),
+        timestamp.day(),
+        timestamp.hour(),
+        timestamp.minute(),
+        timestamp.second(),
+    )
+}
+
+impl traits::Entry for Ext2Entry {
+    type File = ext2::File<PiExt2Handle>;
+    type Dir = ext2::Dir<PiExt2Handle>;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        self.entry.name()
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn as_file(&self) -> Option<&Self::File> {
+        self.entry.as_file()
+    }
+
+    fn as_dir(&self) -> Option<&Self::Dir> {
+        self.entry.as_dir()
+    }
+
+    fn into_file(self) -> Option<Self::File> {
+        self.entry.into_file()
+    }
+
+    fn into_dir(self) -> Option<Self::Dir> {
+        self.entry.into_dir()
+    }
+
+    read_only_entry_setters!();
+}
+
+impl Fs for PiVFatHandle {
+    fn fs_type(&self) -> &'static str {
+        "vfat"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Entry> {
+        FileSystem::open(self, path).map(Entry::from)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<File> {
+        FileSystem::create_file(self, path).map(File::new)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<Dir> {
+        FileSystem::create_dir(self, path).map(Dir::new)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        FileSystem::remove(self, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        FileSystem::rename(self, from, to)
+    }
+
+    fn statfs(&self) -> io::Result<FsStats> {
+        self.lock(|vfat| vfat.statfs())
+    }
+
+    fn check(&self, mode: Mode) -> io::Result<Vec<Problem>> {
+        check::check(self, mode)
+    }
+
+    /// Also marks the volume as cleanly unmounted.
+    fn sync(&self) -> io::Result<()> {
+        self.lock(|vfat| vfat.unmount())
+    }
+}
+
+/// exFAT volumes are mounted read-only: they have nothing to sync and can't
+/// be checked.
+impl Fs for ExFat<PiExFatHandle> {
+    fn fs_type(&self) -> &'static str {
+        "exfat"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Entry> {
+        FileSystem::open(self, path).map(Entry::from)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<File> {
+        FileSystem::create_file(self, path).map(File::new)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<Dir> {
+        FileSystem::create_dir(self, path).map(Dir::new)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        FileSystem::remove(self, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        FileSystem::rename(self, from, to)
+    }
+
+    fn statfs(&self) -> io::Result<FsStats> {
+        Ok(self.lock(|volume| volume.statfs()))
+    }
+}
+
+/// ext2 volumes are mounted read-only, like exFAT volumes.
+impl Fs for Ext2<PiExt2Handle> {
+    fn fs_type(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Entry> {
+        FileSystem::open(self, path).map(Entry::from)
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<File> {
+        FileSystem::create_file(self, path).map(File::new)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<Dir> {
+        FileSystem::create_dir(self, path).map(Dir::new)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        FileSystem::remove(self, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        FileSystem::rename(self, from, to)
+    }
+}
@@ -133,6 +133,7 @@ unsafe fn kmain2() -> ! {
     *addr = 0;
     VMM.wait();
     info!("Initialized core {}", core_idx);
+    SCHEDULER.wait();
     SCHEDULER.start()
 }
 
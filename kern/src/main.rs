@@ -1,5 +1,6 @@
 #![feature(alloc_error_handler)]
 #![feature(const_fn)]
+#![feature(const_vec_new)]
 #![feature(decl_macro)]
 #![feature(asm)]
 #![feature(global_asm)]
@@ -68,10 +69,12 @@ unsafe fn kmain() -> ! {
     );
 
     ALLOCATOR.initialize();
-    FILESYSTEM.initialize();
     VMM.initialize();
-    SCHEDULER.initialize();
     init::initialize_app_cores();
     VMM.wait();
+    // The file systems are shared through `Arc`s, whose atomic reference
+    // counts need the MMU.
+    FILESYSTEM.initialize();
+    SCHEDULER.initialize();
     SCHEDULER.start();
 }
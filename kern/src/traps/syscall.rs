@@ -1,12 +1,16 @@
 use alloc::boxed::Box;
 use core::time::Duration;
 use pi::timer::current_time;
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+
+use fat32::traits::{Entry as _, File as _, FileSystem as _, Metadata as _};
 
 use crate::console::{kprint, kprintln};
+use crate::fs::File;
 use crate::param::USER_IMG_BASE;
 use crate::process::{Process, State};
 use crate::traps::TrapFrame;
-use crate::{ETHERNET, SCHEDULER};
+use crate::{ETHERNET, FILESYSTEM, SCHEDULER};
 use smoltcp::wire::{IpAddress, IpEndpoint};
 
 use kernel_api::*;
@@ -261,6 +265,235 @@ pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
     }
 }
 
+/// Returns a UTF-8 string from a virtual address and a length.
+///
+/// # Errors
+/// This functions returns `Err(OsError::BadAddress)` if the string is not
+/// entirely in userspace, and `Err(OsError::InvalidArgument)` if it is not
+/// UTF-8 encoded.
+unsafe fn to_user_str<'a>(va: usize, len: usize) -> OsResult<&'a str> {
+    to_user_slice(va, len)
+        .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument))
+}
+
+/// Runs `f` on the file of descriptor `fd` of the current process. The file
+/// is taken out of the descriptor table while `f` runs, so that `f` does not
+/// run in the scheduler's critical region.
+///
+/// # Errors
+/// This function returns `OsError::InvalidFileDescriptor` if `fd` is not open.
+fn with_file<R, F>(fd: usize, tf: &TrapFrame, f: F) -> OsResult<R>
+where
+    F: FnOnce(&mut File) -> OsResult<R>,
+{
+    let mut file = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).take_file(fd))
+        .ok_or(OsError::InvalidFileDescriptor)?;
+    let result = f(&mut file);
+    SCHEDULER.critical(|scheduler| scheduler.find_process(tf).files[fd] = Some(file));
+    result
+}
+
+/// Opens a file and adds it to the current process's descriptor table.
+///
+/// This system call takes the address of an absolute path as the first
+/// parameter, the length of the path as the second parameter, and flags as
+/// the third parameter. With the `O_CREATE` flag, the file is created if it
+/// does not exist.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the file descriptor, the lowest one that is not open.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+/// - `OsError::ExpectedFileFoundDir`: The path is a directory.
+/// - `OsError::NoEntry`: The file does not exist, and `O_CREATE` was not given.
+/// - All the other errors from opening or creating the file.
+pub fn sys_open(va: usize, len: usize, flags: u64, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_str(va, len) }
+        .and_then(|path| {
+            let entry = match FILESYSTEM.open(path) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound && flags & O_CREATE != 0 => {
+                    return FILESYSTEM.create_file(path).map_err(OsError::from);
+                }
+                result => result?,
+            };
+            entry.into_file().ok_or(OsError::ExpectedFileFoundDir)
+        })
+        .map(|file| SCHEDULER.critical(|scheduler| scheduler.find_process(tf).add_file(file)));
+
+    match result {
+        Ok(fd) => {
+            tf.gen_reg[0] = fd as u64;
+            tf.gen_reg[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.gen_reg[7] = e as u64;
+        }
+    }
+}
+
+/// Reads from an open file.
+///
+/// This system call takes a file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the
+/// buffer as the third parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read, 0 at the end of the file.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The file descriptor is not open.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - All the other errors from reading the file.
+pub fn sys_read(fd: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice_mut(va, len) }
+        .and_then(|buf| with_file(fd, tf, |file| file.read(buf).map_err(OsError::from)));
+
+    match result {
+        Ok(read) => {
+            tf.gen_reg[0] = read as u64;
+            tf.gen_reg[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.gen_reg[7] = e as u64;
+        }
+    }
+}
+
+/// Writes to an open file.
+///
+/// This system call takes a file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the
+/// buffer as the third parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The file descriptor is not open.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - All the other errors from writing the file.
+pub fn sys_write_fd(fd: usize, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_slice(va, len) }
+        .and_then(|buf| with_file(fd, tf, |file| file.write(buf).map_err(OsError::from)));
+
+    match result {
+        Ok(written) => {
+            tf.gen_reg[0] = written as u64;
+            tf.gen_reg[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.gen_reg[7] = e as u64;
+        }
+    }
+}
+
+/// Closes an open file, writing back its modified data first. The file
+/// descriptor can then be reused by `sys_open`.
+///
+/// This system call takes a file descriptor as the first parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The file descriptor is not open.
+/// - All the other errors from writing back the file, which is closed anyway.
+pub fn sys_close(fd: usize, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).take_file(fd))
+        .ok_or(OsError::InvalidFileDescriptor)
+        .and_then(|mut file| file.sync().map_err(OsError::from));
+
+    match result {
+        Ok(()) => {
+            tf.gen_reg[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.gen_reg[7] = e as u64;
+        }
+    }
+}
+
+/// Moves the position of an open file.
+///
+/// This system call takes a file descriptor as the first parameter, an
+/// offset as the second parameter, and the origin of the offset as the third
+/// parameter: `SEEK_SET`, `SEEK_CUR` or `SEEK_END`. The offset is signed for
+/// the last two.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new position, from the start of the file.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidFileDescriptor`: The file descriptor is not open.
+/// - `OsError::InvalidArgument`: The origin is unknown.
+/// - All the other errors from seeking the file.
+pub fn sys_seek(fd: usize, offset: u64, whence: u64, tf: &mut TrapFrame) {
+    let pos = match whence {
+        SEEK_SET => Ok(SeekFrom::Start(offset)),
+        SEEK_CUR => Ok(SeekFrom::Current(offset as i64)),
+        SEEK_END => Ok(SeekFrom::End(offset as i64)),
+        _ => Err(OsError::InvalidArgument),
+    };
+    let result = pos.and_then(|pos| with_file(fd, tf, |file| file.seek(pos).map_err(OsError::from)));
+
+    match result {
+        Ok(new_pos) => {
+            tf.gen_reg[0] = new_pos;
+            tf.gen_reg[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.gen_reg[7] = e as u64;
+        }
+    }
+}
+
+/// Returns the status of an entry.
+///
+/// This system call takes the address of an absolute path as the first
+/// parameter and the length of the path as the second parameter.
+///
+/// In addition to the usual status value, this system call returns three
+/// parameters:
+///
+/// - x0: the size of the file, or 0 for a directory
+/// - x1: is_dir
+/// - x2: is_read_only
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+/// - `OsError::NoEntry`: The entry does not exist.
+/// - All the other errors from opening the entry.
+pub fn sys_stat(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = unsafe { to_user_str(va, len) }
+        .and_then(|path| FILESYSTEM.open(path).map_err(OsError::from));
+
+    match result {
+        Ok(entry) => {
+            tf.gen_reg[0] = entry.as_file().map(|file| file.size()).unwrap_or(0);
+            tf.gen_reg[1] = entry.is_dir() as u64;
+            tf.gen_reg[2] = entry.metadata().read_only() as u64;
+            tf.gen_reg[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.gen_reg[7] = e as u64;
+        }
+    }
+}
+
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     // info!("handle syscall {}", num);
     match num as usize {
@@ -271,6 +504,12 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
         NR_WRITE => sys_write(tf.gen_reg[0] as u8, tf),
         NR_GETPID => sys_getpid(tf),
         NR_WRITE_STR => sys_write_str(tf.gen_reg[0] as usize, tf.gen_reg[1] as usize, tf),
+        NR_OPEN => sys_open(tf.gen_reg[0] as usize, tf.gen_reg[1] as usize, tf.gen_reg[2], tf),
+        NR_READ => sys_read(tf.gen_reg[0] as usize, tf.gen_reg[1] as usize, tf.gen_reg[2] as usize, tf),
+        NR_WRITE_FD => sys_write_fd(tf.gen_reg[0] as usize, tf.gen_reg[1] as usize, tf.gen_reg[2] as usize, tf),
+        NR_CLOSE => sys_close(tf.gen_reg[0] as usize, tf),
+        NR_SEEK => sys_seek(tf.gen_reg[0] as usize, tf.gen_reg[1], tf.gen_reg[2], tf),
+        NR_STAT => sys_stat(tf.gen_reg[0] as usize, tf.gen_reg[1] as usize, tf),
         _ => kprintln!("Unknown syscall ID {}", num),
     }
 }
@@ -289,6 +289,11 @@ impl UserPageTable {
 
         unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) }
     }
+
+    /// Returns the number of pages allocated in the page table.
+    pub fn page_count(&self) -> usize {
+        self.into_iter().filter(|entry| entry.is_valid()).count()
+    }
 }
 
 impl Deref for KernPageTable {
@@ -0,0 +1,300 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use fat32::check::{self, Mode};
+use fat32::mkfs;
+use fat32::traits::{Dir, Entry, FileSystem, Metadata, Timestamp as _};
+use fat32::traits::File as _;
+use fat32::vfat::{Timestamp, VFat, VFatHandle};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone)]
+struct Handle(Arc<Mutex<VFat<Handle>>>);
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle")
+    }
+}
+
+impl VFatHandle for Handle {
+    fn new(val: VFat<Handle>) -> Self {
+        Handle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut VFat<Handle>) -> R) -> R {
+        f(&mut self.0.lock().expect("poisoned VFat lock"))
+    }
+
+    fn now(&self) -> Timestamp {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let secs = secs % 86400;
+        Timestamp::new(year as usize, month, day, (secs / 3600) as u8, (secs / 60 % 60) as u8, (secs % 60) as u8)
+    }
+}
+
+/// Converts a number of days since 1970-01-01 to a (year, month, day) date in
+/// the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn mount(image: &str, writable: bool) -> io::Result<Handle> {
+    let file = OpenOptions::new().read(true).write(writable).open(image)?;
+    let vfat = VFat::<Handle>::from(file)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("cannot mount {}: {:?}", image, e)))?;
+
+    if vfat.lock(|vfat| vfat.needs_check()) {
+        eprintln!("fat32-tool: warning: {} was not cleanly unmounted, run `check`", image);
+    }
+    Ok(vfat)
+}
+
+/// Runs `f` on the image mounted for writing, then unmounts it cleanly. If
+/// `f` fails, what it changed is still written back, but the image stays
+/// marked dirty so that `check` repairs whatever `f` left half done.
+fn modify<R>(image: &str, f: impl FnOnce(&Handle) -> io::Result<R>) -> io::Result<()> {
+    let vfat = mount(image, true)?;
+    match f(&vfat) {
+        Ok(_) => vfat.lock(|vfat| vfat.unmount()),
+        Err(e) => {
+            if let Err(flush_error) = vfat.lock(|vfat| vfat.flush()) {
+                eprintln!("fat32-tool: warning: cannot write {} back: {}", image, flush_error);
+            }
+            Err(e)
+        }
+    }
+}
+
+fn ls(vfat: &Handle, path: &str, long: bool, all: bool) -> io::Result<()> {
+    let entry = vfat.open(path)?;
+    let dir = match entry.into_dir() {
+        Some(dir) => dir,
+        None => {
+            println!("{}", path);
+            return Ok(());
+        }
+    };
+
+    for entry in dir.entries()? {
+        let metadata = entry.metadata();
+        if !all && (metadata.hidden() || entry.name() == "." || entry.name() == "..") {
+            continue;
+        }
+
+        if long {
+            let modified = metadata.modified();
+            let size = entry.as_file().map(|file| file.size()).unwrap_or(0);
+            println!(
+                "{}{}{} {:>10} {:04}-{:02}-{:02} {:02}:{:02} {}",
+                if entry.is_dir() { 'd' } else { '-' },
+                if metadata.read_only() { 'r' } else { '-' },
+                if metadata.hidden() { 'h' } else { '-' },
+                size,
+                modified.year(),
+                modified.month(),
+                modified.day(),
+                modified.hour(),
+                modified.minute(),
+                entry.name()
+            );
+        } else {
+            println!("{}", entry.name());
+        }
+    }
+    Ok(())
+}
+
+fn cat(vfat: &Handle, path: &str) -> io::Result<()> {
+    let mut file = vfat.open_file(path)?;
+    let stdout = io::stdout();
+    io::copy(&mut file, &mut stdout.lock())?;
+    Ok(())
+}
+
+fn copy_in(vfat: &Handle, from: &str, to: &str) -> io::Result<()> {
+    let mut data = Vec::new();
+    File::open(from)?.read_to_end(&mut data)?;
+
+    // Overwrite the destination, like cp(1) would.
+    match vfat.remove(to) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        result => result?,
+    }
+
+    let mut file = vfat.create_file(to)?;
+    file.write_all(&data)?;
+    file.sync()
+}
+
+fn copy_out(vfat: &Handle, from: &str, to: &str) -> io::Result<()> {
+    let mut file = vfat.open_file(from)?;
+    io::copy(&mut file, &mut File::create(to)?)?;
+    Ok(())
+}
+
+fn info(vfat: &Handle) -> io::Result<()> {
+    let fat_type = vfat.lock(|vfat| vfat.fat_type);
+    let stats = vfat.lock(|vfat| vfat.statfs())?;
+
+    println!("type:         {:?}", fat_type);
+    println!("label:        {}", if stats.volume_label.is_empty() { "(no label)" } else { &stats.volume_label });
+    println!("serial:       {:04X}-{:04X}", stats.serial >> 16, stats.serial & 0xFFFF);
+    println!("cluster size: {}", stats.cluster_size);
+    println!("clusters:     {} total, {} used, {} free", stats.total_clusters, stats.used_clusters, stats.free_clusters);
+    println!("bytes:        {} total, {} used, {} free", stats.total_bytes(), stats.used_bytes(), stats.free_bytes());
+    Ok(())
+}
+
+fn fsck(vfat: &Handle, mode: Mode) -> io::Result<()> {
+    let problems = check::check(vfat, mode)?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    match (problems.len(), mode) {
+        (0, _) => println!("no problems found"),
+        (n, Mode::Repair) => println!("{} problem(s) repaired", n),
+        (n, Mode::ReadOnly) => {
+            println!("{} problem(s) found, run `check --repair` to repair", n);
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn format(image: &str, matches: &ArgMatches) -> io::Result<()> {
+    let size: u64 = parse(matches.value_of("size").unwrap(), "size")?;
+    let mut options = mkfs::Options::default();
+    if let Some(cluster_size) = matches.value_of("cluster-size") {
+        options.cluster_size = parse(cluster_size, "cluster size")?;
+    }
+    if let Some(label) = matches.value_of("label") {
+        options.volume_label = label.to_string();
+    }
+    options.serial = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0);
+
+    let file = OpenOptions::new().read(true).write(true).create(true).open(image)?;
+    file.set_len(size * 1024 * 1024)?;
+    mkfs::format(file, size * 1024 * 1024 / 512, &options)
+}
+
+fn parse<T: std::str::FromStr>(value: &str, what: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {}: {}", what, value)))
+}
+
+fn run(matches: &ArgMatches) -> io::Result<()> {
+    let image = matches.value_of("image").unwrap();
+    let path = |m: &ArgMatches, name| m.value_of(name).unwrap().to_string();
+
+    match matches.subcommand() {
+        ("mkfs", Some(m)) => format(image, m),
+        ("ls", Some(m)) => {
+            let vfat = mount(image, false)?;
+            ls(&vfat, m.value_of("path").unwrap_or("/"), m.is_present("long"), m.is_present("all"))
+        }
+        ("cat", Some(m)) => cat(&mount(image, false)?, &path(m, "path")),
+        ("cp", Some(m)) => match m.subcommand() {
+            ("in", Some(m)) => modify(image, |vfat| copy_in(vfat, &path(m, "from"), &path(m, "to"))),
+            ("out", Some(m)) => copy_out(&mount(image, false)?, &path(m, "from"), &path(m, "to")),
+            _ => unreachable!(),
+        },
+        ("mkdir", Some(m)) => modify(image, |vfat| vfat.create_dir(path(m, "path"))),
+        ("rm", Some(m)) => modify(image, |vfat| vfat.remove(path(m, "path"))),
+        ("info", Some(_)) => info(&mount(image, false)?),
+        ("check", Some(m)) => {
+            let mode = if m.is_present("repair") { Mode::Repair } else { Mode::ReadOnly };
+            match mode {
+                Mode::Repair => modify(image, |vfat| fsck(vfat, mode)),
+                Mode::ReadOnly => fsck(&mount(image, false)?, mode),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let path_arg = |name, help| Arg::with_name(name).required(true).help(help);
+
+    App::new("fat32-tool")
+        .about("Inspect and modify FAT32 disk images without mounting them.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("image").required(true).help("Path to the disk image"))
+        .subcommand(
+            SubCommand::with_name("mkfs")
+                .about("Create or overwrite the image with an empty FAT32 volume")
+                .arg(Arg::with_name("size").short("s").long("size").takes_value(true).default_value("128")
+                    .help("Size of the image in MiB"))
+                .arg(Arg::with_name("cluster-size").short("c").long("cluster-size").takes_value(true)
+                    .help("Cluster size in bytes"))
+                .arg(Arg::with_name("label").short("L").long("label").takes_value(true)
+                    .help("Volume label")),
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("List a directory")
+                .arg(Arg::with_name("long").short("l").help("Show attributes, sizes and modification times"))
+                .arg(Arg::with_name("all").short("a").help("Show hidden entries, `.` and `..`"))
+                .arg(Arg::with_name("path").help("Directory to list, `/` by default")),
+        )
+        .subcommand(SubCommand::with_name("cat").about("Print a file").arg(path_arg("path", "File to print")))
+        .subcommand(
+            SubCommand::with_name("cp")
+                .about("Copy a file into or out of the image")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("in")
+                        .about("Copy a host file into the image")
+                        .arg(path_arg("from", "Host file"))
+                        .arg(path_arg("to", "Destination in the image")),
+                )
+                .subcommand(
+                    SubCommand::with_name("out")
+                        .about("Copy a file out of the image")
+                        .arg(path_arg("from", "File in the image"))
+                        .arg(path_arg("to", "Host destination")),
+                ),
+        )
+        .subcommand(SubCommand::with_name("mkdir").about("Create a directory").arg(path_arg("path", "Directory")))
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Remove a file or an empty directory")
+                .arg(path_arg("path", "Entry to remove")),
+        )
+        .subcommand(SubCommand::with_name("info").about("Show volume information and usage"))
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check the volume for inconsistencies")
+                .arg(Arg::with_name("repair").short("r").long("repair").help("Repair the problems found")),
+        )
+}
+
+fn main() {
+    let matches = app().get_matches();
+    if let Err(e) = run(&matches) {
+        let image = matches.value_of("image").unwrap();
+        eprintln!("fat32-tool: {}: {}", Path::new(image).display(), e);
+        process::exit(1);
+    }
+}
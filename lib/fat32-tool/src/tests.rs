@@ -0,0 +1,111 @@
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+use fat32::check::{self, Mode};
+use fat32::traits::{Dir, Entry, FileSystem};
+use fat32::traits::File as _;
+use fat32::vfat::VFatHandle;
+
+use super::{app, mount, run};
+
+/// A file in the temporary directory, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        let path = env::temp_dir().join(format!("fat32-tool-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        TempFile(path)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().expect("temporary path is UTF-8")
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Runs the tool with the arguments `args` on `image`.
+fn tool(image: &TempFile, args: &[&str]) -> std::io::Result<()> {
+    let mut argv = vec!["fat32-tool", image.path()];
+    argv.extend_from_slice(args);
+    run(&app().get_matches_from(argv))
+}
+
+fn entry_names(image: &TempFile, path: &str) -> Vec<String> {
+    let vfat = mount(image.path(), false).expect("mount image");
+    let dir = vfat.open_dir(path).expect("open dir");
+    dir.entries().expect("entries").map(|entry| entry.name().to_string()).collect()
+}
+
+#[test]
+fn test_round_trip() {
+    let image = TempFile::new("round-trip.img");
+    let host = TempFile::new("round-trip.txt");
+    let out = TempFile::new("round-trip.out");
+    let data: Vec<u8> = (0..500).flat_map(|i| format!("line {}\n", i).into_bytes()).collect();
+    fs::write(&host.0, &data).unwrap();
+
+    tool(&image, &["mkfs", "-s", "40", "-c", "512", "-L", "ROUNDTRIP"]).expect("mkfs");
+    tool(&image, &["mkdir", "/docs"]).expect("mkdir");
+    tool(&image, &["cp", "in", host.path(), "/docs/data.txt"]).expect("cp in");
+    tool(&image, &["ls", "-l", "/docs"]).expect("ls");
+    tool(&image, &["cat", "/docs/data.txt"]).expect("cat");
+    tool(&image, &["cp", "out", "/docs/data.txt", out.path()]).expect("cp out");
+    assert_eq!(fs::read(&out.0).unwrap(), data);
+    assert_eq!(entry_names(&image, "/docs"), vec![".", "..", "data.txt"]);
+
+    // Copying over an existing file replaces it.
+    fs::write(&host.0, b"replaced").unwrap();
+    tool(&image, &["cp", "in", host.path(), "/docs/data.txt"]).expect("cp in over file");
+    let vfat = mount(image.path(), false).unwrap();
+    let mut contents = String::new();
+    vfat.open_file("/docs/data.txt").unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "replaced");
+    drop(vfat);
+
+    tool(&image, &["rm", "/docs/data.txt"]).expect("rm");
+    assert_eq!(entry_names(&image, "/docs"), vec![".", ".."]);
+    tool(&image, &["info"]).expect("info");
+    tool(&image, &["check"]).expect("check");
+    assert!(!mount(image.path(), false).unwrap().lock(|vfat| vfat.needs_check()));
+}
+
+#[test]
+fn test_failed_copy_is_written_back() {
+    let image = TempFile::new("disk-full.img");
+    let host = TempFile::new("disk-full.bin");
+    tool(&image, &["mkfs", "-s", "34", "-c", "512"]).expect("mkfs");
+    let free_clusters = |image: &TempFile| {
+        let vfat = mount(image.path(), false).unwrap();
+        let stats = vfat.lock(|vfat| vfat.statfs()).unwrap();
+        stats.free_clusters
+    };
+
+    // Fill the volume up to 100 clusters, then copy in a file that does not
+    // fit: it fails once the clusters it wrote are still in the cache.
+    fs::write(&host.0, vec![0xA5u8; (free_clusters(&image) as usize - 100) * 512]).unwrap();
+    tool(&image, &["cp", "in", host.path(), "/fill.bin"]).expect("cp in");
+    fs::write(&host.0, vec![0x5Au8; 200 * 512]).unwrap();
+    let e = tool(&image, &["cp", "in", host.path(), "/big.bin"]).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::Other);
+
+    // The clusters allocated before the error reached the image, but not the
+    // entry of the file: the volume is left for `check` to repair.
+    assert_eq!(free_clusters(&image), 0);
+    let vfat = mount(image.path(), false).unwrap();
+    assert!(vfat.lock(|vfat| vfat.needs_check()));
+    assert_eq!(vfat.open_file("/big.bin").expect("entry was created").size(), 0);
+    assert!(!check::check(&vfat, Mode::ReadOnly).expect("check").is_empty());
+    drop(vfat);
+
+    tool(&image, &["check", "--repair"]).expect("repair");
+    let vfat = mount(image.path(), false).unwrap();
+    assert_eq!(check::check(&vfat, Mode::ReadOnly).expect("check"), vec![]);
+}
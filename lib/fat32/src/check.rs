@@ -0,0 +1,416 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use shim::io;
+
+use crate::vfat::dir::{lfn_checksum, short_name_string, VFatRegularDirEntry};
+use crate::vfat::{Attributes, Cluster, EntryPos, Status, VFat, VFatHandle, END_OF_CHAIN};
+
+/// Whether `check()` only reports problems or also fixes them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Leave the volume untouched.
+    ReadOnly,
+    /// Fix every reported problem: chains are truncated at invalid or
+    /// cross-linked clusters, file sizes and chain lengths are made to agree,
+    /// lost chains are freed and long file names with a bad checksum are
+    /// deleted, leaving their 8.3 alias.
+    Repair,
+}
+
+/// An inconsistency found by `check()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The chain of `path` runs into `cluster`, which already belongs to the
+    /// chain of `other`. `other` is `path` itself if the chain loops.
+    CrossLinked { path: String, other: String, cluster: u32 },
+    /// A chain of `len` allocated clusters, starting at `start`, that no
+    /// directory entry refers to.
+    LostChain { start: u32, len: u32 },
+    /// The chain of the file `path` holds `clusters` clusters while its size
+    /// calls for `expected`.
+    ChainLength { path: String, clusters: u32, expected: u32 },
+    /// The chain of `path` refers to `cluster`, which is out of range or not
+    /// marked as allocated in the FAT.
+    InvalidCluster { path: String, cluster: u32 },
+    /// The long file name entries of `path` do not carry the checksum of its
+    /// 8.3 name. `path` ends in the 8.3 name.
+    BadLfnChecksum { path: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::CrossLinked { path, other, cluster } if path == other => {
+                write!(f, "{}: cluster chain loops back to cluster {}", path, cluster)
+            }
+            Problem::CrossLinked { path, other, cluster } => {
+                write!(f, "{}: cluster {} is cross-linked with {}", path, cluster, other)
+            }
+            Problem::LostChain { start, len } => {
+                write!(f, "lost chain of {} cluster(s) starting at cluster {}", len, start)
+            }
+            Problem::ChainLength { path, clusters, expected } => write!(
+                f,
+                "{}: chain has {} cluster(s) but the file size needs {}",
+                path, clusters, expected
+            ),
+            Problem::InvalidCluster { path, cluster } => {
+                write!(f, "{}: invalid cluster {} in chain", path, cluster)
+            }
+            Problem::BadLfnChecksum { path } => {
+                write!(f, "{}: long file name does not match its 8.3 name", path)
+            }
+        }
+    }
+}
+
+/// Walks the directory tree and the FAT of `vfat` and returns every
+/// inconsistency found. With `Mode::Repair`, the problems are also fixed and
+/// the changes are flushed to the device. A volume found consistent, or
+/// repaired, is marked clean again by `VFat::unmount()`.
+///
+/// # Errors
+///
+/// Returns an error if reading from or writing to the device fails.
+pub fn check<HANDLE: VFatHandle>(vfat: &HANDLE, mode: Mode) -> io::Result<Vec<Problem>> {
+    vfat.lock(|vfat| Checker::new(vfat, mode).run())
+}
+
+/// Owner of clusters that belong to a lost chain.
+const LOST: u32 = u32::max_value();
+
+struct Checker<'a, HANDLE: VFatHandle> {
+    vfat: &'a mut VFat<HANDLE>,
+    repair: bool,
+    /// For every data cluster, 0 if no chain claimed it yet, or the index in
+    /// `paths` plus one of the entry whose chain holds it.
+    owners: Vec<u32>,
+    paths: Vec<String>,
+    problems: Vec<Problem>,
+}
+
+impl<'a, HANDLE: VFatHandle> Checker<'a, HANDLE> {
+    fn new(vfat: &'a mut VFat<HANDLE>, mode: Mode) -> Checker<'a, HANDLE> {
+        let owners = vec![0; vfat.num_clusters() as usize];
+        Checker { vfat, repair: mode == Mode::Repair, owners, paths: Vec::new(), problems: Vec::new() }
+    }
+
+    fn run(mut self) -> io::Result<Vec<Problem>> {
+        let root = self.vfat.root_dir_cluster;
+        let root_id = self.add_path(String::from("/"));
+        let chain = match self.vfat.is_root_region(root) {
+            true => vec![root],
+            false => self.claim_chain(root_id, root, None)?,
+        };
+
+        let mut pending = vec![(root_id, chain)];
+        while let Some((id, chain)) = pending.pop() {
+            self.check_dir(id, &chain, &mut pending)?;
+        }
+        self.check_lost_chains()?;
+
+        if self.repair {
+            // Repairs allocate and free clusters behind the allocator's back.
+            self.vfat.invalidate_free_count()?;
+            self.vfat.flush()?;
+        }
+        if self.repair || self.problems.is_empty() {
+            self.vfat.set_checked();
+        }
+        Ok(self.problems)
+    }
+
+    fn add_path(&mut self, path: String) -> u32 {
+        self.paths.push(path);
+        self.paths.len() as u32
+    }
+
+    fn path(&self, id: u32) -> String {
+        self.paths[id as usize - 1].clone()
+    }
+
+    fn is_valid(&self, cluster: Cluster) -> bool {
+        cluster.0 >= 2 && cluster.0 < self.vfat.num_clusters() + 2
+    }
+
+    fn owner(&mut self, cluster: Cluster) -> &mut u32 {
+        &mut self.owners[cluster.0 as usize - 2]
+    }
+
+    /// Follows the chain starting at `start` on behalf of the entry `id`,
+    /// claiming every cluster in it, and returns the clusters claimed. The
+    /// chain stops at the first invalid or already claimed cluster; when
+    /// repairing, the chain is cut there, clearing the cluster of the entry
+    /// at `pos` if no cluster was valid.
+    fn claim_chain(&mut self, id: u32, start: Cluster, pos: Option<EntryPos>) -> io::Result<Vec<Cluster>> {
+        let mut chain: Vec<Cluster> = Vec::new();
+        let mut cluster = start;
+        loop {
+            let problem = if !self.is_valid(cluster) {
+                Some(Problem::InvalidCluster { path: self.path(id), cluster: cluster.0 })
+            } else if *self.owner(cluster) != 0 {
+                let other = *self.owner(cluster);
+                let other = if other == LOST { String::from("a lost chain") } else { self.path(other) };
+                Some(Problem::CrossLinked { path: self.path(id), other, cluster: cluster.0 })
+            } else {
+                None
+            };
+
+            if let Some(problem) = problem {
+                self.problems.push(problem);
+                self.cut_chain(chain.last().cloned(), pos)?;
+                return Ok(chain);
+            }
+
+            *self.owner(cluster) = id;
+            chain.push(cluster);
+            match self.vfat.fat_entry(cluster)?.status() {
+                Status::Data(next) => cluster = next,
+                Status::Eoc(_) => return Ok(chain),
+                _ => {
+                    // A free, reserved or bad cluster can't be part of a chain.
+                    self.problems.push(Problem::InvalidCluster { path: self.path(id), cluster: cluster.0 });
+                    if self.repair {
+                        self.vfat.set_fat_entry(cluster, END_OF_CHAIN)?;
+                    }
+                    return Ok(chain);
+                }
+            }
+        }
+    }
+
+    /// When repairing, ends a chain at `last`, or empties the entry at `pos`
+    /// if the chain has no valid cluster at all.
+    fn cut_chain(&mut self, last: Option<Cluster>, pos: Option<EntryPos>) -> io::Result<()> {
+        if !self.repair {
+            return Ok(());
+        }
+
+        match (last, pos) {
+            (Some(last), _) => self.vfat.set_fat_entry(last, END_OF_CHAIN),
+            (None, Some(pos)) => {
+                let mut regular = self.vfat.read_dir_entry(pos)?;
+                if regular.attributes.is_directory() {
+                    return self.vfat.delete_dir_entry(pos);
+                }
+                regular.set_cluster(Cluster::from(0));
+                regular.set_size(0);
+                self.vfat.write_dir_entry(pos, &regular)
+            }
+            (None, None) => Ok(()),
+        }
+    }
+
+    /// Checks every entry of the directory `id` whose chain is `chain`,
+    /// queueing its subdirectories in `pending`.
+    fn check_dir(&mut self, id: u32, chain: &[Cluster], pending: &mut Vec<(u32, Vec<Cluster>)>) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        if self.vfat.is_root_region(chain[0]) {
+            self.vfat.read_all_chain(chain[0], &mut bytes)?;
+        }
+        for &cluster in chain.iter().filter(|cluster| cluster.0 >= 2) {
+            self.vfat.read_all_cluster(cluster, &mut bytes)?;
+        }
+
+        let dir_path = self.path(id);
+        let mut lfn_slots: Vec<usize> = Vec::new();
+        for (index, slot) in bytes.chunks(32).enumerate() {
+            match slot[0] {
+                0x00 => break,
+                0xE5 => {
+                    lfn_slots.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            if slot[11] & 0x3F == 0x0F {
+                lfn_slots.push(index);
+                continue;
+            }
+
+            let mut raw = [0u8; 32];
+            raw.copy_from_slice(slot);
+            let regular = VFatRegularDirEntry::from_bytes(raw);
+            let short_name = regular.short_name();
+            let lfn: Vec<&[u8]> = lfn_slots.drain(..).map(|i| &bytes[i * 32..(i + 1) * 32]).collect();
+            if regular.attributes.0 & Attributes::VOLUME_ID.0 != 0
+                || &short_name == b".          "
+                || &short_name == b"..         "
+            {
+                continue;
+            }
+
+            let pos = EntryPos { dir_cluster: chain[0], index, lfn_entries: lfn.len() };
+            let checksum = lfn_checksum(&short_name);
+            let name = if lfn.iter().all(|slot| slot[13] == checksum) {
+                long_name(&lfn).unwrap_or_else(|| short_name_string(&short_name))
+            } else {
+                let path = join(&dir_path, &short_name_string(&short_name));
+                self.problems.push(Problem::BadLfnChecksum { path });
+                if self.repair {
+                    for lfn_index in index - lfn.len()..index {
+                        self.vfat.write_dir_slots(chain[0], lfn_index, &[0xE5])?;
+                    }
+                }
+                short_name_string(&short_name)
+            };
+
+            let entry_id = self.add_path(join(&dir_path, &name));
+            let start = regular.cluster();
+            if regular.attributes.is_directory() {
+                if start.0 == 0 {
+                    self.problems.push(Problem::InvalidCluster { path: self.path(entry_id), cluster: 0 });
+                    self.cut_chain(None, Some(pos))?;
+                    continue;
+                }
+
+                let entry_chain = self.claim_chain(entry_id, start, Some(pos))?;
+                if !entry_chain.is_empty() {
+                    pending.push((entry_id, entry_chain));
+                }
+            } else {
+                let entry_chain = match start.0 {
+                    0 => Vec::new(),
+                    _ => self.claim_chain(entry_id, start, Some(pos))?,
+                };
+                // A chain without a single valid cluster was already reported.
+                if start.0 == 0 || !entry_chain.is_empty() {
+                    self.check_chain_length(entry_id, &regular, &entry_chain, pos)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compares the length of the chain of a file with the number of clusters
+    /// its size needs, and makes them agree when repairing: surplus clusters
+    /// are freed and a size beyond the end of the chain is cut.
+    fn check_chain_length(
+        &mut self,
+        id: u32,
+        regular: &VFatRegularDirEntry,
+        chain: &[Cluster],
+        pos: EntryPos,
+    ) -> io::Result<()> {
+        let bytes_per_cluster = self.vfat.bytes_per_cluster() as u64;
+        let expected = ((regular.size() as u64 + bytes_per_cluster - 1) / bytes_per_cluster) as usize;
+        if chain.len() == expected {
+            return Ok(());
+        }
+
+        self.problems.push(Problem::ChainLength {
+            path: self.path(id),
+            clusters: chain.len() as u32,
+            expected: expected as u32,
+        });
+        if !self.repair {
+            return Ok(());
+        }
+
+        let mut regular = *regular;
+        if chain.len() > expected {
+            for &cluster in &chain[expected..] {
+                *self.owner(cluster) = 0;
+            }
+            match expected {
+                0 => regular.set_cluster(Cluster::from(0)),
+                _ => self.vfat.set_fat_entry(chain[expected - 1], END_OF_CHAIN)?,
+            }
+            self.vfat.free_chain(chain[expected])?;
+        } else {
+            regular.set_size((chain.len() as u64 * bytes_per_cluster) as u32);
+        }
+        self.vfat.write_dir_entry(pos, &regular)
+    }
+
+    /// Reports every allocated cluster that no chain claimed, grouped into
+    /// chains, and frees them when repairing.
+    fn check_lost_chains(&mut self) -> io::Result<()> {
+        let num_clusters = self.vfat.num_clusters();
+
+        // Clusters that another unclaimed cluster links to aren't chain heads.
+        let mut linked = vec![false; num_clusters as usize];
+        for raw_cluster in 2..num_clusters + 2 {
+            let cluster = Cluster::from(raw_cluster);
+            if *self.owner(cluster) != 0 {
+                continue;
+            }
+            if let Status::Data(next) = self.vfat.fat_entry(cluster)?.status() {
+                if self.is_valid(next) {
+                    linked[next.0 as usize - 2] = true;
+                }
+            }
+        }
+
+        // Heads come first so that chains are reported from their start;
+        // what remains unclaimed afterwards are chains that loop.
+        for &heads_only in &[true, false] {
+            for raw_cluster in 2..num_clusters + 2 {
+                let cluster = Cluster::from(raw_cluster);
+                if *self.owner(cluster) != 0 || (heads_only && linked[raw_cluster as usize - 2]) {
+                    continue;
+                }
+                match self.vfat.fat_entry(cluster)?.status() {
+                    Status::Data(_) | Status::Eoc(_) => self.claim_lost_chain(cluster)?,
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn claim_lost_chain(&mut self, start: Cluster) -> io::Result<()> {
+        let mut len = 0;
+        let mut cluster = start;
+        loop {
+            *self.owner(cluster) = LOST;
+            len += 1;
+
+            let next = match self.vfat.fat_entry(cluster)?.status() {
+                Status::Data(next) if self.is_valid(next) && *self.owner(next) == 0 => next,
+                _ => break,
+            };
+            cluster = next;
+        }
+
+        self.problems.push(Problem::LostChain { start: start.0, len });
+        if self.repair {
+            // End the chain first so that freeing it stays within it.
+            self.vfat.set_fat_entry(cluster, END_OF_CHAIN)?;
+            self.vfat.free_chain(start)?;
+        }
+        Ok(())
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "/" => format!("/{}", name),
+        _ => format!("{}/{}", dir, name),
+    }
+}
+
+/// Decodes the name stored in the raw long file name entries `slots`, given in
+/// on-disk order. Returns `None` if there are no entries or the name is not
+/// valid UTF-16.
+fn long_name(slots: &[&[u8]]) -> Option<String> {
+    if slots.is_empty() {
+        return None;
+    }
+
+    let mut units = Vec::new();
+    for slot in slots.iter().rev() {
+        for range in &[1..11, 14..26, 28..32] {
+            for pair in slot[range.clone()].chunks(2) {
+                units.push(u16::from_le_bytes([pair[0], pair[1]]));
+            }
+        }
+    }
+
+    let len = units.iter().position(|&unit| unit == 0x0000 || unit == 0xFFFF).unwrap_or(units.len());
+    String::from_utf16(&units[..len]).ok()
+}
@@ -0,0 +1,127 @@
+use core::fmt;
+use core::mem::size_of;
+use shim::const_assert_size;
+
+use crate::exfat::Error;
+use crate::traits::BlockDevice;
+
+/// File system name of an exFAT boot sector.
+const FILE_SYSTEM_NAME: [u8; 8] = *b"EXFAT   ";
+
+/// Signature at the end of every boot sector.
+const BOOT_SIGNATURE: u16 = 0xAA55;
+
+/// The first 512 bytes of the main boot sector of an exFAT volume.
+#[repr(C, packed)]
+pub struct BootSector {
+    jump_boot: [u8; 3],
+    file_system_name: [u8; 8],
+    must_be_zero: [u8; 53],
+    pub partition_offset: u64,
+    pub volume_length: u64,
+    /// Sector of the first FAT, relative to the start of the volume.
+    pub fat_offset: u32,
+    pub fat_length: u32,
+    /// Sector of cluster 2, relative to the start of the volume.
+    pub cluster_heap_offset: u32,
+    pub cluster_count: u32,
+    pub first_cluster_of_root_directory: u32,
+    pub volume_serial_number: u32,
+    pub file_system_revision: u16,
+    pub volume_flags: u16,
+    bytes_per_sector_shift: u8,
+    sectors_per_cluster_shift: u8,
+    pub number_of_fats: u8,
+    drive_select: u8,
+    percent_in_use: u8,
+    reserved: [u8; 7],
+    boot_code: [u8; 390],
+    boot_signature: u16,
+}
+
+const_assert_size!(BootSector, 512);
+
+impl BootSector {
+    /// Reads the boot sector of the volume starting at sector `start` of
+    /// `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the sector is not an exFAT boot sector and
+    /// `BadGeometry` if the layout it describes can't be right.
+    pub fn from<T: BlockDevice>(mut device: T, start: u64) -> Result<BootSector, Error> {
+        let mut buf = [0u8; size_of::<BootSector>()];
+        if device.read_sector(start, &mut buf)? != buf.len() {
+            return Err(Error::BadSignature);
+        }
+        let boot_sector = unsafe { core::mem::transmute::<[u8; size_of::<BootSector>()], BootSector>(buf) };
+
+        if boot_sector.file_system_name != FILE_SYSTEM_NAME || boot_sector.boot_signature != BOOT_SIGNATURE {
+            return Err(Error::BadSignature);
+        }
+
+        // Sectors are 512 bytes to 4 KiB, clusters at most 32 MiB.
+        let (bytes_shift, cluster_shift) = (boot_sector.bytes_per_sector_shift, boot_sector.sectors_per_cluster_shift);
+        if !(9..=12).contains(&bytes_shift)
+            || bytes_shift as u32 + cluster_shift as u32 > 25
+            || boot_sector.number_of_fats == 0
+            || boot_sector.cluster_count == 0
+            || boot_sector.cluster_count > 0xFFFFFFF5
+            || (boot_sector.fat_length as u64) * boot_sector.bytes_per_sector() / 4 < boot_sector.cluster_count as u64 + 2
+            || (boot_sector.cluster_heap_offset as u64)
+                < boot_sector.fat_offset as u64 + boot_sector.fat_length as u64 * boot_sector.number_of_fats as u64
+            || boot_sector.volume_length
+                < boot_sector.cluster_heap_offset as u64 + boot_sector.cluster_count as u64 * boot_sector.sectors_per_cluster()
+            || !boot_sector.is_valid_cluster(boot_sector.first_cluster_of_root_directory)
+        {
+            return Err(Error::BadGeometry);
+        }
+
+        Ok(boot_sector)
+    }
+
+    /// Returns `true` if sector `start` of `device` carries the exFAT file
+    /// system name, whether or not the rest of the boot sector is valid.
+    pub fn is_exfat<T: BlockDevice>(mut device: T, start: u64) -> bool {
+        let mut buf = [0u8; size_of::<BootSector>()];
+        match device.read_sector(start, &mut buf) {
+            Ok(read) => read == buf.len() && buf[3..11] == FILE_SYSTEM_NAME,
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the size of a sector in bytes.
+    pub fn bytes_per_sector(&self) -> u64 {
+        1 << self.bytes_per_sector_shift
+    }
+
+    /// Returns the number of sectors in a cluster.
+    pub fn sectors_per_cluster(&self) -> u64 {
+        1 << self.sectors_per_cluster_shift
+    }
+
+    /// Returns `true` if `cluster` is a cluster of the cluster heap.
+    pub fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && (cluster as u64) < self.cluster_count as u64 + 2
+    }
+}
+
+impl fmt::Debug for BootSector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BootSector")
+            .field("partition_offset", &{ self.partition_offset })
+            .field("volume_length", &{ self.volume_length })
+            .field("fat_offset", &{ self.fat_offset })
+            .field("fat_length", &{ self.fat_length })
+            .field("cluster_heap_offset", &{ self.cluster_heap_offset })
+            .field("cluster_count", &{ self.cluster_count })
+            .field("first_cluster_of_root_directory", &{ self.first_cluster_of_root_directory })
+            .field("volume_serial_number", &{ self.volume_serial_number })
+            .field("file_system_revision", &{ self.file_system_revision })
+            .field("volume_flags", &{ self.volume_flags })
+            .field("bytes_per_sector_shift", &self.bytes_per_sector_shift)
+            .field("sectors_per_cluster_shift", &self.sectors_per_cluster_shift)
+            .field("number_of_fats", &self.number_of_fats)
+            .finish()
+    }
+}
@@ -0,0 +1,261 @@
+use alloc::string::String;
+use alloc::vec::{IntoIter, Vec};
+
+use shim::ffi::OsStr;
+use shim::io;
+
+use crate::exfat::{Entry, ExFat, ExFatHandle, File, Stream};
+use crate::traits;
+use crate::vfat::{Attributes, Date, Metadata, Time, Timestamp};
+
+/// Size of a directory entry.
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// Entry types, with the in-use bit set.
+const END_OF_DIRECTORY: u8 = 0x00;
+const ALLOCATION_BITMAP: u8 = 0x81;
+const UPCASE_TABLE: u8 = 0x82;
+const VOLUME_LABEL: u8 = 0x83;
+const FILE: u8 = 0x85;
+const STREAM_EXTENSION: u8 = 0xC0;
+const FILE_NAME: u8 = 0xC1;
+
+/// Stream extension flag: the data is in consecutive clusters and its FAT
+/// entries are not maintained.
+const NO_FAT_CHAIN: u8 = 0x02;
+
+/// Number of UTF-16 code units in a file name entry.
+const NAME_UNITS_PER_ENTRY: usize = 15;
+
+/// A file entry set has a stream extension and 1 to 17 file name entries.
+const MIN_SECONDARY_COUNT: usize = 2;
+const MAX_SECONDARY_COUNT: usize = 18;
+
+/// Longest volume label, in UTF-16 code units.
+const MAX_LABEL_LEN: usize = 11;
+
+/// An entry of the root directory describing the volume itself.
+#[derive(Debug)]
+pub(crate) enum VolumeEntry {
+    Bitmap(Stream),
+    UpcaseTable(Stream),
+    Label(String),
+}
+
+/// A file entry set: a file entry, its stream extension and its file name
+/// entries.
+#[derive(Debug)]
+pub(crate) struct FileEntrySet {
+    pub name: Vec<u16>,
+    pub metadata: Metadata,
+    pub stream: Stream,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    read_u32(bytes, offset) as u64 | (read_u32(bytes, offset + 4) as u64) << 32
+}
+
+/// Returns the timestamp stored in the exFAT format, which packs the FAT
+/// time in the low and the FAT date in the high 16 bits.
+fn timestamp(raw: u32) -> Timestamp {
+    Timestamp { time: Time(raw as u16), date: Date((raw >> 16) as u16) }
+}
+
+/// Returns the stream described by an allocation bitmap or up-case table
+/// entry. Both are always stored in FAT chains.
+fn fat_chained_stream(bytes: &[u8]) -> Stream {
+    let size = read_u64(bytes, 24);
+    Stream { first_cluster: read_u32(bytes, 20), contiguous: false, valid_size: size, size }
+}
+
+/// Returns the checksum of the entry set in `entries`, which skips the
+/// checksum field of the first entry.
+fn entry_set_checksum(entries: &[u8]) -> u16 {
+    let mut checksum: u16 = 0;
+    for (i, &byte) in entries.iter().enumerate() {
+        if i == 2 || i == 3 {
+            continue;
+        }
+        checksum = (if checksum & 1 != 0 { 0x8000u16 } else { 0 })
+            .wrapping_add(checksum >> 1)
+            .wrapping_add(byte as u16);
+    }
+    checksum
+}
+
+fn corrupted(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returns the allocation bitmap, up-case table and volume label entries in
+/// `bytes`, the data of a root directory. Other entries are skipped without
+/// being checked, so a corrupted file entry set can't prevent mounting.
+pub(crate) fn parse_volume_entries(bytes: &[u8]) -> Vec<VolumeEntry> {
+    let mut entries = Vec::new();
+    for entry in bytes.chunks_exact(DIR_ENTRY_SIZE) {
+        match entry[0] {
+            END_OF_DIRECTORY => break,
+            ALLOCATION_BITMAP => entries.push(VolumeEntry::Bitmap(fat_chained_stream(entry))),
+            UPCASE_TABLE => entries.push(VolumeEntry::UpcaseTable(fat_chained_stream(entry))),
+            VOLUME_LABEL => {
+                let len = core::cmp::min(entry[1] as usize, MAX_LABEL_LEN);
+                let units: Vec<u16> = (0..len).map(|c| read_u16(entry, 2 + 2 * c)).collect();
+                entries.push(VolumeEntry::Label(String::from_utf16_lossy(&units)));
+            }
+            _ => (),
+        }
+    }
+    entries
+}
+
+/// Parses the directory entries in `bytes` up to the end of directory marker
+/// and returns the file entry sets in use. Other entries are skipped.
+pub(crate) fn parse_file_entry_sets(bytes: &[u8]) -> io::Result<Vec<FileEntrySet>> {
+    let entries: Vec<&[u8]> = bytes.chunks_exact(DIR_ENTRY_SIZE).collect();
+    let mut sets = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let entry = entries[i];
+        match entry[0] {
+            END_OF_DIRECTORY => break,
+            FILE => {
+                let secondary_count = entry[1] as usize;
+                if !(MIN_SECONDARY_COUNT..=MAX_SECONDARY_COUNT).contains(&secondary_count)
+                    || i + secondary_count >= entries.len()
+                {
+                    return Err(corrupted("corrupted directory entry set"));
+                }
+                let set = &bytes[i * DIR_ENTRY_SIZE..(i + secondary_count + 1) * DIR_ENTRY_SIZE];
+                if entry_set_checksum(set) != read_u16(entry, 2) {
+                    return Err(corrupted("directory entry set checksum mismatch"));
+                }
+
+                let stream_entry = entries[i + 1];
+                if stream_entry[0] != STREAM_EXTENSION {
+                    return Err(corrupted("file entry is not followed by a stream extension"));
+                }
+                let name_len = stream_entry[3] as usize;
+                let num_name_entries = (name_len + NAME_UNITS_PER_ENTRY - 1) / NAME_UNITS_PER_ENTRY;
+                let name_entries = &entries[i + 2..i + secondary_count + 1];
+                if name_len == 0
+                    || name_entries.len() < num_name_entries
+                    || name_entries[..num_name_entries].iter().any(|e| e[0] != FILE_NAME)
+                {
+                    return Err(corrupted("corrupted file name entries"));
+                }
+                let name: Vec<u16> = name_entries[..num_name_entries].iter()
+                    .flat_map(|e| (0..NAME_UNITS_PER_ENTRY).map(move |c| read_u16(e, 2 + 2 * c)))
+                    .take(name_len)
+                    .collect();
+
+                let stream = Stream {
+                    first_cluster: read_u32(stream_entry, 20),
+                    contiguous: stream_entry[1] & NO_FAT_CHAIN != 0,
+                    valid_size: read_u64(stream_entry, 8),
+                    size: read_u64(stream_entry, 24),
+                };
+                if stream.valid_size > stream.size {
+                    return Err(corrupted("valid data length exceeds the data length"));
+                }
+
+                let metadata = Metadata::new(
+                    Attributes(read_u16(entry, 4) as u8),
+                    timestamp(read_u32(entry, 8)),
+                    timestamp(read_u32(entry, 16)),
+                    timestamp(read_u32(entry, 12)),
+                );
+                sets.push(FileEntrySet { name, metadata, stream });
+                i += secondary_count;
+            }
+            // Deleted entries, stray secondary entries and primary entries
+            // other than files.
+            _ => (),
+        }
+        i += 1;
+    }
+    Ok(sets)
+}
+
+#[derive(Clone, Debug)]
+pub struct Dir<HANDLE: ExFatHandle> {
+    pub exfat: ExFat<HANDLE>,
+    pub name: String,
+    pub stream: Stream,
+    pub metadata: Metadata,
+}
+
+impl<HANDLE: ExFatHandle> Dir<HANDLE> {
+    /// Finds the entry named `name` in `self` and returns it. Comparison is
+    /// case-insensitive, through the volume's up-case table.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
+    /// is returned.
+    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry<HANDLE>> {
+        let name = match name.as_ref().to_str() {
+            Some(name) => name,
+            None => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "`name` contains invalid UTF-8 characters",
+            )),
+        };
+        let units: Vec<u16> = name.encode_utf16().collect();
+
+        for set in self.entry_sets()? {
+            if self.exfat.lock(|volume| volume.upcase().eq_ignore_case(&set.name, &units)) {
+                return Ok(self.entry_from(set));
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("`{}` not found in `{}`", name, self.name),
+        ))
+    }
+
+    //  * A method to read and parse the entry sets of the directory.
+    fn entry_sets(&self) -> io::Result<Vec<FileEntrySet>> {
+        let stream = self.stream;
+        let mut bytes = Vec::new();
+        self.exfat.lock(|volume| volume.read_all_stream(&stream, &mut bytes))?;
+        parse_file_entry_sets(&bytes)
+    }
+
+    //  * A method to build the entry of a file entry set.
+    fn entry_from(&self, set: FileEntrySet) -> Entry<HANDLE> {
+        let FileEntrySet { name, metadata, stream } = set;
+        let name = String::from_utf16_lossy(&name);
+        if metadata.attributes.is_directory() {
+            Entry::Dir(Dir { exfat: self.exfat.clone(), name, stream, metadata })
+        } else {
+            Entry::File(File { exfat: self.exfat.clone(), name, stream, seek_pos: 0, metadata, position: None })
+        }
+    }
+}
+
+impl<HANDLE: ExFatHandle> traits::Dir for Dir<HANDLE> {
+    type Entry = Entry<HANDLE>;
+    type Iter = IntoIter<Entry<HANDLE>>;
+
+    /// Reads the whole directory and returns its files and directories. exFAT
+    /// directories have no `.` and `..` entries.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let entries: Vec<Entry<HANDLE>> = self.entry_sets()?
+            .into_iter()
+            .map(|set| self.entry_from(set))
+            .collect();
+        Ok(entries.into_iter())
+    }
+}
@@ -0,0 +1,48 @@
+use shim::io;
+
+use crate::gpt;
+use crate::mbr;
+use crate::partition;
+
+#[derive(Debug)]
+pub enum Error {
+    Mbr(mbr::Error),
+    Gpt(gpt::Error),
+    Io(io::Error),
+    /// The boot sector does not carry the exFAT file system name or the boot
+    /// signature.
+    BadSignature,
+    /// The boot sector describes a sector size, cluster size or layout that
+    /// can't be right or that the device can't hold.
+    BadGeometry,
+    NotFound,
+}
+
+impl From<mbr::Error> for Error {
+    fn from(error: mbr::Error) -> Error {
+        Error::Mbr(error)
+    }
+}
+
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<partition::Error> for Error {
+    fn from(error: partition::Error) -> Error {
+        match error {
+            partition::Error::Mbr(error) => Error::Mbr(error),
+            partition::Error::Gpt(error) => Error::Gpt(error),
+            partition::Error::Io(error) => Error::Io(error),
+            partition::Error::NotFound => Error::NotFound,
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use core::fmt::Debug;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::path::{Component, Path};
+
+use crate::exfat::dir::{parse_volume_entries, VolumeEntry};
+use crate::exfat::{BootSector, Dir, Entry, Error, File, UpcaseTable};
+use crate::gpt::BASIC_DATA_PARTITION;
+use crate::partition::{self, PartitionType};
+use crate::traits;
+use crate::traits::{BlockDevice, FileSystem};
+use crate::vfat::{CachedPartition, FsStats, Metadata, Partition};
+
+/// MBR partition type of exFAT volumes, which NTFS volumes share.
+const EXFAT_PARTITION_TYPE: u8 = 0x07;
+
+/// FAT entries at or above this value end a cluster chain.
+const END_OF_CHAIN: u32 = 0xFFFFFFF8;
+
+/// Up-case tables larger than this can't be valid, even uncompressed.
+const MAX_UPCASE_TABLE_SIZE: u64 = 2 * 0x10000;
+
+/// A generic trait that handles a critical section as a closure
+pub trait ExFatHandle: Clone + Debug + Send + Sync {
+    fn new(val: Volume) -> Self;
+    fn lock<R>(&self, f: impl FnOnce(&mut Volume) -> R) -> R;
+}
+
+/// Where the data of a file or directory is stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stream {
+    /// First cluster of the data, or 0 if the stream is empty.
+    pub first_cluster: u32,
+    /// Whether the data is stored in consecutive clusters, whose FAT entries
+    /// are not maintained.
+    pub contiguous: bool,
+    /// Number of bytes that were written. The rest reads as zeroes.
+    pub valid_size: u64,
+    pub size: u64,
+}
+
+/// A cluster of a stream and its index in the stream. Files remember the
+/// last cluster they read so that sequential reads continue from it rather
+/// than walk the FAT from the start of the stream again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChainPosition {
+    index: u64,
+    cluster: u32,
+}
+
+/// The state of a mounted exFAT volume, shared by its entries through an
+/// `ExFatHandle`.
+#[derive(Debug)]
+pub struct Volume {
+    device: CachedPartition,
+    bytes_per_sector: u64,
+    sectors_per_cluster: u64,
+    fat_start_sector: u64,
+    cluster_heap_start_sector: u64,
+    num_clusters: u32,
+    root: Stream,
+    upcase: UpcaseTable,
+    /// The allocation bitmap: a bit per cluster, set if it is in use.
+    bitmap: Vec<u8>,
+    volume_label: String,
+    serial: u32,
+}
+
+/// A read-only exFAT file system. Cloning it is cheap: clones share the
+/// volume.
+#[derive(Clone, Debug)]
+pub struct ExFat<HANDLE: ExFatHandle>(HANDLE);
+
+impl<HANDLE: ExFatHandle> ExFat<HANDLE> {
+    /// Mounts the exFAT volume of `device`: the first exFAT partition of its
+    /// MBR or GUID partition table, or the whole device if it starts with an
+    /// exFAT boot sector.
+    pub fn from<T>(mut device: T) -> Result<ExFat<HANDLE>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let start = find_exfat_partition(&mut device)?;
+        let boot_sector = BootSector::from(&mut device, start)?;
+        if boot_sector.bytes_per_sector() < device.sector_size() {
+            return Err(Error::BadGeometry);
+        }
+
+        let partition = Partition {
+            start,
+            num_sectors: boot_sector.volume_length,
+            sector_size: boot_sector.bytes_per_sector(),
+        };
+        let mut volume = Volume {
+            device: CachedPartition::new(device, partition),
+            bytes_per_sector: boot_sector.bytes_per_sector(),
+            sectors_per_cluster: boot_sector.sectors_per_cluster(),
+            fat_start_sector: boot_sector.fat_offset as u64,
+            cluster_heap_start_sector: boot_sector.cluster_heap_offset as u64,
+            num_clusters: boot_sector.cluster_count,
+            root: Stream { first_cluster: 0, contiguous: false, valid_size: 0, size: 0 },
+            upcase: UpcaseTable::ascii(),
+            bitmap: Vec::new(),
+            volume_label: String::new(),
+            serial: boot_sector.volume_serial_number,
+        };
+
+        // The root directory has no stream extension entry: its size is the
+        // length of its cluster chain.
+        let root_cluster = boot_sector.first_cluster_of_root_directory;
+        let root_size = volume.chain_len(root_cluster)? * volume.bytes_per_cluster();
+        volume.root = Stream { first_cluster: root_cluster, contiguous: false, valid_size: root_size, size: root_size };
+
+        let mut root_bytes = Vec::new();
+        let root = volume.root;
+        volume.read_all_stream(&root, &mut root_bytes)?;
+        let mut bitmap = None;
+        for entry in parse_volume_entries(&root_bytes) {
+            match entry {
+                VolumeEntry::Bitmap(stream) if bitmap.is_none() => bitmap = Some(stream),
+                VolumeEntry::UpcaseTable(stream) => {
+                    if stream.size > MAX_UPCASE_TABLE_SIZE {
+                        return Err(Error::BadGeometry);
+                    }
+                    let mut bytes = Vec::new();
+                    volume.read_all_stream(&stream, &mut bytes)?;
+                    volume.upcase = UpcaseTable::from_bytes(&bytes);
+                }
+                VolumeEntry::Label(label) => volume.volume_label = label,
+                _ => (),
+            }
+        }
+
+        let bitmap = bitmap.ok_or(Error::BadGeometry)?;
+        let bitmap_size = (volume.num_clusters as u64 + 7) / 8;
+        if bitmap.size < bitmap_size {
+            return Err(Error::BadGeometry);
+        }
+        volume.bitmap.resize(bitmap_size as usize, 0);
+        let mut bitmap_bytes = core::mem::take(&mut volume.bitmap);
+        volume.read_stream(&bitmap, 0, &mut bitmap_bytes)?;
+        volume.bitmap = bitmap_bytes;
+
+        Ok(ExFat(HANDLE::new(volume)))
+    }
+
+    //  * A method to run `f` with exclusive access to the volume.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut Volume) -> R) -> R {
+        self.0.lock(f)
+    }
+}
+
+impl Volume {
+    //  * A method to return the number of bytes in a cluster.
+    pub fn bytes_per_cluster(&self) -> u64 {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    //  * A method to return the volume's up-case table.
+    pub fn upcase(&self) -> &UpcaseTable {
+        &self.upcase
+    }
+
+    //  * A method to return the size, usage and identity of the volume.
+    pub fn statfs(&self) -> FsStats {
+        let mut used_clusters = 0;
+        for cluster in 0..self.num_clusters as usize {
+            if self.bitmap[cluster / 8] & (1 << (cluster % 8)) != 0 {
+                used_clusters += 1;
+            }
+        }
+
+        FsStats {
+            total_clusters: self.num_clusters,
+            free_clusters: self.num_clusters - used_clusters,
+            used_clusters,
+            cluster_size: self.bytes_per_cluster() as u32,
+            volume_label: self.volume_label.clone(),
+            serial: self.serial,
+        }
+    }
+
+    //  * A method to read from an offset of `stream` into a buffer. Bytes
+    //    past the valid size of the stream read as zeroes.
+    pub fn read_stream(&mut self, stream: &Stream, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_stream_from(stream, offset, buf, &mut None)
+    }
+
+    //  * A method to read from an offset of `stream` into a buffer, walking
+    //    the chain from `position`, if it comes before the offset, and
+    //    leaving `position` at the last cluster read.
+    pub fn read_stream_from(
+        &mut self,
+        stream: &Stream,
+        offset: u64,
+        buf: &mut [u8],
+        position: &mut Option<ChainPosition>,
+    ) -> io::Result<usize> {
+        if offset >= stream.size {
+            return Ok(0);
+        }
+
+        let len = core::cmp::min(buf.len() as u64, stream.size - offset) as usize;
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let mut index = offset / bytes_per_cluster;
+        let mut cluster = self.cluster_at(stream, index, *position)?;
+        let mut bytes_read = 0;
+        while bytes_read < len {
+            let position = offset + bytes_read as u64;
+            let cluster_offset = position % bytes_per_cluster;
+            let sector = self.cluster_start_sector(cluster)? + cluster_offset / self.bytes_per_sector;
+            let sector_offset = (cluster_offset % self.bytes_per_sector) as usize;
+            let n = core::cmp::min(self.bytes_per_sector as usize - sector_offset, len - bytes_read);
+
+            let dst = &mut buf[bytes_read..bytes_read + n];
+            if position >= stream.valid_size {
+                dst.iter_mut().for_each(|byte| *byte = 0);
+            } else {
+                dst.copy_from_slice(&self.device.get(sector)?[sector_offset..sector_offset + n]);
+                let valid = core::cmp::min(stream.valid_size - position, n as u64) as usize;
+                dst[valid..].iter_mut().for_each(|byte| *byte = 0);
+            }
+            bytes_read += n;
+
+            if bytes_read < len && (position + n as u64) % bytes_per_cluster == 0 {
+                cluster = self.next_cluster(stream, cluster)?;
+                index += 1;
+            }
+        }
+
+        *position = Some(ChainPosition { index, cluster });
+        Ok(bytes_read)
+    }
+
+    //  * A method to read all of the data of `stream` into a vector. The size
+    //    of the stream is checked against its clusters first: it comes from
+    //    the disk and is allocated whole.
+    pub fn read_all_stream(&mut self, stream: &Stream, buf: &mut Vec<u8>) -> io::Result<usize> {
+        self.check_stream_size(stream)?;
+        let start = buf.len();
+        buf.resize(start + stream.size as usize, 0);
+        self.read_stream(stream, 0, &mut buf[start..])?;
+        Ok(buf.len())
+    }
+
+    //  * A method to return the first sector of `cluster`.
+    fn cluster_start_sector(&self, cluster: u32) -> io::Result<u64> {
+        self.check_cluster(cluster)?;
+        Ok(self.cluster_heap_start_sector + (cluster as u64 - 2) * self.sectors_per_cluster)
+    }
+
+    //  * A method to return an error if `cluster` is not in the cluster heap.
+    fn check_cluster(&self, cluster: u32) -> io::Result<()> {
+        if cluster < 2 || cluster as u64 >= self.num_clusters as u64 + 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster number is out of range"));
+        }
+        Ok(())
+    }
+
+    //  * A method to read the FAT entry of `cluster`.
+    fn fat_entry(&mut self, cluster: u32) -> io::Result<u32> {
+        self.check_cluster(cluster)?;
+        let offset = cluster as u64 * 4;
+        let sector = self.fat_start_sector + offset / self.bytes_per_sector;
+        let offset = (offset % self.bytes_per_sector) as usize;
+        let bytes = &self.device.get(sector)?[offset..offset + 4];
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    //  * A method to return the cluster after `cluster` in `stream`.
+    fn next_cluster(&mut self, stream: &Stream, cluster: u32) -> io::Result<u32> {
+        if stream.contiguous {
+            return Ok(cluster + 1);
+        }
+        match self.fat_entry(cluster)? {
+            next if next >= END_OF_CHAIN => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream is larger than its cluster chain",
+            )),
+            next => Ok(next),
+        }
+    }
+
+    //  * A method to return an error if `stream` is larger than the cluster
+    //    heap or than its clusters.
+    fn check_stream_size(&mut self, stream: &Stream) -> io::Result<()> {
+        if stream.size == 0 {
+            return Ok(());
+        }
+
+        let clusters = (stream.size - 1) / self.bytes_per_cluster() + 1;
+        if clusters > self.num_clusters as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "stream is larger than the cluster heap"));
+        }
+        let chain_len = match stream.contiguous {
+            true => {
+                self.check_cluster(stream.first_cluster)?;
+                self.num_clusters as u64 + 2 - stream.first_cluster as u64
+            }
+            false => self.chain_len(stream.first_cluster)?,
+        };
+        if clusters > chain_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "stream is larger than its cluster chain"));
+        }
+        Ok(())
+    }
+
+    //  * A method to return the cluster holding cluster `index` of `stream`,
+    //    walking the chain from `from` if it comes before it.
+    fn cluster_at(&mut self, stream: &Stream, index: u64, from: Option<ChainPosition>) -> io::Result<u32> {
+        if index >= self.num_clusters as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "stream is larger than the cluster heap"));
+        }
+        if stream.contiguous {
+            let cluster = stream.first_cluster as u64 + index;
+            self.check_cluster(core::cmp::min(cluster, u32::max_value() as u64) as u32)?;
+            return Ok(cluster as u32);
+        }
+
+        let (mut cluster, start) = match from {
+            Some(from) if from.index <= index => (from.cluster, from.index),
+            _ => (stream.first_cluster, 0),
+        };
+        for _ in start..index {
+            cluster = self.next_cluster(stream, cluster)?;
+        }
+        Ok(cluster)
+    }
+
+    //  * A method to return the number of clusters in the chain starting at
+    //    `cluster`.
+    fn chain_len(&mut self, mut cluster: u32) -> io::Result<u64> {
+        let mut len = 1;
+        loop {
+            match self.fat_entry(cluster)? {
+                next if next >= END_OF_CHAIN => return Ok(len),
+                next => cluster = next,
+            }
+            len += 1;
+            if len > self.num_clusters as u64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster chain contains a cycle"));
+            }
+        }
+    }
+}
+
+/// Returns the first sector of the exFAT volume of `device`: sector 0 if the
+/// device starts with an exFAT boot sector, as left by formatting a whole
+/// card, or else the first partition holding one, in the order of
+/// `partition::partitions()`.
+fn find_exfat_partition<T: BlockDevice>(mut device: T) -> Result<u64, Error> {
+    if BootSector::is_exfat(&mut device, 0) {
+        return Ok(0);
+    }
+
+    // The partition types are shared with other file systems.
+    partition::partitions(&mut device)?.iter()
+        .filter(|partition| match partition.partition_type {
+            PartitionType::Mbr(partition_type) => partition_type == EXFAT_PARTITION_TYPE,
+            PartitionType::Gpt(type_guid) => type_guid == BASIC_DATA_PARTITION,
+        })
+        .map(|partition| partition.start)
+        .find(|&start| BootSector::is_exfat(&mut device, start))
+        .ok_or(Error::NotFound)
+}
+
+/// Returns the error returned by every operation that would modify the
+/// volume.
+pub(crate) fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "exFAT volumes are mounted read-only")
+}
+
+impl<'a, HANDLE: ExFatHandle> FileSystem for &'a ExFat<HANDLE> {
+    type File = File<HANDLE>;
+    type Dir = Dir<HANDLE>;
+    type Entry = Entry<HANDLE>;
+
+    fn open_root_dir(self) -> Entry<HANDLE> {
+        let root = self.lock(|volume| volume.root);
+        Entry::Dir(Dir {
+            exfat: self.clone(),
+            name: String::from(""),
+            stream: root,
+            metadata: Metadata::empty(),
+        })
+    }
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Path must be absolute",
+            ))
+        }
+
+        // Directories have no `.` and `..` entries: keep the path walked so
+        // far to go back up.
+        let mut entries = vec![self.open_root_dir()];
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir => {
+                    entries.truncate(1);
+                    continue;
+                }
+                Component::CurDir => continue,
+                Component::ParentDir => None,
+                Component::Normal(name) => Some(name),
+                Component::Prefix(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "RustOS does not accept Windows path prefix in path",
+                    ))
+                }
+            };
+
+            let entry = entries.last().unwrap();
+            let dir = match traits::Entry::as_dir(entry) {
+                Some(dir) => dir,
+                None => return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("`{}` is not a directory", traits::Entry::name(entry)),
+                )),
+            };
+            match name {
+                Some(name) => {
+                    let next = dir.find(name)?;
+                    entries.push(next);
+                }
+                None if entries.len() > 1 => {
+                    entries.pop();
+                }
+                None => (),
+            }
+        }
+
+        Ok(entries.pop().unwrap())
+    }
+
+    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
+        Err(read_only())
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::Dir> {
+        Err(read_only())
+    }
+
+    fn remove<P: AsRef<Path>>(self, _path: P) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, _from: P, _to: Q) -> io::Result<()> {
+        Err(read_only())
+    }
+}
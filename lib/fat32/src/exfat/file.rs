@@ -0,0 +1,82 @@
+use alloc::string::String;
+
+use shim::io::{self, SeekFrom};
+
+use crate::exfat::exfat::read_only;
+use crate::exfat::{ChainPosition, ExFat, ExFatHandle, Stream};
+use crate::vfat::Metadata;
+use crate::traits;
+
+#[derive(Clone, Debug)]
+pub struct File<HANDLE: ExFatHandle> {
+    pub exfat: ExFat<HANDLE>,
+    pub name: String,
+    pub stream: Stream,
+    pub seek_pos: u64,
+    pub metadata: Metadata,
+    /// The last cluster read, from which the next read walks the chain.
+    pub position: Option<ChainPosition>,
+}
+
+impl<HANDLE: ExFatHandle> traits::File for File<HANDLE> {
+    /// Nothing is ever buffered: the volume is read-only.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.stream.size
+    }
+
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl<HANDLE: ExFatHandle> io::Read for File<HANDLE> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (stream, seek_pos, position) = (self.stream, self.seek_pos, &mut self.position);
+        let bytes_read = self.exfat.lock(|volume| volume.read_stream_from(&stream, seek_pos, buf, position))?;
+        self.seek_pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<HANDLE: ExFatHandle> io::Write for File<HANDLE> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<HANDLE: ExFatHandle> io::Seek for File<HANDLE> {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
+    /// file returns an `InvalidInput` error.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of a file or beyond the end of the file results
+    /// in an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => self.stream.size as i128 + offset as i128,
+            SeekFrom::Current(offset) => self.seek_pos as i128 + offset as i128,
+        };
+
+        if new_pos < 0 || new_pos > self.stream.size as i128 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Seek outside of file `{}` at index `{}`, when size is {}", self.name, new_pos, self.stream.size),
+            ));
+        }
+
+        self.seek_pos = new_pos as u64;
+        Ok(self.seek_pos)
+    }
+}
@@ -0,0 +1,15 @@
+pub(crate) mod boot;
+pub(crate) mod dir;
+pub(crate) mod entry;
+pub(crate) mod error;
+pub(crate) mod exfat;
+pub(crate) mod file;
+pub(crate) mod upcase;
+
+pub use self::boot::BootSector;
+pub use self::dir::Dir;
+pub use self::entry::Entry;
+pub use self::error::Error;
+pub use self::exfat::{ChainPosition, ExFat, ExFatHandle, Stream, Volume};
+pub use self::file::File;
+pub use self::upcase::UpcaseTable;
@@ -0,0 +1,55 @@
+use alloc::vec::Vec;
+
+/// Number of UTF-16 code units the up-case table can map.
+const TABLE_LEN: usize = 0x10000;
+
+/// Marks a run of identity mappings in a compressed up-case table: it is
+/// followed by the length of the run.
+const IDENTITY_RUN: u16 = 0xFFFF;
+
+/// The up-case table of a volume, which maps UTF-16 code units to their
+/// upper case. File names are compared after mapping them through it.
+#[derive(Clone, Debug)]
+pub struct UpcaseTable(Vec<u16>);
+
+impl UpcaseTable {
+    /// Returns the table that only maps ASCII letters, used by volumes that
+    /// have no up-case table.
+    pub fn ascii() -> UpcaseTable {
+        UpcaseTable((0..128u16).map(|c| (c as u8).to_ascii_uppercase() as u16).collect())
+    }
+
+    /// Returns the table stored in `bytes`, which may be compressed. Code
+    /// units past the end of the table map to themselves.
+    pub fn from_bytes(bytes: &[u8]) -> UpcaseTable {
+        let mut table = Vec::new();
+        let mut units = bytes.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+        while let Some(unit) = units.next() {
+            if table.len() >= TABLE_LEN {
+                break;
+            }
+            match unit {
+                IDENTITY_RUN => {
+                    let run = units.next().unwrap_or(0) as usize;
+                    let end = core::cmp::min(table.len() + run, TABLE_LEN);
+                    while table.len() < end {
+                        table.push(table.len() as u16);
+                    }
+                }
+                unit => table.push(unit),
+            }
+        }
+        UpcaseTable(table)
+    }
+
+    /// Returns the upper case of `unit`.
+    pub fn upcase(&self, unit: u16) -> u16 {
+        self.0.get(unit as usize).cloned().unwrap_or(unit)
+    }
+
+    /// Returns `true` if the UTF-16 names `a` and `b` are equal, ignoring
+    /// case.
+    pub fn eq_ignore_case(&self, a: &[u16], b: &[u16]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(&a, &b)| self.upcase(a) == self.upcase(b))
+    }
+}
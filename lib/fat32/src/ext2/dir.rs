@@ -0,0 +1,129 @@
+use alloc::string::String;
+use alloc::vec::{IntoIter, Vec};
+
+use shim::ffi::OsStr;
+use shim::io;
+
+use crate::ext2::{Entry, Ext2, Ext2Handle, File, Inode, Metadata};
+use crate::traits;
+
+/// Size of the fixed part of a directory entry, before its name.
+const DIR_ENTRY_HEADER_SIZE: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct Dir<HANDLE: Ext2Handle> {
+    pub ext2: Ext2<HANDLE>,
+    pub inode_number: u32,
+    pub inode: Inode,
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+impl<HANDLE: Ext2Handle> Dir<HANDLE> {
+    /// Finds the entry named `name` in `self` and returns it. Names are
+    /// compared exactly, as ext2 names are case sensitive.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
+    /// is returned.
+    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry<HANDLE>> {
+        let name = match name.as_ref().to_str() {
+            Some(name) => name,
+            None => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "`name` contains invalid UTF-8 characters",
+            )),
+        };
+
+        for entry in traits::Dir::entries(self)? {
+            if traits::Entry::name(&entry) == name {
+                return Ok(entry);
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("`{}` not found in `{}`", name, self.name),
+        ))
+    }
+
+    /// Builds the entry named `name` for inode number `number`, or returns
+    /// `None` for inodes that are neither regular files nor directories, such
+    /// as symbolic links and device nodes.
+    fn entry_from(&self, name: String, number: u32, inode: Inode) -> Option<Entry<HANDLE>> {
+        let metadata = Metadata::from(&inode, &name);
+        if inode.is_dir() {
+            Some(Entry::Dir(Dir { ext2: self.ext2.clone(), inode_number: number, inode, name, metadata }))
+        } else if inode.is_file() {
+            Some(Entry::File(File {
+                ext2: self.ext2.clone(),
+                inode_number: number,
+                inode,
+                name,
+                size: inode.size(),
+                seek_pos: 0,
+                metadata,
+            }))
+        } else {
+            None
+        }
+    }
+}
+
+impl<HANDLE: Ext2Handle> traits::Dir for Dir<HANDLE> {
+    type Entry = Entry<HANDLE>;
+    type Iter = IntoIter<Entry<HANDLE>>;
+
+    /// Reads the whole directory and returns its entries, `.` and `..`
+    /// included. Entries that are neither files nor directories are left out.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let inode = self.inode;
+        let entries = self.ext2.lock(|volume| -> io::Result<_> {
+            // A directory has no holes, so its size can't exceed its blocks,
+            // nor the volume.
+            let superblock = volume.superblock();
+            let volume_size = superblock.blocks_count as u64 * superblock.block_size();
+            if inode.size() > inode.sectors as u64 * 512 || inode.size() > volume_size {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "directory is larger than its blocks"));
+            }
+
+            let mut bytes = Vec::new();
+            volume.read_all_inode_data(&inode, &mut bytes)?;
+
+            let mut entries = Vec::new();
+            let mut offset = 0;
+            while offset + DIR_ENTRY_HEADER_SIZE <= bytes.len() {
+                let header = &bytes[offset..offset + DIR_ENTRY_HEADER_SIZE];
+                let number = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+                let record_len = u16::from_le_bytes([header[4], header[5]]) as usize;
+                let name_len = header[6] as usize;
+
+                if record_len < DIR_ENTRY_HEADER_SIZE
+                    || record_len % 4 != 0
+                    || offset + record_len > bytes.len()
+                    || DIR_ENTRY_HEADER_SIZE + name_len > record_len
+                {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupted directory entry"));
+                }
+
+                // Inode 0 marks an unused record.
+                if number != 0 {
+                    let name = &bytes[offset + DIR_ENTRY_HEADER_SIZE..offset + DIR_ENTRY_HEADER_SIZE + name_len];
+                    entries.push((String::from_utf8_lossy(name).into_owned(), number, volume.read_inode(number)?));
+                }
+                offset += record_len;
+            }
+            Ok(entries)
+        })?;
+
+        let entries: Vec<Entry<HANDLE>> = entries
+            .into_iter()
+            .filter_map(|(name, number, inode)| self.entry_from(name, number, inode))
+            .collect();
+        Ok(entries.into_iter())
+    }
+}
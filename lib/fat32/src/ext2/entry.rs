@@ -0,0 +1,97 @@
+use shim::io;
+
+use crate::ext2::ext2::read_only;
+use crate::ext2::{Dir, Ext2Handle, File, Metadata, Timestamp};
+use crate::traits;
+
+#[derive(Clone, Debug)]
+pub enum Entry<HANDLE: Ext2Handle> {
+    File(File<HANDLE>),
+    Dir(Dir<HANDLE>),
+}
+
+impl<HANDLE: Ext2Handle> Entry<HANDLE> {
+    /// Returns the number of the entry's inode.
+    pub fn inode_number(&self) -> u32 {
+        match self {
+            Entry::File(file) => file.inode_number,
+            Entry::Dir(dir) => dir.inode_number,
+        }
+    }
+}
+
+impl<HANDLE: Ext2Handle> traits::Entry for Entry<HANDLE> {
+    type File = File<HANDLE>;
+    type Dir = Dir<HANDLE>;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        match self {
+            Entry::File(file) => &file.name,
+            Entry::Dir(dir) => &dir.name,
+        }
+    }
+
+    fn metadata(&self) -> &Self::Metadata {
+        match self {
+            Entry::File(file) => &file.metadata,
+            Entry::Dir(dir) => &dir.metadata,
+        }
+    }
+
+    fn as_file(&self) -> Option<&File<HANDLE>> {
+        match self {
+            Entry::File(file) => Some(file),
+            Entry::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir<HANDLE>> {
+        match self {
+            Entry::File(_) => None,
+            Entry::Dir(dir) => Some(dir),
+        }
+    }
+
+    fn into_file(self) -> Option<File<HANDLE>> {
+        match self {
+            Entry::File(file) => Some(file),
+            Entry::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir<HANDLE>> {
+        match self {
+            Entry::File(_) => None,
+            Entry::Dir(dir) => Some(dir),
+        }
+    }
+
+    fn set_read_only(&mut self, _read_only: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn set_hidden(&mut self, _hidden: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn set_system(&mut self, _system: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn set_archive(&mut self, _archive: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn set_created(&mut self, _timestamp: Timestamp) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn set_accessed(&mut self, _timestamp: Timestamp) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn set_modified(&mut self, _timestamp: Timestamp) -> io::Result<()> {
+        Err(read_only())
+    }
+}
@@ -0,0 +1,307 @@
+use core::fmt::Debug;
+use core::mem::size_of;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::io;
+use shim::path::{Component, Path};
+
+use crate::ext2::inode::NUM_DIRECT_BLOCKS;
+use crate::ext2::{BlockGroupDescriptor, Dir, Entry, Error, File, Inode, Metadata, Superblock, ROOT_INODE};
+use crate::gpt::LINUX_FILESYSTEM_PARTITION;
+use crate::mbr;
+use crate::partition::{self, PartitionType};
+use crate::traits;
+use crate::traits::{BlockDevice, FileSystem};
+use crate::vfat::{CachedPartition, Partition};
+
+/// MBR partition type of Linux native file systems.
+const LINUX_PARTITION_TYPE: u8 = 0x83;
+
+/// A generic trait that handles a critical section as a closure
+pub trait Ext2Handle: Clone + Debug + Send + Sync {
+    fn new(val: Volume) -> Self;
+    fn lock<R>(&self, f: impl FnOnce(&mut Volume) -> R) -> R;
+}
+
+/// The state of a mounted ext2 volume, shared by its entries through an
+/// `Ext2Handle`.
+#[derive(Debug)]
+pub struct Volume {
+    /// The volume's blocks, a block per logical sector.
+    device: CachedPartition,
+    superblock: Superblock,
+    groups: Vec<BlockGroupDescriptor>,
+    block_size: u64,
+    inode_size: u64,
+    root: Inode,
+}
+
+/// A read-only ext2 file system. Cloning it is cheap: clones share the
+/// volume.
+#[derive(Clone, Debug)]
+pub struct Ext2<HANDLE: Ext2Handle>(HANDLE);
+
+impl<HANDLE: Ext2Handle> Ext2<HANDLE> {
+    /// Mounts the ext2 volume of `device`: the first Linux partition of its
+    /// MBR or GUID partition table, or the whole device if it has no
+    /// partition table.
+    pub fn from<T>(mut device: T) -> Result<Ext2<HANDLE>, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let start = find_ext2_partition(&mut device)?;
+        let superblock = Superblock::from(&mut device, start)?;
+
+        let block_size = superblock.block_size();
+        if block_size < device.sector_size() {
+            return Err(Error::BadGeometry);
+        }
+        let partition = Partition {
+            start,
+            num_sectors: superblock.blocks_count as u64,
+            sector_size: block_size,
+        };
+
+        let mut volume = Volume {
+            device: CachedPartition::new(device, partition),
+            groups: Vec::new(),
+            block_size,
+            inode_size: superblock.inode_size() as u64,
+            superblock,
+            root: Inode::from_bytes([0; size_of::<Inode>()]),
+        };
+
+        // The group descriptor table starts in the block after the superblock.
+        let table_block = volume.superblock.first_data_block as u64 + 1;
+        let num_groups = volume.superblock.num_groups() as usize;
+        let mut table = Vec::new();
+        while table.len() < num_groups * size_of::<BlockGroupDescriptor>() {
+            let block = table_block + (table.len() as u64 / block_size);
+            volume.device.read_all_sector(block, &mut table)?;
+        }
+        for raw in table.chunks(size_of::<BlockGroupDescriptor>()).take(num_groups) {
+            let mut bytes = [0u8; size_of::<BlockGroupDescriptor>()];
+            bytes.copy_from_slice(raw);
+            volume.groups.push(BlockGroupDescriptor::from_bytes(bytes));
+        }
+
+        volume.root = volume.read_inode(ROOT_INODE)?;
+        if !volume.root.is_dir() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "root inode is not a directory")));
+        }
+
+        Ok(Ext2(HANDLE::new(volume)))
+    }
+
+    //  * A method to run `f` with exclusive access to the volume.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut Volume) -> R) -> R {
+        self.0.lock(f)
+    }
+}
+
+impl Volume {
+    //  * A method to return the volume's superblock.
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    //  * A method to read inode number `number`.
+    pub fn read_inode(&mut self, number: u32) -> io::Result<Inode> {
+        if number == 0 || number > self.superblock.inodes_count {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "inode number is out of range"));
+        }
+
+        let inodes_per_group = self.superblock.inodes_per_group;
+        let group = match self.groups.get(((number - 1) / inodes_per_group) as usize) {
+            Some(group) => *group,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "inode lies beyond the last block group")),
+        };
+
+        let offset = ((number - 1) % inodes_per_group) as u64 * self.inode_size;
+        let block = group.inode_table as u64 + offset / self.block_size;
+        let offset = (offset % self.block_size) as usize;
+
+        let mut bytes = [0u8; size_of::<Inode>()];
+        bytes.copy_from_slice(&self.device.get(block)?[offset..offset + size_of::<Inode>()]);
+        Ok(Inode::from_bytes(bytes))
+    }
+
+    //  * A method to read from an offset of the data of `inode` into a buffer.
+    //    Holes read as zeroes.
+    pub fn read_inode_data(&mut self, inode: &Inode, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let size = inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = core::cmp::min(buf.len() as u64, size - offset) as usize;
+        let mut bytes_read = 0;
+        while bytes_read < len {
+            let position = offset + bytes_read as u64;
+            let block_offset = (position % self.block_size) as usize;
+            let n = core::cmp::min(self.block_size as usize - block_offset, len - bytes_read);
+
+            let dst = &mut buf[bytes_read..bytes_read + n];
+            match self.data_block(inode, position / self.block_size)? {
+                0 => dst.iter_mut().for_each(|byte| *byte = 0),
+                block => dst.copy_from_slice(&self.device.get(block as u64)?[block_offset..block_offset + n]),
+            }
+            bytes_read += n;
+        }
+
+        Ok(bytes_read)
+    }
+
+    //  * A method to read all of the data of `inode` into a vector.
+    pub fn read_all_inode_data(&mut self, inode: &Inode, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let start = buf.len();
+        buf.resize(start + inode.size() as usize, 0);
+        self.read_inode_data(inode, 0, &mut buf[start..])?;
+        Ok(buf.len())
+    }
+
+    //  * A method to return the block holding block `index` of the data of
+    //    `inode`, following the indirect blocks as needed, or 0 for a hole.
+    fn data_block(&mut self, inode: &Inode, index: u64) -> io::Result<u32> {
+        let pointers = { inode.block };
+        if index < NUM_DIRECT_BLOCKS as u64 {
+            return Ok(pointers[index as usize]);
+        }
+
+        // Find how many levels of indirect blocks map `index`.
+        let per_block = self.block_size / 4;
+        let mut index = index - NUM_DIRECT_BLOCKS as u64;
+        let mut level = 1;
+        let mut span = per_block;
+        while index >= span {
+            index -= span;
+            level += 1;
+            span *= per_block;
+            if level > 3 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "offset lies beyond the largest ext2 file"));
+            }
+        }
+
+        let mut block = pointers[NUM_DIRECT_BLOCKS + level - 1];
+        while level > 0 && block != 0 {
+            span /= per_block;
+            block = self.block_pointer(block, (index / span) as usize)?;
+            index %= span;
+            level -= 1;
+        }
+        Ok(block)
+    }
+
+    //  * A method to read pointer `i` of the indirect block `block`.
+    fn block_pointer(&mut self, block: u32, i: usize) -> io::Result<u32> {
+        let bytes = self.device.get(block as u64)?;
+        Ok(u32::from_le_bytes([bytes[4 * i], bytes[4 * i + 1], bytes[4 * i + 2], bytes[4 * i + 3]]))
+    }
+}
+
+/// Returns the first sector of the ext2 volume of `device`: the first Linux
+/// partition, in the order of `partition::partitions()`. A device without an
+/// MBR signature is taken to hold a single volume from its first sector on,
+/// as left by `mke2fs` run on a whole disk or image.
+fn find_ext2_partition<T: BlockDevice>(device: T) -> Result<u64, Error> {
+    let partitions = match partition::partitions(device) {
+        Ok(partitions) => partitions,
+        Err(partition::Error::Mbr(mbr::Error::BadSignature)) => return Ok(0),
+        Err(error) => return Err(error.into()),
+    };
+
+    partitions.iter()
+        .find(|partition| match partition.partition_type {
+            PartitionType::Mbr(partition_type) => partition_type == LINUX_PARTITION_TYPE,
+            PartitionType::Gpt(type_guid) => type_guid == LINUX_FILESYSTEM_PARTITION,
+        })
+        .map(|partition| partition.start)
+        .ok_or(Error::NotFound)
+}
+
+/// Returns the error returned by every operation that would modify the
+/// volume.
+pub(crate) fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "ext2 volumes are mounted read-only")
+}
+
+impl<'a, HANDLE: Ext2Handle> FileSystem for &'a Ext2<HANDLE> {
+    type File = File<HANDLE>;
+    type Dir = Dir<HANDLE>;
+    type Entry = Entry<HANDLE>;
+
+    fn open_root_dir(self) -> Entry<HANDLE> {
+        let root = self.lock(|volume| volume.root);
+        Entry::Dir(Dir {
+            ext2: self.clone(),
+            inode_number: ROOT_INODE,
+            inode: root,
+            name: String::from(""),
+            metadata: Metadata::from(&root, ""),
+        })
+    }
+
+    fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Path must be absolute",
+            ))
+        }
+
+        let mut entry = self.open_root_dir();
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir => {
+                    entry = self.open_root_dir();
+                    continue;
+                }
+                Component::CurDir => continue,
+                // Directories have real `..` entries.
+                Component::ParentDir => "..",
+                Component::Normal(name) => match name.to_str() {
+                    Some(name) => name,
+                    None => return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "path contains invalid UTF-8 characters",
+                    )),
+                },
+                Component::Prefix(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "RustOS does not accept Windows path prefix in path",
+                    ))
+                }
+            };
+
+            entry = match traits::Entry::as_dir(&entry) {
+                Some(dir) => dir.find(name)?,
+                None => return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("`{}` is not a directory", traits::Entry::name(&entry)),
+                )),
+            };
+        }
+
+        Ok(entry)
+    }
+
+    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
+        Err(read_only())
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::Dir> {
+        Err(read_only())
+    }
+
+    fn remove<P: AsRef<Path>>(self, _path: P) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, _from: P, _to: Q) -> io::Result<()> {
+        Err(read_only())
+    }
+}
@@ -0,0 +1,81 @@
+use alloc::string::String;
+
+use shim::io::{self, SeekFrom};
+
+use crate::ext2::ext2::read_only;
+use crate::ext2::{Ext2, Ext2Handle, Inode, Metadata};
+use crate::traits;
+
+#[derive(Clone, Debug)]
+pub struct File<HANDLE: Ext2Handle> {
+    pub ext2: Ext2<HANDLE>,
+    pub inode_number: u32,
+    pub inode: Inode,
+    pub name: String,
+    pub size: u64,
+    pub seek_pos: u64,
+    pub metadata: Metadata,
+}
+
+impl<HANDLE: Ext2Handle> traits::File for File<HANDLE> {
+    /// Nothing is ever buffered: the volume is read-only.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl<HANDLE: Ext2Handle> io::Read for File<HANDLE> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (inode, seek_pos) = (self.inode, self.seek_pos);
+        let bytes_read = self.ext2.lock(|volume| volume.read_inode_data(&inode, seek_pos, buf))?;
+        self.seek_pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<HANDLE: Ext2Handle> io::Write for File<HANDLE> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<HANDLE: Ext2Handle> io::Seek for File<HANDLE> {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
+    /// file returns an `InvalidInput` error.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of a file or beyond the end of the file results
+    /// in an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => self.size as i128 + offset as i128,
+            SeekFrom::Current(offset) => self.seek_pos as i128 + offset as i128,
+        };
+
+        if new_pos < 0 || new_pos > self.size as i128 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Seek outside of file `{}` at index `{}`, when size is {}", self.name, new_pos, self.size),
+            ));
+        }
+
+        self.seek_pos = new_pos as u64;
+        Ok(self.seek_pos)
+    }
+}
@@ -0,0 +1,88 @@
+use core::fmt;
+use core::mem::size_of;
+use shim::const_assert_size;
+
+/// Inode number of the root directory.
+pub const ROOT_INODE: u32 = 2;
+
+/// Number of block pointers in `Inode::block` that point at data directly.
+/// They are followed by a single, a double and a triple indirect pointer.
+pub const NUM_DIRECT_BLOCKS: usize = 12;
+
+const TYPE_MASK: u16 = 0xF000;
+const TYPE_DIRECTORY: u16 = 0x4000;
+const TYPE_REGULAR: u16 = 0x8000;
+
+/// The first 128 bytes of an on-disk inode, which every revision shares.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct Inode {
+    pub mode: u16,
+    pub uid: u16,
+    size_low: u32,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    pub dtime: u32,
+    pub gid: u16,
+    pub links_count: u16,
+    /// Number of 512 byte sectors holding the inode's data and metadata.
+    pub sectors: u32,
+    pub flags: u32,
+    osd1: u32,
+    /// Direct, single, double and triple indirect block pointers. Pointer 0
+    /// stands for a hole, which reads as zeroes.
+    pub block: [u32; 15],
+    pub generation: u32,
+    file_acl: u32,
+    /// High 32 bits of the size of regular files.
+    size_high: u32,
+    faddr: u32,
+    osd2: [u8; 12],
+}
+
+const_assert_size!(Inode, 128);
+
+impl Inode {
+    /// Returns the inode stored in the first 128 bytes of `bytes`.
+    pub fn from_bytes(bytes: [u8; size_of::<Inode>()]) -> Inode {
+        unsafe { core::mem::transmute::<[u8; size_of::<Inode>()], Inode>(bytes) }
+    }
+
+    /// Returns `true` if the inode is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.mode & TYPE_MASK == TYPE_DIRECTORY
+    }
+
+    /// Returns `true` if the inode is a regular file.
+    pub fn is_file(&self) -> bool {
+        self.mode & TYPE_MASK == TYPE_REGULAR
+    }
+
+    /// Returns the size of the inode's data in bytes. Only regular files
+    /// have a 64-bit size.
+    pub fn size(&self) -> u64 {
+        match self.is_file() {
+            true => (self.size_high as u64) << 32 | self.size_low as u64,
+            false => self.size_low as u64,
+        }
+    }
+}
+
+impl fmt::Debug for Inode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Inode")
+            .field("mode", &{ self.mode })
+            .field("uid", &{ self.uid })
+            .field("size", &self.size())
+            .field("atime", &{ self.atime })
+            .field("ctime", &{ self.ctime })
+            .field("mtime", &{ self.mtime })
+            .field("gid", &{ self.gid })
+            .field("links_count", &{ self.links_count })
+            .field("sectors", &{ self.sectors })
+            .field("flags", &{ self.flags })
+            .field("block", &{ self.block })
+            .finish()
+    }
+}
@@ -0,0 +1,123 @@
+use core::fmt;
+
+use crate::ext2::Inode;
+use crate::traits;
+
+/// A point in time, as the number of seconds since 01/01/1970 00:00:00 UTC.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp(pub u32);
+
+impl Timestamp {
+    /// Returns the (year, month, day) of the timestamp's date in the
+    /// proleptic Gregorian calendar.
+    fn date(&self) -> (usize, u8, u8) {
+        let days = (self.0 / 86400) as i64;
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year as usize, month, day)
+    }
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        self.date().0
+    }
+
+    fn month(&self) -> u8 {
+        self.date().1
+    }
+
+    fn day(&self) -> u8 {
+        self.date().2
+    }
+
+    fn hour(&self) -> u8 {
+        (self.0 / 3600 % 24) as u8
+    }
+
+    fn minute(&self) -> u8 {
+        (self.0 / 60 % 60) as u8
+    }
+
+    fn second(&self) -> u8 {
+        (self.0 % 60) as u8
+    }
+}
+
+/// Metadata of an ext2 entry, taken from its inode.
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    /// File type and permission bits.
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
+    pub links_count: u16,
+    /// Whether the entry's name starts with a dot.
+    hidden: bool,
+    /// Time of the last change to the inode. ext2 does not record when an
+    /// inode was created; this is the closest it has.
+    changed: Timestamp,
+    accessed: Timestamp,
+    modified: Timestamp,
+}
+
+impl Metadata {
+    pub fn from(inode: &Inode, name: &str) -> Metadata {
+        Metadata {
+            mode: inode.mode,
+            uid: inode.uid,
+            gid: inode.gid,
+            links_count: inode.links_count,
+            hidden: name.starts_with('.'),
+            changed: Timestamp(inode.ctime),
+            accessed: Timestamp(inode.atime),
+            modified: Timestamp(inode.mtime),
+        }
+    }
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    /// Whether no one may write to the entry.
+    fn read_only(&self) -> bool {
+        self.mode & 0o222 == 0
+    }
+
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+
+    fn system(&self) -> bool {
+        false
+    }
+
+    fn archive(&self) -> bool {
+        false
+    }
+
+    fn created(&self) -> Timestamp {
+        self.changed
+    }
+
+    fn accessed(&self) -> Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Timestamp {
+        self.modified
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
@@ -0,0 +1,17 @@
+pub(crate) mod dir;
+pub(crate) mod entry;
+pub(crate) mod error;
+pub(crate) mod ext2;
+pub(crate) mod file;
+pub(crate) mod inode;
+pub(crate) mod metadata;
+pub(crate) mod superblock;
+
+pub use self::dir::Dir;
+pub use self::entry::Entry;
+pub use self::error::Error;
+pub use self::ext2::{Ext2, Ext2Handle, Volume};
+pub use self::file::File;
+pub use self::inode::{Inode, ROOT_INODE};
+pub use self::metadata::{Metadata, Timestamp};
+pub use self::superblock::{BlockGroupDescriptor, Superblock};
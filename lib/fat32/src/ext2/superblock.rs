@@ -0,0 +1,222 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+use shim::const_assert_size;
+
+use crate::ext2::Error;
+use crate::traits::BlockDevice;
+
+/// Magic number of an ext2 superblock.
+const MAGIC: u16 = 0xEF53;
+
+/// Offset in bytes of the superblock from the start of the volume.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+
+/// Incompatible feature: directory entries record the type of their inode.
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+
+/// Incompatible features this driver can read. Volumes using any other, like
+/// compression, journal recovery or ext4 extents, are refused.
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE;
+
+/// The ext2 superblock.
+#[repr(C, packed)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    reserved_blocks_count: u32,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    log_block_size: u32,
+    log_frag_size: u32,
+    pub blocks_per_group: u32,
+    frags_per_group: u32,
+    pub inodes_per_group: u32,
+    mount_time: u32,
+    write_time: u32,
+    mount_count: u16,
+    max_mount_count: u16,
+    magic: u16,
+    pub state: u16,
+    errors: u16,
+    minor_rev_level: u16,
+    last_check: u32,
+    check_interval: u32,
+    creator_os: u32,
+    rev_level: u32,
+    def_resuid: u16,
+    def_resgid: u16,
+    // The fields below are only valid from revision 1 on.
+    first_inode: u32,
+    inode_size: u16,
+    block_group_nr: u16,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    pub uuid: [u8; 16],
+    volume_name: [u8; 16],
+    last_mounted: [u8; 64],
+    algorithm_usage_bitmap: u32,
+    reserved: [u8; 820],
+}
+
+const_assert_size!(Superblock, 1024);
+
+impl Superblock {
+    /// Reads the superblock of the ext2 volume starting at sector `start` of
+    /// `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the magic number is invalid, `BadGeometry` if
+    /// the block size, group layout or inode size can't be right, and
+    /// `UnsupportedFeatures` if the volume uses incompatible features this
+    /// driver can't read.
+    pub fn from<T: BlockDevice>(mut device: T, start: u64) -> Result<Superblock, Error> {
+        // The superblock may start in the middle of a device sector.
+        let sector_size = device.sector_size();
+        let first_sector = start + SUPERBLOCK_OFFSET / sector_size;
+        let offset = (SUPERBLOCK_OFFSET % sector_size) as usize;
+        let mut bytes: Vec<u8> = Vec::new();
+        while bytes.len() < offset + size_of::<Superblock>() {
+            let sector = first_sector + (bytes.len() as u64 / sector_size);
+            device.read_all_sector(sector, &mut bytes)?;
+        }
+
+        let mut superblock_bytes = [0u8; size_of::<Superblock>()];
+        superblock_bytes.copy_from_slice(&bytes[offset..offset + size_of::<Superblock>()]);
+        let superblock = unsafe {
+            core::mem::transmute::<[u8; size_of::<Superblock>()], Superblock>(superblock_bytes)
+        };
+
+        if superblock.magic != MAGIC {
+            return Err(Error::BadSignature);
+        }
+
+        let inode_size = superblock.inode_size() as u64;
+        // Blocks are at most 64 KiB. A group's block and inode bitmaps are
+        // a block each, which bounds the blocks and inodes of a group.
+        if superblock.log_block_size > 6
+            || superblock.blocks_per_group == 0
+            || superblock.inodes_per_group == 0
+            || superblock.blocks_per_group as u64 > 8 * superblock.block_size()
+            || superblock.inodes_per_group as u64 > 8 * superblock.block_size()
+            || superblock.first_data_block >= superblock.blocks_count
+            || inode_size < 128
+            || !inode_size.is_power_of_two()
+            || inode_size > superblock.block_size()
+        {
+            return Err(Error::BadGeometry);
+        }
+
+        // Every group holds the same number of inodes, and the group
+        // descriptor table fits in the first group, after the superblock.
+        // These bound the table, which is read whole when mounting.
+        let num_groups = superblock.num_groups() as u64;
+        let table_size = num_groups * size_of::<BlockGroupDescriptor>() as u64;
+        if superblock.inodes_count as u64 != num_groups * superblock.inodes_per_group as u64
+            || table_size > (superblock.blocks_per_group as u64 - 1) * superblock.block_size()
+        {
+            return Err(Error::BadGeometry);
+        }
+
+        let unsupported = superblock.feature_incompat & !SUPPORTED_INCOMPAT;
+        if superblock.rev_level >= 1 && unsupported != 0 {
+            return Err(Error::UnsupportedFeatures(unsupported));
+        }
+
+        Ok(superblock)
+    }
+
+    /// Returns the size of a block in bytes.
+    pub fn block_size(&self) -> u64 {
+        1024 << self.log_block_size
+    }
+
+    /// Returns the size of an on-disk inode in bytes.
+    pub fn inode_size(&self) -> u16 {
+        match self.rev_level {
+            0 => 128,
+            _ => self.inode_size,
+        }
+    }
+
+    /// Returns the number of block groups in the volume.
+    pub fn num_groups(&self) -> u32 {
+        let blocks = (self.blocks_count - self.first_data_block) as u64;
+        let blocks_per_group = self.blocks_per_group as u64;
+        ((blocks + blocks_per_group - 1) / blocks_per_group) as u32
+    }
+
+    /// Returns whether directory entries record the type of their inode.
+    pub fn has_file_types(&self) -> bool {
+        self.rev_level >= 1 && self.feature_incompat & INCOMPAT_FILETYPE != 0
+    }
+
+    /// Returns the volume name, which is empty if the volume has none.
+    pub fn volume_name(&self) -> String {
+        let len = self.volume_name.iter().position(|&c| c == 0).unwrap_or(self.volume_name.len());
+        String::from_utf8_lossy(&self.volume_name[..len]).into_owned()
+    }
+}
+
+impl fmt::Debug for Superblock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Superblock")
+            .field("inodes_count", &{ self.inodes_count })
+            .field("blocks_count", &{ self.blocks_count })
+            .field("free_blocks_count", &{ self.free_blocks_count })
+            .field("free_inodes_count", &{ self.free_inodes_count })
+            .field("first_data_block", &{ self.first_data_block })
+            .field("log_block_size", &{ self.log_block_size })
+            .field("blocks_per_group", &{ self.blocks_per_group })
+            .field("inodes_per_group", &{ self.inodes_per_group })
+            .field("magic", &{ self.magic })
+            .field("state", &{ self.state })
+            .field("rev_level", &{ self.rev_level })
+            .field("inode_size", &{ self.inode_size })
+            .field("feature_compat", &{ self.feature_compat })
+            .field("feature_incompat", &{ self.feature_incompat })
+            .field("feature_ro_compat", &{ self.feature_ro_compat })
+            .field("volume_name", &self.volume_name())
+            .finish()
+    }
+}
+
+/// An entry of the block group descriptor table.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct BlockGroupDescriptor {
+    pub block_bitmap: u32,
+    pub inode_bitmap: u32,
+    pub inode_table: u32,
+    pub free_blocks_count: u16,
+    pub free_inodes_count: u16,
+    pub used_dirs_count: u16,
+    pad: u16,
+    reserved: [u8; 12],
+}
+
+const_assert_size!(BlockGroupDescriptor, 32);
+
+impl BlockGroupDescriptor {
+    /// Returns the descriptor stored in the 32 bytes of `bytes`.
+    pub fn from_bytes(bytes: [u8; size_of::<BlockGroupDescriptor>()]) -> BlockGroupDescriptor {
+        unsafe { core::mem::transmute::<[u8; size_of::<BlockGroupDescriptor>()], BlockGroupDescriptor>(bytes) }
+    }
+}
+
+impl fmt::Debug for BlockGroupDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockGroupDescriptor")
+            .field("block_bitmap", &{ self.block_bitmap })
+            .field("inode_bitmap", &{ self.inode_bitmap })
+            .field("inode_table", &{ self.inode_table })
+            .field("free_blocks_count", &{ self.free_blocks_count })
+            .field("free_inodes_count", &{ self.free_inodes_count })
+            .field("used_dirs_count", &{ self.used_dirs_count })
+            .finish()
+    }
+}
@@ -0,0 +1,216 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+use shim::const_assert_size;
+use shim::io;
+
+use crate::traits::BlockDevice;
+
+/// Partition type GUID of a Microsoft basic data partition, the type FAT32
+/// volumes are usually created with.
+pub const BASIC_DATA_PARTITION: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+/// Partition type GUID of an EFI system partition, which holds a FAT volume.
+pub const EFI_SYSTEM_PARTITION: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+
+/// Partition type GUID of a Linux file system data partition.
+pub const LINUX_FILESYSTEM_PARTITION: [u8; 16] = [
+    0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+];
+
+/// MBR partition type of the single entry in a protective MBR.
+pub const PROTECTIVE_MBR_TYPE: u8 = 0xEE;
+
+const SIGNATURE: [u8; 8] = *b"EFI PART";
+
+/// Upper bound on the size of the partition entry array, to refuse headers
+/// that would make us allocate unbounded memory.
+const MAX_ENTRIES_SIZE: u64 = 1024 * 1024;
+
+/// The GUID partition table header.
+#[repr(C, packed)]
+pub struct GptHeader {
+    signature: [u8; 8],
+    revision: u32,
+    header_size: u32,
+    header_crc32: u32,
+    reserved: u32,
+    current_lba: u64,
+    backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: [u8; 16],
+    pub partition_entries_lba: u64,
+    pub num_partition_entries: u32,
+    pub partition_entry_size: u32,
+    partition_entries_crc32: u32,
+}
+
+const_assert_size!(GptHeader, 92);
+
+impl fmt::Debug for GptHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptHeader")
+            .field("signature", &self.signature)
+            .field("revision", &{ self.revision })
+            .field("header_size", &{ self.header_size })
+            .field("header_crc32", &{ self.header_crc32 })
+            .field("current_lba", &{ self.current_lba })
+            .field("backup_lba", &{ self.backup_lba })
+            .field("first_usable_lba", &{ self.first_usable_lba })
+            .field("last_usable_lba", &{ self.last_usable_lba })
+            .field("disk_guid", &self.disk_guid)
+            .field("partition_entries_lba", &{ self.partition_entries_lba })
+            .field("num_partition_entries", &{ self.num_partition_entries })
+            .field("partition_entry_size", &{ self.partition_entry_size })
+            .field("partition_entries_crc32", &{ self.partition_entries_crc32 })
+            .finish()
+    }
+}
+
+/// An entry of the GUID partition entry array.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct GptPartitionEntry {
+    pub type_guid: [u8; 16],
+    pub unique_guid: [u8; 16],
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+    name: [u16; 36],
+}
+
+const_assert_size!(GptPartitionEntry, 128);
+
+impl GptPartitionEntry {
+    /// Returns the number of sectors in the partition.
+    pub fn num_sectors(&self) -> u64 {
+        self.last_lba - self.first_lba + 1
+    }
+}
+
+impl fmt::Debug for GptPartitionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptPartitionEntry")
+            .field("type_guid", &self.type_guid)
+            .field("unique_guid", &self.unique_guid)
+            .field("first_lba", &{ self.first_lba })
+            .field("last_lba", &{ self.last_lba })
+            .field("attributes", &{ self.attributes })
+            .finish()
+    }
+}
+
+/// The GUID partition table (GPT): its header and the partitions in use.
+#[derive(Debug)]
+pub struct GuidPartitionTable {
+    pub header: GptHeader,
+    /// Entries whose type GUID is not all zeroes, in table order.
+    pub partitions: Vec<GptPartitionEntry>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an I/O error while reading the GPT.
+    Io(io::Error),
+    /// The GPT header signature was invalid.
+    BadSignature,
+    /// The GPT header size or partition entry array layout is invalid.
+    BadHeader,
+    /// The CRC32 of the GPT header did not match.
+    BadHeaderCrc,
+    /// The CRC32 of the partition entry array did not match.
+    BadEntriesCrc,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl GuidPartitionTable {
+    /// Reads and returns the primary GUID partition table from `device`. The
+    /// header is read from LBA 1, where a device sector is one LBA.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the header signature is not `EFI PART`.
+    /// Returns `BadHeader` if the header size or the partition entry array
+    /// layout is invalid. Returns `BadHeaderCrc` or `BadEntriesCrc` if the
+    /// CRC32 of the header or of the partition entry array does not match.
+    /// Returns `Io(err)` if the I/O error `err` occured while reading.
+    pub fn from<T: BlockDevice>(mut device: T) -> Result<GuidPartitionTable, Error> {
+        let sector_size = device.sector_size() as usize;
+        let mut header_sector = Vec::new();
+        device.read_all_sector(1, &mut header_sector)?;
+
+        let mut header_bytes = [0u8; size_of::<GptHeader>()];
+        header_bytes.copy_from_slice(&header_sector[..size_of::<GptHeader>()]);
+        let header = unsafe { core::mem::transmute::<[u8; size_of::<GptHeader>()], GptHeader>(header_bytes) };
+
+        if header.signature != SIGNATURE {
+            return Err(Error::BadSignature);
+        }
+
+        let header_size = header.header_size as usize;
+        if header_size < size_of::<GptHeader>() || header_size > sector_size {
+            return Err(Error::BadHeader);
+        }
+
+        // The header CRC is computed with the CRC field itself zeroed.
+        let mut crc_bytes = header_sector[..header_size].to_vec();
+        crc_bytes[16..20].copy_from_slice(&[0; 4]);
+        if crc32(&crc_bytes) != header.header_crc32 {
+            return Err(Error::BadHeaderCrc);
+        }
+
+        let entry_size = header.partition_entry_size as usize;
+        let entries_size = header.num_partition_entries as u64 * entry_size as u64;
+        if entry_size < size_of::<GptPartitionEntry>() || entry_size % 8 != 0 || entries_size > MAX_ENTRIES_SIZE {
+            return Err(Error::BadHeader);
+        }
+
+        let mut entries = Vec::new();
+        let num_sectors = (entries_size as usize + sector_size - 1) / sector_size;
+        for i in 0..num_sectors as u64 {
+            device.read_all_sector(header.partition_entries_lba + i, &mut entries)?;
+        }
+        entries.truncate(entries_size as usize);
+        if crc32(&entries) != header.partition_entries_crc32 {
+            return Err(Error::BadEntriesCrc);
+        }
+
+        let mut partitions = Vec::new();
+        for raw_entry in entries.chunks(entry_size) {
+            let mut entry_bytes = [0u8; size_of::<GptPartitionEntry>()];
+            entry_bytes.copy_from_slice(&raw_entry[..size_of::<GptPartitionEntry>()]);
+            let entry = unsafe {
+                core::mem::transmute::<[u8; size_of::<GptPartitionEntry>()], GptPartitionEntry>(entry_bytes)
+            };
+
+            if entry.type_guid != [0; 16] && entry.last_lba >= entry.first_lba {
+                partitions.push(entry);
+            }
+        }
+
+        Ok(GuidPartitionTable { header, partitions })
+    }
+}
+
+/// Returns the CRC32 (IEEE 802.3, as used by GPT) of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
@@ -78,6 +78,9 @@ pub enum Error {
     UnknownBootIndicator(u8),
     /// The MBR magic signature was invalid.
     BadSignature,
+    /// The chain of extended boot records of an extended partition loops or
+    /// describes partitions outside of the extended partition.
+    BadExtendedPartition,
 }
 
 impl MasterBootRecord {
@@ -89,10 +92,21 @@ impl MasterBootRecord {
     /// Returns `UnknownBootIndicator(n)` if partition `n` contains an invalid
     /// boot indicator. Returns `Io(err)` if the I/O error `err` occured while
     /// reading the MBR.
-    pub fn from<T: BlockDevice>(mut device: T) -> Result<MasterBootRecord, Error> {
-        // Read the MBR(0'th) sector to memory.
+    pub fn from<T: BlockDevice>(device: T) -> Result<MasterBootRecord, Error> {
+        MasterBootRecord::from_sector(device, 0)
+    }
+
+    /// Reads and returns the boot record at sector `sector` of `device`. Besides
+    /// the MBR in sector 0, extended boot records (EBR), which describe the
+    /// logical partitions of an extended partition, have this layout.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as `from()`.
+    pub fn from_sector<T: BlockDevice>(mut device: T, sector: u64) -> Result<MasterBootRecord, Error> {
+        // Read the boot record sector to memory.
         let mut mbr_sector: [u8; size_of::<MasterBootRecord>()] = [0; size_of::<MasterBootRecord>()];
-        match device.read_sector(0, &mut mbr_sector) {
+        match device.read_sector(sector, &mut mbr_sector) {
             Ok(_) => (),
             Err(error) => return Err(Error::Io(error)),
         }
@@ -0,0 +1,237 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::min;
+use shim::io;
+
+use crate::traits::BlockDevice;
+use crate::vfat::{BiosParameterBlock, FsInfo, END_OF_CHAIN};
+
+/// MBR partition type of a FAT32 volume addressed with LBA.
+pub const FAT32_LBA_PARTITION_TYPE: u8 = 0x0C;
+
+/// The smallest and largest number of data clusters of a FAT32 volume.
+const MIN_CLUSTERS: u64 = 65525;
+const MAX_CLUSTERS: u64 = 0x0FFFFFF5;
+
+const RESERVED_SECTORS: u16 = 32;
+const BACKUP_BOOT_SECTOR: u64 = 6;
+
+/// Number of sectors zeroed with a single write.
+const ZERO_CHUNK_SECTORS: u64 = 128;
+
+/// Options of `format()`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Size of a cluster in bytes: a power of two no smaller than the sector
+    /// size and no larger than 128 sectors. When 0, a size is picked from the
+    /// size of the volume.
+    pub cluster_size: u32,
+    /// Volume label, at most 11 ASCII characters. It is stored in upper case.
+    pub volume_label: String,
+    /// Volume serial number.
+    pub serial: u32,
+    /// Number of FATs, usually 2.
+    pub num_fats: u8,
+    /// First sector of the partition. Sectors before it are left alone except
+    /// for the MBR.
+    pub partition_start: u64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            cluster_size: 0,
+            volume_label: String::from("NO NAME"),
+            serial: 0,
+            num_fats: 2,
+            partition_start: 2048,
+        }
+    }
+}
+
+/// The geometry of a volume being formatted.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Layout {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub num_fats: u8,
+    pub hidden_sectors: u32,
+    pub total_sectors: u32,
+    pub sectors_per_fat: u32,
+}
+
+impl Layout {
+    fn new(sector_size: u64, num_sectors: u64, options: &Options) -> io::Result<Layout> {
+        if !(512..=4096).contains(&sector_size) || !sector_size.is_power_of_two() {
+            return Err(invalid_input("unsupported sector size"));
+        }
+        if options.num_fats == 0 {
+            return Err(invalid_input("at least one FAT is required"));
+        }
+        if options.partition_start == 0 || options.partition_start >= num_sectors {
+            return Err(invalid_input("partition start is outside of the device"));
+        }
+
+        let total_sectors = num_sectors - options.partition_start;
+        if total_sectors > u32::max_value() as u64 || options.partition_start > u32::max_value() as u64 {
+            return Err(invalid_input("device is too large for an MBR partition"));
+        }
+
+        let cluster_size = match options.cluster_size {
+            0 => default_cluster_size(total_sectors * sector_size) as u64,
+            size => size as u64,
+        };
+        if !cluster_size.is_power_of_two() || cluster_size < sector_size || cluster_size / sector_size > 128 {
+            return Err(invalid_input("invalid cluster size"));
+        }
+        let sectors_per_cluster = cluster_size / sector_size;
+
+        // Each FAT sector maps `sector_size / 4` clusters, two of which are
+        // reserved. Solve for the smallest FAT that maps every cluster left
+        // over once the reserved sectors and the FATs themselves are removed.
+        let reserved = RESERVED_SECTORS as u64;
+        let num_fats = options.num_fats as u64;
+        let available = total_sectors.saturating_sub(reserved);
+        let per_sector = sectors_per_cluster * (sector_size / 4) + num_fats;
+        let sectors_per_fat = (available + 2 * sectors_per_cluster + per_sector - 1) / per_sector;
+        let num_clusters = available.saturating_sub(num_fats * sectors_per_fat) / sectors_per_cluster;
+
+        if num_clusters < MIN_CLUSTERS {
+            return Err(invalid_input("volume is too small for FAT32 with this cluster size"));
+        }
+        if num_clusters > MAX_CLUSTERS {
+            return Err(invalid_input("volume is too large for FAT32 with this cluster size"));
+        }
+
+        Ok(Layout {
+            bytes_per_sector: sector_size as u16,
+            sectors_per_cluster: sectors_per_cluster as u8,
+            reserved_sectors: RESERVED_SECTORS,
+            num_fats: options.num_fats,
+            hidden_sectors: options.partition_start as u32,
+            total_sectors: total_sectors as u32,
+            sectors_per_fat: sectors_per_fat as u32,
+        })
+    }
+
+    fn fat_start(&self) -> u64 {
+        self.reserved_sectors as u64
+    }
+
+    fn data_start(&self) -> u64 {
+        self.fat_start() + self.num_fats as u64 * self.sectors_per_fat as u64
+    }
+
+    fn num_clusters(&self) -> u32 {
+        (self.total_sectors - self.data_start() as u32) / self.sectors_per_cluster as u32
+    }
+}
+
+/// Formats the first `num_sectors` sectors of `device` as a single FAT32
+/// volume: an MBR with one partition entry, the extended BIOS parameter block
+/// and its backup, FSInfo, the FATs and an empty root directory in cluster 2.
+///
+/// # Errors
+///
+/// Returns an error of kind `InvalidInput` if the options are invalid or if
+/// the volume would have too few or too many clusters for FAT32. I/O errors
+/// of `device` are returned as is.
+pub fn format<T: BlockDevice>(mut device: T, num_sectors: u64, options: &Options) -> io::Result<()> {
+    let sector_size = device.sector_size();
+    let layout = Layout::new(sector_size, num_sectors, options)?;
+    let volume_label = volume_label(&options.volume_label)?;
+    let start = options.partition_start;
+    let sector_size = sector_size as usize;
+
+    // Clear the reserved region, the FATs and the root directory cluster
+    // before anything makes the volume recognizable.
+    let cluster_sectors = layout.sectors_per_cluster as u64;
+    zero_sectors(&mut device, start, layout.data_start() + cluster_sectors)?;
+
+    let mut sector = vec![0u8; sector_size];
+    let ebpb = BiosParameterBlock::new_fat32(&layout, options.serial, volume_label);
+    sector[..512].copy_from_slice(&ebpb.to_bytes());
+    device.write_sector(start, &sector)?;
+    device.write_sector(start + BACKUP_BOOT_SECTOR, &sector)?;
+
+    // Every cluster but the root directory's is free.
+    let mut sector = vec![0u8; sector_size];
+    let fsinfo = FsInfo::new(layout.num_clusters() - 1, 3);
+    sector[..512].copy_from_slice(&fsinfo.to_bytes());
+    device.write_sector(start + 1, &sector)?;
+    device.write_sector(start + BACKUP_BOOT_SECTOR + 1, &sector)?;
+
+    // Entry 0 holds the media descriptor, entry 1 the clean shutdown and no
+    // error bits, and entry 2 the root directory's single-cluster chain.
+    let mut sector = vec![0u8; sector_size];
+    sector[0..4].copy_from_slice(&0x0FFFFFF8u32.to_le_bytes());
+    sector[4..8].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+    sector[8..12].copy_from_slice(&END_OF_CHAIN.to_le_bytes());
+    for fat in 0..layout.num_fats as u64 {
+        device.write_sector(start + layout.fat_start() + fat * layout.sectors_per_fat as u64, &sector)?;
+    }
+
+    // The partition entry goes last so that an interrupted format does not
+    // leave a volume that looks valid.
+    let mut sector = vec![0u8; sector_size];
+    let entry = &mut sector[446..462];
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[4] = FAT32_LBA_PARTITION_TYPE;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[8..12].copy_from_slice(&layout.hidden_sectors.to_le_bytes());
+    entry[12..16].copy_from_slice(&layout.total_sectors.to_le_bytes());
+    sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+    device.write_sector(0, &sector)?;
+
+    Ok(())
+}
+
+/// Returns the cluster size Microsoft's formatter uses for a FAT32 volume of
+/// `volume_size` bytes.
+fn default_cluster_size(volume_size: u64) -> u32 {
+    const MIB: u64 = 1024 * 1024;
+    match volume_size {
+        size if size <= 260 * MIB => 512,
+        size if size <= 8 * 1024 * MIB => 4096,
+        size if size <= 16 * 1024 * MIB => 8192,
+        size if size <= 32 * 1024 * MIB => 16384,
+        _ => 32768,
+    }
+}
+
+/// Returns `label` upper-cased and padded with spaces to the on-disk size.
+fn volume_label(label: &str) -> io::Result<[u8; 11]> {
+    const INVALID: &[u8] = b"\"*+,./:;<=>?[\\]|";
+
+    let label = if label.is_empty() { "NO NAME" } else { label };
+    if label.len() > 11 || !label.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        return Err(invalid_input("invalid volume label"));
+    }
+    if label.bytes().any(|b| INVALID.contains(&b)) {
+        return Err(invalid_input("invalid volume label"));
+    }
+
+    let mut bytes = [b' '; 11];
+    for (byte, c) in bytes.iter_mut().zip(label.bytes()) {
+        *byte = c.to_ascii_uppercase();
+    }
+    Ok(bytes)
+}
+
+/// Writes zeroes to `count` sectors starting at sector `start`.
+fn zero_sectors<T: BlockDevice>(device: &mut T, start: u64, count: u64) -> io::Result<()> {
+    let sector_size = device.sector_size();
+    let zeroes: Vec<u8> = vec![0; (min(count, ZERO_CHUNK_SECTORS) * sector_size) as usize];
+    let mut written = 0;
+    while written < count {
+        let num_sectors = min(count - written, ZERO_CHUNK_SECTORS);
+        device.write_sectors(start + written, &zeroes[..(num_sectors * sector_size) as usize])?;
+        written += num_sectors;
+    }
+    Ok(())
+}
+
+fn invalid_input(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
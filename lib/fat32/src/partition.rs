@@ -0,0 +1,222 @@
+use alloc::vec::Vec;
+use shim::io;
+
+use crate::gpt::{self, GuidPartitionTable, PROTECTIVE_MBR_TYPE};
+use crate::mbr::{self, MasterBootRecord};
+use crate::traits::BlockDevice;
+
+/// MBR partition types of extended partitions, which hold a chain of extended
+/// boot records describing logical partitions.
+const EXTENDED_PARTITION_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// Bound on the length of a chain of extended boot records, so that a chain
+/// that loops is detected.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+/// How a partition table identifies the contents of a partition.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartitionType {
+    /// An MBR partition type, like 0x0C for FAT32.
+    Mbr(u8),
+    /// A GPT partition type GUID, as stored on disk.
+    Gpt([u8; 16]),
+}
+
+/// A partition of a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    pub partition_type: PartitionType,
+    /// The device sector where the partition begins.
+    pub start: u64,
+    /// Number of device sectors in the partition.
+    pub num_sectors: u64,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Mbr(mbr::Error),
+    Gpt(gpt::Error),
+    Io(io::Error),
+    /// There is no partition with the requested index.
+    NotFound,
+}
+
+impl From<mbr::Error> for Error {
+    fn from(error: mbr::Error) -> Error {
+        Error::Mbr(error)
+    }
+}
+
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// Returns the partitions of `device`, in the order their indices refer to.
+///
+/// A protective MBR hands over to the GUID partition table, whose partitions
+/// are listed in table order. Otherwise the primary partitions of the MBR are
+/// listed in table order, followed by the logical partitions of its extended
+/// partitions. Empty entries and extended partitions themselves are left out.
+///
+/// # Errors
+///
+/// Returns the error of reading the MBR or the GPT, or
+/// `Mbr(BadExtendedPartition)` if the chain of extended boot records of an
+/// extended partition is invalid.
+pub fn partitions<T: BlockDevice>(mut device: T) -> Result<Vec<PartitionInfo>, Error> {
+    let mbr = MasterBootRecord::from(&mut device)?;
+
+    let is_protective = mbr.partition_table.iter().any(|entry| entry.partition_type == PROTECTIVE_MBR_TYPE);
+    if is_protective {
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        return Ok(gpt.partitions.iter()
+            .map(|entry| PartitionInfo {
+                partition_type: PartitionType::Gpt(entry.type_guid),
+                start: entry.first_lba,
+                num_sectors: entry.num_sectors(),
+            })
+            .collect());
+    }
+
+    let mut partitions = Vec::new();
+    let mut extended = Vec::new();
+    for entry in mbr.partition_table.iter() {
+        if entry.partition_type == 0 || entry.total_sectors == 0 {
+            continue;
+        }
+        let partition = PartitionInfo {
+            partition_type: PartitionType::Mbr(entry.partition_type),
+            start: entry.relative_sector as u64,
+            num_sectors: entry.total_sectors as u64,
+        };
+        if EXTENDED_PARTITION_TYPES.contains(&entry.partition_type) {
+            extended.push(partition);
+        } else {
+            partitions.push(partition);
+        }
+    }
+
+    for container in extended {
+        logical_partitions(&mut device, container, &mut partitions)?;
+    }
+    Ok(partitions)
+}
+
+/// Appends the logical partitions of the extended partition `container` to
+/// `partitions`. Each extended boot record describes a logical partition,
+/// relative to the record, and the location of the next record, relative to
+/// the extended partition.
+fn logical_partitions<T: BlockDevice>(
+    mut device: T,
+    container: PartitionInfo,
+    partitions: &mut Vec<PartitionInfo>,
+) -> Result<(), Error> {
+    let end = container.start + container.num_sectors;
+    let mut ebr_sector = container.start;
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        let ebr = MasterBootRecord::from_sector(&mut device, ebr_sector)?;
+
+        let logical = &ebr.partition_table[0];
+        if logical.partition_type != 0 && logical.total_sectors != 0 {
+            let start = ebr_sector + logical.relative_sector as u64;
+            let num_sectors = logical.total_sectors as u64;
+            if start + num_sectors > end {
+                return Err(Error::Mbr(mbr::Error::BadExtendedPartition));
+            }
+            partitions.push(PartitionInfo { partition_type: PartitionType::Mbr(logical.partition_type), start, num_sectors });
+        }
+
+        let next = &ebr.partition_table[1];
+        if next.partition_type == 0 || next.relative_sector == 0 {
+            return Ok(());
+        }
+        ebr_sector = container.start + next.relative_sector as u64;
+        if ebr_sector >= end {
+            return Err(Error::Mbr(mbr::Error::BadExtendedPartition));
+        }
+    }
+    Err(Error::Mbr(mbr::Error::BadExtendedPartition))
+}
+
+/// Returns partition `index` of `device`, as numbered by `partitions()`, as
+/// a block device of its own.
+///
+/// # Errors
+///
+/// Returns `NotFound` if `device` has no partition `index`, or the error of
+/// reading its partition tables.
+pub fn open<T: BlockDevice>(mut device: T, index: usize) -> Result<PartitionDevice<T>, Error> {
+    match partitions(&mut device)?.get(index) {
+        Some(&partition) => Ok(PartitionDevice::new(device, partition)),
+        None => Err(Error::NotFound),
+    }
+}
+
+/// A partition of a block device, accessed as a device of its own: sector 0 of
+/// a `PartitionDevice` is the first sector of the partition, and sectors past
+/// its end can't be accessed.
+#[derive(Clone, Debug)]
+pub struct PartitionDevice<T: BlockDevice> {
+    device: T,
+    partition: PartitionInfo,
+}
+
+impl<T: BlockDevice> PartitionDevice<T> {
+    /// Returns the device for `partition` of `device`.
+    pub fn new(device: T, partition: PartitionInfo) -> PartitionDevice<T> {
+        PartitionDevice { device, partition }
+    }
+
+    /// Returns the location and type of the partition.
+    pub fn partition(&self) -> PartitionInfo {
+        self.partition
+    }
+
+    /// Returns the device the partition belongs to.
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    //  * A method to return the device sector of sector `n` of the partition,
+    //    checking that the `num_sectors` sectors from it lie within the partition.
+    fn device_sector(&self, n: u64, num_sectors: u64) -> io::Result<u64> {
+        match n.checked_add(num_sectors) {
+            Some(end) if end <= self.partition.num_sectors => Ok(self.partition.start + n),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "sector lies beyond the end of the partition")),
+        }
+    }
+}
+
+impl<T: BlockDevice> BlockDevice for PartitionDevice<T> {
+    fn sector_size(&self) -> u64 {
+        self.device.sector_size()
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = self.device_sector(n, 1)?;
+        self.device.read_sector(sector, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector = self.device_sector(n, 1)?;
+        self.device.write_sector(sector, buf)
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = self.device_sector(n, buf.len() as u64 / self.sector_size())?;
+        self.device.read_sectors(sector, buf)
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector = self.device_sector(n, buf.len() as u64 / self.sector_size())?;
+        self.device.write_sectors(sector, buf)
+    }
+}
@@ -7,12 +7,18 @@ use std::io::Cursor;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+use crate::check;
+use crate::exfat;
+use crate::ext2;
+use crate::gpt;
 use crate::mbr;
+use crate::mkfs;
+use crate::partition;
 use crate::traits::*;
 use crate::vfat;
 
 use mbr::{MasterBootRecord, PartitionEntry, CHS};
-use vfat::{BiosParameterBlock, VFat, VFatHandle};
+use vfat::{BiosParameterBlock, CacheStats, CachedPartition, Cluster, Extent, Partition, VFat, VFatHandle};
 
 #[derive(Clone)]
 struct StdVFatHandle(Arc<Mutex<VFat<Self>>>);
@@ -489,3 +495,2372 @@ fn shuffle_test() {
     let hash = hash_files_recursive_from(vfat, "/");
     assert_hash_eq!("mock 1 file hashes", hash, hash_for!("files-1"));
 }
+
+const MOCK_PARTITION_START: usize = 1;
+const MOCK_RESERVED_SECTORS: usize = 32;
+const MOCK_SECTORS_PER_FAT: usize = 516;
+const MOCK_NUM_CLUSTERS: usize = 66000;
+const MOCK_DATA_START: usize = MOCK_RESERVED_SECTORS + 2 * MOCK_SECTORS_PER_FAT;
+
+fn write_u16(image: &mut [u8], offset: usize, value: u16) {
+    image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(image: &mut [u8], offset: usize, value: u32) {
+    image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Builds an empty FAT32 image in memory: an MBR with a single FAT32
+/// partition, 512 byte sectors, one sector per cluster, two FATs and an empty
+/// root directory in cluster 2. The volume has just over the 65525 clusters
+/// that the FAT specification requires of a FAT32 volume.
+fn mock_fat32_image() -> Vec<u8> {
+    let total_sectors = MOCK_DATA_START + MOCK_NUM_CLUSTERS;
+    let mut image = vec![0u8; (MOCK_PARTITION_START + total_sectors) * 512];
+
+    // MBR with a single FAT32 (LBA) partition.
+    image[446 + 4] = 0xC;
+    write_u32(&mut image, 446 + 8, MOCK_PARTITION_START as u32);
+    write_u32(&mut image, 446 + 12, total_sectors as u32);
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    // Extended BIOS parameter block.
+    let ebpb = MOCK_PARTITION_START * 512;
+    image[ebpb..ebpb + 3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    write_u16(&mut image, ebpb + 11, 512);
+    image[ebpb + 13] = 1;
+    write_u16(&mut image, ebpb + 14, MOCK_RESERVED_SECTORS as u16);
+    image[ebpb + 16] = 2;
+    image[ebpb + 21] = 0xF8;
+    write_u32(&mut image, ebpb + 32, total_sectors as u32);
+    write_u32(&mut image, ebpb + 36, MOCK_SECTORS_PER_FAT as u32);
+    write_u32(&mut image, ebpb + 44, 2);
+    write_u16(&mut image, ebpb + 48, 1);
+    image[ebpb + 66] = 0x29;
+    write_u32(&mut image, ebpb + 67, 0x1234ABCD);
+    image[ebpb + 71..ebpb + 82].copy_from_slice(b"RUSTOS     ");
+    image[ebpb + 510..ebpb + 512].copy_from_slice(&[0x55, 0xAA]);
+
+    // FSInfo sector: every cluster but the root directory's is free.
+    let fsinfo = ebpb + 512;
+    write_u32(&mut image, fsinfo, 0x41615252);
+    write_u32(&mut image, fsinfo + 484, 0x61417272);
+    write_u32(&mut image, fsinfo + 488, MOCK_NUM_CLUSTERS as u32 - 1);
+    write_u32(&mut image, fsinfo + 492, 3);
+    write_u32(&mut image, fsinfo + 508, 0xAA550000);
+
+    // Reserved FAT entries and the root directory's chain, in both FATs.
+    for fat in 0..2 {
+        let start = (MOCK_PARTITION_START + MOCK_RESERVED_SECTORS + fat * MOCK_SECTORS_PER_FAT) * 512;
+        write_u32(&mut image, start, 0x0FFFFFF8);
+        write_u32(&mut image, start + 4, 0x0FFFFFFF);
+        write_u32(&mut image, start + 8, 0x0FFFFFFF);
+    }
+
+    image
+}
+
+/// Writes a regular entry with the 8.3 name `name` at `index` in the root
+/// directory of a `mock_fat32_image()`.
+fn mock_root_entry(image: &mut [u8], index: usize, name: &[u8; 11], cluster: u32, size: u32) {
+    mock_root_entry_at(image, MOCK_PARTITION_START, index, name, cluster, size);
+}
+
+/// Like `mock_root_entry()`, for a volume starting at sector `start`.
+fn mock_root_entry_at(image: &mut [u8], start: usize, index: usize, name: &[u8; 11], cluster: u32, size: u32) {
+    let entry = (start + MOCK_DATA_START) * 512 + index * 32;
+    image[entry..entry + 11].copy_from_slice(name);
+    write_u16(image, entry + 20, (cluster >> 16) as u16);
+    write_u16(image, entry + 26, cluster as u16);
+    write_u32(image, entry + 28, size);
+}
+
+fn vfat_from_image(image: Vec<u8>) -> StdVFatHandle {
+    VFat::<StdVFatHandle>::from(Cursor::new(image)).expect("failed to initialize VFAT from image")
+}
+
+fn read_to_vec<T: File>(mut file: T) -> Vec<u8> {
+    let mut data = vec![0u8; file.size() as usize];
+    file.read_exact(&mut data).expect("read file");
+    data
+}
+
+#[test]
+fn test_write_grows_file() {
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"HELLO   TXT", 0, 0);
+    let vfat = vfat_from_image(image);
+
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+    let mut file = vfat.open_file("/HELLO.TXT").expect("open file");
+    file.write_all(&data).expect("write file");
+    file.sync().expect("sync file");
+    assert_eq!(file.size(), 3000);
+
+    let file = vfat.open_file("/HELLO.TXT").expect("reopen file");
+    assert_eq!(file.size(), 3000);
+    let modified = file.metadata.modified();
+    assert_eq!((modified.year(), modified.month(), modified.day()), (1980, 1, 1));
+    assert_eq!(read_to_vec(file), data);
+}
+
+#[test]
+fn test_write_at_seek_position() {
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"HELLO   TXT", 0, 0);
+    let vfat = vfat_from_image(image);
+
+    let mut expected = vec![b'a'; 1000];
+    let mut file = vfat.open_file("/HELLO.TXT").expect("open file");
+    file.write_all(&expected).expect("write file");
+
+    // Overwrite across a cluster boundary.
+    file.seek(io::SeekFrom::Start(500)).expect("seek");
+    file.write_all(&[b'b'; 20]).expect("overwrite");
+    expected[500..520].copy_from_slice(&[b'b'; 20]);
+
+    // Append at the end of the file.
+    file.seek(io::SeekFrom::End(0)).expect("seek to end");
+    file.write_all(&[b'c'; 100]).expect("append");
+    expected.extend_from_slice(&[b'c'; 100]);
+    file.flush().expect("flush");
+
+    let file = vfat.open_file("/HELLO.TXT").expect("reopen file");
+    assert_eq!(file.size(), 1100);
+    assert_eq!(read_to_vec(file), expected);
+}
+
+#[test]
+fn test_write_past_4_gib() {
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"HUGE    BIN", 3, u32::max_value() - 10);
+    let vfat = vfat_from_image(image);
+
+    let mut file = vfat.open_file("/HUGE.BIN").expect("open file");
+    file.seek(io::SeekFrom::End(0)).expect("seek to end");
+    let e = file.write(&[0; 20]).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(file.size(), u32::max_value() as u64 - 10);
+    assert!(!file.dirty);
+}
+
+fn entry_names<T: Dir>(dir: T) -> Vec<String> {
+    let mut names: Vec<String> = dir
+        .entries()
+        .expect("entries interator")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_create_and_remove() {
+    let vfat = vfat_from_image(mock_fat32_image());
+
+    let dir = vfat.create_dir("/LOGS").expect("create dir");
+    assert_eq!(entry_names(dir), vec![".", ".."]);
+
+    let mut file = vfat.create_file("/LOGS/BOOT.LOG").expect("create file");
+    file.write_all(b"booted").expect("write file");
+    file.sync().expect("sync file");
+
+    let e = vfat.create_file("/LOGS/BOOT.LOG").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["LOGS"]);
+    assert_eq!(entry_names(vfat.open_dir("/LOGS").unwrap()), vec![".", "..", "BOOT.LOG"]);
+    assert_eq!(read_to_vec(vfat.open_file("/logs/boot.log").unwrap()), b"booted");
+
+    let e = vfat.remove("/LOGS").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+
+    vfat.remove("/LOGS/BOOT.LOG").expect("remove file");
+    vfat.remove("/LOGS").expect("remove dir");
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), Vec::<String>::new());
+    expect_variant!(vfat.open("/LOGS"), Err(ref e) if e.kind() == io::ErrorKind::NotFound);
+
+    // Allocation resumes after the last cluster handed out (the FSInfo next
+    // free hint) rather than reusing the clusters that were just freed.
+    let dir = vfat.create_dir("/TMP").expect("create dir");
+    assert_eq!(dir.cluster.0, 5);
+}
+
+#[test]
+fn test_rename() {
+    let vfat = vfat_from_image(mock_fat32_image());
+
+    vfat.create_dir("/A").expect("create dir");
+    vfat.create_dir("/B").expect("create dir");
+    let mut file = vfat.create_file("/A/DATA.BIN").expect("create file");
+    file.write_all(&[7; 700]).expect("write file");
+    file.sync().expect("sync file");
+
+    vfat.rename("/A/DATA.BIN", "/B/MOVED.BIN").expect("move file");
+    assert_eq!(entry_names(vfat.open_dir("/A").unwrap()), vec![".", ".."]);
+    assert_eq!(read_to_vec(vfat.open_file("/B/MOVED.BIN").unwrap()), vec![7; 700]);
+
+    vfat.rename("/B", "/A/C").expect("move dir");
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["A"]);
+    assert_eq!(read_to_vec(vfat.open_file("/A/C/MOVED.BIN").unwrap()), vec![7; 700]);
+
+    let a = vfat.open_dir("/A").unwrap();
+    let dot_dot = vfat.open_dir("/A/C").unwrap().find("..").unwrap();
+    assert_eq!(dot_dot.cluster(), a.cluster);
+
+    let e = vfat.rename("/A", "/A/C/D").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    // Names are case-insensitive: `/a/c` is still inside of `/A`.
+    let e = vfat.rename("/A", "/a/c/D").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    let e = vfat.rename("/A/C", "/a/c/D").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(entry_names(vfat.open_dir("/A/C").unwrap()), vec![".", "..", "MOVED.BIN"]);
+}
+
+#[test]
+fn test_sync_after_remove() {
+    let vfat = vfat_from_image(mock_fat32_image());
+    let mut file = vfat.create_file("/A.TXT").expect("create file");
+    file.write_all(&[1; 700]).expect("write file");
+    file.sync().expect("sync file");
+
+    // A dirty handle to a removed file must not write into the deleted slot,
+    // nor into the entry that reuses it.
+    file.write_all(&[2; 700]).expect("grow file");
+    vfat.remove("/A.TXT").expect("remove file");
+    assert_eq!(file.sync().unwrap_err().kind(), io::ErrorKind::NotFound);
+    let mut other = vfat.create_file("/B.TXT").expect("create file");
+    other.write_all(b"other").expect("write file");
+    other.sync().expect("sync file");
+    assert_eq!(other.entry, file.entry);
+
+    assert_eq!(file.sync().unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(file.set_len(0).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(file.write(b"x").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(read_to_vec(vfat.open_file("/B.TXT").unwrap()), b"other");
+    assert_eq!(check::check(&vfat, check::Mode::ReadOnly).expect("check"), vec![]);
+}
+
+#[test]
+fn test_sync_after_rename() {
+    let vfat = vfat_from_image(mock_fat32_image());
+    let mut file = vfat.create_file("/A.TXT").expect("create file");
+    file.write_all(&[1; 700]).expect("write file");
+    file.sync().expect("sync file");
+
+    file.write_all(&[2; 700]).expect("grow file");
+    vfat.rename("/A.TXT", "/B.TXT").expect("rename file");
+    vfat.create_file("/C.TXT").expect("create file");
+    assert_eq!(file.sync().unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    // Neither the renamed entry nor the one in the old slot changed.
+    assert_eq!(read_to_vec(vfat.open_file("/B.TXT").unwrap()), vec![1; 700]);
+    let other = vfat.open_file("/C.TXT").unwrap();
+    assert_eq!((other.size(), other.cluster), (0, Cluster(0)));
+}
+
+#[test]
+fn test_remove_unreadable_dir() {
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"SUB        ", 3, 0);
+    image[(MOCK_PARTITION_START + MOCK_DATA_START) * 512 + 11] = 0x10;
+
+    // Past `.` and `..`, the first cluster only holds deleted entries, and
+    // the chain then runs out of range.
+    let offset = mock_cluster_offset(3);
+    image[offset..offset + 11].copy_from_slice(b".          ");
+    image[offset + 32..offset + 43].copy_from_slice(b"..         ");
+    image[offset + 11] = 0x10;
+    image[offset + 43] = 0x10;
+    for slot in 2..16 {
+        image[offset + slot * 32] = 0xE5;
+    }
+    mock_fat_entry(&mut image, 3, 70000);
+
+    let vfat = vfat_from_image(image);
+    assert!(vfat.open_dir("/SUB").unwrap().is_empty().is_err());
+    assert!(vfat.remove("/SUB").is_err());
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["SUB"]);
+}
+
+fn raw_root_dir(vfat: &StdVFatHandle) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    vfat.lock(|vfat| vfat.read_all_chain(vfat.root_dir_cluster, &mut bytes))
+        .expect("read root directory");
+    bytes
+}
+
+#[test]
+fn test_create_long_file_names() {
+    let vfat = vfat_from_image(mock_fat32_image());
+
+    let long_name = "A rather long file name for testing.text";
+    vfat.create_file("/Hello World.txt").expect("create file");
+    vfat.create_file("/hello world!.txt").expect("create file");
+    vfat.create_file("/README").expect("create file");
+    vfat.create_file("/notes.md").expect("create file");
+    vfat.create_dir(format!("/{}", long_name)).expect("create dir");
+
+    let mut expected = vec!["Hello World.txt", "hello world!.txt", "README", "notes.md", long_name];
+    expected.sort();
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), expected);
+    vfat.open_file("/HELLO WORLD.TXT").expect("case-insensitive lookup");
+
+    let bytes = raw_root_dir(&vfat);
+    let slots: Vec<&[u8]> = bytes.chunks(32).collect();
+
+    // "Hello World.txt": 2 LFN entries stored last-first, then the alias.
+    assert_eq!(&slots[2][..11], b"HELLOW~1TXT");
+    let checksum = vfat::dir::lfn_checksum(b"HELLOW~1TXT");
+    assert_eq!((slots[0][0], slots[0][11], slots[0][13]), (0x42, 0x0F, checksum));
+    assert_eq!((slots[1][0], slots[1][11], slots[1][13]), (0x01, 0x0F, checksum));
+
+    // A second name with the same basis gets the next numeric tail.
+    assert_eq!(&slots[5][..11], b"HELLOW~2TXT");
+
+    // Exact 8.3 names need no long file name entries.
+    assert_eq!(&slots[6][..11], b"README     ");
+
+    // Case-only differences keep the basis without a tail.
+    assert_eq!(slots[7][11], 0x0F);
+    assert_eq!(&slots[8][..11], b"NOTES   MD ");
+
+    // 40 characters take 4 LFN entries; the last is NUL-terminated and padded.
+    assert_eq!(slots[9][0], 0x44);
+    assert_eq!(&slots[13][..11], b"ARATHE~1TEX");
+    assert_eq!(&slots[9][1..5], &[b't', 0, 0, 0]);
+    assert_eq!(&slots[9][5..7], &[0xFF, 0xFF]);
+}
+
+fn mock_fat_entry(image: &mut [u8], cluster: u32, value: u32) {
+    for fat in 0..2 {
+        let start = (MOCK_PARTITION_START + MOCK_RESERVED_SECTORS + fat * MOCK_SECTORS_PER_FAT) * 512;
+        write_u32(image, start + cluster as usize * 4, value);
+    }
+}
+
+fn mock_cluster_offset(cluster: u32) -> usize {
+    (MOCK_PARTITION_START + MOCK_DATA_START + cluster as usize - 2) * 512
+}
+
+/// Builds an image holding `FRAG.BIN`, a file whose chain is scattered
+/// across the data region, and returns it with the file's expected contents.
+fn mock_fragmented_image() -> (Vec<u8>, Vec<u8>) {
+    let chain = [10u32, 5, 20, 6, 7];
+    let size = chain.len() * 512 - 100;
+    let data: Vec<u8> = (0..size as u32).map(|i| (i * 7 % 253) as u8).collect();
+
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"FRAG    BIN", chain[0], size as u32);
+    for (i, &cluster) in chain.iter().enumerate() {
+        let next = chain.get(i + 1).cloned().unwrap_or(0x0FFFFFFF);
+        mock_fat_entry(&mut image, cluster, next);
+
+        let chunk = &data[i * 512..core::cmp::min(size, (i + 1) * 512)];
+        let offset = mock_cluster_offset(cluster);
+        image[offset..offset + chunk.len()].copy_from_slice(chunk);
+    }
+
+    (image, data)
+}
+
+#[test]
+fn test_read_fragmented_file() {
+    let (image, data) = mock_fragmented_image();
+    let vfat = vfat_from_image(image);
+
+    let file = vfat.open_file("/FRAG.BIN").expect("open file");
+    assert_eq!(file.size(), data.len() as u64);
+    assert_eq!(read_to_vec(file), data);
+
+    let mut file = vfat.open_file("/FRAG.BIN").expect("reopen file");
+    for &offset in &[0usize, 1, 511, 512, 1000, 1536, 2047, 2300] {
+        file.seek(io::SeekFrom::Start(offset as u64)).expect("seek");
+        let mut buf = [0u8; 300];
+        let read = file.read(&mut buf).expect("read at offset");
+        let expected = &data[offset..core::cmp::min(data.len(), offset + 300)];
+        assert_eq!(&buf[..read], expected, "read at offset {}", offset);
+    }
+
+    file.seek(io::SeekFrom::End(0)).expect("seek to end");
+    assert_eq!(file.read(&mut [0u8; 16]).expect("read at end"), 0);
+}
+
+#[test]
+fn test_file_extents() {
+    let (image, _) = mock_fragmented_image();
+    let vfat = vfat_from_image(image);
+
+    let extents = vfat
+        .lock(|vfat| vfat.chain_extents(Cluster::from(10)))
+        .expect("chain extents");
+    assert_eq!(
+        extents,
+        vec![
+            Extent { start: Cluster::from(10), len: 1 },
+            Extent { start: Cluster::from(5), len: 1 },
+            Extent { start: Cluster::from(20), len: 1 },
+            Extent { start: Cluster::from(6), len: 2 },
+        ]
+    );
+}
+
+/// A block device whose storage outlives the `VFat` or `CachedPartition`
+/// that owns it, so tests can observe what was actually written back.
+/// Every read request it serves is counted; see `reads()`.
+#[derive(Clone)]
+struct SharedDevice(Arc<Mutex<Vec<u8>>>, Arc<Mutex<usize>>);
+
+impl SharedDevice {
+    fn new(image: Vec<u8>) -> SharedDevice {
+        SharedDevice(Arc::new(Mutex::new(image)), Arc::new(Mutex::new(0)))
+    }
+
+    fn reads(&self) -> usize {
+        *self.1.lock().unwrap()
+    }
+
+    fn sector(&self, n: usize) -> Vec<u8> {
+        self.0.lock().unwrap()[n * 512..(n + 1) * 512].to_vec()
+    }
+}
+
+impl BlockDevice for SharedDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len(), 512);
+        self.read_sectors(n, &mut buf[..len])
+    }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        *self.1.lock().unwrap() += 1;
+        let data = self.0.lock().unwrap();
+        let start = n as usize * 512;
+        buf.copy_from_slice(&data[start..start + buf.len()]);
+        Ok(buf.len())
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.0.lock().unwrap();
+        let start = n as usize * 512;
+        let len = std::cmp::min(buf.len(), 512);
+        data[start..start + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+fn mock_cached_partition(device: SharedDevice, capacity: usize) -> CachedPartition {
+    let partition = Partition { start: 1, num_sectors: 8, sector_size: 512 };
+    CachedPartition::with_capacity(device, partition, capacity)
+}
+
+#[test]
+fn test_cache_lru_eviction() {
+    let device = SharedDevice::new(vec![0u8; 512 * 9]);
+    let mut cache = mock_cached_partition(device, 2);
+    assert_eq!(cache.capacity(), 2);
+
+    cache.get(0).expect("read sector 0");
+    cache.get(1).expect("read sector 1");
+    cache.get(0).expect("read sector 0 again");
+    cache.get(2).expect("read sector 2");
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 3, evictions: 1 });
+
+    // Sector 1 was the least recently used, so sector 0 is still cached.
+    cache.get(0).expect("read sector 0 after eviction");
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 3, evictions: 1 });
+    cache.get(1).expect("read sector 1 after eviction");
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 4, evictions: 2 });
+
+    cache.set_capacity(1).expect("shrink cache");
+    assert_eq!(cache.stats().evictions, 3);
+    assert!(cache.get(8).is_err());
+}
+
+#[test]
+fn test_cache_lru_after_many_hits() {
+    let device = SharedDevice::new(vec![0u8; 512 * 9]);
+    let mut cache = mock_cached_partition(device, 3);
+
+    for sector in 0..3 {
+        cache.get(sector).expect("fill cache");
+    }
+    for _ in 0..100 {
+        cache.get(0).expect("hit sector 0");
+        cache.get(2).expect("hit sector 2");
+    }
+
+    // Sector 1 is the least recently used, however long ago it was read.
+    cache.get(3).expect("read sector 3");
+    cache.get(0).expect("read sector 0");
+    cache.get(2).expect("read sector 2");
+    assert_eq!(cache.stats().misses, 4);
+    cache.get(1).expect("read sector 1");
+    assert_eq!(cache.stats(), CacheStats { hits: 202, misses: 5, evictions: 2 });
+}
+
+#[test]
+fn test_cache_write_back() {
+    let device = SharedDevice::new(vec![0u8; 512 * 9]);
+    let mut cache = mock_cached_partition(device.clone(), 1);
+
+    cache.write_sector(0, &[0xAB; 512]).expect("write sector 0");
+    assert_eq!(device.sector(1), vec![0u8; 512]);
+
+    // Reading another sector evicts the dirty one, which must reach the disk.
+    cache.get(3).expect("read sector 3");
+    assert_eq!(device.sector(1), vec![0xABu8; 512]);
+
+    cache.write_sector(3, &[0xCD; 512]).expect("write sector 3");
+    assert_eq!(device.sector(4), vec![0u8; 512]);
+    cache.flush().expect("flush");
+    assert_eq!(device.sector(4), vec![0xCDu8; 512]);
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2, evictions: 1 });
+}
+
+#[test]
+fn test_file_sync_flushes_cache() {
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"HELLO   TXT", 0, 0);
+    let device = SharedDevice::new(image);
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+    vfat.lock(|vfat| vfat.set_cache_capacity(4)).expect("set cache capacity");
+
+    let data: Vec<u8> = (0..5000u32).map(|i| (i % 241) as u8).collect();
+    let mut file = vfat.open_file("/HELLO.TXT").expect("open file");
+    file.write_all(&data).expect("write file");
+    file.sync().expect("sync file");
+    assert!(vfat.lock(|vfat| vfat.cache_stats()).evictions > 0);
+
+    // A fresh volume over the same storage only sees what reached the disk.
+    let image = device.0.lock().unwrap().clone();
+    let file = vfat_from_image(image).open_file("/HELLO.TXT").expect("reopen file");
+    assert_eq!(file.size(), 5000);
+    assert_eq!(read_to_vec(file), data);
+}
+
+#[test]
+fn test_read_contiguous_file_in_one_request() {
+    let data: Vec<u8> = (0..4096u32).map(|i| (i % 239) as u8).collect();
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"BIG     BIN", 3, data.len() as u32);
+    for cluster in 3..11 {
+        let next = if cluster == 10 { 0x0FFFFFFF } else { cluster + 1 };
+        mock_fat_entry(&mut image, cluster, next);
+    }
+    let offset = mock_cluster_offset(3);
+    image[offset..offset + data.len()].copy_from_slice(&data);
+
+    let device = SharedDevice::new(image);
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+    let mut file = vfat.open_file("/BIG.BIN").expect("open file");
+
+    let reads = device.reads();
+    let mut buf = vec![0u8; data.len()];
+    file.read_exact(&mut buf).expect("read file");
+    assert_eq!(buf, data);
+    assert_eq!(device.reads() - reads, 1);
+
+    // Cached sectors are served from memory; only the uncached run is read.
+    let mut cache = mock_cached_partition(SharedDevice::new(vec![7u8; 512 * 9]), 8);
+    cache.get(2).expect("read sector 2");
+    let mut buf = vec![0u8; 512 * 4];
+    assert_eq!(cache.read_sectors(0, &mut buf).expect("read sectors"), 512 * 4);
+    assert_eq!(buf, vec![7u8; 512 * 4]);
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 4, evictions: 0 });
+}
+
+fn mock_fsinfo(device: &SharedDevice) -> (u32, u32) {
+    let sector = device.sector(MOCK_PARTITION_START + 1);
+    let read_u32 = |offset: usize| u32::from_le_bytes([sector[offset], sector[offset + 1], sector[offset + 2], sector[offset + 3]]);
+    (read_u32(488), read_u32(492))
+}
+
+#[test]
+fn test_statfs() {
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"HELLO   TXT", 0, 0);
+    let device = SharedDevice::new(image);
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.total_clusters, MOCK_NUM_CLUSTERS as u32);
+    assert_eq!(stats.free_clusters, MOCK_NUM_CLUSTERS as u32 - 1);
+    assert_eq!(stats.used_clusters, 1);
+    assert_eq!(stats.cluster_size, 512);
+    assert_eq!(stats.volume_label, "RUSTOS");
+    assert_eq!(stats.serial, 0x1234ABCD);
+    assert_eq!(stats.used_bytes(), 512);
+
+    let mut file = vfat.open_file("/HELLO.TXT").expect("open file");
+    file.write_all(&[1u8; 3000]).expect("write file");
+    file.sync().expect("sync file");
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.used_clusters, 7);
+    assert_eq!(mock_fsinfo(&device), (MOCK_NUM_CLUSTERS as u32 - 7, 9));
+
+    vfat.remove("/HELLO.TXT").expect("remove file");
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.used_clusters, 1);
+    assert_eq!(mock_fsinfo(&device), (MOCK_NUM_CLUSTERS as u32 - 1, 9));
+}
+
+#[test]
+fn test_statfs_counts_free_clusters() {
+    let mut image = mock_fat32_image();
+    let fsinfo = (MOCK_PARTITION_START + 1) * 512;
+    write_u32(&mut image, fsinfo + 488, 0xFFFFFFFF);
+    write_u32(&mut image, fsinfo + 492, 0xFFFFFFFF);
+    for cluster in 3..13 {
+        mock_fat_entry(&mut image, cluster, 0x0FFFFFFF);
+    }
+    let device = SharedDevice::new(image);
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.free_clusters, MOCK_NUM_CLUSTERS as u32 - 11);
+    vfat.lock(|vfat| vfat.flush()).expect("flush");
+    assert_eq!(mock_fsinfo(&device), (MOCK_NUM_CLUSTERS as u32 - 11, 2));
+
+    // The invalid hint falls back to the first data cluster.
+    let cluster = vfat.lock(|vfat| vfat.alloc_cluster(None)).expect("alloc cluster");
+    assert_eq!(cluster, Cluster::from(13));
+}
+
+fn mock_fat(device: &SharedDevice, fat: usize) -> Vec<u8> {
+    let start = MOCK_PARTITION_START + MOCK_RESERVED_SECTORS + fat * MOCK_SECTORS_PER_FAT;
+    (start..start + MOCK_SECTORS_PER_FAT).flat_map(|sector| device.sector(sector)).collect()
+}
+
+#[test]
+fn test_fat_mirroring() {
+    let mut image = mock_fat32_image();
+    mock_root_entry(&mut image, 0, b"HELLO   TXT", 0, 0);
+    let device = SharedDevice::new(image);
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+
+    let mut file = vfat.open_file("/HELLO.TXT").expect("open file");
+    file.write_all(&[1u8; 2000]).expect("write file");
+    file.sync().expect("sync file");
+    vfat.create_dir("/LOGS").expect("create dir");
+    vfat.remove("/HELLO.TXT").expect("remove file");
+
+    let (fat0, fat1) = (mock_fat(&device, 0), mock_fat(&device, 1));
+    assert_eq!(&fat0[12..28], &[0; 16][..]);
+    assert_eq!(&fat0[28..32], &[0xFF, 0xFF, 0xFF, 0x0F]);
+    assert!(fat0 == fat1, "FATs differ");
+}
+
+#[test]
+fn test_active_fat() {
+    // Mirroring is disabled and FAT 1 is the active FAT. FAT 0 claims that
+    // the root directory continues into cluster 3, which must be ignored.
+    let mut image = mock_fat32_image();
+    let ebpb = MOCK_PARTITION_START * 512;
+    write_u16(&mut image, ebpb + 40, 0x81);
+    let fat0 = (MOCK_PARTITION_START + MOCK_RESERVED_SECTORS) * 512;
+    write_u32(&mut image, fat0 + 8, 3);
+    mock_root_entry(&mut image, 0, b"HELLO   TXT", 0, 0);
+    let device = SharedDevice::new(image);
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+
+    let fat0_before = mock_fat(&device, 0);
+    let mut file = vfat.open_file("/HELLO.TXT").expect("open file");
+    file.write_all(&[1u8; 1000]).expect("write file");
+    file.sync().expect("sync file");
+
+    assert_eq!(mock_fat(&device, 0), fat0_before);
+    assert_eq!(&mock_fat(&device, 1)[12..20], &[4, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0x0F]);
+    assert_eq!(read_to_vec(vfat.open_file("/HELLO.TXT").unwrap()), vec![1u8; 1000]);
+}
+
+/// Builds an image whose root directory and FAT hold one of each problem
+/// `check` looks for.
+fn mock_corrupted_image() -> Vec<u8> {
+    let mut image = mock_fat32_image();
+    let chains: &[&[u32]] = &[&[3, 4], &[5, 6, 7], &[8], &[20, 21, 22]];
+    for chain in chains {
+        for (i, &cluster) in chain.iter().enumerate() {
+            mock_fat_entry(&mut image, cluster, chain.get(i + 1).cloned().unwrap_or(0x0FFFFFFF));
+        }
+    }
+    mock_fat_entry(&mut image, 10, 4);
+    mock_fat_entry(&mut image, 9, 70000);
+    mock_fat_entry(&mut image, 30, 31);
+    mock_fat_entry(&mut image, 31, 30);
+
+    mock_root_entry(&mut image, 0, b"GOOD    TXT", 3, 600);
+    mock_root_entry(&mut image, 1, b"CROSS   TXT", 10, 1024);
+    mock_root_entry(&mut image, 2, b"LONG    TXT", 5, 100);
+    mock_root_entry(&mut image, 3, b"SHORT   TXT", 8, 2000);
+    mock_root_entry(&mut image, 4, b"BAD     TXT", 9, 1000);
+
+    // A long file name entry whose checksum belongs to another 8.3 name.
+    let lfn = (MOCK_PARTITION_START + MOCK_DATA_START) * 512 + 5 * 32;
+    image[lfn] = 0x41;
+    for (i, &c) in b"lfn.txt".iter().enumerate() {
+        image[lfn + 1 + 2 * i] = c;
+    }
+    image[lfn + 11] = 0x0F;
+    image[lfn + 13] = vfat::dir::lfn_checksum(b"OTHER   TXT");
+    mock_root_entry(&mut image, 6, b"LFN     TXT", 0, 0);
+
+    image
+}
+
+#[test]
+fn test_check() {
+    let vfat = vfat_from_image(mock_corrupted_image());
+    let path = |path: &str| String::from(path);
+    let expected = vec![
+        check::Problem::CrossLinked { path: path("/CROSS.TXT"), other: path("/GOOD.TXT"), cluster: 4 },
+        check::Problem::ChainLength { path: path("/CROSS.TXT"), clusters: 1, expected: 2 },
+        check::Problem::ChainLength { path: path("/LONG.TXT"), clusters: 3, expected: 1 },
+        check::Problem::ChainLength { path: path("/SHORT.TXT"), clusters: 1, expected: 4 },
+        check::Problem::InvalidCluster { path: path("/BAD.TXT"), cluster: 70000 },
+        check::Problem::ChainLength { path: path("/BAD.TXT"), clusters: 1, expected: 2 },
+        check::Problem::BadLfnChecksum { path: path("/LFN.TXT") },
+        check::Problem::LostChain { start: 20, len: 3 },
+        check::Problem::LostChain { start: 30, len: 2 },
+    ];
+    assert_eq!(check::check(&vfat, check::Mode::ReadOnly).expect("check"), expected);
+    assert_eq!(check::check(&vfat, check::Mode::ReadOnly).expect("check again"), expected);
+
+    assert_eq!(check::check(&vfat, check::Mode::Repair).expect("repair"), expected);
+    assert_eq!(check::check(&vfat, check::Mode::ReadOnly).expect("check repaired"), vec![]);
+
+    assert_eq!(vfat.open_file("/SHORT.TXT").unwrap().size(), 512);
+    assert_eq!(vfat.open_file("/CROSS.TXT").unwrap().size(), 512);
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.used_clusters, 7);
+}
+
+#[test]
+fn test_check_clean_volume() {
+    let vfat = vfat_from_image(mock_fat32_image());
+    vfat.create_dir("/LOGS").expect("create dir");
+    let mut file = vfat.create_file("/LOGS/A rather long name.txt").expect("create file");
+    file.write_all(&[7u8; 1500]).expect("write file");
+    file.sync().expect("sync file");
+    vfat.create_file("/EMPTY").expect("create file");
+
+    assert_eq!(check::check(&vfat, check::Mode::ReadOnly).expect("check"), vec![]);
+}
+
+const MOCK_GPT_PARTITION_START: usize = 64;
+
+/// Wraps the FAT32 volume of `mock_fat32_image()` in a GUID partition table:
+/// a protective MBR, the GPT header at LBA 1 and 128 partition entries
+/// starting at LBA 2, the second of which is the volume.
+fn mock_gpt_image() -> Vec<u8> {
+    let volume = mock_fat32_image().split_off(MOCK_PARTITION_START * 512);
+    let volume_sectors = volume.len() / 512;
+    let mut image = vec![0u8; MOCK_GPT_PARTITION_START * 512];
+    image.extend_from_slice(&volume);
+
+    let total_sectors = image.len() / 512;
+    image[446 + 4] = 0xEE;
+    write_u32(&mut image, 446 + 8, 1);
+    write_u32(&mut image, 446 + 12, (total_sectors - 1) as u32);
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    // An EFI system partition without a FAT32 boot sector comes first.
+    let entries = 2 * 512;
+    image[entries..entries + 16].copy_from_slice(&gpt::EFI_SYSTEM_PARTITION);
+    write_u32(&mut image, entries + 32, 34);
+    write_u32(&mut image, entries + 40, 40);
+    image[entries + 128..entries + 144].copy_from_slice(&gpt::BASIC_DATA_PARTITION);
+    write_u32(&mut image, entries + 128 + 32, MOCK_GPT_PARTITION_START as u32);
+    write_u32(&mut image, entries + 128 + 40, (MOCK_GPT_PARTITION_START + volume_sectors - 1) as u32);
+    let entries_crc = gpt::crc32(&image[entries..entries + 128 * 128]);
+
+    let header = 512;
+    image[header..header + 8].copy_from_slice(b"EFI PART");
+    write_u32(&mut image, header + 8, 0x00010000);
+    write_u32(&mut image, header + 12, 92);
+    write_u32(&mut image, header + 24, 1);
+    write_u32(&mut image, header + 40, 34);
+    write_u32(&mut image, header + 72, 2);
+    write_u32(&mut image, header + 80, 128);
+    write_u32(&mut image, header + 84, 128);
+    write_u32(&mut image, header + 88, entries_crc);
+    let header_crc = gpt::crc32(&image[header..header + 92]);
+    write_u32(&mut image, header + 16, header_crc);
+
+    image
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(gpt::crc32(b""), 0);
+    assert_eq!(gpt::crc32(b"123456789"), 0xCBF43926);
+}
+
+#[test]
+fn test_gpt() {
+    let mut image = mock_gpt_image();
+    let gpt = gpt::GuidPartitionTable::from(Cursor::new(&mut image[..])).expect("valid GPT");
+    assert_eq!(gpt.partitions.len(), 2);
+    assert_eq!(gpt.partitions[1].type_guid, gpt::BASIC_DATA_PARTITION);
+    assert_eq!({ gpt.partitions[1].first_lba }, MOCK_GPT_PARTITION_START as u64);
+
+    let mut image = mock_gpt_image();
+    mock_root_entry_at(&mut image, MOCK_GPT_PARTITION_START, 0, b"HELLO   TXT", 0, 0);
+    let vfat = vfat_from_image(image);
+    let mut file = vfat.open_file("/HELLO.TXT").expect("open file on GPT volume");
+    file.write_all(b"hello, gpt").expect("write file");
+    file.sync().expect("sync file");
+    assert_eq!(read_to_vec(vfat.open_file("/HELLO.TXT").unwrap()), b"hello, gpt");
+}
+
+#[test]
+fn test_gpt_bad_crc() {
+    let mut image = mock_gpt_image();
+    image[512 + 56] ^= 1;
+    let e = gpt::GuidPartitionTable::from(Cursor::new(&mut image[..])).unwrap_err();
+    expect_variant!(e, gpt::Error::BadHeaderCrc);
+    expect_variant!(VFat::<StdVFatHandle>::from(Cursor::new(image)), Err(vfat::Error::Gpt(gpt::Error::BadHeaderCrc)));
+
+    let mut image = mock_gpt_image();
+    image[2 * 512 + 128 + 32] ^= 1;
+    let e = gpt::GuidPartitionTable::from(Cursor::new(&mut image[..])).unwrap_err();
+    expect_variant!(e, gpt::Error::BadEntriesCrc);
+
+    let mut image = mock_gpt_image();
+    image[512] = b'X';
+    let e = gpt::GuidPartitionTable::from(Cursor::new(&mut image[..])).unwrap_err();
+    expect_variant!(e, gpt::Error::BadSignature);
+}
+
+/// Builds a FAT12 or FAT16 image, depending on `num_clusters`, with one
+/// sector per cluster and a fixed root directory of `root_entries` entries.
+fn mock_small_fat_image(num_clusters: usize, root_entries: usize) -> Vec<u8> {
+    let fat16 = num_clusters >= 4085;
+    let fat_bytes = if fat16 { (num_clusters + 2) * 2 } else { (num_clusters + 2) * 3 / 2 + 1 };
+    let sectors_per_fat = (fat_bytes + 511) / 512;
+    let root_sectors = root_entries * 32 / 512;
+    let total_sectors = 1 + 2 * sectors_per_fat + root_sectors + num_clusters;
+    let mut image = vec![0u8; (MOCK_PARTITION_START + total_sectors) * 512];
+
+    image[446 + 4] = if fat16 { 0x06 } else { 0x01 };
+    write_u32(&mut image, 446 + 8, MOCK_PARTITION_START as u32);
+    write_u32(&mut image, 446 + 12, total_sectors as u32);
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let bpb = MOCK_PARTITION_START * 512;
+    image[bpb..bpb + 3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    write_u16(&mut image, bpb + 11, 512);
+    image[bpb + 13] = 1;
+    write_u16(&mut image, bpb + 14, 1);
+    image[bpb + 16] = 2;
+    write_u16(&mut image, bpb + 17, root_entries as u16);
+    write_u16(&mut image, bpb + 19, total_sectors as u16);
+    image[bpb + 21] = 0xF8;
+    write_u16(&mut image, bpb + 22, sectors_per_fat as u16);
+    image[bpb + 38] = 0x29;
+    write_u32(&mut image, bpb + 39, 0xCAFEF00D);
+    image[bpb + 43..bpb + 54].copy_from_slice(b"SMALL      ");
+    image[bpb + 510..bpb + 512].copy_from_slice(&[0x55, 0xAA]);
+
+    for fat in 0..2 {
+        let start = (MOCK_PARTITION_START + 1 + fat * sectors_per_fat) * 512;
+        let reserved: &[u8] = if fat16 { &[0xF8, 0xFF, 0xFF, 0xFF] } else { &[0xF8, 0xFF, 0xFF] };
+        image[start..start + reserved.len()].copy_from_slice(reserved);
+    }
+
+    image
+}
+
+fn exercise_small_fat(vfat: &StdVFatHandle) {
+    let data: Vec<u8> = (0..5000u32).map(|i| (i % 233) as u8).collect();
+    let mut file = vfat.create_file("/Some notes.txt").expect("create file in root");
+    file.write_all(&data).expect("write file");
+    file.sync().expect("sync file");
+
+    vfat.create_dir("/LOGS").expect("create dir");
+    let mut file = vfat.create_file("/LOGS/BOOT.LOG").expect("create file in dir");
+    file.write_all(b"booted").expect("write file");
+    file.sync().expect("sync file");
+
+    assert_eq!(entry_names(vfat.open_dir("/").unwrap()), vec!["LOGS", "Some notes.txt"]);
+    assert_eq!(entry_names(vfat.open_dir("/LOGS").unwrap()), vec![".", "..", "BOOT.LOG"]);
+    assert_eq!(read_to_vec(vfat.open_file("/Some notes.txt").unwrap()), data);
+    assert_eq!(read_to_vec(vfat.open_file("/LOGS/BOOT.LOG").unwrap()), b"booted");
+    assert_eq!(check::check(vfat, check::Mode::ReadOnly).expect("check"), vec![]);
+
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.used_clusters, 10 + 1 + 1);
+    assert_eq!(stats.volume_label, "SMALL");
+    assert_eq!(stats.serial, 0xCAFEF00D);
+
+    vfat.remove("/Some notes.txt").expect("remove file");
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.used_clusters, 2);
+}
+
+#[test]
+fn test_fat16() {
+    let vfat = vfat_from_image(mock_small_fat_image(5000, 512));
+    assert_eq!(vfat.lock(|vfat| vfat.fat_type), vfat::FatType::Fat16);
+    exercise_small_fat(&vfat);
+}
+
+#[test]
+fn test_fat12() {
+    let device = SharedDevice::new(mock_small_fat_image(1000, 16));
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+    assert_eq!(vfat.lock(|vfat| vfat.fat_type), vfat::FatType::Fat12);
+    exercise_small_fat(&vfat);
+
+    // Entries are packed 12 bits apiece: clusters 2 to 11 were freed with the
+    // removed file, while 12 and 13 hold `/LOGS` and its file.
+    vfat.lock(|vfat| vfat.flush()).expect("flush");
+    let fat = device.sector(MOCK_PARTITION_START + 1);
+    assert_eq!(&fat[..6], &[0xF8, 0xFF, 0xFF, 0x00, 0x00, 0x00]);
+    assert_eq!(&fat[15..21], &[0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF]);
+
+    // The fixed root directory region has room for 16 entries only.
+    for i in 0..15 {
+        vfat.create_file(format!("/F{}", i)).expect("create file in root");
+    }
+    let e = vfat.create_file("/ONE.MOR").unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::Other);
+}
+
+/// Builds an image with a FAT12 primary partition, a Linux primary partition
+/// and an extended partition holding two FAT16 logical partitions, and
+/// returns it along with the partitions in the order `partitions()` lists them.
+fn mock_partitioned_image() -> (Vec<u8>, Vec<partition::PartitionInfo>) {
+    let volume = |num_clusters| mock_small_fat_image(num_clusters, 512).split_off(MOCK_PARTITION_START * 512);
+    let (fat12, first_logical, second_logical) = (volume(1000), volume(5000), volume(6000));
+    let linux = vec![0u8; 8 * 512];
+
+    let info = |partition_type, start: usize, volume: &[u8]| partition::PartitionInfo {
+        partition_type: partition::PartitionType::Mbr(partition_type),
+        start: start as u64,
+        num_sectors: (volume.len() / 512) as u64,
+    };
+    let mut partitions = vec![info(0x01, 1, &fat12)];
+    partitions.push(info(0x83, partitions[0].start as usize + fat12.len() / 512, &linux));
+    let extended = partitions[1].start as usize + linux.len() / 512;
+    let second_ebr = extended + 1 + first_logical.len() / 512;
+    partitions.push(info(0x06, extended + 1, &first_logical));
+    partitions.push(info(0x06, second_ebr + 1, &second_logical));
+
+    let mut image = vec![0u8; 512];
+    for (start, volume) in [(1, &fat12), (partitions[1].start as usize, &linux)].iter() {
+        image.resize(start * 512, 0);
+        image.extend_from_slice(volume);
+    }
+    for (ebr, volume) in [(extended, &first_logical), (second_ebr, &second_logical)].iter() {
+        image.resize(ebr * 512, 0);
+        image.extend_from_slice(&[0u8; 512]);
+        image.extend_from_slice(volume);
+    }
+    let extended_sectors = image.len() / 512 - extended;
+
+    let mut entry = |sector: usize, index: usize, partition_type: u8, start: usize, num_sectors: usize| {
+        let entry = sector * 512 + 446 + index * 16;
+        image[entry + 4] = partition_type;
+        write_u32(&mut image, entry + 8, start as u32);
+        write_u32(&mut image, entry + 12, num_sectors as u32);
+        image[sector * 512 + 510..sector * 512 + 512].copy_from_slice(&[0x55, 0xAA]);
+    };
+    entry(0, 0, 0x01, 1, fat12.len() / 512);
+    entry(0, 1, 0x83, partitions[1].start as usize, linux.len() / 512);
+    entry(0, 2, 0x0F, extended, extended_sectors);
+
+    // Logical partitions are relative to their EBR, the next EBR is relative
+    // to the extended partition.
+    entry(extended, 0, 0x06, 1, first_logical.len() / 512);
+    entry(extended, 1, 0x05, second_ebr - extended, second_logical.len() / 512 + 1);
+    entry(second_ebr, 0, 0x06, 1, second_logical.len() / 512);
+
+    (image, partitions)
+}
+
+#[test]
+fn test_partitions() {
+    let (mut image, expected) = mock_partitioned_image();
+    let partitions = partition::partitions(Cursor::new(&mut image[..])).expect("partitions");
+    assert_eq!(partitions, expected);
+
+    let mut image = mock_gpt_image();
+    let partitions = partition::partitions(Cursor::new(&mut image[..])).expect("partitions");
+    assert_eq!(partitions.len(), 2);
+    assert_eq!(partitions[1].partition_type, partition::PartitionType::Gpt(gpt::BASIC_DATA_PARTITION));
+    assert_eq!(partitions[1].start, MOCK_GPT_PARTITION_START as u64);
+
+    // An EBR that links back to itself.
+    let (mut image, expected) = mock_partitioned_image();
+    let second_ebr = expected[3].start as usize - 1;
+    let extended = expected[2].start as usize - 1;
+    image[second_ebr * 512 + 446 + 16 + 4] = 0x05;
+    write_u32(&mut image, second_ebr * 512 + 446 + 16 + 8, (second_ebr - extended) as u32);
+    let e = partition::partitions(Cursor::new(&mut image[..])).unwrap_err();
+    expect_variant!(e, partition::Error::Mbr(mbr::Error::BadExtendedPartition));
+
+    // A logical partition that ends past its extended partition.
+    let (mut image, _) = mock_partitioned_image();
+    write_u32(&mut image, second_ebr * 512 + 446 + 12, expected[3].num_sectors as u32 + 1);
+    let e = partition::partitions(Cursor::new(&mut image[..])).unwrap_err();
+    expect_variant!(e, partition::Error::Mbr(mbr::Error::BadExtendedPartition));
+}
+
+#[test]
+fn test_mount_each_partition() {
+    let (image, partitions) = mock_partitioned_image();
+    let device = SharedDevice::new(image);
+
+    // The first FAT partition is mounted, not the last.
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+    assert_eq!(vfat.lock(|vfat| vfat.fat_type), vfat::FatType::Fat12);
+
+    let total_clusters = |index| {
+        let vfat = VFat::<StdVFatHandle>::from_partition(device.clone(), index).expect("mount partition");
+        vfat.lock(|vfat| vfat.statfs()).expect("statfs").total_clusters
+    };
+    assert_eq!(total_clusters(0), 1000);
+    assert_eq!(total_clusters(2), 5000);
+    assert_eq!(total_clusters(3), 6000);
+    expect_variant!(VFat::<StdVFatHandle>::from_partition(device.clone(), 1), Err(_));
+    expect_variant!(VFat::<StdVFatHandle>::from_partition(device.clone(), 4), Err(vfat::Error::NotFound));
+
+    // Volumes are independent of one another.
+    let first = VFat::<StdVFatHandle>::from_partition(device.clone(), 2).expect("mount partition");
+    exercise_small_fat(&first);
+    let second = VFat::<StdVFatHandle>::from_partition(device.clone(), 3).expect("mount partition");
+    assert!(entry_names(second.open_dir("/").unwrap()).is_empty());
+    assert_eq!(check::check(&second, check::Mode::ReadOnly).expect("check"), vec![]);
+    assert!(entry_names(vfat.open_dir("/").unwrap()).is_empty());
+    assert_eq!(partition::partitions(device.clone()).expect("partitions"), partitions);
+}
+
+#[test]
+fn test_partition_device() {
+    let (image, partitions) = mock_partitioned_image();
+    let mut device = partition::open(Cursor::new(image), 1).expect("open partition");
+    assert_eq!(device.partition(), partitions[1]);
+
+    let mut buf = [0u8; 1024];
+    device.write_sector(7, &[0xAB; 512]).expect("write last sector");
+    assert_eq!(device.read_sector(7, &mut buf[..512]).expect("read last sector"), 512);
+    assert_eq!(&buf[..512], &[0xAB; 512][..]);
+    assert_eq!(device.read_sector(8, &mut buf).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(device.write_sector(8, &buf).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(device.read_sectors(7, &mut buf).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(device.read_sectors(6, &mut buf).expect("read sectors"), 1024);
+
+    let image = device.into_inner().into_inner();
+    let start = partitions[1].start as usize * 512;
+    assert_eq!(&image[start + 7 * 512..start + 8 * 512], &[0xAB; 512][..]);
+    assert!(image[start + 8 * 512..start + 9 * 512].iter().all(|&b| b != 0xAB));
+
+    expect_variant!(partition::open(Cursor::new(image), 4), Err(partition::Error::NotFound));
+}
+
+/// A device that only stores the sectors written to it, for volumes too
+/// large to keep in memory. Unwritten sectors read as zeroes.
+#[derive(Default)]
+struct SparseDevice(std::collections::HashMap<u64, Vec<u8>>);
+
+impl BlockDevice for SparseDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len(), 512);
+        match self.0.get(&n) {
+            Some(sector) => buf[..len].copy_from_slice(&sector[..len]),
+            None => buf[..len].iter_mut().for_each(|b| *b = 0),
+        }
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len(), 512);
+        let sector = self.0.entry(n).or_insert_with(|| vec![0; 512]);
+        sector[..len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_mkfs() {
+    let num_sectors = 2048 + 70000;
+    let mut device = Cursor::new(vec![0u8; num_sectors * 512]);
+    let options = mkfs::Options {
+        volume_label: "scratch".into(),
+        serial: 0xCAFEF00D,
+        ..Default::default()
+    };
+    mkfs::format(&mut device, num_sectors as u64, &options).expect("format");
+
+    let image = device.into_inner();
+    assert_eq!(image[446 + 4], mkfs::FAT32_LBA_PARTITION_TYPE);
+    assert_eq!(&image[446 + 8..446 + 12], &2048u32.to_le_bytes());
+
+    let vfat = vfat_from_image(image);
+    assert_eq!(vfat.lock(|vfat| vfat.fat_type), vfat::FatType::Fat32);
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.cluster_size, 512);
+    assert_eq!(stats.total_clusters, 68890);
+    assert_eq!(stats.free_clusters, stats.total_clusters - 1);
+    assert_eq!(stats.volume_label, "SCRATCH");
+    assert_eq!(stats.serial, 0xCAFEF00D);
+    assert!(entry_names(vfat.open_dir("/").unwrap()).is_empty());
+
+    vfat.create_dir("/LOGS").expect("create dir");
+    let mut file = vfat.create_file("/LOGS/boot.log").expect("create file");
+    file.write_all(&[3u8; 5000]).expect("write file");
+    file.sync().expect("sync file");
+    assert_eq!(read_to_vec(vfat.open_file("/LOGS/boot.log").unwrap()), vec![3u8; 5000]);
+    assert_eq!(check::check(&vfat, check::Mode::ReadOnly).expect("check"), vec![]);
+}
+
+#[test]
+fn test_mkfs_cluster_size() {
+    // 1 GiB, too large for a `Vec` but not for a sparse device.
+    let num_sectors = 2 * 1024 * 1024;
+    let mut device = SparseDevice::default();
+    let options = mkfs::Options { cluster_size: 4096, ..Default::default() };
+    mkfs::format(&mut device, num_sectors, &options).expect("format");
+
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("failed to initialize VFAT");
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.cluster_size, 4096);
+    assert_eq!(stats.volume_label, "NO NAME");
+    assert!(stats.total_bytes() > 1000 * 1024 * 1024);
+    assert_eq!(stats.free_clusters, stats.total_clusters - 1);
+
+    let mut file = vfat.create_file("/DATA.BIN").expect("create file");
+    file.write_all(&[9u8; 10000]).expect("write file");
+    file.sync().expect("sync file");
+    let stats = vfat.lock(|vfat| vfat.statfs()).expect("statfs");
+    assert_eq!(stats.used_clusters, 4);
+}
+
+#[test]
+fn test_mkfs_invalid_options() {
+    let invalid = |num_sectors: u64, options: mkfs::Options| {
+        let mut device = SparseDevice::default();
+        let e = mkfs::format(&mut device, num_sectors, &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(device.0.is_empty());
+    };
+
+    // Too few clusters for FAT32, with the default and a larger cluster size.
+    invalid(60000, Default::default());
+    invalid(500000, mkfs::Options { cluster_size: 4096, ..Default::default() });
+    invalid(100000, mkfs::Options { cluster_size: 3000, ..Default::default() });
+    invalid(100000, mkfs::Options { cluster_size: 128 * 1024, ..Default::default() });
+    invalid(100000, mkfs::Options { volume_label: "TWELVE CHARS".into(), ..Default::default() });
+    invalid(100000, mkfs::Options { volume_label: "A/B".into(), ..Default::default() });
+    invalid(100000, mkfs::Options { num_fats: 0, ..Default::default() });
+}
+
+#[test]
+fn test_dir_entries_are_read_lazily() {
+    let device = SharedDevice::new(mock_fat32_image());
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+    vfat.create_dir("/LOGS").expect("create dir");
+    for i in 0..200 {
+        vfat.create_file(format!("/LOGS/{:03}.LOG", i)).expect("create file");
+    }
+
+    // Remount so that nothing is cached.
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+    let dir = vfat.open_dir("/LOGS").expect("open dir");
+
+    let reads = device.reads();
+    let first: Vec<String> = dir.entries().unwrap().take(3).map(|e| e.name().to_string()).collect();
+    assert_eq!(first, vec![".", "..", "000.LOG"]);
+    assert!(device.reads() - reads <= 2, "{} reads", device.reads() - reads);
+
+    let reads = device.reads();
+    assert_eq!(dir.entries().unwrap().count(), 202);
+    assert!(device.reads() - reads > 10);
+
+    dir.find("005.LOG").expect("find entry");
+}
+
+#[test]
+fn test_dir_entries_from_position() {
+    let vfat = vfat_from_image(mock_fat32_image());
+    vfat.create_dir("/LOGS").expect("create dir");
+    for i in 0..40 {
+        vfat.create_file(format!("/LOGS/A long log file name {}.txt", i)).expect("create file");
+    }
+    let dir = vfat.open_dir("/LOGS").expect("open dir");
+    let all: Vec<String> = dir.entries().unwrap().map(|e| e.name().to_string()).collect();
+    assert_eq!(all.len(), 42);
+
+    // Resume in batches of 5, as a getdents-style caller would.
+    let mut resumed = Vec::new();
+    let mut position = 0;
+    loop {
+        let mut entries = dir.entries_from(position).expect("entries from position");
+        let batch: Vec<String> = entries.by_ref().take(5).map(|e| e.name().to_string()).collect();
+        if batch.is_empty() {
+            break;
+        }
+        resumed.extend(batch);
+        position = entries.position();
+    }
+    assert_eq!(resumed, all);
+
+    assert_eq!(dir.entries_from(100000).unwrap().count(), 0);
+}
+
+#[test]
+fn test_file_set_len() {
+    let vfat = vfat_from_image(mock_fat32_image());
+    let mut file = vfat.create_file("/DATA.BIN").expect("create file");
+    file.write_all(&[5u8; 3000]).expect("write file");
+    file.sync().expect("sync file");
+    let used = |vfat: &StdVFatHandle| vfat.lock(|vfat| vfat.statfs()).unwrap().used_clusters;
+    assert_eq!(used(&vfat), 7);
+
+    // Truncating frees the clusters past the new end.
+    file.set_len(700).expect("truncate");
+    assert_eq!(file.seek(io::SeekFrom::Current(0)).unwrap(), 700);
+    assert_eq!(used(&vfat), 3);
+    assert_eq!(read_to_vec(vfat.open_file("/DATA.BIN").unwrap()), vec![5u8; 700]);
+
+    // Extending zeroes both the stale tail of the last cluster and the new
+    // clusters.
+    file.set_len(2000).expect("extend");
+    assert_eq!(used(&vfat), 5);
+    let data = read_to_vec(vfat.open_file("/DATA.BIN").unwrap());
+    assert_eq!(&data[..700], &[5u8; 700][..]);
+    assert_eq!(&data[700..], &[0u8; 1300][..]);
+
+    file.set_len(0).expect("truncate to zero");
+    assert_eq!(used(&vfat), 1);
+    let mut file = vfat.open_file("/DATA.BIN").unwrap();
+    assert_eq!(file.size(), 0);
+
+    // Preallocating an empty file.
+    file.set_len(1024).expect("preallocate");
+    assert_eq!(used(&vfat), 3);
+    assert_eq!(read_to_vec(vfat.open_file("/DATA.BIN").unwrap()), vec![0u8; 1024]);
+    assert_eq!(check::check(&vfat, check::Mode::ReadOnly).expect("check"), vec![]);
+}
+
+#[test]
+fn test_entry_setters() {
+    let vfat = vfat_from_image(mock_fat32_image());
+    vfat.create_file("/NOTES.TXT").expect("create file");
+
+    let mut entry = vfat.open("/NOTES.TXT").expect("open entry");
+    entry.set_read_only(true).unwrap();
+    entry.set_hidden(true).unwrap();
+    entry.set_system(true).unwrap();
+    entry.set_archive(true).unwrap();
+    entry.set_created(vfat::Timestamp::new(2001, 2, 3, 4, 5, 6)).unwrap();
+    entry.set_modified(vfat::Timestamp::new(2019, 12, 31, 23, 59, 58)).unwrap();
+    entry.set_accessed(vfat::Timestamp::new(2020, 6, 7, 8, 9, 10)).unwrap();
+    entry.set_hidden(false).unwrap();
+
+    for entry in &[entry, vfat.open("/NOTES.TXT").unwrap()] {
+        let metadata = entry.metadata();
+        assert!(metadata.read_only() && !metadata.hidden() && metadata.system() && metadata.archive());
+        let created = metadata.created();
+        assert_eq!((created.year(), created.month(), created.day()), (2001, 2, 3));
+        assert_eq!((created.hour(), created.minute(), created.second()), (4, 5, 6));
+        let modified = metadata.modified();
+        assert_eq!((modified.year(), modified.hour(), modified.second()), (2019, 23, 58));
+        let accessed = metadata.accessed();
+        assert_eq!((accessed.year(), accessed.month(), accessed.day(), accessed.hour()), (2020, 6, 7, 0));
+    }
+
+    let mut root = vfat.open("/").unwrap();
+    assert_eq!(root.set_hidden(true).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_timestamp_clamps_years() {
+    let early = vfat::Timestamp::new(1970, 6, 7, 8, 9, 10);
+    assert_eq!((early.year(), early.month(), early.day(), early.hour()), (1980, 1, 1, 0));
+    let late = vfat::Timestamp::new(2200, 6, 7, 8, 9, 10);
+    assert_eq!((late.year(), late.month(), late.day()), (2107, 12, 31));
+    assert_eq!((late.hour(), late.minute(), late.second()), (23, 59, 58));
+    let last = vfat::Timestamp::new(2107, 6, 7, 8, 9, 10);
+    assert_eq!((last.year(), last.month(), last.day()), (2107, 6, 7));
+}
+
+/// Reads FAT entry 1 of both FATs of a `mock_fat32_image()` on `device`.
+fn mock_volume_flags(device: &SharedDevice) -> (u32, u32) {
+    let fat = |n| {
+        let sector = device.sector(MOCK_PARTITION_START + MOCK_RESERVED_SECTORS + n * MOCK_SECTORS_PER_FAT);
+        u32::from_le_bytes([sector[4], sector[5], sector[6], sector[7]])
+    };
+    (fat(0), fat(1))
+}
+
+#[test]
+fn test_dirty_flag() {
+    let device = SharedDevice::new(mock_fat32_image());
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+    assert!(!vfat.lock(|vfat| vfat.needs_check()));
+
+    // Reading leaves the volume clean; the first modification marks it dirty
+    // on disk right away.
+    vfat.open_dir("/").expect("open root");
+    assert_eq!(mock_volume_flags(&device), (0x0FFFFFFF, 0x0FFFFFFF));
+    let mut file = vfat.create_file("/A.TXT").expect("create file");
+    assert_eq!(mock_volume_flags(&device), (0x07FFFFFF, 0x07FFFFFF));
+    file.write_all(b"hello").expect("write file");
+    file.sync().expect("sync file");
+
+    vfat.lock(|vfat| vfat.unmount()).expect("unmount");
+    assert_eq!(mock_volume_flags(&device), (0x0FFFFFFF, 0x0FFFFFFF));
+}
+
+#[test]
+fn test_dirty_volume_needs_check() {
+    let mut image = mock_fat32_image();
+    mock_fat_entry(&mut image, 1, 0x07FFFFFF);
+    let device = SharedDevice::new(image);
+    let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+    assert!(vfat.lock(|vfat| vfat.needs_check()));
+
+    // The volume stays dirty until it has been checked.
+    vfat.create_file("/A.TXT").expect("create file");
+    vfat.lock(|vfat| vfat.unmount()).expect("unmount");
+    assert_eq!(mock_volume_flags(&device), (0x07FFFFFF, 0x07FFFFFF));
+
+    assert_eq!(check::check(&vfat, check::Mode::ReadOnly).expect("check"), vec![]);
+    assert!(!vfat.lock(|vfat| vfat.needs_check()));
+    vfat.lock(|vfat| vfat.unmount()).expect("unmount");
+    assert_eq!(mock_volume_flags(&device), (0x0FFFFFFF, 0x0FFFFFFF));
+
+    // Recorded hard errors also call for a check.
+    let mut image = mock_fat32_image();
+    mock_fat_entry(&mut image, 1, 0x0BFFFFFF);
+    assert!(vfat_from_image(image).lock(|vfat| vfat.needs_check()));
+}
+
+/// A device that loses every sector written after its first `budget`
+/// writes, as if power was cut at that point.
+#[derive(Clone)]
+struct CrashDevice(Arc<Mutex<Vec<u8>>>, Arc<Mutex<usize>>);
+
+impl BlockDevice for CrashDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.0.lock().unwrap();
+        let len = std::cmp::min(buf.len(), 512);
+        buf[..len].copy_from_slice(&data[n as usize * 512..n as usize * 512 + len]);
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len(), 512);
+        let mut budget = self.1.lock().unwrap();
+        if *budget > 0 {
+            *budget -= 1;
+            self.0.lock().unwrap()[n as usize * 512..n as usize * 512 + len].copy_from_slice(&buf[..len]);
+        }
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_cache_writes_back_in_order() {
+    let device = CrashDevice(Arc::new(Mutex::new(vec![0u8; 512 * 9])), Arc::new(Mutex::new(2)));
+    let partition = Partition { start: 1, num_sectors: 8, sector_size: 512 };
+    let mut cache = CachedPartition::with_capacity(device.clone(), partition, 3);
+
+    cache.write_sector(5, &[5; 512]).expect("write sector 5");
+    cache.write_sector(2, &[2; 512]).expect("write sector 2");
+    cache.write_sector(7, &[7; 512]).expect("write sector 7");
+    cache.write_sector(5, &[6; 512]).expect("write sector 5 again");
+
+    // Evicting sector 2 first writes back sector 5, which was modified
+    // earlier; the device then loses every later write.
+    cache.get(0).expect("read sector 0");
+    cache.flush().expect("flush");
+    let sector = |n: usize| device.0.lock().unwrap()[n * 512];
+    assert_eq!((sector(6), sector(3), sector(8)), (6, 2, 0));
+}
+
+#[test]
+fn test_crash_only_loses_clusters() {
+    let mut base = mock_fat32_image();
+    mock_root_entry(&mut base, 0, b"KEEP    TXT", 3, 1500);
+    mock_fat_entry(&mut base, 3, 4);
+    mock_fat_entry(&mut base, 4, 5);
+    mock_fat_entry(&mut base, 5, 0x0FFFFFFF);
+    write_u32(&mut base, (MOCK_PARTITION_START + 1) * 512 + 488, MOCK_NUM_CLUSTERS as u32 - 4);
+    write_u32(&mut base, (MOCK_PARTITION_START + 1) * 512 + 492, 6);
+
+    let run = |budget: usize| -> Vec<u8> {
+        let device = CrashDevice(Arc::new(Mutex::new(base.clone())), Arc::new(Mutex::new(budget)));
+        let vfat = VFat::<StdVFatHandle>::from(device.clone()).expect("failed to initialize VFAT");
+        vfat.create_dir("/LOGS").expect("create dir");
+        let mut file = vfat.create_file("/LOGS/boot.log").expect("create file");
+        file.write_all(&[1u8; 2000]).expect("write file");
+        file.sync().expect("sync file");
+        file.set_len(600).expect("truncate file");
+        vfat.remove("/KEEP.TXT").expect("remove file");
+        vfat.lock(|vfat| vfat.unmount()).expect("unmount");
+        let image = device.0.lock().unwrap().clone();
+        image
+    };
+
+    let complete = run(usize::max_value());
+    let mut budget = 0;
+    loop {
+        let image = run(budget);
+        let vfat = vfat_from_image(image.clone());
+        for problem in check::check(&vfat, check::Mode::ReadOnly).expect("check") {
+            // A truncated file may keep clusters past its new end, which are
+            // as harmless as lost ones.
+            match problem {
+                check::Problem::LostChain { .. } => (),
+                check::Problem::ChainLength { clusters, expected, .. } if clusters > expected => (),
+                problem => panic!("crash after {} writes: {}", budget, problem),
+            }
+        }
+        if image == complete {
+            break;
+        }
+        budget += 1;
+    }
+    assert!(budget > 5);
+}
+
+/// Mounts `image`, then lists every directory and reads every file in it,
+/// returning the first error.
+fn walk_image(image: Vec<u8>) -> io::Result<()> {
+    fn walk(dir: vfat::Dir<StdVFatHandle>) -> io::Result<()> {
+        let mut entries = dir.entries()?;
+        for entry in &mut entries {
+            if entry.name() == "." || entry.name() == ".." {
+                continue;
+            }
+            match entry {
+                vfat::Entry::Dir(dir) => walk(dir)?,
+                vfat::Entry::File(mut file) => {
+                    let mut data = Vec::new();
+                    file.read_to_end(&mut data)?;
+                }
+            }
+        }
+        entries.take_error().map_or(Ok(()), Err)
+    }
+
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(image))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+    walk(vfat.open_dir("/")?)
+}
+
+#[test]
+fn test_corrupted_images() {
+    let ebpb = MOCK_PARTITION_START * 512;
+    let root_entry = (MOCK_PARTITION_START + MOCK_DATA_START) * 512;
+    let corpus: Vec<(&str, Box<dyn Fn(&mut Vec<u8>)>)> = vec![
+        ("zero bytes per sector", Box::new(|image| write_u16(image, ebpb + 11, 0))),
+        ("zero sectors per cluster", Box::new(|image| image[ebpb + 13] = 0)),
+        ("zero FATs", Box::new(|image| image[ebpb + 16] = 0)),
+        ("FAT too small for the volume", Box::new(|image| write_u32(image, ebpb + 36, 1))),
+        ("root cluster out of range", Box::new(|image| write_u32(image, ebpb + 44, 70000))),
+        ("root cluster reserved", Box::new(|image| write_u32(image, ebpb + 44, 1))),
+        ("huge FAT", Box::new(|image| write_u32(image, ebpb + 36, u32::max_value()))),
+        ("truncated image", Box::new(|image| image.truncate((MOCK_PARTITION_START + 40) * 512))),
+        ("root directory loops", Box::new(|image| mock_fat_entry(image, 2, 2))),
+        ("root directory runs out of range", Box::new(|image| mock_fat_entry(image, 2, 70000))),
+        ("file chain loops", Box::new(|image| {
+            mock_root_entry(image, 0, b"LOOP    BIN", 3, 100 * 512);
+            mock_fat_entry(image, 3, 4);
+            mock_fat_entry(image, 4, 3);
+        })),
+        ("file chain runs out of range", Box::new(|image| {
+            mock_root_entry(image, 0, b"FAR     BIN", 3, 2 * 512);
+            mock_fat_entry(image, 3, 0x0FFFFFEF);
+        })),
+        ("file starts out of range", Box::new(|image| mock_root_entry(image, 0, b"FAR     BIN", 70000, 512))),
+        ("file starts at a reserved cluster", Box::new(|image| mock_root_entry(image, 0, b"ONE     BIN", 1, 512))),
+        ("file larger than its chain", Box::new(|image| {
+            mock_root_entry(image, 0, b"SHORT   BIN", 3, 5 * 512);
+            mock_fat_entry(image, 3, 0x0FFFFFFF);
+        })),
+        ("file chain hits a free cluster", Box::new(|image| {
+            mock_root_entry(image, 0, b"FREE    BIN", 3, 5 * 512);
+            mock_fat_entry(image, 3, 4);
+        })),
+        ("subdirectory loops", Box::new(move |image| {
+            mock_root_entry(image, 0, b"SUB        ", 3, 0);
+            image[root_entry + 11] = 0x10;
+            mock_fat_entry(image, 3, 3);
+        })),
+    ];
+
+    for (name, corrupt) in corpus {
+        let mut image = mock_fat32_image();
+        corrupt(&mut image);
+        assert!(walk_image(image).is_err(), "{}: corruption went unnoticed", name);
+    }
+}
+
+#[test]
+fn test_corrupted_dir_entries() {
+    let root_entry = (MOCK_PARTITION_START + MOCK_DATA_START) * 512;
+    let mut image = mock_fat32_image();
+
+    // Long file name entries with out of range sequence numbers: the 8.3
+    // names are used instead.
+    for (i, &sequence_number) in [0x40u8, 0x5F].iter().enumerate() {
+        let lfn = root_entry + 2 * i * 32;
+        image[lfn] = sequence_number;
+        image[lfn + 1] = b'x';
+        image[lfn + 11] = 0x0F;
+    }
+    mock_root_entry(&mut image, 1, b"ZERO    TXT", 0, 0);
+    mock_root_entry(&mut image, 3, b"HIGH    TXT", 0, 0);
+
+    // An 8.3 name in an OEM code page rather than ASCII.
+    mock_root_entry(&mut image, 4, b"CAF\x90    TXT", 0, 0);
+
+    let vfat = vfat_from_image(image.clone());
+    let names = entry_names(vfat.open_dir("/").unwrap());
+    assert_eq!(names, vec!["CAF\u{FFFD}.TXT", "HIGH.TXT", "ZERO.TXT"]);
+    walk_image(image).expect("walk image");
+}
+
+#[derive(Clone)]
+struct StdExt2Handle(Arc<Mutex<ext2::Volume>>);
+
+impl Debug for StdExt2Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "StdExt2Handle")
+    }
+}
+
+impl ext2::Ext2Handle for StdExt2Handle {
+    fn new(val: ext2::Volume) -> Self {
+        StdExt2Handle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut ext2::Volume) -> R) -> R {
+        f(&mut self.0.lock().expect("all okay"))
+    }
+}
+
+const MOCK_EXT2_BLOCK_SIZE: usize = 1024;
+const MOCK_EXT2_BLOCKS: usize = 512;
+const MOCK_EXT2_INODES: usize = 32;
+const MOCK_EXT2_INODE_TABLE: usize = 5;
+
+const MOCK_EXT2_DIR: u16 = 0o040755;
+const MOCK_EXT2_FILE: u16 = 0o100644;
+const MOCK_EXT2_SYMLINK: u16 = 0o120777;
+
+/// An ext2 image under construction, with 1 KiB blocks and a single block
+/// group: the superblock in block 1, the group descriptor table in block 2,
+/// the inode table in blocks 5 to 8 and data from block 9 on. Bitmaps are
+/// left empty as the driver never reads them.
+struct MockExt2 {
+    image: Vec<u8>,
+    next_block: u32,
+}
+
+impl MockExt2 {
+    fn new() -> MockExt2 {
+        let mut image = vec![0u8; MOCK_EXT2_BLOCKS * MOCK_EXT2_BLOCK_SIZE];
+
+        let sb = 1024;
+        write_u32(&mut image, sb, MOCK_EXT2_INODES as u32);
+        write_u32(&mut image, sb + 4, MOCK_EXT2_BLOCKS as u32);
+        write_u32(&mut image, sb + 20, 1);
+        write_u32(&mut image, sb + 32, 8192);
+        write_u32(&mut image, sb + 40, MOCK_EXT2_INODES as u32);
+        write_u16(&mut image, sb + 56, 0xEF53);
+        write_u16(&mut image, sb + 58, 1);
+        write_u32(&mut image, sb + 76, 1);
+        write_u32(&mut image, sb + 84, 11);
+        write_u16(&mut image, sb + 88, 128);
+        write_u32(&mut image, sb + 96, 0x2);
+        image[sb + 120..sb + 126].copy_from_slice(b"rustos");
+
+        let gd = 2 * MOCK_EXT2_BLOCK_SIZE;
+        write_u32(&mut image, gd, 3);
+        write_u32(&mut image, gd + 4, 4);
+        write_u32(&mut image, gd + 8, MOCK_EXT2_INODE_TABLE as u32);
+
+        MockExt2 { image, next_block: 9 }
+    }
+
+    fn block_mut(&mut self, block: u32) -> &mut [u8] {
+        let start = block as usize * MOCK_EXT2_BLOCK_SIZE;
+        &mut self.image[start..start + MOCK_EXT2_BLOCK_SIZE]
+    }
+
+    fn alloc_block(&mut self) -> u32 {
+        self.next_block += 1;
+        self.next_block - 1
+    }
+
+    /// Writes inode `number` with mode `mode` holding `data`. Blocks of
+    /// `data` that are all zeroes are left as holes, except in directories.
+    fn inode(&mut self, number: u32, mode: u16, mtime: u32, data: &[u8]) {
+        let per_block = MOCK_EXT2_BLOCK_SIZE / 4;
+        let mut pointers = [0u32; 15];
+        let mut num_blocks = 0;
+        let mut single = 0;
+        let mut double = 0;
+        let mut inner = 0;
+
+        for (index, chunk) in data.chunks(MOCK_EXT2_BLOCK_SIZE).enumerate() {
+            if mode != MOCK_EXT2_DIR && chunk.iter().all(|&byte| byte == 0) {
+                continue;
+            }
+            let block = self.alloc_block();
+            self.block_mut(block)[..chunk.len()].copy_from_slice(chunk);
+            num_blocks += 1;
+
+            if index < 12 {
+                pointers[index] = block;
+            } else if index < 12 + per_block {
+                if single == 0 {
+                    single = self.alloc_block();
+                    num_blocks += 1;
+                    pointers[12] = single;
+                }
+                write_u32(self.block_mut(single), 4 * (index - 12), block);
+            } else {
+                let index = index - 12 - per_block;
+                assert!(index < per_block * per_block, "file too large for the mock image");
+                if double == 0 {
+                    double = self.alloc_block();
+                    num_blocks += 1;
+                    pointers[13] = double;
+                }
+                if index % per_block == 0 || inner == 0 {
+                    inner = self.alloc_block();
+                    num_blocks += 1;
+                    write_u32(self.block_mut(double), 4 * (index / per_block), inner);
+                }
+                write_u32(self.block_mut(inner), 4 * (index % per_block), block);
+            }
+        }
+
+        let offset = MOCK_EXT2_INODE_TABLE * MOCK_EXT2_BLOCK_SIZE + (number as usize - 1) * 128;
+        let inode = &mut self.image[offset..offset + 128];
+        write_u16(inode, 0, mode);
+        write_u32(inode, 4, data.len() as u32);
+        write_u32(inode, 8, mtime + 2);
+        write_u32(inode, 12, mtime + 1);
+        write_u32(inode, 16, mtime);
+        write_u16(inode, 26, if mode == MOCK_EXT2_DIR { 2 } else { 1 });
+        write_u32(inode, 28, num_blocks * (MOCK_EXT2_BLOCK_SIZE as u32 / 512));
+        for (i, &pointer) in pointers.iter().enumerate() {
+            write_u32(inode, 40 + 4 * i, pointer);
+        }
+    }
+
+    /// Writes directory inode `number` with the entries `entries`, `.` and
+    /// `..` included, as (inode, file type, name) triples.
+    fn dir(&mut self, number: u32, entries: &[(u32, u8, &str)]) {
+        let mut data = vec![0u8; MOCK_EXT2_BLOCK_SIZE];
+        let mut offset = 0;
+        for (i, &(inode, file_type, name)) in entries.iter().enumerate() {
+            let record_len = if i + 1 == entries.len() {
+                MOCK_EXT2_BLOCK_SIZE - offset
+            } else {
+                (8 + name.len() + 3) / 4 * 4
+            };
+            write_u32(&mut data, offset, inode);
+            write_u16(&mut data, offset + 4, record_len as u16);
+            data[offset + 6] = name.len() as u8;
+            data[offset + 7] = file_type;
+            data[offset + 8..offset + 8 + name.len()].copy_from_slice(name.as_bytes());
+            offset += record_len;
+        }
+        self.inode(number, MOCK_EXT2_DIR, 1_000_000_000, &data);
+    }
+}
+
+fn mock_ext2_big_file() -> Vec<u8> {
+    // 12 direct blocks, 256 through the single indirect block and 2 through
+    // the double indirect block.
+    (0..270 * 1024u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn mock_ext2_sparse_file() -> Vec<u8> {
+    let mut data = vec![0u8; 3000];
+    data[..5].copy_from_slice(b"start");
+    data[2997..].copy_from_slice(b"end");
+    data
+}
+
+/// Builds an ext2 image holding `/hello.txt`, `/.profile`, `/big.bin`, a
+/// symbolic link `/link` and `/docs/sparse.bin`, whose second block is a
+/// hole.
+fn mock_ext2_image() -> Vec<u8> {
+    let mut mock = MockExt2::new();
+    mock.dir(2, &[
+        (2, 2, "."),
+        (2, 2, ".."),
+        (12, 1, "hello.txt"),
+        (13, 2, "docs"),
+        (14, 1, "big.bin"),
+        (0, 0, "deleted"),
+        (15, 7, "link"),
+        (17, 1, ".profile"),
+    ]);
+    mock.inode(12, MOCK_EXT2_FILE, 1_000_000_000, b"Hello, ext2!\n");
+    mock.dir(13, &[(13, 2, "."), (2, 2, ".."), (16, 1, "sparse.bin")]);
+    mock.inode(14, MOCK_EXT2_FILE, 1_000_000_000, &mock_ext2_big_file());
+    mock.inode(15, MOCK_EXT2_SYMLINK, 1_000_000_000, b"hello.txt");
+    mock.inode(16, MOCK_EXT2_FILE, 1_000_000_000, &mock_ext2_sparse_file());
+    mock.inode(17, 0o100444, 1_000_000_000, b"");
+    mock.image
+}
+
+fn ext2_from_image(image: Vec<u8>) -> ext2::Ext2<StdExt2Handle> {
+    ext2::Ext2::<StdExt2Handle>::from(Cursor::new(image)).expect("failed to initialize ext2 from image")
+}
+
+#[test]
+fn check_ext2_struct_sizes() {
+    check_size!(ext2::Superblock, 1024);
+    check_size!(ext2::BlockGroupDescriptor, 32);
+    check_size!(ext2::Inode, 128);
+}
+
+#[test]
+fn test_ext2_entries() {
+    let ext2 = ext2_from_image(mock_ext2_image());
+    assert_eq!(ext2.lock(|volume| volume.superblock().volume_name()), "rustos");
+
+    let root = (&ext2).open_dir("/").expect("open root");
+    assert_eq!(entry_names(root), vec![".", "..", ".profile", "big.bin", "docs", "hello.txt"]);
+
+    let docs = (&ext2).open_dir("/docs").expect("open docs");
+    assert_eq!(entry_names(docs), vec![".", "..", "sparse.bin"]);
+
+    assert!((&ext2).open("/docs/sparse.bin").unwrap().is_file());
+    assert!((&ext2).open("/docs/..").unwrap().is_dir());
+    assert!((&ext2).open("/Hello.txt").is_err());
+    assert!((&ext2).open("/link").is_err());
+    assert!((&ext2).open("/hello.txt/docs").is_err());
+}
+
+#[test]
+fn test_ext2_read_files() {
+    let ext2 = ext2_from_image(mock_ext2_image());
+
+    assert_eq!(read_to_vec((&ext2).open_file("/hello.txt").unwrap()), b"Hello, ext2!\n");
+    assert_eq!(read_to_vec((&ext2).open_file("/docs/../hello.txt").unwrap()), b"Hello, ext2!\n");
+    assert_eq!(read_to_vec((&ext2).open_file("/big.bin").unwrap()), mock_ext2_big_file());
+    assert_eq!(read_to_vec((&ext2).open_file("/docs/sparse.bin").unwrap()), mock_ext2_sparse_file());
+    assert_eq!(read_to_vec((&ext2).open_file("/.profile").unwrap()), b"");
+}
+
+#[test]
+fn test_ext2_seek() {
+    let ext2 = ext2_from_image(mock_ext2_image());
+    let expected = mock_ext2_big_file();
+
+    let mut file = (&ext2).open_file("/big.bin").unwrap();
+    let mut buf = [0u8; 100];
+    for &offset in &[0u64, 1000, 12 * 1024 - 50, 268 * 1024 - 50, 270 * 1024 - 100] {
+        file.seek(io::SeekFrom::Start(offset)).expect("seek");
+        file.read_exact(&mut buf).expect("read");
+        assert_eq!(&buf[..], &expected[offset as usize..offset as usize + 100]);
+    }
+
+    assert_eq!(file.seek(io::SeekFrom::End(0)).unwrap(), expected.len() as u64);
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+    assert!(file.seek(io::SeekFrom::End(1)).is_err());
+    assert!(file.seek(io::SeekFrom::Current(-(expected.len() as i64) - 1)).is_err());
+}
+
+#[test]
+fn test_ext2_metadata() {
+    let ext2 = ext2_from_image(mock_ext2_image());
+
+    let entry = (&ext2).open("/hello.txt").unwrap();
+    let metadata = entry.metadata();
+    let modified = metadata.modified();
+    assert_eq!(
+        (modified.year(), modified.month(), modified.day(), modified.hour(), modified.minute(), modified.second()),
+        (2001, 9, 9, 1, 46, 40)
+    );
+    assert_eq!(metadata.accessed().second(), 42);
+    assert_eq!(metadata.created().second(), 41);
+    assert!(!metadata.read_only());
+    assert!(!metadata.hidden());
+
+    let metadata = *(&ext2).open("/.profile").unwrap().metadata();
+    assert!(metadata.read_only());
+    assert!(metadata.hidden());
+}
+
+#[test]
+fn test_ext2_is_read_only() {
+    let ext2 = ext2_from_image(mock_ext2_image());
+    let is_read_only = |result: io::Result<()>| {
+        result.err().map(|e| e.kind()) == Some(io::ErrorKind::PermissionDenied)
+    };
+
+    assert!(is_read_only((&ext2).create_file("/new.txt").map(|_| ())));
+    assert!(is_read_only((&ext2).create_dir("/new").map(|_| ())));
+    assert!(is_read_only((&ext2).remove("/hello.txt")));
+    assert!(is_read_only((&ext2).rename("/hello.txt", "/bye.txt")));
+
+    let mut entry = (&ext2).open("/hello.txt").unwrap();
+    assert!(is_read_only(entry.set_hidden(true)));
+
+    let mut file = entry.into_file().unwrap();
+    assert!(is_read_only(file.write(b"bye").map(|_| ())));
+    assert!(is_read_only(file.set_len(0)));
+    assert_eq!(read_to_vec(file), b"Hello, ext2!\n");
+}
+
+#[test]
+fn test_ext2_mbr_partition() {
+    let start = 63;
+    let volume = mock_ext2_image();
+    let mut image = vec![0u8; start * 512];
+    image[446 + 4] = 0x83;
+    write_u32(&mut image, 446 + 8, start as u32);
+    write_u32(&mut image, 446 + 12, (volume.len() / 512) as u32);
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+    image.extend_from_slice(&volume);
+
+    let ext2 = ext2_from_image(image);
+    assert_eq!(read_to_vec((&ext2).open_file("/big.bin").unwrap()), mock_ext2_big_file());
+
+    // An MBR without a Linux partition.
+    let mut image = vec![0u8; 512];
+    image[446 + 4] = 0xC;
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+    image.extend_from_slice(&volume);
+    expect_variant!(ext2::Ext2::<StdExt2Handle>::from(Cursor::new(image)).map(|_| ()), Err(ext2::Error::NotFound));
+}
+
+#[test]
+fn test_ext2_bad_superblock() {
+    let from = |image: Vec<u8>| ext2::Ext2::<StdExt2Handle>::from(Cursor::new(image)).map(|_| ());
+
+    let mut image = mock_ext2_image();
+    write_u16(&mut image, 1024 + 56, 0x1234);
+    expect_variant!(from(image), Err(ext2::Error::BadSignature));
+
+    // Extents, as used by ext4.
+    let mut image = mock_ext2_image();
+    write_u32(&mut image, 1024 + 96, 0x2 | 0x40);
+    expect_variant!(from(image), Err(ext2::Error::UnsupportedFeatures(0x40)));
+
+    let mut image = mock_ext2_image();
+    write_u32(&mut image, 1024 + 24, 20);
+    expect_variant!(from(image), Err(ext2::Error::BadGeometry));
+
+    // Groups larger than their bitmaps can describe.
+    let mut image = mock_ext2_image();
+    write_u32(&mut image, 1024 + 32, 8 * 1024 + 1);
+    expect_variant!(from(image), Err(ext2::Error::BadGeometry));
+    let mut image = mock_ext2_image();
+    write_u32(&mut image, 1024 + 40, 8 * 1024 + 1);
+    expect_variant!(from(image), Err(ext2::Error::BadGeometry));
+
+    // A block per group and as many inodes as groups: the group descriptor
+    // table would be 128 GiB.
+    let mut image = mock_ext2_image();
+    write_u32(&mut image, 1024, u32::max_value() - 1);
+    write_u32(&mut image, 1024 + 4, u32::max_value());
+    write_u32(&mut image, 1024 + 32, 1);
+    write_u32(&mut image, 1024 + 40, 1);
+    expect_variant!(from(image), Err(ext2::Error::BadGeometry));
+
+    // Groups holding fewer inodes than the volume counts.
+    let mut image = mock_ext2_image();
+    write_u32(&mut image, 1024 + 40, 16);
+    expect_variant!(from(image), Err(ext2::Error::BadGeometry));
+
+    // A root directory claiming 4 GiB of blocks on a 512 KiB volume.
+    let mut image = mock_ext2_image();
+    let root = MOCK_EXT2_INODE_TABLE * MOCK_EXT2_BLOCK_SIZE + 128;
+    write_u32(&mut image, root + 4, u32::max_value());
+    write_u32(&mut image, root + 28, u32::max_value());
+    let ext2 = ext2_from_image(image);
+    assert_eq!((&ext2).open_dir("/").unwrap().entries().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    // A root directory with a record running past the end of its block.
+    let mut image = mock_ext2_image();
+    write_u16(&mut image, 9 * 1024 + 4, 2000);
+    let ext2 = ext2_from_image(image);
+    assert!((&ext2).open_dir("/").unwrap().entries().is_err());
+}
+
+/// Lists every file and directory under `dir`, with the contents of files,
+/// sorted by path.
+fn tree<T: Dir>(dir: T, prefix: &str, out: &mut Vec<(String, Option<Vec<u8>>)>) {
+    for entry in dir.entries().expect("entries") {
+        let path = format!("{}/{}", prefix, entry.name());
+        if entry.name() == "." || entry.name() == ".." {
+            continue;
+        }
+        if entry.is_dir() {
+            out.push((path.clone(), None));
+            tree(entry.into_dir().unwrap(), &path, out);
+        } else {
+            out.push((path, Some(read_to_vec(entry.into_file().unwrap()))));
+        }
+    }
+    out.sort();
+}
+
+#[test]
+fn test_ext2_matches_fat32() {
+    let ext2 = ext2_from_image(mock_ext2_image());
+
+    // The same files, copied to a FAT32 volume.
+    let vfat = vfat_from_image(mock_fat32_image());
+    vfat.create_dir("/docs").unwrap();
+    for path in &["/hello.txt", "/.profile", "/big.bin", "/docs/sparse.bin"] {
+        let data = read_to_vec((&ext2).open_file(path).unwrap());
+        let mut file = vfat.create_file(path).unwrap();
+        file.write_all(&data).unwrap();
+        file.sync().unwrap();
+    }
+
+    let mut expected = Vec::new();
+    tree((&ext2).open_dir("/").unwrap(), "", &mut expected);
+    let mut actual = Vec::new();
+    tree(vfat.open_dir("/").unwrap(), "", &mut actual);
+    assert_eq!(actual, expected);
+}
+
+#[derive(Clone)]
+struct StdExFatHandle(Arc<Mutex<exfat::Volume>>);
+
+impl Debug for StdExFatHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "StdExFatHandle")
+    }
+}
+
+impl exfat::ExFatHandle for StdExFatHandle {
+    fn new(val: exfat::Volume) -> Self {
+        StdExFatHandle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut exfat::Volume) -> R) -> R {
+        f(&mut self.0.lock().expect("all okay"))
+    }
+}
+
+const MOCK_EXFAT_SECTORS: usize = 1024;
+const MOCK_EXFAT_FAT_OFFSET: usize = 24;
+const MOCK_EXFAT_HEAP_OFFSET: usize = 32;
+const MOCK_EXFAT_CLUSTER_SIZE: usize = 2048;
+const MOCK_EXFAT_CLUSTERS: usize = (MOCK_EXFAT_SECTORS - MOCK_EXFAT_HEAP_OFFSET) * 512 / MOCK_EXFAT_CLUSTER_SIZE;
+
+/// An exFAT image under construction, with 512 byte sectors, 2 KiB clusters,
+/// an allocation bitmap in cluster 2, an up-case table in cluster 3 and the
+/// root directory in clusters 4 and 9.
+struct MockExFat {
+    image: Vec<u8>,
+    root: Vec<u8>,
+    used: Vec<u32>,
+}
+
+impl MockExFat {
+    fn new() -> MockExFat {
+        let mut image = vec![0u8; MOCK_EXFAT_SECTORS * 512];
+        image[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+        image[3..11].copy_from_slice(b"EXFAT   ");
+        write_u32(&mut image, 72, MOCK_EXFAT_SECTORS as u32);
+        write_u32(&mut image, 80, MOCK_EXFAT_FAT_OFFSET as u32);
+        write_u32(&mut image, 84, (MOCK_EXFAT_HEAP_OFFSET - MOCK_EXFAT_FAT_OFFSET) as u32);
+        write_u32(&mut image, 88, MOCK_EXFAT_HEAP_OFFSET as u32);
+        write_u32(&mut image, 92, MOCK_EXFAT_CLUSTERS as u32);
+        write_u32(&mut image, 96, 4);
+        write_u32(&mut image, 100, 0xCAFE1234);
+        write_u16(&mut image, 104, 0x0100);
+        image[108] = 9;
+        image[109] = 2;
+        image[110] = 1;
+        image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        let mut mock = MockExFat { image, root: Vec::new(), used: Vec::new() };
+        mock.fat(0, 0xFFFFFFF8);
+        mock.fat(1, 0xFFFFFFFF);
+
+        // Up-case table, compressed: ASCII letters and Latin-1 letters from
+        // U+00E0 on map to their upper case, everything else to itself.
+        let mut upcase: Vec<u16> = vec![0xFFFF, 0x61];
+        upcase.extend(0x41..=0x5A);
+        upcase.extend(&[0xFFFF, 0xE0 - 0x7B]);
+        upcase.extend(0xC0..=0xDE);
+        upcase.extend(&[0xFFFF, 0xFFFF - 0xFF + 1]);
+        let upcase: Vec<u8> = upcase.iter().flat_map(|unit| unit.to_le_bytes().to_vec()).collect();
+        mock.chain(&[3], &upcase);
+
+        // Critical primary entries of the root directory.
+        let mut entry = [0u8; 32];
+        entry[0] = 0x83;
+        entry[1] = 6;
+        for (i, unit) in "RUSTOS".encode_utf16().enumerate() {
+            write_u16(&mut entry, 2 + 2 * i, unit);
+        }
+        mock.root.extend_from_slice(&entry);
+
+        let mut entry = [0u8; 32];
+        entry[0] = 0x81;
+        write_u32(&mut entry, 20, 2);
+        write_u64(&mut entry, 24, (MOCK_EXFAT_CLUSTERS as u64 + 7) / 8);
+        mock.root.extend_from_slice(&entry);
+
+        let mut entry = [0u8; 32];
+        entry[0] = 0x82;
+        write_u32(&mut entry, 20, 3);
+        write_u64(&mut entry, 24, upcase.len() as u64);
+        mock.root.extend_from_slice(&entry);
+
+        mock
+    }
+
+    fn fat(&mut self, cluster: u32, value: u32) {
+        write_u32(&mut self.image, MOCK_EXFAT_FAT_OFFSET * 512 + cluster as usize * 4, value);
+    }
+
+    fn cluster_offset(cluster: u32) -> usize {
+        MOCK_EXFAT_HEAP_OFFSET * 512 + (cluster as usize - 2) * MOCK_EXFAT_CLUSTER_SIZE
+    }
+
+    /// Writes `data` to `clusters` and marks them as used. Their FAT entries
+    /// are left alone.
+    fn data(&mut self, clusters: &[u32], data: &[u8]) {
+        for (&cluster, chunk) in clusters.iter().zip(data.chunks(MOCK_EXFAT_CLUSTER_SIZE)) {
+            let offset = MockExFat::cluster_offset(cluster);
+            self.image[offset..offset + chunk.len()].copy_from_slice(chunk);
+        }
+        self.used.extend_from_slice(clusters);
+    }
+
+    /// Writes `data` to `clusters`, linked in a FAT chain.
+    fn chain(&mut self, clusters: &[u32], data: &[u8]) {
+        for pair in clusters.windows(2) {
+            self.fat(pair[0], pair[1]);
+        }
+        self.fat(*clusters.last().unwrap(), 0xFFFFFFFF);
+        self.data(clusters, data);
+    }
+
+    /// Returns the entry set of a file named `name`, whose data is stored as
+    /// described by the arguments.
+    fn entry_set(name: &str, attributes: u16, cluster: u32, contiguous: bool, valid: u64, size: u64) -> Vec<u8> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let name_entries = (name.len() + 14) / 15;
+        let mut set = vec![0u8; 32 * (2 + name_entries)];
+
+        set[0] = 0x85;
+        set[1] = 1 + name_entries as u8;
+        write_u16(&mut set, 4, attributes);
+        // 2001-09-09 01:46:40, modified a day later, accessed two days later.
+        write_u32(&mut set, 8, (0x2B29 << 16) | 0x0DD4);
+        write_u32(&mut set, 12, (0x2B2A << 16) | 0x0DD4);
+        write_u32(&mut set, 16, (0x2B2B << 16) | 0x0DD4);
+
+        set[32] = 0xC0;
+        set[33] = 0x01 | if contiguous { 0x02 } else { 0 };
+        set[35] = name.len() as u8;
+        write_u64(&mut set, 40, valid);
+        write_u32(&mut set, 52, cluster);
+        write_u64(&mut set, 56, size);
+
+        for (i, chunk) in name.chunks(15).enumerate() {
+            let entry = 64 + 32 * i;
+            set[entry] = 0xC1;
+            for (c, &unit) in chunk.iter().enumerate() {
+                write_u16(&mut set, entry + 2 + 2 * c, unit);
+            }
+        }
+
+        let checksum = set.iter().enumerate()
+            .filter(|&(i, _)| i != 2 && i != 3)
+            .fold(0u16, |sum, (_, &byte)| ((sum & 1) << 15).wrapping_add(sum >> 1).wrapping_add(byte as u16));
+        write_u16(&mut set, 2, checksum);
+        set
+    }
+
+    /// Writes the root directory and the allocation bitmap and returns the
+    /// image.
+    fn finish(mut self) -> Vec<u8> {
+        let root = std::mem::replace(&mut self.root, Vec::new());
+        assert!(root.len() <= 2 * MOCK_EXFAT_CLUSTER_SIZE);
+        self.chain(&[4, 9], &root);
+
+        let mut bitmap = vec![0u8; (MOCK_EXFAT_CLUSTERS + 7) / 8];
+        for &cluster in self.used.iter().chain(&[2]) {
+            let bit = cluster as usize - 2;
+            bitmap[bit / 8] |= 1 << (bit % 8);
+        }
+        self.chain(&[2], &bitmap);
+        self.image
+    }
+}
+
+fn write_u64(image: &mut [u8], offset: usize, value: u64) {
+    image[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn mock_exfat_data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32).map(|i| ((i + seed) % 251) as u8).collect()
+}
+
+/// Name of the file in `/docs` of `mock_exfat_image()`, which takes three
+/// file name entries.
+const MOCK_EXFAT_LONG_NAME: &str = "A long file name with ünïcödé characters.txt";
+
+/// Builds an exFAT image holding:
+///
+///   * `/hello.txt`, in a single contiguous cluster;
+///   * `/big.bin`, in a fragmented FAT chain;
+///   * `/contig.bin`, in contiguous clusters without a FAT chain;
+///   * `/.hidden`, an empty hidden file;
+///   * `/docs/MOCK_EXFAT_LONG_NAME`, whose valid data length is half its
+///     size.
+///
+/// The root directory also holds a deleted entry set.
+fn mock_exfat_image() -> Vec<u8> {
+    let mut mock = MockExFat::new();
+
+    mock.data(&[6], b"Hello, exFAT!");
+    mock.root.extend(MockExFat::entry_set("hello.txt", 0x20, 6, true, 13, 13));
+
+    mock.chain(&[10, 12, 11, 20], &mock_exfat_data(7000, 0));
+    mock.root.extend(MockExFat::entry_set("big.bin", 0x20, 10, false, 7000, 7000));
+
+    let mut deleted = MockExFat::entry_set("deleted.txt", 0x20, 6, true, 13, 13);
+    deleted.iter_mut().step_by(32).for_each(|entry_type| *entry_type &= 0x7F);
+    mock.root.extend(deleted);
+
+    mock.data(&(30..40).collect::<Vec<u32>>(), &mock_exfat_data(20000, 7));
+    mock.root.extend(MockExFat::entry_set("contig.bin", 0x20, 30, true, 20000, 20000));
+
+    mock.root.extend(MockExFat::entry_set(".hidden", 0x22, 0, false, 0, 0));
+
+    let docs = MockExFat::entry_set(MOCK_EXFAT_LONG_NAME, 0x20, 8, true, 50, 100);
+    mock.data(&[8], &mock_exfat_data(50, 3));
+    mock.data(&[7], &docs);
+    mock.root.extend(MockExFat::entry_set("docs", 0x10, 7, true, 2048, 2048));
+
+    mock.finish()
+}
+
+fn exfat_from_image(image: Vec<u8>) -> exfat::ExFat<StdExFatHandle> {
+    exfat::ExFat::<StdExFatHandle>::from(Cursor::new(image)).expect("failed to initialize exFAT from image")
+}
+
+#[test]
+fn check_exfat_boot_sector_size() {
+    check_size!(exfat::BootSector, 512);
+}
+
+#[test]
+fn test_exfat_entries() {
+    let exfat = exfat_from_image(mock_exfat_image());
+
+    let root = (&exfat).open_dir("/").expect("open root");
+    assert_eq!(entry_names(root), vec![".hidden", "big.bin", "contig.bin", "docs", "hello.txt"]);
+
+    let docs = (&exfat).open_dir("/docs").expect("open docs");
+    assert_eq!(entry_names(docs), vec![MOCK_EXFAT_LONG_NAME]);
+
+    // Names are compared through the up-case table.
+    assert!((&exfat).open("/HELLO.TXT").unwrap().is_file());
+    assert!((&exfat).open("/Docs/A LONG FILE NAME WITH ÜNÏCÖDÉ CHARACTERS.TXT").unwrap().is_file());
+    assert!((&exfat).open("/docs/..").unwrap().is_dir());
+    assert!((&exfat).open("/deleted.txt").is_err());
+    assert!((&exfat).open("/hello.txt/..").is_err());
+    assert!((&exfat).open("/docs/hello.txt").is_err());
+}
+
+#[test]
+fn test_exfat_read_files() {
+    let exfat = exfat_from_image(mock_exfat_image());
+
+    assert_eq!(read_to_vec((&exfat).open_file("/hello.txt").unwrap()), b"Hello, exFAT!");
+    assert_eq!(read_to_vec((&exfat).open_file("/docs/../hello.txt").unwrap()), b"Hello, exFAT!");
+    assert_eq!(read_to_vec((&exfat).open_file("/big.bin").unwrap()), mock_exfat_data(7000, 0));
+    assert_eq!(read_to_vec((&exfat).open_file("/contig.bin").unwrap()), mock_exfat_data(20000, 7));
+    assert_eq!(read_to_vec((&exfat).open_file("/.hidden").unwrap()), b"");
+
+    // Sequential reads continue the fragmented chain where the last one
+    // stopped, across and at cluster boundaries.
+    let mut file = (&exfat).open_file("/big.bin").unwrap();
+    let mut data = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        match file.read(&mut buf).expect("read") {
+            0 => break,
+            n => data.extend_from_slice(&buf[..n]),
+        }
+    }
+    assert_eq!(data, mock_exfat_data(7000, 0));
+
+    // Bytes past the valid data length read as zeroes.
+    let mut expected = mock_exfat_data(50, 3);
+    expected.resize(100, 0);
+    let path = format!("/docs/{}", MOCK_EXFAT_LONG_NAME);
+    assert_eq!(read_to_vec((&exfat).open_file(&path).unwrap()), expected);
+}
+
+#[test]
+fn test_exfat_seek() {
+    let exfat = exfat_from_image(mock_exfat_image());
+    let expected = mock_exfat_data(7000, 0);
+
+    let mut file = (&exfat).open_file("/big.bin").unwrap();
+    let mut buf = [0u8; 100];
+    // Backward seeks walk the chain from its start again.
+    for &offset in &[0u64, 2000, 4090, 6144, 6900, 4090, 0, 2047] {
+        file.seek(io::SeekFrom::Start(offset)).expect("seek");
+        file.read_exact(&mut buf).expect("read");
+        assert_eq!(&buf[..], &expected[offset as usize..offset as usize + 100]);
+    }
+
+    assert_eq!(file.seek(io::SeekFrom::End(0)).unwrap(), 7000);
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+    assert!(file.seek(io::SeekFrom::End(1)).is_err());
+}
+
+#[test]
+fn test_exfat_metadata() {
+    let exfat = exfat_from_image(mock_exfat_image());
+
+    let entry = (&exfat).open("/hello.txt").unwrap();
+    let metadata = entry.metadata();
+    let created = metadata.created();
+    assert_eq!(
+        (created.year(), created.month(), created.day(), created.hour(), created.minute(), created.second()),
+        (2001, 9, 9, 1, 46, 40)
+    );
+    assert_eq!(metadata.modified().day(), 10);
+    assert_eq!(metadata.accessed().day(), 11);
+    assert!(metadata.archive());
+    assert!(!metadata.hidden());
+
+    assert!((&exfat).open("/.hidden").unwrap().metadata().hidden());
+}
+
+#[test]
+fn test_exfat_statfs() {
+    let exfat = exfat_from_image(mock_exfat_image());
+    let stats = exfat.lock(|volume| volume.statfs());
+
+    assert_eq!(stats.volume_label, "RUSTOS");
+    assert_eq!(stats.serial, 0xCAFE1234);
+    assert_eq!(stats.cluster_size, 2048);
+    assert_eq!(stats.total_clusters, MOCK_EXFAT_CLUSTERS as u32);
+    // Bitmap, up-case table, root directory, hello.txt, docs and its file,
+    // big.bin and contig.bin.
+    assert_eq!(stats.used_clusters, 1 + 1 + 2 + 1 + 2 + 4 + 10);
+    assert_eq!(stats.free_clusters, MOCK_EXFAT_CLUSTERS as u32 - stats.used_clusters);
+}
+
+#[test]
+fn test_exfat_is_read_only() {
+    let exfat = exfat_from_image(mock_exfat_image());
+    let is_read_only = |result: io::Result<()>| {
+        result.err().map(|e| e.kind()) == Some(io::ErrorKind::PermissionDenied)
+    };
+
+    assert!(is_read_only((&exfat).create_file("/new.txt").map(|_| ())));
+    assert!(is_read_only((&exfat).remove("/hello.txt")));
+    let mut file = (&exfat).open_file("/hello.txt").unwrap();
+    assert!(is_read_only(file.write(b"bye").map(|_| ())));
+}
+
+#[test]
+fn test_exfat_partitions() {
+    let start = 2048;
+    let volume = mock_exfat_image();
+    let mbr = |partition_type: u8| {
+        let mut image = vec![0u8; start * 512];
+        image[446 + 4] = partition_type;
+        write_u32(&mut image, 446 + 8, start as u32);
+        write_u32(&mut image, 446 + 12, MOCK_EXFAT_SECTORS as u32);
+        image[510..512].copy_from_slice(&[0x55, 0xAA]);
+        image.extend_from_slice(&volume);
+        image
+    };
+
+    let exfat = exfat_from_image(mbr(0x07));
+    assert_eq!(read_to_vec((&exfat).open_file("/big.bin").unwrap()), mock_exfat_data(7000, 0));
+
+    let from = |image: Vec<u8>| exfat::ExFat::<StdExFatHandle>::from(Cursor::new(image)).map(|_| ());
+    expect_variant!(from(mbr(0x0C)), Err(exfat::Error::NotFound));
+
+    // FAT drivers must not take an exFAT volume for theirs.
+    assert!(VFat::<StdVFatHandle>::from(Cursor::new(mbr(0x07))).is_err());
+}
+
+#[test]
+fn test_exfat_corrupted_images() {
+    let from = |image: Vec<u8>| exfat::ExFat::<StdExFatHandle>::from(Cursor::new(image)).map(|_| ());
+
+    let mut image = mock_exfat_image();
+    image[108] = 13;
+    expect_variant!(from(image), Err(exfat::Error::BadGeometry));
+
+    let mut image = mock_exfat_image();
+    write_u32(&mut image, 96, MOCK_EXFAT_CLUSTERS as u32 + 2);
+    expect_variant!(from(image), Err(exfat::Error::BadGeometry));
+
+    let mut image = mock_exfat_image();
+    image[510] = 0;
+    expect_variant!(from(image), Err(exfat::Error::BadSignature));
+
+    // The root directory chain loops.
+    let mut image = mock_exfat_image();
+    write_u32(&mut image, MOCK_EXFAT_FAT_OFFSET * 512 + 9 * 4, 4);
+    expect_variant!(from(image), Err(exfat::Error::Io(_)));
+
+    // A file name changed without updating the entry set checksum.
+    let mut image = mock_exfat_image();
+    let name = MockExFat::cluster_offset(4) + 3 * 32 + 64 + 2;
+    image[name] = b'j';
+    let exfat = exfat_from_image(image);
+    assert!((&exfat).open_dir("/").unwrap().entries().is_err());
+
+    // Directories whose size exceeds the cluster heap or their chain, which
+    // must not be allocated whole.
+    for &(contiguous, size) in &[(true, 1u64 << 40), (false, 1 << 40), (false, 3 * 2048), (true, 2048 * 2048)] {
+        let mut mock = MockExFat::new();
+        mock.chain(&[7, 8], &[0u8; 2 * 2048]);
+        mock.root.extend(MockExFat::entry_set("huge", 0x10, 7, contiguous, size, size));
+        let exfat = exfat_from_image(mock.finish());
+        let e = (&exfat).open_dir("/huge").unwrap().entries().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    // A fragmented file whose chain ends early.
+    let mut image = mock_exfat_image();
+    write_u32(&mut image, MOCK_EXFAT_FAT_OFFSET * 512 + 11 * 4, 0xFFFFFFFF);
+    let exfat = exfat_from_image(image);
+    let mut data = Vec::new();
+    assert!((&exfat).open_file("/big.bin").unwrap().read_to_end(&mut data).is_err());
+}
+
+#[test]
+fn test_exfat_matches_fat32() {
+    let exfat = exfat_from_image(mock_exfat_image());
+
+    let vfat = vfat_from_image(mock_fat32_image());
+    vfat.create_dir("/docs").unwrap();
+    let long_name = format!("/docs/{}", MOCK_EXFAT_LONG_NAME);
+    for path in &["/hello.txt", "/big.bin", "/contig.bin", "/.hidden", &long_name] {
+        let data = read_to_vec((&exfat).open_file(path).unwrap());
+        let mut file = vfat.create_file(path).unwrap();
+        file.write_all(&data).unwrap();
+        file.sync().unwrap();
+    }
+
+    let mut expected = Vec::new();
+    tree((&exfat).open_dir("/").unwrap(), "", &mut expected);
+    let mut actual = Vec::new();
+    tree(vfat.open_dir("/").unwrap(), "", &mut actual);
+    assert_eq!(actual, expected);
+}
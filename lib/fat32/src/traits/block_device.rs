@@ -56,9 +56,56 @@ pub trait BlockDevice: Send {
     /// error of `UnexpectedEof` if the length of `buf` is less than
     /// `self.sector_size()`.
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Read consecutive sectors, starting at sector `n`, into `buf`.
+    ///
+    /// `buf.len() / self.sector_size()` whole sectors are read into `buf`; any
+    /// trailing bytes of `buf` are left untouched. The number of bytes read is
+    /// returned. The default implementation calls `read_sector()` once per
+    /// sector; devices that can transfer several sectors per request should
+    /// override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or reading from `self` fails.
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let num_sectors = buf.len() / sector_size;
+
+        let mut read = 0;
+        for (i, sector) in buf[..num_sectors * sector_size].chunks_mut(sector_size).enumerate() {
+            read += self.read_sector(n + i as u64, sector)?;
+        }
+        Ok(read)
+    }
+
+    /// Overwrites consecutive sectors, starting at sector `n`, with the
+    /// contents of `buf`.
+    ///
+    /// `buf.len() / self.sector_size()` whole sectors are written; any trailing
+    /// bytes of `buf` are ignored. The number of bytes written is returned.
+    /// The default implementation calls `write_sector()` once per sector.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or writing to `self` fails.
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let num_sectors = buf.len() / sector_size;
+
+        let mut written = 0;
+        for (i, sector) in buf[..num_sectors * sector_size].chunks(sector_size).enumerate() {
+            written += self.write_sector(n + i as u64, sector)?;
+        }
+        Ok(written)
+    }
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
+    fn sector_size(&self) -> u64 {
+        (**self).sector_size()
+    }
+
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         (*self).read_sector(n, buf)
     }
@@ -66,6 +113,14 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
+
+    fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (*self).read_sectors(n, buf)
+    }
+
+    fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        (*self).write_sectors(n, buf)
+    }
 }
 
 macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
@@ -87,11 +142,27 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             self.write_all(&buf[..to_write])?;
             Ok(to_write)
         }
+
+        fn read_sectors(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size();
+            let to_read = buf.len() - buf.len() % sector_size as usize;
+            self.seek(io::SeekFrom::Start(n * sector_size))?;
+            self.read_exact(&mut buf[..to_read])?;
+            Ok(to_read)
+        }
+
+        fn write_sectors(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size();
+            let to_write = buf.len() - buf.len() % sector_size as usize;
+            self.seek(io::SeekFrom::Start(n * sector_size))?;
+            self.write_all(&buf[..to_write])?;
+            Ok(to_write)
+        }
     }
 }
 
 impl_for_read_write_seek!(<'a> shim::io::Cursor<&'a mut [u8]>);
 impl_for_read_write_seek!(shim::io::Cursor<Vec<u8>>);
 impl_for_read_write_seek!(shim::io::Cursor<Box<[u8]>>);
-#[cfg(test)]
+#[cfg(not(feature = "no_std"))]
 impl_for_read_write_seek!(::std::fs::File);
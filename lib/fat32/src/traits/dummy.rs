@@ -34,6 +34,9 @@ impl File for Dummy {
     fn size(&self) -> u64 {
         panic!("Dummy")
     }
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        panic!("Dummy")
+    }
 }
 
 /// Trait implemented by directories in a file system.
@@ -78,6 +81,27 @@ impl Entry for Dummy {
     fn into_dir(self) -> Option<Self::Dir> {
         panic!("Dummy")
     }
+    fn set_read_only(&mut self, _read_only: bool) -> io::Result<()> {
+        panic!("Dummy")
+    }
+    fn set_hidden(&mut self, _hidden: bool) -> io::Result<()> {
+        panic!("Dummy")
+    }
+    fn set_system(&mut self, _system: bool) -> io::Result<()> {
+        panic!("Dummy")
+    }
+    fn set_archive(&mut self, _archive: bool) -> io::Result<()> {
+        panic!("Dummy")
+    }
+    fn set_created(&mut self, _timestamp: Dummy) -> io::Result<()> {
+        panic!("Dummy")
+    }
+    fn set_accessed(&mut self, _timestamp: Dummy) -> io::Result<()> {
+        panic!("Dummy")
+    }
+    fn set_modified(&mut self, _timestamp: Dummy) -> io::Result<()> {
+        panic!("Dummy")
+    }
 }
 
 impl Timestamp for Dummy {
@@ -109,6 +133,12 @@ impl Metadata for Dummy {
     fn hidden(&self) -> bool {
         panic!("Dummy")
     }
+    fn system(&self) -> bool {
+        panic!("Dummy")
+    }
+    fn archive(&self) -> bool {
+        panic!("Dummy")
+    }
     fn created(&self) -> Self::Timestamp {
         panic!("Dummy")
     }
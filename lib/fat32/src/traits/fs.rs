@@ -2,6 +2,9 @@ use shim::{io, path::Path};
 
 use crate::traits::Metadata;
 
+/// The timestamp type of the metadata of entries of type `E`.
+pub type EntryTimestamp<E> = <<E as Entry>::Metadata as Metadata>::Timestamp;
+
 /// Trait implemented by files in the file system.
 pub trait File: io::Read + io::Write + io::Seek + Sized {
     /// Writes any buffered data to disk.
@@ -9,6 +12,11 @@ pub trait File: io::Read + io::Write + io::Seek + Sized {
 
     /// Returns the size of the file in bytes.
     fn size(&self) -> u64;
+
+    /// Truncates or extends the file to `size` bytes. Extended bytes read as
+    /// zeroes. The seek position is moved back to the new end of the file if
+    /// it was past it.
+    fn set_len(&mut self, size: u64) -> io::Result<()>;
 }
 
 /// Trait implemented by directories in a file system.
@@ -54,6 +62,28 @@ pub trait Entry: Sized {
     /// returns `None`.
     fn into_dir(self) -> Option<Self::Dir>;
 
+    /// Sets whether the entry is read only.
+    fn set_read_only(&mut self, read_only: bool) -> io::Result<()>;
+
+    /// Sets whether the entry is hidden from directory traversals.
+    fn set_hidden(&mut self, hidden: bool) -> io::Result<()>;
+
+    /// Sets whether the entry belongs to the operating system.
+    fn set_system(&mut self, system: bool) -> io::Result<()>;
+
+    /// Sets whether the entry changed since it was last archived.
+    fn set_archive(&mut self, archive: bool) -> io::Result<()>;
+
+    /// Sets the timestamp when the entry was created.
+    fn set_created(&mut self, timestamp: EntryTimestamp<Self>) -> io::Result<()>;
+
+    /// Sets the timestamp of the entry's last access. File systems may store
+    /// it with a coarser resolution.
+    fn set_accessed(&mut self, timestamp: EntryTimestamp<Self>) -> io::Result<()>;
+
+    /// Sets the timestamp of the entry's last modification.
+    fn set_modified(&mut self, timestamp: EntryTimestamp<Self>) -> io::Result<()>;
+
     /// Returns `true` if this entry is a file or `false` otherwise.
     fn is_file(&self) -> bool {
         self.as_file().is_some()
@@ -115,4 +145,43 @@ pub trait FileSystem: Sized {
             .into_dir()
             .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
     }
+
+    /// Creates a new, empty regular file at `path` and returns it. `path` must
+    /// be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open()` on the parent of
+    /// `path`, this method returns an error kind of `AlreadyExists` if an entry
+    /// already exists at `path`.
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File>;
+
+    /// Creates a new, empty directory at `path` and returns it. `path` must be
+    /// absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open()` on the parent of
+    /// `path`, this method returns an error kind of `AlreadyExists` if an entry
+    /// already exists at `path`.
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir>;
+
+    /// Removes the file or empty directory at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open()`, this method returns
+    /// an error kind of `Other` if the entry at `path` is a directory that is
+    /// not empty.
+    fn remove<P: AsRef<Path>>(self, path: P) -> io::Result<()>;
+
+    /// Moves the entry at `from` to `to`. Both paths must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the error conditions for `open()` on `from` and the
+    /// parent of `to`, this method returns an error kind of `AlreadyExists` if
+    /// an entry already exists at `to`, and an error kind of `InvalidInput` if
+    /// `to` is inside of `from`.
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()>;
 }
@@ -34,6 +34,12 @@ pub trait Metadata: Sized {
     /// Whether the entry should be "hidden" from directory traversals.
     fn hidden(&self) -> bool;
 
+    /// Whether the entry belongs to the operating system.
+    fn system(&self) -> bool;
+
+    /// Whether the entry changed since it was last archived.
+    fn archive(&self) -> bool;
+
     /// The timestamp when the entry was created.
     fn created(&self) -> Self::Timestamp;
 
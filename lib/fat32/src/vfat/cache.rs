@@ -1,4 +1,5 @@
 use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::vec::Vec;
 use core::fmt;
 use hashbrown::HashMap;
@@ -6,10 +7,28 @@ use shim::io;
 
 use crate::traits::BlockDevice;
 
+/// Number of sectors a `CachedPartition` holds when no capacity is given.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
 #[derive(Debug)]
 struct CacheEntry {
     data: Vec<u8>,
-    dirty: bool,
+    /// When the entry was first modified since it was last written back, or
+    /// `None` if it is clean.
+    dirtied: Option<u64>,
+    /// Value of the partition's access clock when this entry was last used.
+    last_used: u64,
+}
+
+/// Counters describing how well a `CachedPartition` is performing.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of accesses served from the cache.
+    pub hits: u64,
+    /// Number of accesses that had to read the sector from the device.
+    pub misses: u64,
+    /// Number of sectors dropped from the cache to make room for others.
+    pub evictions: u64,
 }
 
 pub struct Partition {
@@ -24,7 +43,16 @@ pub struct Partition {
 pub struct CachedPartition {
     device: Box<dyn BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
+    /// Every use of a cached sector, oldest first, as its time and physical
+    /// sector. A use is stale once the sector was used again or evicted;
+    /// stale uses are skipped by `evict()` and dropped by `touch()`.
+    recency: VecDeque<(u64, u64)>,
+    /// The dirty sectors, by the time they were first modified.
+    dirty: BTreeMap<u64, u64>,
     partition: Partition,
+    capacity: usize,
+    clock: u64,
+    stats: CacheStats,
 }
 
 impl CachedPartition {
@@ -41,20 +69,106 @@ impl CachedPartition {
     /// `partition.sector_size` must be an integer multiple of
     /// `device.sector_size()`.
     ///
+    /// At most `DEFAULT_CACHE_CAPACITY` sectors are kept in memory. Use
+    /// `with_capacity()` to choose a different bound.
+    ///
     /// # Panics
     ///
     /// Panics if the partition's sector size is < the device's sector size.
     pub fn new<T>(device: T, partition: Partition) -> CachedPartition
+    where
+        T: BlockDevice + 'static,
+    {
+        CachedPartition::with_capacity(device, partition, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Creates a new `CachedPartition` like `new()` that holds at most
+    /// `capacity` sectors in memory. When the cache is full, the least
+    /// recently used sector is evicted, and written back to `device` first if
+    /// it is dirty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the partition's sector size is < the device's sector size or
+    /// if `capacity` is 0.
+    pub fn with_capacity<T>(device: T, partition: Partition, capacity: usize) -> CachedPartition
     where
         T: BlockDevice + 'static,
     {
         assert!(partition.sector_size >= device.sector_size());
+        assert!(capacity > 0);
 
         CachedPartition {
             device: Box::new(device),
             cache: HashMap::new(),
+            recency: VecDeque::new(),
+            dirty: BTreeMap::new(),
             partition: partition,
+            capacity,
+            clock: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Returns the maximum number of sectors held in memory.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the maximum number of sectors held in memory, evicting the
+    /// least recently used sectors if more than `capacity` are cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing an evicted dirty sector to the disk fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        assert!(capacity > 0);
+        self.capacity = capacity;
+        while self.cache.len() > self.capacity {
+            self.evict()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the hit, miss and eviction counters of this cache.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Writes every dirty sector back to the disk, in the order they were
+    /// first modified. Sectors stay cached.
+    ///
+    /// Sectors are always written back in that order, by `flush()` as well as
+    /// by eviction, so a sector never reaches the disk before one that was
+    /// modified earlier. A sector modified again before it is written back
+    /// keeps its place: callers that need a later change on disk before
+    /// another one must flush in between.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing a sector to the disk fails. Sectors that
+    /// were not written remain dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_back(u64::max_value())
+    }
+
+    /// Writes back, in order, the dirty sectors first modified no later than
+    /// `until`.
+    fn write_back(&mut self, until: u64) -> io::Result<()> {
+        while let Some((&dirtied, &physical_sector)) = self.dirty.iter().next() {
+            if dirtied > until {
+                break;
+            }
+            let entry = self.cache.get_mut(&physical_sector).expect("dirty sector is cached");
+            self.device.write_sectors(physical_sector, &entry.data)?;
+            entry.dirtied = None;
+            self.dirty.remove(&dirtied);
         }
+        Ok(())
     }
 
     /// Returns the number of physical sectors that corresponds to
@@ -76,23 +190,96 @@ impl CachedPartition {
         Some(physical_sector)
     }
 
+    /// Like `virtual_to_physical()`, but returns an error if `virt` is out of
+    /// range.
+    fn physical_sector(&self, virt: u64) -> io::Result<u64> {
+        match self.virtual_to_physical(virt) {
+            Some(physical_sector) => Ok(physical_sector),
+            None => Err(io::Error::new(io::ErrorKind::Other, "virtual sectors could not be mapped to physical sector")),
+        }
+    }
+
     fn get_cache_entry(&mut self, sector: u64) -> io::Result<&mut CacheEntry> {
-        let physical_sector = match self.virtual_to_physical(sector) {
-            Some(physical_sector) => physical_sector,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "virtual sectors could not be mapped to physical sector")),
-        };
+        let physical_sector = self.physical_sector(sector)?;
 
-        if self.cache.get(&physical_sector).is_none() {
-            let mut bytes = Vec::new();
-            for i in 0..self.factor() {
-                self.device.read_all_sector(physical_sector + i, &mut bytes)?;
-            }
-            self.cache.insert(physical_sector, CacheEntry{ data: bytes, dirty: false});
+        if self.cache.contains_key(&physical_sector) {
+            self.stats.hits += 1;
+        } else {
+            let mut bytes = vec![0u8; self.partition.sector_size as usize];
+            self.device.read_sectors(physical_sector, &mut bytes)?;
+            self.insert(physical_sector, bytes)?;
         }
 
+        self.touch(physical_sector);
         Ok(self.cache.get_mut(&physical_sector).unwrap())
     }
 
+    /// Records a use of the cached sector `physical_sector`. Stale uses are
+    /// dropped once they outnumber the cached sectors, so that recording a
+    /// use takes amortized constant time.
+    fn touch(&mut self, physical_sector: u64) {
+        self.clock += 1;
+        self.cache.get_mut(&physical_sector).unwrap().last_used = self.clock;
+        self.recency.push_back((self.clock, physical_sector));
+
+        if self.recency.len() > 2 * self.capacity {
+            let cache = &self.cache;
+            self.recency.retain(|&(used, sector)| cache.get(&sector).map(|entry| entry.last_used) == Some(used));
+        }
+    }
+
+    /// Caches the clean logical sector `data` read from `physical_sector`,
+    /// evicting another sector first if the cache is full.
+    fn insert(&mut self, physical_sector: u64, data: Vec<u8>) -> io::Result<()> {
+        self.stats.misses += 1;
+        if self.cache.len() >= self.capacity {
+            self.evict()?;
+        }
+
+        self.cache.insert(physical_sector, CacheEntry { data, dirtied: None, last_used: 0 });
+        self.touch(physical_sector);
+        Ok(())
+    }
+
+    /// Reads the logical sectors starting at `sector` that fill `buf`, none of
+    /// which are cached, from the disk with a single request and caches them.
+    fn read_uncached(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<()> {
+        let sector_size = self.partition.sector_size as usize;
+        let num_sectors = (buf.len() / sector_size) as u64;
+        let physical_sector = self.physical_sector(sector)?;
+        self.physical_sector(sector + num_sectors - 1)?;
+
+        self.device.read_sectors(physical_sector, buf)?;
+        for (i, data) in buf.chunks(sector_size).enumerate() {
+            self.insert(physical_sector + i as u64 * self.factor(), data.to_vec())?;
+        }
+        Ok(())
+    }
+
+    /// Drops the least recently used sector from the cache, writing it back to
+    /// the disk first if it is dirty, after the sectors modified before it.
+    fn evict(&mut self) -> io::Result<()> {
+        let (victim, dirtied) = loop {
+            let (used, physical_sector) = match self.recency.front() {
+                Some(&oldest) => oldest,
+                None => return Ok(()),
+            };
+            match self.cache.get(&physical_sector) {
+                Some(entry) if entry.last_used == used => break (physical_sector, entry.dirtied),
+                _ => self.recency.pop_front(),
+            };
+        };
+
+        if let Some(dirtied) = dirtied {
+            self.write_back(dirtied)?;
+        }
+
+        self.recency.pop_front();
+        self.cache.remove(&victim);
+        self.stats.evictions += 1;
+        Ok(())
+    }
+
     /// Returns a mutable reference to the cached sector `sector`. If the sector
     /// is not already cached, the sector is first read from the disk.
     ///
@@ -104,8 +291,15 @@ impl CachedPartition {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
-        let cache_entry = self.get_cache_entry(sector)?;
-        cache_entry.dirty = true;
+        let physical_sector = self.physical_sector(sector)?;
+        self.get_cache_entry(sector)?;
+
+        let clock = self.clock;
+        let cache_entry = self.cache.get_mut(&physical_sector).unwrap();
+        if cache_entry.dirtied.is_none() {
+            cache_entry.dirtied = Some(clock);
+            self.dirty.insert(clock, physical_sector);
+        }
         Ok(cache_entry.data.as_mut_slice())
     }
 
@@ -138,9 +332,8 @@ impl BlockDevice for CachedPartition {
         Ok(bytes_read)
     }
 
-    // TODO: this is completely untested.
     fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
-        let cache_entry = self.get_mut(sector).unwrap();
+        let cache_entry = self.get_mut(sector)?;
         let mut bytes_written = 0;
         for (dst, src) in cache_entry.iter_mut().zip(buf.iter()) {
             *dst = *src;
@@ -148,6 +341,38 @@ impl BlockDevice for CachedPartition {
         }
         Ok(bytes_written)
     }
+
+    /// Reads consecutive sectors into `buf`, serving cached sectors from
+    /// memory and fetching each run of uncached sectors from the disk with a
+    /// single multi-sector request.
+    fn read_sectors(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.partition.sector_size as usize;
+        let num_sectors = buf.len() / sector_size;
+
+        let mut i = 0;
+        while i < num_sectors {
+            let physical_sector = self.physical_sector(sector + i as u64)?;
+            if self.cache.contains_key(&physical_sector) {
+                let bytes = self.get(sector + i as u64)?;
+                buf[i * sector_size..(i + 1) * sector_size].copy_from_slice(bytes);
+                i += 1;
+                continue;
+            }
+
+            let mut end = i + 1;
+            while end < num_sectors {
+                match self.virtual_to_physical(sector + end as u64) {
+                    Some(physical_sector) if !self.cache.contains_key(&physical_sector) => end += 1,
+                    _ => break,
+                }
+            }
+
+            self.read_uncached(sector + i as u64, &mut buf[i * sector_size..end * sector_size])?;
+            i = end;
+        }
+
+        Ok(num_sectors * sector_size)
+    }
 }
 
 impl fmt::Debug for CachedPartition {
@@ -155,6 +380,8 @@ impl fmt::Debug for CachedPartition {
         f.debug_struct("CachedPartition")
             .field("device", &"<block device>")
             .field("cache", &self.cache)
+            .field("capacity", &self.capacity)
+            .field("stats", &self.stats)
             .finish()
     }
 }
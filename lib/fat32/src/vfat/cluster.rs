@@ -8,3 +8,12 @@ impl From<u32> for Cluster {
 }
 
 // TODO: Implement any useful helper methods on `Cluster`.
+
+/// A run of `len` physically contiguous clusters starting at `start`. A chain
+/// is mapped as a list of extents so that a cluster at any offset into the
+/// chain can be found without walking the FAT.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct Extent {
+    pub start: Cluster,
+    pub len: u32,
+}
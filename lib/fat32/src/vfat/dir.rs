@@ -1,6 +1,3 @@
-use core::marker::PhantomData;
-use core::str::from_utf8;
-
 use alloc::string::String;
 use alloc::vec::Vec;
 
@@ -11,10 +8,12 @@ use shim::io;
 use crate::traits;
 use crate::util::VecExt;
 use crate::vfat::{Attributes, Date, Metadata, Timestamp};
-use crate::vfat::{Cluster, Entry, File, VFatHandle};
+use crate::vfat::{Cluster, Entry, File, Status, VFatHandle};
 
 const LONG_FILENAME_MARKER: u8 = 0xF;
 const LONG_FILENAME_MAX_CHARS: u8 = 13;
+const LONG_FILENAME_MAX_LEN: usize = 255;
+const LONG_FILENAME_LAST_ENTRY: u8 = 0x40;
 
 #[derive(Clone, Debug)]
 pub struct Dir<HANDLE: VFatHandle> {
@@ -22,6 +21,8 @@ pub struct Dir<HANDLE: VFatHandle> {
     pub cluster: Cluster,
     pub name: String,
     pub metadata: Metadata,
+    /// Location of this directory's entry in its parent. `None` for the root.
+    pub entry: Option<EntryPos>,
 }
 
 #[repr(C, packed)]
@@ -42,6 +43,88 @@ pub struct VFatRegularDirEntry {
 
 const_assert_size!(VFatRegularDirEntry, 32);
 
+impl VFatRegularDirEntry {
+    /// Returns a new entry with the 8.3 name `short_name` whose creation,
+    /// modification and access times are all `now`.
+    pub fn new(
+        short_name: [u8; 11],
+        attributes: Attributes,
+        cluster: Cluster,
+        size: u32,
+        now: Timestamp,
+    ) -> VFatRegularDirEntry {
+        let mut entry = VFatRegularDirEntry {
+            name: [0; 8],
+            extension: [0; 3],
+            attributes,
+            windows_nt_reserved: 0,
+            creation_time_tenth_seconds: 0,
+            create_timestamp: now,
+            last_accessed_date: now.date,
+            first_cluster_high_16: 0,
+            last_modification_timestamp: now,
+            first_cluster_low_16: 0,
+            size,
+        };
+        entry.set_short_name(short_name);
+        entry.set_cluster(cluster);
+        entry
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> VFatRegularDirEntry {
+        unsafe { core::mem::transmute(bytes) }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        unsafe { core::mem::transmute(*self) }
+    }
+
+    /// Returns the space padded 8.3 name of the entry.
+    pub fn short_name(&self) -> [u8; 11] {
+        let mut short_name = [0u8; 11];
+        short_name[..8].copy_from_slice(&self.name);
+        short_name[8..].copy_from_slice(&self.extension);
+        short_name
+    }
+
+    /// Sets the space padded 8.3 name of the entry. The first 8 bytes of
+    /// `short_name` are the name and the last 3 bytes are the extension.
+    pub fn set_short_name(&mut self, short_name: [u8; 11]) {
+        self.name.copy_from_slice(&short_name[..8]);
+        self.extension.copy_from_slice(&short_name[8..]);
+    }
+
+    /// Returns the first cluster of the entry's data.
+    pub fn cluster(&self) -> Cluster {
+        Cluster::from(((self.first_cluster_high_16 as u32) << 16) | self.first_cluster_low_16 as u32)
+    }
+
+    pub fn set_cluster(&mut self, cluster: Cluster) {
+        self.first_cluster_high_16 = (cluster.0 >> 16) as u16;
+        self.first_cluster_low_16 = cluster.0 as u16;
+    }
+
+    /// Returns the size of the entry's data in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        self.size = size;
+    }
+}
+
+/// The on-disk location of a regular directory entry: the first cluster of
+/// the directory holding it, the index of the 32-byte entry within that
+/// directory's cluster chain, and the number of long file name entries that
+/// immediately precede it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EntryPos {
+    pub dir_cluster: Cluster,
+    pub index: usize,
+    pub lfn_entries: usize,
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct VFatLfnDirEntry {
@@ -68,16 +151,237 @@ pub struct VFatUnknownDirEntry {
 
 const_assert_size!(VFatUnknownDirEntry, 32);
 
+#[derive(Copy, Clone)]
 pub union VFatDirEntry {
     unknown: VFatUnknownDirEntry,
     regular: VFatRegularDirEntry,
     long_filename: VFatLfnDirEntry,
 }
 
+/// An iterator over the entries of a directory. The directory's clusters are
+/// read one at a time as iteration reaches them, so stopping early does not
+/// read the rest of the chain.
 pub struct EntryIterator<HANDLE: VFatHandle> {
-    phantom: PhantomData<HANDLE>,
-    entries: Vec<Entry<HANDLE>>,
-    curr: usize,
+    dir: Dir<HANDLE>,
+    /// Raw slots of the cluster being parsed.
+    slots: Vec<VFatDirEntry>,
+    /// Position of `slots[0]` in the directory.
+    first_slot: usize,
+    /// The cluster following the one in `slots`, if any.
+    next_cluster: Option<Cluster>,
+    /// Number of clusters of the chain walked so far, to detect chains that
+    /// loop.
+    chain_len: usize,
+    /// Position of the next slot to parse.
+    position: usize,
+    /// Whether a regular entry has been found.
+    found: bool,
+    done: bool,
+    error: Option<io::Error>,
+}
+
+/// Returns `name` as a `&str`, or an error of `InvalidInput` if it contains
+/// invalid UTF-8 characters.
+fn utf8_name(name: &OsStr) -> io::Result<&str> {
+    name.to_str().ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "`name` contains invalid UTF-8 characters",
+    ))
+}
+
+/// Returns an error of `InvalidInput` if `name` is `.` or `..`, which cannot
+/// be created, removed or renamed.
+fn check_not_dot(name: &str) -> io::Result<()> {
+    if name == "." || name == ".." {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{}` cannot be modified", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns an error of `InvalidInput` if `name` cannot be stored as a long
+/// file name.
+fn check_valid_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && name.encode_utf16().count() <= LONG_FILENAME_MAX_LEN
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c));
+
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{}` is not a valid file name", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the space padded, upper case 8.3 basis name for `name` along with
+/// whether the conversion was lossy: characters were dropped or replaced, or
+/// the name or extension were truncated.
+fn short_name_basis(name: &str) -> ([u8; 11], bool) {
+    fn convert(part: &str, max_len: usize, out: &mut [u8], lossy: &mut bool) {
+        let mut len = 0;
+        for c in part.chars() {
+            if c == ' ' || c == '.' {
+                *lossy = true;
+                continue;
+            }
+            if len == max_len {
+                *lossy = true;
+                break;
+            }
+
+            let c = c.to_ascii_uppercase();
+            out[len] = if c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c) {
+                c as u8
+            } else {
+                *lossy = true;
+                b'_'
+            };
+            len += 1;
+        }
+    }
+
+    let trimmed = name.trim_start_matches('.');
+    let mut lossy = trimmed.len() != name.len();
+    let (base, extension) = match trimmed.rfind('.') {
+        Some(dot) => (&trimmed[..dot], &trimmed[dot + 1..]),
+        None => (trimmed, ""),
+    };
+
+    let mut basis = [b' '; 11];
+    convert(base, 8, &mut basis[..8], &mut lossy);
+    convert(extension, 3, &mut basis[8..], &mut lossy);
+    if basis[0] == b' ' {
+        basis[0] = b'_';
+        lossy = true;
+    }
+
+    (basis, lossy)
+}
+
+/// Returns the name an 8.3 `short_name` is displayed as: the trimmed name and,
+/// if there is one, a `.` followed by the trimmed extension.
+pub(crate) fn short_name_string(short_name: &[u8; 11]) -> String {
+    let base = String::from_utf8_lossy(&short_name[..8]);
+    let extension = String::from_utf8_lossy(&short_name[8..]);
+    let (base, extension) = (base.trim_end(), extension.trim_end());
+    if extension.is_empty() {
+        String::from(base)
+    } else {
+        format!("{}.{}", base, extension)
+    }
+}
+
+/// Returns an 8.3 alias for `name` that does not collide with any of the
+/// `existing` short names. The alias is the basis name itself if the
+/// conversion was not lossy and the basis is unused. Otherwise a numeric tail
+/// is appended, as in `NAME~1.EXT`.
+fn unique_short_name(name: &str, existing: &[[u8; 11]]) -> io::Result<[u8; 11]> {
+    let (basis, lossy) = short_name_basis(name);
+    if !lossy && !existing.contains(&basis) {
+        return Ok(basis);
+    }
+
+    let base_len = basis[..8].iter().position(|&b| b == b' ').unwrap_or(8);
+    for n in 1..1000000 {
+        let tail = format!("~{}", n);
+        let prefix_len = core::cmp::min(base_len, 8 - tail.len());
+
+        let mut alias = basis;
+        alias[prefix_len..prefix_len + tail.len()].copy_from_slice(tail.as_bytes());
+        for byte in alias[prefix_len + tail.len()..8].iter_mut() {
+            *byte = b' ';
+        }
+
+        if !existing.contains(&alias) {
+            return Ok(alias);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("no unique 8.3 alias left for `{}`", name),
+    ))
+}
+
+/// Returns the checksum of an 8.3 name that is stored in each of the long
+/// file name entries belonging to it.
+pub fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &byte| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(byte)
+    })
+}
+
+/// Returns the raw long file name entries for `name` in the order they are
+/// stored on disk: the entry holding the end of the name, flagged as the last
+/// in its sequence, comes first.
+fn lfn_entries(name: &[u16], checksum: u8) -> Vec<u8> {
+    let max_chars = LONG_FILENAME_MAX_CHARS as usize;
+    let count = (name.len() + max_chars - 1) / max_chars;
+
+    let mut bytes = Vec::with_capacity(count * 32);
+    for i in (0..count).rev() {
+        // The name is terminated by a NUL if it does not fill the last entry,
+        // and any remaining characters are padded with 0xFFFF.
+        let mut chars = [0xFFFFu16; LONG_FILENAME_MAX_CHARS as usize];
+        for (j, c) in chars.iter_mut().enumerate() {
+            let idx = i * max_chars + j;
+            if idx < name.len() {
+                *c = name[idx];
+            } else if idx == name.len() {
+                *c = 0x0000;
+            }
+        }
+
+        let mut sequence_number = (i + 1) as u8;
+        if i == count - 1 {
+            sequence_number |= LONG_FILENAME_LAST_ENTRY;
+        }
+
+        let mut entry = VFatLfnDirEntry {
+            sequence_number,
+            name_first: [0; 5],
+            attributes: Attributes(LONG_FILENAME_MARKER),
+            vfat_type: 0,
+            checksum,
+            name_second: [0; 6],
+            zeroes: [0; 2],
+            name_third: [0; 2],
+        };
+        let (mut first, mut second, mut third) = ([0u16; 5], [0u16; 6], [0u16; 2]);
+        first.copy_from_slice(&chars[..5]);
+        second.copy_from_slice(&chars[5..11]);
+        third.copy_from_slice(&chars[11..]);
+        entry.name_first = first;
+        entry.name_second = second;
+        entry.name_third = third;
+
+        let raw: [u8; 32] = unsafe { core::mem::transmute(entry) };
+        bytes.extend_from_slice(&raw);
+    }
+
+    bytes
+}
+
+/// Returns the index of the first run of `count` consecutive free slots in
+/// the raw directory `bytes`. The run may extend past the end of `bytes`.
+fn free_slots(bytes: &[u8], count: usize) -> usize {
+    let mut run = 0;
+    for (index, slot) in bytes.chunks(32).enumerate() {
+        if slot[0] == 0x00 || slot[0] == 0xE5 {
+            run += 1;
+            if run == count {
+                return index + 1 - count;
+            }
+        } else {
+            run = 0;
+        }
+    }
+
+    bytes.len() / 32 - run
 }
 
 impl<HANDLE: VFatHandle> Dir<HANDLE> {
@@ -92,88 +396,422 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
     /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
     /// is returned.
     pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry<HANDLE>> {
-        let name = match name.as_ref().to_str() {
-            Some(str) => str,
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "`name` contains invalid UTF-8 characters",
-                ))
-            }
-        };
+        let name = utf8_name(name.as_ref())?;
 
-        for entry in traits::Dir::entries(self)? {
-            if traits::Entry::name(&entry) == name {
+        let mut entries = traits::Dir::entries(self)?;
+        for entry in &mut entries {
+            if traits::Entry::name(&entry).eq_ignore_ascii_case(name) {
                 return Ok(entry);
             }
         }
+        if let Some(error) = entries.take_error() {
+            return Err(error);
+        }
 
         Err(io::Error::new(
             io::ErrorKind::NotFound,
             format!("`{}` not found in `{}`", name, self.name),
         ))
     }
-}
 
-impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
-    type Item = Entry<HANDLE>;
+    /// Returns an iterator over the entries of `self` that starts at
+    /// `position`, as returned by `EntryIterator::position()`. Position 0 is
+    /// the start of the directory.
+    pub fn entries_from(&self, position: usize) -> io::Result<EntryIterator<HANDLE>> {
+        EntryIterator::new(self, position)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.curr >= self.entries.len() {
-            None
+    /// Returns `true` if `self` contains no entries other than `.` and `..`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of reading the entries of `self`, if it could not be
+    /// read up to its first other entry.
+    pub fn is_empty(&self) -> io::Result<bool> {
+        let mut entries = traits::Dir::entries(self)?;
+        let empty = (&mut entries).all(|entry| {
+            let name = traits::Entry::name(&entry);
+            name == "." || name == ".."
+        });
+        match entries.take_error() {
+            Some(error) => Err(error),
+            None => Ok(empty),
+        }
+    }
+
+    /// Returns `true` if `self` is the directory starting at `ancestor` or
+    /// lies below it, following the `..` entries up to the root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the `..` entries loop, or the
+    /// error of reading one of them.
+    fn is_within(&self, ancestor: Cluster) -> io::Result<bool> {
+        self.vfat.lock(|vfat| -> io::Result<bool> {
+            let mut cluster = self.cluster;
+            // No directory lies deeper than the number of clusters.
+            for _ in 0..=vfat.num_clusters() {
+                if cluster == ancestor {
+                    return Ok(true);
+                }
+                if cluster == vfat.root_dir_cluster || cluster.0 == 0 {
+                    return Ok(false);
+                }
+                let dot_dot = EntryPos { dir_cluster: cluster, index: 1, lfn_entries: 0 };
+                cluster = vfat.read_dir_entry(dot_dot)?.cluster();
+            }
+            Err(io::Error::new(io::ErrorKind::InvalidData, "`..` entries loop"))
+        })
+    }
+
+    /// Creates a new entry named `name` in `self` with attributes
+    /// `attributes`. New files are empty and own no clusters. New directories
+    /// are allocated a cluster holding their `.` and `..` entries.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists in `self`, an error of
+    /// `AlreadyExists` is returned.
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    pub fn create<P: AsRef<OsStr>>(&self, name: P, attributes: Attributes) -> io::Result<Entry<HANDLE>> {
+        let name = utf8_name(name.as_ref())?;
+        check_not_dot(name)?;
+        self.check_not_found(name, None)?;
+
+        let now = self.vfat.now();
+        let cluster = if attributes.is_directory() {
+            self.vfat.lock(|vfat| -> io::Result<Cluster> {
+                let cluster = vfat.alloc_cluster(None)?;
+                let parent = if self.cluster == vfat.root_dir_cluster {
+                    Cluster(0)
+                } else {
+                    self.cluster
+                };
+
+                let dot = VFatRegularDirEntry::new(*b".          ", Attributes::DIRECTORY, cluster, 0, now);
+                let dot_dot = VFatRegularDirEntry::new(*b"..         ", Attributes::DIRECTORY, parent, 0, now);
+                vfat.write_dir_slots(cluster, 0, &dot.to_bytes())?;
+                vfat.write_dir_slots(cluster, 1, &dot_dot.to_bytes())?;
+                vfat.flush()?;
+                Ok(cluster)
+            })?
         } else {
-            let item = self.entries[self.curr].clone();
-            self.curr += 1;
-            Some(item)
+            Cluster(0)
+        };
+
+        let mut regular = VFatRegularDirEntry::new([b' '; 11], attributes, cluster, 0, now);
+        let pos = self.insert(name, &mut regular)?;
+        Ok(self.entry_from(String::from(name), regular, pos))
+    }
+
+    /// Removes the entry named `name` from `self` and frees its clusters.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If the entry is a directory that is not empty, an error of `Other` is
+    /// returned.
+    pub fn remove<P: AsRef<OsStr>>(&self, name: P) -> io::Result<()> {
+        let name = utf8_name(name.as_ref())?;
+        check_not_dot(name)?;
+
+        let entry = self.find(name)?;
+        if let Entry::Dir(dir) = &entry {
+            if !dir.is_empty()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("directory `{}` is not empty", name),
+                ));
+            }
         }
+
+        // The entry is deleted on disk before its clusters are freed so that
+        // a crash in between only loses clusters.
+        let (pos, cluster) = (entry.pos(), entry.cluster());
+        self.vfat.lock(|vfat| -> io::Result<()> {
+            if let Some(pos) = pos {
+                vfat.delete_dir_entry(pos)?;
+                vfat.flush()?;
+            }
+            vfat.free_chain(cluster)
+        })
     }
-}
 
-impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
-    type Entry = Entry<HANDLE>;
-    type Iter = EntryIterator<HANDLE>;
+    /// Moves the entry named `name` in `self` to the directory `to` under the
+    /// name `new_name`. The entry keeps its clusters, size, attributes and
+    /// timestamps.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned. If an entry named `new_name` already exists in `to`, an error
+    /// of `AlreadyExists` is returned. If the entry is a directory and `to` is
+    /// that directory or lies below it, an error of `InvalidInput` is returned.
+    pub fn rename<P, Q>(&self, name: P, to: &Dir<HANDLE>, new_name: Q) -> io::Result<()>
+    where
+        P: AsRef<OsStr>,
+        Q: AsRef<OsStr>,
+    {
+        let name = utf8_name(name.as_ref())?;
+        let new_name = utf8_name(new_name.as_ref())?;
+        check_not_dot(name)?;
+        check_not_dot(new_name)?;
+
+        let entry = self.find(name)?;
+        let pos = match entry.pos() {
+            Some(pos) => pos,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot rename the root")),
+        };
+        to.check_not_found(new_name, Some(pos))?;
+        if let Entry::Dir(dir) = &entry {
+            if to.is_within(dir.cluster)? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot move a directory inside of itself",
+                ));
+            }
+        }
 
-    #[allow(safe_packed_borrows)]
-    fn entries(&self) -> io::Result<Self::Iter> {
-        let vfat_entries = self.vfat.lock(|vfat| -> io::Result<Vec<VFatDirEntry>> {
+        // The new entry reaches the disk before the old one is deleted, so a
+        // crash in between leaves both names rather than losing the entry.
+        let mut regular = self.vfat.lock(|vfat| vfat.read_dir_entry(pos))?;
+        to.insert(new_name, &mut regular)?;
+        self.vfat.lock(|vfat| vfat.flush())?;
+
+        self.vfat.lock(|vfat| -> io::Result<()> {
+            vfat.delete_dir_entry(pos)?;
+
+            // A moved directory's `..` entry must point at its new parent.
+            if let Entry::Dir(dir) = &entry {
+                if self.cluster != to.cluster {
+                    let parent = if to.cluster == vfat.root_dir_cluster {
+                        Cluster(0)
+                    } else {
+                        to.cluster
+                    };
+                    let dot_dot = EntryPos { dir_cluster: dir.cluster, index: 1, lfn_entries: 0 };
+                    let mut regular = vfat.read_dir_entry(dot_dot)?;
+                    regular.set_cluster(parent);
+                    vfat.write_dir_entry(dot_dot, &regular)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns an error of `AlreadyExists` if an entry named `name`, other
+    /// than the entry at `except`, exists in `self`.
+    fn check_not_found(&self, name: &str, except: Option<EntryPos>) -> io::Result<()> {
+        match self.find(name) {
+            Ok(entry) if entry.pos().is_some() && entry.pos() == except => Ok(()),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("`{}` already exists in `{}`", name, self.name),
+            )),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes `regular` into the first run of free slots of `self` that can
+    /// hold it under the name `name` and returns its position. `regular` is
+    /// given a unique 8.3 alias, preceded by long file name entries if `name`
+    /// is not exactly representable as that alias.
+    fn insert(&self, name: &str, regular: &mut VFatRegularDirEntry) -> io::Result<EntryPos> {
+        check_valid_name(name)?;
+
+        self.vfat.lock(|vfat| -> io::Result<EntryPos> {
             let mut bytes: Vec<u8> = Vec::new();
             vfat.read_all_chain(self.cluster, &mut bytes)?;
-            Ok(unsafe { bytes.cast() })
+
+            let existing: Vec<[u8; 11]> = bytes
+                .chunks(32)
+                .filter(|slot| slot[0] != 0x00 && slot[0] != 0xE5 && slot[11] != LONG_FILENAME_MARKER)
+                .map(|slot| {
+                    let mut short_name = [0u8; 11];
+                    short_name.copy_from_slice(&slot[..11]);
+                    short_name
+                })
+                .collect();
+
+            let short_name = unique_short_name(name, &existing)?;
+            regular.set_short_name(short_name);
+
+            let mut slots = Vec::new();
+            if short_name_string(&short_name) != name {
+                let long_name: Vec<u16> = name.encode_utf16().collect();
+                slots = lfn_entries(&long_name, lfn_checksum(&short_name));
+            }
+            slots.extend_from_slice(&regular.to_bytes());
+
+            let count = slots.len() / 32;
+            let index = free_slots(&bytes, count);
+            vfat.write_dir_slots(self.cluster, index, &slots)?;
+
+            Ok(EntryPos {
+                dir_cluster: self.cluster,
+                index: index + count - 1,
+                lfn_entries: count - 1,
+            })
+        })
+    }
+
+    /// Builds the `Entry` for the regular directory entry `regular` located at
+    /// `pos` in `self`.
+    fn entry_from(&self, name: String, regular: VFatRegularDirEntry, pos: EntryPos) -> Entry<HANDLE> {
+        if regular.attributes.is_directory() {
+            Entry::Dir(Dir {
+                vfat: self.vfat.clone(),
+                cluster: regular.cluster(),
+                name,
+                metadata: Metadata::from(regular),
+                entry: Some(pos),
+            })
+        } else {
+            Entry::File(File {
+                vfat: self.vfat.clone(),
+                cluster: regular.cluster(),
+                name,
+                size: regular.size as u64,
+                seek_pos: 0,
+                metadata: Metadata::from(regular),
+                entry: Some(pos),
+                short_name: regular.short_name(),
+                entry_cluster: regular.cluster(),
+                dirty: false,
+                extents: None,
+            })
+        }
+    }
+}
+
+impl<HANDLE: VFatHandle> EntryIterator<HANDLE> {
+    /// Returns an iterator over the entries of `dir` that starts parsing at
+    /// slot `position`.
+    fn new(dir: &Dir<HANDLE>, position: usize) -> io::Result<EntryIterator<HANDLE>> {
+        let (slots, first_slot, next_cluster, chain_len) = dir.vfat.lock(|vfat| -> io::Result<_> {
+            let mut bytes: Vec<u8> = Vec::new();
+            if vfat.is_root_region(dir.cluster) {
+                // The fixed root region of FAT12 and FAT16 is small and not a
+                // chain, so it is read whole.
+                vfat.read_all_chain(dir.cluster, &mut bytes)?;
+                return Ok((bytes, 0, None, 0));
+            }
+
+            let slots_per_cluster = vfat.bytes_per_cluster() / 32;
+            let skipped = position / slots_per_cluster;
+            vfat.check_chain_len(skipped + 1)?;
+            match vfat.nth_cluster(dir.cluster, skipped)? {
+                Some(cluster) if cluster.0 >= 2 => {
+                    vfat.read_all_cluster(cluster, &mut bytes)?;
+                    let next = next_cluster(vfat.fat_entry(cluster)?.status());
+                    Ok((bytes, skipped * slots_per_cluster, next, skipped + 1))
+                }
+                _ => Ok((bytes, skipped * slots_per_cluster, None, skipped)),
+            }
         })?;
 
-        let mut curr = 0;
-        let mut entries: Vec<Entry<HANDLE>> = Vec::new();
+        Ok(EntryIterator {
+            dir: dir.clone(),
+            slots: unsafe { slots.cast() },
+            first_slot,
+            next_cluster,
+            chain_len,
+            position,
+            found: position > 0,
+            done: false,
+            error: None,
+        })
+    }
+
+    /// Returns the position of the next slot to be parsed. Passing it to
+    /// `Dir::entries_from()` resumes iteration after the last entry returned.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the I/O error that ended iteration early, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Returns the raw slot at `index`, reading the directory's next cluster
+    /// if `index` is past the ones read so far. Returns `None` at the end of
+    /// the chain.
+    fn slot(&mut self, index: usize) -> io::Result<Option<VFatDirEntry>> {
+        while index >= self.first_slot + self.slots.len() {
+            let cluster = match self.next_cluster {
+                Some(cluster) => cluster,
+                None => return Ok(None),
+            };
+
+            let vfat = &self.dir.vfat;
+            let chain_len = self.chain_len + 1;
+            let (bytes, next) = vfat.lock(|vfat| -> io::Result<_> {
+                vfat.check_chain_len(chain_len)?;
+                let mut bytes: Vec<u8> = Vec::new();
+                vfat.read_all_cluster(cluster, &mut bytes)?;
+                Ok((bytes, next_cluster(vfat.fat_entry(cluster)?.status())))
+            })?;
+
+            self.first_slot += self.slots.len();
+            self.slots = unsafe { bytes.cast() };
+            self.next_cluster = next;
+            self.chain_len = chain_len;
+        }
 
-        'outer: while curr < vfat_entries.len() {
-            let mut unknown_dir_entry: VFatUnknownDirEntry = unsafe { vfat_entries[curr].unknown };
+        Ok(Some(self.slots[index - self.first_slot]))
+    }
+
+    #[allow(safe_packed_borrows)]
+    fn next_entry(&mut self) -> io::Result<Option<Entry<HANDLE>>> {
+        loop {
+            let mut slot = match self.slot(self.position)? {
+                Some(slot) => slot,
+                None => return Ok(None),
+            };
+            let mut unknown_dir_entry: VFatUnknownDirEntry = unsafe { slot.unknown };
 
             if unknown_dir_entry.first_byte == 0x00 {
                 // `0x00` means that the previous entry was the last. If no entries have been
                 // found, keep looking for entries.
-                if entries.len() > 0 {
-                    break;
+                if self.found {
+                    return Ok(None);
                 }
-                curr += 1;
+                self.position += 1;
                 continue;
-            } if unknown_dir_entry.first_byte == 0xE5 {
+            }
+            if unknown_dir_entry.first_byte == 0xE5 {
                 // Ignore entry if it is deleted/unused.
-                curr += 1;
+                self.position += 1;
                 continue;
             }
 
             // Compute the long file name if it exists.
+            let lfn_start = self.position;
+            let mut curr = self.position;
             let mut long_name: Vec<u16> = Vec::new();
+            let mut long_name_valid = true;
             while unknown_dir_entry.attributes.0 & LONG_FILENAME_MARKER == LONG_FILENAME_MARKER {
-                let long_filename = unsafe { vfat_entries[curr].long_filename };
-
-                // Compute the index for this long file name entry
-                let lfn_sequence_number = (long_filename.sequence_number & 0b1111) - 1;
-                let mut lfn_idx = lfn_sequence_number * LONG_FILENAME_MAX_CHARS;
+                let long_filename = unsafe { slot.long_filename };
+
+                // Compute the index for this long file name entry. Sequence
+                // numbers run from 1 to 20; any other number means the entry
+                // is corrupted and the 8.3 name is used instead.
+                let lfn_sequence_number = (long_filename.sequence_number & 0b11111) as usize;
+                let max_sequence_number = (LONG_FILENAME_MAX_LEN + 12) / LONG_FILENAME_MAX_CHARS as usize;
+                if lfn_sequence_number == 0 || lfn_sequence_number > max_sequence_number {
+                    long_name_valid = false;
+                }
+                let mut lfn_idx = lfn_sequence_number.saturating_sub(1) * LONG_FILENAME_MAX_CHARS as usize;
 
                 // Resize long_name if the new sequnce number is the largest seen so far.
-                let diff = (lfn_idx + LONG_FILENAME_MAX_CHARS) as i32 - long_name.len() as i32;
-                if diff > 0 {
-                    long_name.resize(long_name.len() + diff as usize, 0);
+                if long_name_valid && lfn_idx + (LONG_FILENAME_MAX_CHARS as usize) > long_name.len() {
+                    long_name.resize(lfn_idx + LONG_FILENAME_MAX_CHARS as usize, 0);
                 }
 
                 let char_sets: [&[u16]; 3] = [
@@ -185,81 +823,122 @@ impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
                 // Insert character into long_filename
                 for char_set in char_sets.iter() {
                     for character in char_set.iter() {
+                        if !long_name_valid {
+                            break;
+                        }
                         // Dereferencing `character` is causes problems on bare metal,
                         // but works fine in qemu
-                        long_name[lfn_idx as usize] = *character;
+                        long_name[lfn_idx] = *character;
                         lfn_idx += 1;
                     }
                 }
 
                 curr += 1;
-                if curr >= vfat_entries.len() {
-                    break 'outer;
-                }
-                unknown_dir_entry = unsafe { vfat_entries[curr].unknown };
+                slot = match self.slot(curr)? {
+                    Some(slot) => slot,
+                    None => {
+                        self.position = curr;
+                        return Ok(None);
+                    }
+                };
+                unknown_dir_entry = unsafe { slot.unknown };
             }
 
-            let regular = unsafe { vfat_entries[curr].regular };
-            // If the first character in the name is 0x00, the previous entry is the last entry.
-            curr += 1;
-
-            // Helper to return a Vector with characters up until
-            // terminating characters
-            fn trim<T: Copy + Into<u16>>(bytes: &[T]) -> Vec<T> {
-                let mut vec: Vec<T> = Vec::new();
-                for &byte in bytes {
-                    if byte.into() == 0x00 || byte.into() == 0x20 {
-                        break;
-                    }
-                    vec.push(byte);
-                }
-                vec
+            let regular = unsafe { slot.regular };
+            let pos = EntryPos {
+                dir_cluster: self.dir.cluster,
+                index: curr,
+                lfn_entries: curr - lfn_start,
+            };
+            self.position = curr + 1;
+            self.found = true;
+
+            if !long_name_valid {
+                long_name.clear();
             }
+            let name = entry_name(&regular, long_name);
+            return Ok(Some(self.dir.entry_from(name, regular, pos)));
+        }
+    }
+}
 
-            // If the long filename has no characters, compute the name from
-            // the regular directory.
-            let name = if long_name.len() == 0 {
-                let mut name = String::from_utf8(trim(&regular.name)).unwrap();
+/// Returns the cluster that follows a directory cluster whose FAT entry has
+/// status `status`, if any.
+fn next_cluster(status: Status) -> Option<Cluster> {
+    match status {
+        Status::Data(next) => Some(next),
+        _ => None,
+    }
+}
 
-                // Add the file extension to the filename if its lenght is >0
-                let extension = trim(&regular.extension);
-                if extension.len() > 0 {
-                    name += ".";
-                    name += from_utf8(extension.as_slice()).unwrap();
-                }
-                name
-            } else {
-                String::from_utf16(&trim(long_name.as_slice())).unwrap()
-            };
+/// Returns the name of the regular entry `regular`: its long file name if
+/// `long_name` holds one, or its 8.3 name otherwise.
+fn entry_name(regular: &VFatRegularDirEntry, long_name: Vec<u16>) -> String {
+    // Helper to return a Vector with characters up until
+    // terminating characters
+    fn trim<T: Copy + Into<u16>>(bytes: &[T]) -> Vec<T> {
+        let mut vec: Vec<T> = Vec::new();
+        for &byte in bytes {
+            if byte.into() == 0x00 || byte.into() == 0x20 {
+                break;
+            }
+            vec.push(byte);
+        }
+        vec
+    }
 
-            let entry_cluster =
-                ((regular.first_cluster_high_16 as u32) << 16) + regular.first_cluster_low_16 as u32;
-
-            let is_directory = regular.attributes.is_directory();
-            entries.push(if is_directory {
-                // TODO: other fields ie. date created, date modified
-                Entry::Dir(Dir {
-                    vfat: self.vfat.clone(),
-                    cluster: Cluster { 0: entry_cluster },
-                    name,
-                    metadata: Metadata::from(regular),
-                })
-            } else {
-                Entry::File(File {
-                    vfat: self.vfat.clone(),
-                    cluster: Cluster { 0: entry_cluster },
-                    name,
-                    size: regular.size as u64,
-                    seek_pos: 0,
-                    metadata: Metadata::from(regular),
-                })
-            });
+    // If the long filename has no characters, compute the name from
+    // the regular directory.
+    if long_name.len() == 0 {
+        // Bytes outside of ASCII belong to an unknown OEM code page.
+        let mut name = String::from_utf8_lossy(&trim(&regular.name)).into_owned();
+
+        // Add the file extension to the filename if its lenght is >0
+        let extension = trim(&regular.extension);
+        if extension.len() > 0 {
+            name += ".";
+            name += &String::from_utf8_lossy(&extension);
         }
+        name
+    } else {
+        // Long file names may contain spaces and end at a NUL or at
+        // the 0xFFFF padding.
+        let long_name: Vec<u16> = long_name
+            .into_iter()
+            .take_while(|&c| c != 0x0000 && c != 0xFFFF)
+            .collect();
+        String::from_utf16_lossy(&long_name)
+    }
+}
 
-        Ok(EntryIterator {
-            phantom: PhantomData,
-            curr: 0,
-            entries,
-        })
+impl<HANDLE: VFatHandle> Iterator for EntryIterator<HANDLE> {
+    type Item = Entry<HANDLE>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(entry),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+}
+
+impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
+    type Entry = Entry<HANDLE>;
+    type Iter = EntryIterator<HANDLE>;
+
+    fn entries(&self) -> io::Result<Self::Iter> {
+        EntryIterator::new(self, 0)
     }
 }
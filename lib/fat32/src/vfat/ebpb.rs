@@ -2,8 +2,9 @@ use core::fmt;
 use core::mem::size_of;
 use shim::const_assert_size;
 
+use crate::mkfs::Layout;
 use crate::traits::BlockDevice;
-use crate::vfat::Error;
+use crate::vfat::{Error, FatType};
 
 #[repr(C, packed)]
 pub struct BiosParameterBlock {
@@ -26,7 +27,7 @@ pub struct BiosParameterBlock {
     fat_minor_version: u8,
     fat_major_version: u8,
     pub root_cluster_num: u32,
-    fsinfo_sector_num: u16,
+    pub fsinfo_sector_num: u16,
     backup_boot_sector_num: u16,
     reserved: [u8; 12],
     drive_num: u8,
@@ -67,6 +68,44 @@ impl BiosParameterBlock {
         Ok(ebpb)
     }
 
+    /// Returns a FAT32 extended BIOS parameter block describing a volume laid
+    /// out as `layout`, with FSInfo in sector 1 and a backup boot sector in
+    /// sector 6.
+    pub(crate) fn new_fat32(layout: &Layout, serial: u32, volume_label: [u8; 11]) -> BiosParameterBlock {
+        BiosParameterBlock {
+            eb_xx_90: [0xEB, 0x58, 0x90],
+            oem_identifier: u64::from_le_bytes(*b"RUSTOS  "),
+            bytes_per_sector: layout.bytes_per_sector,
+            sectors_per_cluster: layout.sectors_per_cluster,
+            num_reserved_sectors: layout.reserved_sectors,
+            num_fats: layout.num_fats,
+            max_directory_entries: 0,
+            num_logical_sectors: 0,
+            media_descriptor_type: 0xF8,
+            sectors_per_fat: 0,
+            sectors_per_track: 63,
+            num_heads_or_sides: 255,
+            num_hidden_sectors: layout.hidden_sectors,
+            greater_num_logical_sectors: layout.total_sectors,
+            sectors_per_fat_32_bit: layout.sectors_per_fat,
+            flags: 0,
+            fat_minor_version: 0,
+            fat_major_version: 0,
+            root_cluster_num: 2,
+            fsinfo_sector_num: 1,
+            backup_boot_sector_num: 6,
+            reserved: [0; 12],
+            drive_num: 0x80,
+            windows_nt_flags: 0,
+            signature: 0x29,
+            volume_id_serial_num: serial,
+            volume_label_string: volume_label,
+            system_id_string: *b"FAT32   ",
+            bootcode: [0; 420],
+            bootable_partition_signature: [0x55, 0xAA],
+        }
+    }
+
     pub fn sectors_per_fat(&self) -> u32 {
         if self.sectors_per_fat == 0 {
             self.sectors_per_fat_32_bit
@@ -75,6 +114,98 @@ impl BiosParameterBlock {
         }
 
     }
+
+    /// Returns `true` if the block's geometry describes a FAT volume of any
+    /// type. Other file systems that share the boot signature, like NTFS or
+    /// exFAT, leave at least one of these fields zeroed.
+    pub fn is_fat(&self) -> bool {
+        let bytes_per_sector = self.bytes_per_sector;
+        bytes_per_sector >= 512
+            && bytes_per_sector.is_power_of_two()
+            && self.sectors_per_cluster.is_power_of_two()
+            && self.num_reserved_sectors != 0
+            && self.num_fats != 0
+            && self.sectors_per_fat() != 0
+            && self.total_sectors() != 0
+    }
+
+    /// Returns the number of sectors of the fixed root directory region of
+    /// FAT12 and FAT16 volumes. Always 0 on FAT32.
+    pub fn root_dir_sectors(&self) -> u32 {
+        let bytes_per_sector = self.bytes_per_sector as u32;
+        if bytes_per_sector == 0 {
+            return 0;
+        }
+        (self.max_directory_entries as u32 * 32 + bytes_per_sector - 1) / bytes_per_sector
+    }
+
+    /// Returns the number of data clusters in the volume, or 0 if the
+    /// geometry leaves no room for any.
+    pub fn num_clusters(&self) -> u32 {
+        if self.sectors_per_cluster == 0 {
+            return 0;
+        }
+        let data_start = self.num_reserved_sectors as u64
+            + self.num_fats as u64 * self.sectors_per_fat() as u64
+            + self.root_dir_sectors() as u64;
+        ((self.total_sectors() as u64).saturating_sub(data_start) / self.sectors_per_cluster as u64) as u32
+    }
+
+    /// Returns the FAT type of the volume, which the specification derives
+    /// from the number of data clusters alone.
+    pub fn fat_type(&self) -> FatType {
+        match self.num_clusters() {
+            n if n < 4085 => FatType::Fat12,
+            n if n < 65525 => FatType::Fat16,
+            _ => FatType::Fat32,
+        }
+    }
+
+    /// Returns the volume label. FAT12 and FAT16 volumes store it, along with
+    /// the serial number, at a different offset than FAT32 volumes.
+    pub fn volume_label(&self) -> [u8; 11] {
+        if self.fat_type() == FatType::Fat32 {
+            return self.volume_label_string;
+        }
+
+        let mut label = [0u8; 11];
+        label.copy_from_slice(&self.to_bytes()[43..54]);
+        label
+    }
+
+    /// Returns the volume serial number.
+    pub fn serial(&self) -> u32 {
+        if self.fat_type() == FatType::Fat32 {
+            return self.volume_id_serial_num;
+        }
+
+        let bytes = self.to_bytes();
+        u32::from_le_bytes([bytes[39], bytes[40], bytes[41], bytes[42]])
+    }
+
+    /// Returns the on-disk representation of `self`.
+    pub(crate) fn to_bytes(&self) -> [u8; size_of::<BiosParameterBlock>()] {
+        unsafe { core::mem::transmute_copy::<BiosParameterBlock, [u8; size_of::<BiosParameterBlock>()]>(self) }
+    }
+
+    /// Returns the index of the only FAT that is in use if FAT mirroring is
+    /// disabled in the extended flags, or `None` if every FAT is kept in sync.
+    /// Only FAT32 volumes have extended flags.
+    pub fn active_fat(&self) -> Option<u8> {
+        if self.fat_type() != FatType::Fat32 || self.flags & 0x80 == 0 {
+            return None;
+        }
+        Some((self.flags & 0x0F) as u8)
+    }
+
+    /// Returns the total number of logical sectors in the volume.
+    pub fn total_sectors(&self) -> u32 {
+        if self.num_logical_sectors == 0 {
+            self.greater_num_logical_sectors
+        } else {
+            self.num_logical_sectors.into()
+        }
+    }
 }
 
 impl fmt::Debug for BiosParameterBlock {
@@ -1,5 +1,8 @@
+use shim::io;
+
 use crate::traits;
-use crate::vfat::{Dir, File, Metadata, VFatHandle};
+use crate::vfat::dir::VFatRegularDirEntry;
+use crate::vfat::{Attributes, Cluster, Dir, EntryPos, File, Metadata, Timestamp, VFatHandle};
 
 // You can change this definition if you want
 #[derive(Clone, Debug)]
@@ -8,7 +11,55 @@ pub enum Entry<HANDLE: VFatHandle> {
     Dir(Dir<HANDLE>),
 }
 
-// TODO: Implement any useful helper methods on `Entry`.
+impl<HANDLE: VFatHandle> Entry<HANDLE> {
+    /// Returns the location of the entry in its parent directory, or `None`
+    /// for the root directory.
+    pub fn pos(&self) -> Option<EntryPos> {
+        match self {
+            Entry::File(file) => file.entry,
+            Entry::Dir(dir) => dir.entry,
+        }
+    }
+
+    /// Returns the first cluster of the entry's data.
+    pub fn cluster(&self) -> Cluster {
+        match self {
+            Entry::File(file) => file.cluster,
+            Entry::Dir(dir) => dir.cluster,
+        }
+    }
+
+    /// Applies `update` to the entry's on-disk directory entry, writes it
+    /// back and refreshes the entry's metadata.
+    ///
+    /// # Errors
+    ///
+    /// The root directory has no directory entry: updating it returns an error
+    /// of `InvalidInput`.
+    fn update(&mut self, update: impl FnOnce(&mut VFatRegularDirEntry)) -> io::Result<()> {
+        let pos = match self.pos() {
+            Some(pos) => pos,
+            None => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the root directory has no attributes or timestamps",
+            )),
+        };
+
+        let (vfat, metadata) = match self {
+            Entry::File(file) => (&file.vfat, &mut file.metadata),
+            Entry::Dir(dir) => (&dir.vfat, &mut dir.metadata),
+        };
+        let regular = vfat.lock(|vfat| -> io::Result<_> {
+            let mut regular = vfat.read_dir_entry(pos)?;
+            update(&mut regular);
+            vfat.write_dir_entry(pos, &regular)?;
+            vfat.flush()?;
+            Ok(regular)
+        })?;
+        *metadata = Metadata::from(regular);
+        Ok(())
+    }
+}
 
 impl<HANDLE: VFatHandle> traits::Entry for Entry<HANDLE> {
     type File = File<HANDLE>;
@@ -56,4 +107,33 @@ impl<HANDLE: VFatHandle> traits::Entry for Entry<HANDLE> {
             Entry::Dir(dir) => Some(dir),
         }
     }
+
+    fn set_read_only(&mut self, read_only: bool) -> io::Result<()> {
+        self.update(|regular| regular.attributes.set(Attributes::READ_ONLY, read_only))
+    }
+
+    fn set_hidden(&mut self, hidden: bool) -> io::Result<()> {
+        self.update(|regular| regular.attributes.set(Attributes::HIDDEN, hidden))
+    }
+
+    fn set_system(&mut self, system: bool) -> io::Result<()> {
+        self.update(|regular| regular.attributes.set(Attributes::SYSTEM, system))
+    }
+
+    fn set_archive(&mut self, archive: bool) -> io::Result<()> {
+        self.update(|regular| regular.attributes.set(Attributes::ARCHIVE, archive))
+    }
+
+    fn set_created(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.update(|regular| regular.create_timestamp = timestamp)
+    }
+
+    /// FAT only records the date of the last access.
+    fn set_accessed(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.update(|regular| regular.last_accessed_date = timestamp.date)
+    }
+
+    fn set_modified(&mut self, timestamp: Timestamp) -> io::Result<()> {
+        self.update(|regular| regular.last_modification_timestamp = timestamp)
+    }
 }
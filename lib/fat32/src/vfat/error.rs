@@ -1,12 +1,18 @@
 use shim::io;
 
+use crate::gpt;
 use crate::mbr;
+use crate::partition;
 
 #[derive(Debug)]
 pub enum Error {
     Mbr(mbr::Error),
+    Gpt(gpt::Error),
     Io(io::Error),
     BadSignature,
+    /// The BIOS parameter block describes a volume layout that can't be
+    /// right, as left by a corrupted boot sector.
+    BadGeometry,
     NotFound,
 }
 
@@ -16,8 +22,25 @@ impl From<mbr::Error> for Error {
     }
 }
 
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
         Error::Io(error)
     }
 }
+
+impl From<partition::Error> for Error {
+    fn from(error: partition::Error) -> Error {
+        match error {
+            partition::Error::Mbr(error) => Error::Mbr(error),
+            partition::Error::Gpt(error) => Error::Gpt(error),
+            partition::Error::Io(error) => Error::Io(error),
+            partition::Error::NotFound => Error::NotFound,
+        }
+    }
+}
@@ -19,6 +19,41 @@ pub enum Status {
     Eoc(u32),
 }
 
+/// The width of the entries of a FAT, determined by the volume's cluster count.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Returns the "clean shutdown" and "no hard errors" bits of FAT entry 1.
+    /// FAT12 volumes have neither.
+    pub(crate) fn volume_flags(self) -> Option<(u32, u32)> {
+        match self {
+            FatType::Fat12 => None,
+            FatType::Fat16 => Some((0x8000, 0x4000)),
+            FatType::Fat32 => Some((0x08000000, 0x04000000)),
+        }
+    }
+
+    /// Returns the size of a FAT entry in bits.
+    pub(crate) fn entry_bits(self) -> u64 {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+}
+
+/// Raw FAT entry value marking a cluster as free.
+pub const FREE_CLUSTER: u32 = 0;
+
+/// Raw FAT entry value marking a cluster as the last in its chain.
+pub const END_OF_CHAIN: u32 = 0x0FFFFFFF;
+
 #[repr(C, packed)]
 pub struct FatEntry(pub u32);
 
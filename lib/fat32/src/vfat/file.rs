@@ -1,9 +1,11 @@
 use alloc::string::String;
+use alloc::vec::Vec;
 
 use shim::io::{self, SeekFrom};
 
 use crate::traits;
-use crate::vfat::{Cluster, Metadata, VFatHandle};
+use crate::vfat::dir::VFatRegularDirEntry;
+use crate::vfat::{Cluster, EntryPos, Extent, Metadata, VFat, VFatHandle};
 
 #[derive(Clone, Debug)]
 pub struct File<HANDLE: VFatHandle> {
@@ -13,45 +15,198 @@ pub struct File<HANDLE: VFatHandle> {
     pub size: u64,
     pub seek_pos: u64,
     pub metadata: Metadata,
+    /// Location of this file's entry in its parent directory.
+    pub entry: Option<EntryPos>,
+    /// 8.3 name and first cluster of the entry as of the last `sync()`, which
+    /// tell whether the entry at `entry` still describes this file.
+    pub short_name: [u8; 11],
+    pub entry_cluster: Cluster,
+    /// Whether the size or first cluster changed since the last `sync()`.
+    pub dirty: bool,
+    /// Map of the file's cluster chain, built on the first read so that
+    /// seeking does not rescan the FAT. Reset whenever the chain may change.
+    pub extents: Option<Vec<Extent>>,
+}
+
+/// The largest size of a FAT file: its directory entry stores a 32-bit size.
+const MAX_FILE_SIZE: u64 = u32::max_value() as u64;
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "FAT files are limited to 4 GiB")
 }
 
 // FIXME: Implement `traits::File` (and its supertraits) for `File`.
 impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
+    /// Persists the file's first cluster, size and modification time into its
+    /// directory entry if the file was written to since the last sync.
     fn sync(&mut self) -> io::Result<()> {
+        if let (true, Some(pos)) = (self.dirty, self.entry) {
+            let now = self.vfat.now();
+            let (cluster, size) = (self.cluster, self.size as u32);
+            let regular = self.vfat.lock(|vfat| -> io::Result<_> {
+                let mut regular = self.read_entry(vfat, pos)?;
+                // The clusters and data must be on disk before the entry
+                // points at them: a crash in between only loses clusters.
+                vfat.flush()?;
+                regular.set_cluster(cluster);
+                regular.set_size(size);
+                regular.last_modification_timestamp = now;
+                regular.last_accessed_date = now.date;
+                vfat.write_dir_entry(pos, &regular)?;
+                Ok(regular)
+            })?;
+            self.metadata = Metadata::from(regular);
+            self.entry_cluster = cluster;
+        }
+
+        self.vfat.lock(|vfat| vfat.flush())?;
+        self.dirty = false;
         Ok(())
     }
 
     fn size(&self) -> u64 {
         self.size
     }
+
+    /// Frees the clusters past the new end of the file or allocates zeroed
+    /// ones up to it, then persists the new size.
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        if size > MAX_FILE_SIZE {
+            return Err(too_large());
+        }
+        self.check_entry()?;
+
+        if size == self.size {
+            return traits::File::sync(self);
+        }
+
+        // A shrinking file's entry is updated before its clusters are freed,
+        // and a growing file's clusters are allocated before its entry is,
+        // so that a crash in between only loses clusters.
+        let (cluster, old_size) = (self.cluster, self.size as usize);
+        if size < self.size {
+            if size == 0 {
+                self.cluster = Cluster(0);
+            }
+            self.set_size(size);
+            traits::File::sync(self)?;
+        }
+
+        let new_cluster = self.vfat.lock(|vfat| vfat.resize_chain(cluster, old_size, size as usize))?;
+        if size > self.size {
+            self.cluster = new_cluster;
+            self.set_size(size);
+        }
+        traits::File::sync(self)
+    }
+}
+
+impl<HANDLE: VFatHandle> File<HANDLE> {
+    /// Reads the file's directory entry at `pos`, checking that it still
+    /// describes this file. Since the file was opened, its entry may have been
+    /// removed, leaving a deleted slot, or moved, and its slot reused by
+    /// another entry that must not be overwritten.
+    ///
+    /// # Errors
+    ///
+    /// If the entry no longer describes this file, an error of `NotFound` is
+    /// returned.
+    fn read_entry(&self, vfat: &mut VFat<HANDLE>, pos: EntryPos) -> io::Result<VFatRegularDirEntry> {
+        let regular = vfat.read_dir_entry(pos)?;
+        if regular.short_name() != self.short_name || regular.cluster() != self.entry_cluster {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` was removed or moved", self.name),
+            ));
+        }
+        Ok(regular)
+    }
+
+    /// Returns an error of `NotFound` if the file's directory entry no longer
+    /// describes this file, before it is changed.
+    fn check_entry(&self) -> io::Result<()> {
+        match self.entry {
+            Some(pos) => self.vfat.lock(|vfat| self.read_entry(vfat, pos).map(|_| ())),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the in-memory size of the file, to be persisted by `sync()`.
+    fn set_size(&mut self, size: u64) {
+        self.extents = None;
+        self.size = size;
+        self.seek_pos = core::cmp::min(self.seek_pos, size);
+        self.dirty = true;
+    }
 }
 
 impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut bytes_read = self.vfat
-            .lock(|vfat| -> io::Result<usize> { vfat.read_chain(self.cluster, self.seek_pos as usize, buf) })?;
+        let bytes_left = (self.size - self.seek_pos) as usize;
+        let len = core::cmp::min(buf.len(), bytes_left);
+        if len == 0 {
+            return Ok(0);
+        }
 
-        let bytes_left = self.size as i128 - self.seek_pos as i128;
-        let diff = bytes_read as i128 - bytes_left;
-        if diff > 0 {
-            bytes_read -= diff as usize;
+        let (cluster, seek_pos) = (self.cluster, self.seek_pos as usize);
+        let extents = &mut self.extents;
+        let bytes_read = self.vfat.lock(|vfat| -> io::Result<usize> {
+            if extents.is_none() {
+                *extents = Some(vfat.chain_extents(cluster)?);
+            }
+            vfat.read_extents(extents.as_ref().unwrap(), seek_pos, &mut buf[..len])
+        })?;
+
+        // Reading nothing before the end of the file means that a corrupted
+        // FAT cut the chain short.
+        if bytes_read == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file is larger than its cluster chain"));
         }
 
-        io::Seek::seek(self, SeekFrom::Current(bytes_read as i64))?;
-        Ok(bytes_read as usize)
+        self.seek_pos += bytes_read as u64;
+        Ok(bytes_read)
     }
 }
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
-    // TODO: this is competely untested.
+    /// Writes `buf` at the current seek position, growing the file and its
+    /// cluster chain as needed. The new size is only persisted to the
+    /// directory entry on `sync()` or `flush()`. Writing past the 4 GiB
+    /// limit of FAT files is an error.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        // TODO: use seek_pos
-        self.vfat
-            .lock(|vfat| -> io::Result<usize> { vfat.write_chain(self.cluster, buf) })
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.seek_pos + buf.len() as u64 > MAX_FILE_SIZE {
+            return Err(too_large());
+        }
+        // The clusters of a removed file may already belong to another one.
+        self.check_entry()?;
+
+        let (cluster, seek_pos) = (self.cluster, self.seek_pos as usize);
+        let (cluster, bytes_written) = self.vfat.lock(|vfat| -> io::Result<_> {
+            // Empty files do not own any clusters yet.
+            let cluster = if cluster.0 < 2 {
+                vfat.alloc_cluster(None)?
+            } else {
+                cluster
+            };
+            Ok((cluster, vfat.write_chain(cluster, seek_pos, buf)?))
+        })?;
+
+        self.cluster = cluster;
+        self.extents = None;
+        self.seek_pos += bytes_written as u64;
+        if self.seek_pos > self.size {
+            self.size = self.seek_pos;
+        }
+        self.dirty = true;
+
+        Ok(bytes_written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        traits::File::sync(self)
     }
 }
 
@@ -72,7 +227,7 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
         let new_pos = match _pos {
             SeekFrom::Start(offset) => offset as i128,
-            SeekFrom::End(offset) => self.size as i128 - 1 + offset as i128,
+            SeekFrom::End(offset) => self.size as i128 + offset as i128,
             SeekFrom::Current(offset) => self.seek_pos as i128 + offset as i128,
         };
 
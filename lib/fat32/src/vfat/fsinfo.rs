@@ -0,0 +1,119 @@
+use alloc::string::String;
+use core::fmt;
+use core::mem::size_of;
+use shim::const_assert_size;
+
+use crate::traits::BlockDevice;
+use crate::vfat::Error;
+
+const LEAD_SIGNATURE: u32 = 0x41615252;
+const STRUCT_SIGNATURE: u32 = 0x61417272;
+const TRAIL_SIGNATURE: u32 = 0xAA550000;
+
+/// Value of `free_count` and `next_free` when the field is not known.
+pub const FSINFO_UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// The FAT32 file system information sector.
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: u32,
+    reserved: [u8; 480],
+    struct_signature: u32,
+    /// Last known number of free clusters, or `FSINFO_UNKNOWN`.
+    pub free_count: u32,
+    /// Cluster at which to start looking for free clusters, or
+    /// `FSINFO_UNKNOWN`.
+    pub next_free: u32,
+    reserved_2: [u8; 12],
+    trail_signature: u32,
+}
+
+const_assert_size!(FsInfo, 512);
+
+impl FsInfo {
+    /// Returns an FSInfo sector with the given hints.
+    pub fn new(free_count: u32, next_free: u32) -> FsInfo {
+        FsInfo {
+            lead_signature: LEAD_SIGNATURE,
+            reserved: [0; 480],
+            struct_signature: STRUCT_SIGNATURE,
+            free_count,
+            next_free,
+            reserved_2: [0; 12],
+            trail_signature: TRAIL_SIGNATURE,
+        }
+    }
+
+    /// Reads the FSInfo sector from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If any of the three FSInfo signatures is invalid, returns an error of
+    /// `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<FsInfo, Error> {
+        let mut fsinfo_sector = [0u8; size_of::<FsInfo>()];
+        device.read_sector(sector, &mut fsinfo_sector)?;
+
+        let fsinfo = unsafe { core::mem::transmute::<[u8; size_of::<FsInfo>()], FsInfo>(fsinfo_sector) };
+
+        if fsinfo.lead_signature != LEAD_SIGNATURE
+            || fsinfo.struct_signature != STRUCT_SIGNATURE
+            || fsinfo.trail_signature != TRAIL_SIGNATURE
+        {
+            return Err(Error::BadSignature);
+        }
+
+        Ok(fsinfo)
+    }
+
+    /// Returns the on-disk representation of `self`.
+    pub fn to_bytes(&self) -> [u8; size_of::<FsInfo>()] {
+        unsafe { core::mem::transmute_copy::<FsInfo, [u8; size_of::<FsInfo>()]>(self) }
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("lead_signature", &{ self.lead_signature })
+            .field("struct_signature", &{ self.struct_signature })
+            .field("free_count", &{ self.free_count })
+            .field("next_free", &{ self.next_free })
+            .field("trail_signature", &{ self.trail_signature })
+            .finish()
+    }
+}
+
+/// Usage statistics of a mounted volume, as reported by `VFat::statfs()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsStats {
+    /// Number of data clusters in the volume.
+    pub total_clusters: u32,
+    /// Number of data clusters that are not allocated to any chain.
+    pub free_clusters: u32,
+    /// Number of data clusters that are allocated.
+    pub used_clusters: u32,
+    /// Size of a cluster in bytes.
+    pub cluster_size: u32,
+    /// Volume label from the EBPB, without trailing padding.
+    pub volume_label: String,
+    /// Volume serial number from the EBPB.
+    pub serial: u32,
+}
+
+impl FsStats {
+    /// Returns the size of the data region in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_clusters as u64 * self.cluster_size as u64
+    }
+
+    /// Returns the number of bytes that can still be allocated.
+    pub fn free_bytes(&self) -> u64 {
+        self.free_clusters as u64 * self.cluster_size as u64
+    }
+
+    /// Returns the number of bytes allocated to files and directories.
+    pub fn used_bytes(&self) -> u64 {
+        self.used_clusters as u64 * self.cluster_size as u64
+    }
+}
@@ -37,6 +37,13 @@ pub struct Metadata {
 }
 
 impl Attributes {
+    pub const READ_ONLY: Attributes = Attributes(0x01);
+    pub const HIDDEN: Attributes = Attributes(0x02);
+    pub const SYSTEM: Attributes = Attributes(0x04);
+    pub const VOLUME_ID: Attributes = Attributes(0x08);
+    pub const DIRECTORY: Attributes = Attributes(0x10);
+    pub const ARCHIVE: Attributes = Attributes(0x20);
+
     pub fn read_only(&self) -> bool {
         self.0 & 0x01 != 0
     }
@@ -45,9 +52,26 @@ impl Attributes {
         self.0 & 0x02 != 0
     }
 
+    pub fn system(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
     pub fn is_directory(&self) -> bool {
         self.0 & 0x10 != 0
     }
+
+    pub fn archive(&self) -> bool {
+        self.0 & 0x20 != 0
+    }
+
+    /// Sets the bits of `flag` if `value` is `true` and clears them otherwise.
+    pub fn set(&mut self, flag: Attributes, value: bool) {
+        if value {
+            self.0 |= flag.0;
+        } else {
+            self.0 &= !flag.0;
+        }
+    }
 }
 
 impl From<u16> for Date {
@@ -66,6 +90,26 @@ impl Timestamp {
     fn from(date: Date, time: Time) -> Timestamp {
         Timestamp {date, time}
     }
+
+    /// Packs a calendar date and 24-hour time into a FAT timestamp. Seconds
+    /// are stored with a two second resolution and are rounded down. FAT
+    /// timestamps range over the years 1980 to 2107: earlier and later times
+    /// are clamped to the first and last representable timestamps.
+    pub fn new(year: usize, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Timestamp {
+        if year < 1980 {
+            return Timestamp::new(1980, 1, 1, 0, 0, 0);
+        } else if year > 2107 {
+            return Timestamp::new(2107, 12, 31, 23, 59, 58);
+        }
+
+        let date = ((year - 1980) as u16 & 0b1111111) << 9
+            | (month as u16 & 0b1111) << 5
+            | (day as u16 & 0b11111);
+        let time = (hour as u16 & 0b11111) << 11
+            | (minute as u16 & 0b111111) << 5
+            | (second as u16 / 2 & 0b11111);
+        Timestamp::from(Date(date), Time(time))
+    }
 }
 
 impl traits::Timestamp for Timestamp {
@@ -107,6 +151,18 @@ impl Metadata {
         }
     }
 
+    /// Returns the metadata of an entry with attributes `attributes` and the
+    /// given timestamps. Only the date of `accessed` is kept, as FAT records
+    /// no time of last access.
+    pub fn new(attributes: Attributes, created: Timestamp, accessed: Timestamp, modified: Timestamp) -> Metadata {
+        Metadata {
+            attributes,
+            create_timestamp: created,
+            last_accessed_date: accessed.date,
+            last_modification_timestamp: modified,
+        }
+    }
+
     pub fn from(dir_entry: VFatRegularDirEntry) -> Metadata {
         Metadata {
             attributes: dir_entry.attributes,
@@ -128,6 +184,14 @@ impl traits::Metadata for Metadata {
         self.attributes.0 & 0b10 != 0
     }
 
+    fn system(&self) -> bool {
+        self.attributes.system()
+    }
+
+    fn archive(&self) -> bool {
+        self.attributes.archive()
+    }
+
     fn created(&self) -> Timestamp {
         self.create_timestamp
     }
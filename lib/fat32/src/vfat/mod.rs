@@ -5,18 +5,22 @@ pub(crate) mod ebpb;
 pub(crate) mod entry;
 pub(crate) mod error;
 pub(crate) mod fat;
+pub(crate) mod fsinfo;
 pub(crate) mod file;
 pub(crate) mod metadata;
 pub(crate) mod vfat;
 
-pub use self::dir::Dir;
+pub use self::cache::{CacheStats, DEFAULT_CACHE_CAPACITY};
+pub use self::dir::{Dir, EntryPos};
 pub use self::ebpb::BiosParameterBlock;
 pub use self::entry::Entry;
 pub use self::error::Error;
+pub use self::fat::FatType;
 pub use self::file::File;
+pub use self::fsinfo::{FsInfo, FsStats, FSINFO_UNKNOWN};
 pub use self::metadata::{Attributes, Date, Metadata, Time, Timestamp};
 pub use self::vfat::{VFat, VFatHandle};
 
 pub(crate) use self::cache::{CachedPartition, Partition};
-pub(crate) use self::cluster::Cluster;
-pub(crate) use self::fat::{FatEntry, Status};
+pub(crate) use self::cluster::{Cluster, Extent};
+pub(crate) use self::fat::{FatEntry, Status, END_OF_CHAIN, FREE_CLUSTER};
@@ -5,78 +5,329 @@ use core::mem::size_of;
 use alloc::vec::Vec;
 use alloc::string::String;
 
+use shim::ffi::OsStr;
 use shim::io;
 use shim::path::{Component, Path};
 
-use crate::mbr::MasterBootRecord;
+use crate::gpt::{BASIC_DATA_PARTITION, EFI_SYSTEM_PARTITION};
+use crate::partition::{self, PartitionType};
 use crate::traits;
 use crate::traits::{BlockDevice, FileSystem};
-use crate::vfat::{BiosParameterBlock, CachedPartition, Metadata, Partition};
-use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Status};
+use crate::vfat::dir::VFatRegularDirEntry;
+use crate::vfat::{Attributes, BiosParameterBlock, CacheStats, CachedPartition, FsInfo, FsStats, FSINFO_UNKNOWN, EntryPos, Extent, Metadata, Partition, Timestamp};
+use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, FatType, File, Status, END_OF_CHAIN, FREE_CLUSTER};
 
 /// A generic trait that handles a critical section as a closure
 pub trait VFatHandle: Clone + Debug + Send + Sync {
     fn new(val: VFat<Self>) -> Self;
     fn lock<R>(&self, f: impl FnOnce(&mut VFat<Self>) -> R) -> R;
+
+    /// Returns the current time, used to stamp entries that are modified.
+    /// Defaults to the FAT epoch (01/01/1980 00:00:00) for systems without a
+    /// real-time clock.
+    fn now(&self) -> Timestamp {
+        Timestamp::new(1980, 1, 1, 0, 0, 0)
+    }
 }
 
 #[derive(Debug)]
 pub struct VFat<HANDLE: VFatHandle> {
     phantom: PhantomData<HANDLE>,
     device: CachedPartition,
+    pub fat_type: FatType,
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
     fat_start_sector: u64,
+    num_fats: u8,
+    /// The only FAT read and written when mirroring is disabled. Otherwise
+    /// FAT 0 is read and every modification is applied to all FATs.
+    active_fat: Option<u8>,
+    /// First sector and length of the fixed root directory region of FAT12
+    /// and FAT16 volumes. The length is 0 on FAT32.
+    root_dir_start_sector: u64,
+    root_dir_sectors: u32,
     data_start_sector: u64,
+    num_clusters: u32,
+    /// Sector of the FSInfo structure, if the volume has a valid one.
+    fsinfo_sector: Option<u64>,
+    /// Number of free clusters, if known.
+    free_clusters: Option<u32>,
+    /// Cluster at which the search for a free cluster starts.
+    next_free: Cluster,
+    volume_label: [u8; 11],
+    serial: u32,
+    /// Whether the clean shutdown bit is cleared on disk.
+    dirty: bool,
+    /// Whether the volume was dirty or recorded hard errors when it was
+    /// mounted and has not been checked since. Such a volume is left dirty on
+    /// unmount.
+    needs_check: bool,
+    /// First cluster of the root directory. Cluster 0 stands for the fixed
+    /// root directory region on FAT12 and FAT16, as in `..` entries.
     pub root_dir_cluster: Cluster,
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Mounts the first FAT partition of `device`, in the order of
+    /// `partition::partitions()`.
     pub fn from<T>(mut device: T) -> Result<HANDLE, Error>
     where
         T: BlockDevice + 'static,
     {
-        let mbr = MasterBootRecord::from(&mut device)?;
+        let (start, num_sectors) = find_fat_partition(&mut device)?;
+        let ebpb = BiosParameterBlock::from(&mut device, start)?;
+        VFat::mount(device, ebpb, start, num_sectors)
+    }
 
-        let mut fat_partition_entry = None;
-        for partition_entry in &mbr.partition_table {
-            if partition_entry.partition_type == 0xB || partition_entry.partition_type == 0xC {
-                fat_partition_entry = Some(partition_entry);
-            }
-        }
+    /// Mounts partition `index` of `device`, numbered as by
+    /// `partition::partitions()`, whatever its partition type.
+    pub fn from_partition<T>(device: T, index: usize) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        VFat::from_volume(partition::open(device, index)?)
+    }
 
-        if fat_partition_entry.is_none() {
-            return Err(Error::NotFound);
-        }
-        let fat_partition_entry = fat_partition_entry.unwrap();
+    /// Mounts the FAT volume that starts at sector 0 of `device`, which has no
+    /// partition table: a `PartitionDevice` or a superfloppy.
+    pub fn from_volume<T>(mut device: T) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let ebpb = BiosParameterBlock::from(&mut device, 0)?;
+        let num_sectors = ebpb.total_sectors() as u64;
+        VFat::mount(device, ebpb, 0, num_sectors)
+    }
 
-        let ebpb =
-            BiosParameterBlock::from(&mut device, fat_partition_entry.relative_sector as u64)?;
+    //  * A method to mount the volume described by `ebpb`, which starts at
+    //    sector `start` of `device` and spans `num_sectors` sectors.
+    fn mount<T>(device: T, ebpb: BiosParameterBlock, start: u64, num_sectors: u64) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        if !ebpb.is_fat() || ebpb.num_clusters() == 0 || ebpb.num_clusters() > MAX_CLUSTERS {
+            return Err(Error::BadGeometry);
+        }
 
         let partition = Partition {
-            start: fat_partition_entry.relative_sector as u64,
-            num_sectors: fat_partition_entry.total_sectors as u64,
+            start,
+            num_sectors,
             sector_size: ebpb.bytes_per_sector as u64,
         };
-        let cached_partition = CachedPartition::new(device, partition);
+        let mut cached_partition = CachedPartition::new(device, partition);
+
+        let fat_type = ebpb.fat_type();
+        let fat_start_sector = ebpb.num_reserved_sectors as u64;
+        let root_dir_start_sector = fat_start_sector + ebpb.num_fats as u64 * ebpb.sectors_per_fat() as u64;
+        let root_dir_sectors = ebpb.root_dir_sectors();
+        let data_start_sector = root_dir_start_sector + root_dir_sectors as u64;
+        let num_clusters = ebpb.num_clusters();
+
+        // Every FAT must map every data cluster.
+        let fat_entries = ebpb.sectors_per_fat() as u64 * ebpb.bytes_per_sector as u64 * 8 / fat_type.entry_bits();
+        if fat_entries < num_clusters as u64 + 2 {
+            return Err(Error::BadGeometry);
+        }
+        let root_dir_cluster = match fat_type {
+            FatType::Fat32 => Cluster::from(ebpb.root_cluster_num),
+            _ => Cluster::from(0),
+        };
+        if fat_type == FatType::Fat32 && (root_dir_cluster.0 < 2 || root_dir_cluster.0 >= num_clusters + 2) {
+            return Err(Error::BadGeometry);
+        }
 
-        let fat_start_sector = ebpb.num_reserved_sectors;
-        let data_start_sector = fat_start_sector as u32 + (ebpb.num_fats as u32 * ebpb.sectors_per_fat());
+        // The FSInfo fields are only hints: ignore values that cannot be right.
+        let fsinfo_sector = ebpb.fsinfo_sector_num as u64;
+        let fsinfo = match fat_type {
+            FatType::Fat32 => FsInfo::from(&mut cached_partition, fsinfo_sector),
+            _ => Err(Error::NotFound),
+        };
+        let (fsinfo_sector, free_clusters, next_free) =
+            match fsinfo {
+                Ok(fsinfo) => {
+                    let (free_count, next_free) = (fsinfo.free_count, fsinfo.next_free);
+                    let free_clusters = if free_count <= num_clusters { Some(free_count) } else { None };
+                    let next_free = if next_free >= 2 && next_free < num_clusters + 2 { next_free } else { 2 };
+                    (Some(fsinfo_sector), free_clusters, next_free)
+                }
+                Err(_) => (None, None, 2),
+            };
 
-        let vfat = VFat {
+        let mut vfat = VFat {
             phantom: PhantomData,
             device: cached_partition,
+            fat_type,
             bytes_per_sector: ebpb.bytes_per_sector,
             sectors_per_cluster: ebpb.sectors_per_cluster,
-            sectors_per_fat: ebpb.sectors_per_fat_32_bit,
-            fat_start_sector: fat_start_sector.into(),
-            data_start_sector: data_start_sector.into(),
-            root_dir_cluster: Cluster::from(ebpb.root_cluster_num),
+            sectors_per_fat: ebpb.sectors_per_fat(),
+            fat_start_sector,
+            num_fats: ebpb.num_fats,
+            active_fat: ebpb.active_fat().filter(|&fat| fat < ebpb.num_fats),
+            root_dir_start_sector,
+            root_dir_sectors,
+            data_start_sector,
+            num_clusters,
+            fsinfo_sector,
+            free_clusters,
+            next_free: Cluster::from(next_free),
+            volume_label: ebpb.volume_label(),
+            serial: ebpb.serial(),
+            dirty: false,
+            needs_check: false,
+            root_dir_cluster,
         };
+        vfat.dirty = !vfat.volume_flags_clean()?;
+        vfat.needs_check = vfat.dirty;
         Ok(VFatHandle::new(vfat))
     }
 
+    //  * A method to write every modified sector back and mark the volume as
+    //    cleanly unmounted. A volume that was dirty when it was mounted stays
+    //    dirty until it has been checked.
+    pub fn unmount(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if self.dirty && !self.needs_check {
+            if let Some((clean, no_errors)) = self.fat_type.volume_flags() {
+                self.update_volume_flags(|raw| raw | clean | no_errors)?;
+                self.flush()?;
+            }
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    //  * A method to return whether the volume was not cleanly unmounted, or
+    //    recorded hard errors, before it was mounted and has not been checked
+    //    since.
+    pub fn needs_check(&self) -> bool {
+        self.needs_check
+    }
+
+    //  * A method to record that the volume was checked and is consistent.
+    pub(crate) fn set_checked(&mut self) {
+        self.needs_check = false;
+    }
+
+    //  * A method to return whether both the clean shutdown and the no hard
+    //    errors bits are set.
+    fn volume_flags_clean(&mut self) -> io::Result<bool> {
+        let (clean, no_errors) = match self.fat_type.volume_flags() {
+            Some(flags) => flags,
+            None => return Ok(true),
+        };
+        let raw = self.read_fat_bytes(self.active_fat.unwrap_or(0), Cluster(1))?;
+        Ok(raw & clean != 0 && raw & no_errors != 0)
+    }
+
+    //  * A method to apply `update` to FAT entry 1 in every FAT in use.
+    fn update_volume_flags(&mut self, update: impl Fn(u32) -> u32) -> io::Result<()> {
+        let fats = match self.active_fat {
+            Some(fat) => fat..fat + 1,
+            None => 0..self.num_fats,
+        };
+        for fat in fats {
+            let raw = self.read_fat_bytes(fat, Cluster(1))?;
+            self.write_fat_bytes(fat, Cluster(1), update(raw))?;
+        }
+        Ok(())
+    }
+
+    //  * A method to clear the clean shutdown bit on disk before the first
+    //    modification of the volume, so that a crash before `unmount()` is
+    //    noticed on the next mount.
+    fn mark_dirty(&mut self) -> io::Result<()> {
+        if self.dirty {
+            return Ok(());
+        }
+        if let Some((clean, _)) = self.fat_type.volume_flags() {
+            self.update_volume_flags(|raw| raw & !clean)?;
+            self.flush()?;
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    //  * A method to write every modified sector back to the device.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.device.flush()
+    }
+
+    //  * A method to bound the number of sectors kept in memory.
+    pub fn set_cache_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        self.device.set_capacity(capacity)
+    }
+
+    //  * A method to inspect the sector cache's hit, miss and eviction counters.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.stats()
+    }
+
+    //  * A method to return the number of data clusters in the volume.
+    pub(crate) fn num_clusters(&self) -> u32 {
+        self.num_clusters
+    }
+
+    //  * A method to report the volume's size, usage and identity.
+    pub fn statfs(&mut self) -> io::Result<FsStats> {
+        let free_clusters = self.free_clusters()?;
+        let label_len = self.volume_label.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+
+        Ok(FsStats {
+            total_clusters: self.num_clusters,
+            free_clusters,
+            used_clusters: self.num_clusters - free_clusters,
+            cluster_size: self.bytes_per_cluster() as u32,
+            volume_label: String::from_utf8_lossy(&self.volume_label[..label_len]).into_owned(),
+            serial: self.serial,
+        })
+    }
+
+    //  * A method to return the number of free clusters, counting them in the
+    //    FAT if FSInfo did not provide a trustworthy value.
+    fn free_clusters(&mut self) -> io::Result<u32> {
+        if let Some(free_clusters) = self.free_clusters {
+            return Ok(free_clusters);
+        }
+
+        let mut free_clusters = 0;
+        for raw_cluster in 2..self.num_clusters + 2 {
+            if self.fat_entry(Cluster::from(raw_cluster))?.status() == Status::Free {
+                free_clusters += 1;
+            }
+        }
+
+        self.free_clusters = Some(free_clusters);
+        self.write_fsinfo()?;
+        Ok(free_clusters)
+    }
+
+    //  * A method to forget the free cluster count so that it is counted again
+    //    in the FAT when it is next needed.
+    pub(crate) fn invalidate_free_count(&mut self) -> io::Result<()> {
+        self.free_clusters = None;
+        self.write_fsinfo()
+    }
+
+    //  * A method to store the free cluster count and next free hint in the
+    //    FSInfo sector.
+    fn write_fsinfo(&mut self) -> io::Result<()> {
+        let sector = match self.fsinfo_sector {
+            Some(sector) => sector,
+            None => return Ok(()),
+        };
+
+        let mut fsinfo = match FsInfo::from(&mut self.device, sector) {
+            Ok(fsinfo) => fsinfo,
+            Err(Error::Io(error)) => return Err(error),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "FSInfo sector is corrupted")),
+        };
+        fsinfo.free_count = self.free_clusters.unwrap_or(FSINFO_UNKNOWN);
+        fsinfo.next_free = self.next_free.0;
+        self.device.write_sector(sector, &fsinfo.to_bytes())?;
+        Ok(())
+    }
+
     //  * A method to read from an offset of a cluster into a buffer.
     fn read_cluster(
         &mut self,
@@ -84,81 +335,149 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         offset: usize,
         buf: &mut [u8],
     ) -> io::Result<usize> {
-        if offset >= (self.bytes_per_sector * (self.sectors_per_cluster as u16)) as usize {
+        self.read_clusters(cluster, 1, offset, buf)
+    }
+
+    //  * A method to read from an offset of `num_clusters` contiguous clusters
+    //    into a buffer with a single device request.
+    fn read_clusters(
+        &mut self,
+        start: Cluster,
+        num_clusters: usize,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        let run_len = num_clusters * self.bytes_per_cluster();
+        if offset >= run_len {
             return Ok(0);
         }
 
-        let first_sector = offset / self.bytes_per_sector as usize;
-        let sector_offset = offset % self.bytes_per_sector as usize;
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let first_sector = offset / bytes_per_sector;
+        let sector_offset = offset % bytes_per_sector;
 
-        let mut bytes = Vec::new();
-        let cluster_sector = self.cluster_raw_sector(cluster);
-        for i in first_sector..self.sectors_per_cluster as usize {
-            self.device.read_all_sector(
-                cluster_sector + i as u64,
-                &mut bytes,
-            )?;
-        }
+        // Only read the sectors that `buf` can hold.
+        let end = core::cmp::min(offset + buf.len(), run_len);
+        let last_sector = (end + bytes_per_sector - 1) / bytes_per_sector;
 
-        let mut read_bytes = 0;
-        for (dst, src) in buf.iter_mut().zip(bytes[sector_offset..].iter()) {
-            *dst = *src;
-            read_bytes += 1;
-        }
+        let mut bytes = vec![0u8; (last_sector - first_sector) * bytes_per_sector];
+        let start_sector = self.cluster_raw_sector(start)? + first_sector as u64;
+        self.device.read_sectors(start_sector, &mut bytes)?;
 
+        let read_bytes = end - offset;
+        buf[..read_bytes].copy_from_slice(&bytes[sector_offset..sector_offset + read_bytes]);
         Ok(read_bytes)
     }
 
     //  * A method to read an entire cluster.
-    fn read_all_cluster(
+    pub(crate) fn read_all_cluster(
         &mut self,
         cluster: Cluster,
         buf: &mut Vec<u8>,
     ) -> io::Result<usize> {
-        for i in 0..self.sectors_per_cluster as usize {
-            self.device.read_all_sector(
-                self.cluster_raw_sector(Cluster {
-                    0: cluster.0 + i as u32,
-                }),
-                buf,
-            )?;
-        }
+        let cluster_sector = self.cluster_raw_sector(cluster)?;
+        let start = buf.len();
+        buf.resize(start + self.bytes_per_cluster(), 0);
+        self.device.read_sectors(cluster_sector, &mut buf[start..])?;
 
         Ok(buf.len())
     }
 
-    //  * A method to read all of the clusters chained from a starting cluster
-    //    into a vector.
+    //  * A method to read from an offset of the chain starting at `start` into
+    //    a buffer. The chain is followed through the FAT, so its clusters need
+    //    not be contiguous.
     pub fn read_chain(&mut self, start: Cluster, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
         // Clusters start at 2.
         if start.0 == 0 || start.0 == 1 {
             return Ok(0)
         }
 
-        let mut bytes_read = 0;
-        let mut cluster = Cluster::from(start.0 + (offset / self.bytes_per_cluster()) as u32);
+        let mut cluster = match self.nth_cluster(start, offset / self.bytes_per_cluster())? {
+            Some(cluster) => cluster,
+            None => return Ok(0),
+        };
         let mut cluster_offset = offset % self.bytes_per_cluster();
 
-        loop {
-            match self.read_cluster(cluster, cluster_offset, &mut buf[bytes_read..]) {
-                Ok(0) => return Ok(buf.len()),
-                Ok(n) => bytes_read += n,
-                Err(err) => return Err(err),
+        let mut bytes_read = 0;
+        while bytes_read < buf.len() {
+            bytes_read += self.read_cluster(cluster, cluster_offset, &mut buf[bytes_read..])?;
+            cluster_offset = 0;
+
+            if bytes_read < buf.len() {
+                cluster = match self.fat_entry(cluster)?.status() {
+                    Status::Data(cluster) => cluster,
+                    _ => break,
+                };
             }
+        }
 
-            cluster_offset = 0;
-            let fat_entry = self.fat_entry(cluster)?;
+        Ok(bytes_read)
+    }
 
-            cluster = match fat_entry.status() {
-                Status::Data(cluster) => cluster,
-                _ => return Ok(buf.len()),
+    //  * A method to map the chain starting at `start` into extents of
+    //    physically contiguous clusters.
+    pub fn chain_extents(&mut self, start: Cluster) -> io::Result<Vec<Extent>> {
+        let mut extents: Vec<Extent> = Vec::new();
+        if start.0 < 2 {
+            return Ok(extents);
+        }
+
+        let mut cluster = start;
+        let mut len = 0;
+        loop {
+            len += 1;
+            self.check_chain_len(len)?;
+            match extents.last_mut() {
+                Some(extent) if extent.start.0 + extent.len == cluster.0 => extent.len += 1,
+                _ => extents.push(Extent { start: cluster, len: 1 }),
+            }
+
+            cluster = match self.fat_entry(cluster)?.status() {
+                Status::Data(next) => next,
+                _ => return Ok(extents),
             };
         }
     }
 
+    //  * A method to read from an offset of the chain mapped by `extents` into
+    //    a buffer, without consulting the FAT.
+    pub fn read_extents(&mut self, extents: &[Extent], offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let mut cluster_index = offset / bytes_per_cluster;
+        let mut cluster_offset = offset % bytes_per_cluster;
+
+        let mut bytes_read = 0;
+        let mut extent_start_index = 0;
+        for extent in extents {
+            let extent_end_index = extent_start_index + extent.len as usize;
+            if cluster_index < extent_end_index {
+                let skipped = cluster_index - extent_start_index;
+                let start = Cluster(extent.start.0 + skipped as u32);
+                let num_clusters = extent.len as usize - skipped;
+                bytes_read += self.read_clusters(start, num_clusters, cluster_offset, &mut buf[bytes_read..])?;
+                cluster_offset = 0;
+                cluster_index = extent_end_index;
+            }
+
+            if bytes_read == buf.len() {
+                break;
+            }
+            extent_start_index = extent_end_index;
+        }
+
+        Ok(bytes_read)
+    }
+
     //  * A method to read all of the clusters chained from a starting cluster
     //    starting at an offset into a vector.
     pub fn read_all_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.is_root_region(start) {
+            let begin = buf.len();
+            buf.resize(begin + self.root_dir_sectors as usize * self.bytes_per_sector as usize, 0);
+            self.device.read_sectors(self.root_dir_start_sector, &mut buf[begin..])?;
+            return Ok(buf.len());
+        }
+
         // Clusters start at 2.
         // self.read_chain(start, 0, buf)
         if start.0 == 0 || start.0 == 1 {
@@ -166,7 +485,10 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         }
 
         let mut cluster = start;
+        let mut len = 0;
         loop {
+            len += 1;
+            self.check_chain_len(len)?;
             self.read_all_cluster(cluster, buf)?;
             let fat_entry = self.fat_entry(cluster)?;
 
@@ -177,68 +499,419 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         }
     }
 
-    //  * A method to write all the contents of buf into all of the clusters chained
-    //  from a starting cluster.
-    pub fn write_chain(&mut self, start: Cluster, buf: &[u8]) -> io::Result<usize> {
+    //  * A method to write all the contents of buf into the clusters chained from
+    //    a starting cluster, starting at an offset. The chain is extended with
+    //    newly allocated clusters when the write runs past its end.
+    pub fn write_chain(&mut self, start: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
+        let bytes_per_cluster = self.bytes_per_cluster();
+
         let mut cluster = start;
+        for _ in 0..offset / bytes_per_cluster {
+            cluster = self.next_or_alloc_cluster(cluster)?;
+        }
+
+        let mut cluster_offset = offset % bytes_per_cluster;
         let mut bytes_written = 0;
-        loop {
-            bytes_written += self.write_cluster(cluster, 0, buf)?;
-            let fat_entry = self.fat_entry(cluster)?;
+        while bytes_written < buf.len() {
+            if cluster_offset == bytes_per_cluster {
+                cluster = self.next_or_alloc_cluster(cluster)?;
+                cluster_offset = 0;
+            }
 
-            cluster = match fat_entry.status() {
-                Status::Data(cluster) => cluster,
-                _ => return Ok(bytes_written),
-            };
+            let n = self.write_cluster(cluster, cluster_offset, &buf[bytes_written..])?;
+            bytes_written += n;
+            cluster_offset += n;
+        }
+
+        Ok(bytes_written)
+    }
+
+    //  * A method to resize the chain starting at `start`, which holds
+    //    `old_len` bytes of data, to hold `new_len` bytes. Clusters past the
+    //    new end are freed and missing ones are allocated. Bytes between the
+    //    old and the new end read as zeroes. Returns the chain's first
+    //    cluster, which is 0 once the chain is empty.
+    pub fn resize_chain(&mut self, start: Cluster, old_len: usize, new_len: usize) -> io::Result<Cluster> {
+        let bytes_per_cluster = self.bytes_per_cluster();
+        let num_clusters = (new_len + bytes_per_cluster - 1) / bytes_per_cluster;
+        if num_clusters == 0 {
+            if start.0 >= 2 {
+                self.free_chain(start)?;
+            }
+            return Ok(Cluster(0));
+        }
+
+        let start = if start.0 < 2 { self.alloc_cluster(None)? } else { start };
+        let mut last = start;
+        for _ in 1..num_clusters {
+            last = self.next_or_alloc_cluster(last)?;
+        }
+        if let Status::Data(next) = self.fat_entry(last)?.status() {
+            self.set_fat_entry(last, END_OF_CHAIN)?;
+            self.free_chain(next)?;
         }
+
+        // Newly allocated clusters are zeroed already, but the rest of the
+        // old last cluster may hold stale data.
+        if new_len > old_len && old_len % bytes_per_cluster != 0 {
+            let stale_end = core::cmp::min(new_len, old_len - old_len % bytes_per_cluster + bytes_per_cluster);
+            self.write_chain(start, old_len, &vec![0u8; stale_end - old_len])?;
+        }
+
+        Ok(start)
     }
 
     //  * A method to write from a buffer into a cluster from an offset
-    //  TODO: this is completely untested.
     fn write_cluster(&mut self, cluster: Cluster, offset: usize, buf: &[u8]) -> io::Result<usize> {
-        if offset >= (self.bytes_per_sector * (self.sectors_per_cluster as u16)) as usize {
-            return Ok(0);
-        }
+        self.mark_dirty()?;
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let cluster_sector = self.cluster_raw_sector(cluster)?;
 
+        let mut offset = offset;
         let mut bytes_written = 0;
-        for i in 0..self.sectors_per_cluster {
-            bytes_written += self.device.write_sector(
-                self.cluster_raw_sector(Cluster {
-                    0: cluster.0 + i as u32,
-                }),
-                buf,
-            )?;
+        while bytes_written < buf.len() && offset < self.bytes_per_cluster() {
+            let sector_offset = offset % bytes_per_sector;
+            let n = core::cmp::min(bytes_per_sector - sector_offset, buf.len() - bytes_written);
+
+            let sector = self.device.get_mut(cluster_sector + (offset / bytes_per_sector) as u64)?;
+            sector[sector_offset..sector_offset + n]
+                .copy_from_slice(&buf[bytes_written..bytes_written + n]);
+
+            bytes_written += n;
+            offset += n;
         }
 
         Ok(bytes_written)
     }
 
+    //  * A method to return the cluster following `cluster` in its chain,
+    //    allocating and linking a new cluster if `cluster` is the last one.
+    fn next_or_alloc_cluster(&mut self, cluster: Cluster) -> io::Result<Cluster> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(next),
+            Status::Eoc(_) => self.alloc_cluster(Some(cluster)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cluster chain contains a free, reserved or bad cluster",
+            )),
+        }
+    }
+
+    //  * A method to allocate a free cluster. The new cluster is zeroed, marked
+    //    as the end of its chain, and appended to `prev` if one is given.
+    pub fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        // Start at the FSInfo hint and wrap around to the first data cluster.
+        let first = self.next_free.0;
+        let last = self.num_clusters + 2;
+        for raw_cluster in (first..last).chain(2..first) {
+            let cluster = Cluster::from(raw_cluster);
+            if self.fat_entry(cluster)?.status() != Status::Free {
+                continue;
+            }
+
+            self.set_fat_entry(cluster, END_OF_CHAIN)?;
+            if let Some(prev) = prev {
+                self.set_fat_entry(prev, cluster.0)?;
+            }
+
+            let zeroes = vec![0u8; self.bytes_per_cluster()];
+            self.write_cluster(cluster, 0, &zeroes)?;
+
+            self.free_clusters = self.free_clusters.map(|free| free.saturating_sub(1));
+            self.next_free = Cluster::from(if raw_cluster + 1 < last { raw_cluster + 1 } else { 2 });
+            self.write_fsinfo()?;
+            return Ok(cluster);
+        }
+
+        Err(io::Error::new(io::ErrorKind::Other, "no free clusters left on the volume"))
+    }
+
+    //  * A method to return the `n`th cluster of the chain starting at
+    //    `start`, or `None` if the chain is shorter than that.
+    pub(crate) fn nth_cluster(&mut self, start: Cluster, n: usize) -> io::Result<Option<Cluster>> {
+        let mut cluster = start;
+        for _ in 0..n {
+            cluster = match self.fat_entry(cluster)?.status() {
+                Status::Data(next) => next,
+                _ => return Ok(None),
+            };
+        }
+        Ok(Some(cluster))
+    }
+
+    //  * A method to free every cluster in the chain starting at `start`.
+    pub fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        let mut cluster = start;
+        let mut freed = 0;
+        while cluster.0 >= 2 {
+            self.check_chain_len(freed as usize + 1)?;
+            let next = self.fat_entry(cluster)?.status();
+            self.set_fat_entry(cluster, FREE_CLUSTER)?;
+            freed += 1;
+            cluster = match next {
+                Status::Data(next) => next,
+                _ => break,
+            };
+        }
+
+        if freed > 0 {
+            self.free_clusters = self.free_clusters.map(|free| free + freed);
+            self.write_fsinfo()?;
+        }
+        Ok(())
+    }
+
+    //  * A method to read the regular directory entry at `pos`.
+    pub fn read_dir_entry(&mut self, pos: EntryPos) -> io::Result<VFatRegularDirEntry> {
+        let entry_size = size_of::<VFatRegularDirEntry>();
+        let bytes_offset = pos.index * entry_size;
+
+        if self.is_root_region(pos.dir_cluster) {
+            let mut bytes = Vec::new();
+            self.read_all_chain(pos.dir_cluster, &mut bytes)?;
+            let mut entry = [0u8; size_of::<VFatRegularDirEntry>()];
+            match bytes.get(bytes_offset..bytes_offset + entry_size) {
+                Some(slot) => entry.copy_from_slice(slot),
+                None => return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "directory entry lies beyond the end of its directory",
+                )),
+            }
+            return Ok(VFatRegularDirEntry::from_bytes(entry));
+        }
+
+        let cluster = match self.nth_cluster(pos.dir_cluster, bytes_offset / self.bytes_per_cluster())? {
+            Some(cluster) => cluster,
+            None => return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "directory entry lies beyond the end of its directory",
+            )),
+        };
+
+        let mut bytes = [0u8; size_of::<VFatRegularDirEntry>()];
+        self.read_cluster(cluster, bytes_offset % self.bytes_per_cluster(), &mut bytes)?;
+        Ok(VFatRegularDirEntry::from_bytes(bytes))
+    }
+
+    //  * A method to overwrite the regular directory entry at `pos`.
+    pub fn write_dir_entry(&mut self, pos: EntryPos, entry: &VFatRegularDirEntry) -> io::Result<()> {
+        self.write_dir_slots(pos.dir_cluster, pos.index, &entry.to_bytes())
+    }
+
+    //  * A method to write raw 32-byte directory entries into the directory
+    //    starting at `dir_cluster`, beginning at entry `index`. The directory
+    //    grows if the entries do not fit in its current chain.
+    pub fn write_dir_slots(&mut self, dir_cluster: Cluster, index: usize, bytes: &[u8]) -> io::Result<()> {
+        let entry_size = size_of::<VFatRegularDirEntry>();
+        if self.is_root_region(dir_cluster) {
+            return self.write_root_region(index * entry_size, bytes);
+        }
+
+        self.write_chain(dir_cluster, index * entry_size, bytes)?;
+        Ok(())
+    }
+
+    //  * A method to write into the fixed root directory region, which can't
+    //    grow like a cluster chain.
+    fn write_root_region(&mut self, offset: usize, buf: &[u8]) -> io::Result<()> {
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        if offset + buf.len() > self.root_dir_sectors as usize * bytes_per_sector {
+            return Err(io::Error::new(io::ErrorKind::Other, "root directory is full"));
+        }
+        self.mark_dirty()?;
+
+        let mut offset = offset;
+        let mut bytes_written = 0;
+        while bytes_written < buf.len() {
+            let sector_offset = offset % bytes_per_sector;
+            let n = core::cmp::min(bytes_per_sector - sector_offset, buf.len() - bytes_written);
+
+            let sector = self.device.get_mut(self.root_dir_start_sector + (offset / bytes_per_sector) as u64)?;
+            sector[sector_offset..sector_offset + n]
+                .copy_from_slice(&buf[bytes_written..bytes_written + n]);
+
+            bytes_written += n;
+            offset += n;
+        }
+        Ok(())
+    }
+
+    //  * A method to tell whether `cluster` stands for the fixed root
+    //    directory region rather than a cluster chain.
+    pub(crate) fn is_root_region(&self, cluster: Cluster) -> bool {
+        cluster.0 == 0 && self.root_dir_sectors > 0
+    }
+
+    //  * A method to mark the entry at `pos`, along with its long file name
+    //    entries, as deleted.
+    pub fn delete_dir_entry(&mut self, pos: EntryPos) -> io::Result<()> {
+        for index in pos.index - pos.lfn_entries..=pos.index {
+            self.write_dir_slots(pos.dir_cluster, index, &[0xE5])?;
+        }
+        Ok(())
+    }
+
     //  * A method to return a reference to a `FatEntry` for a cluster where the
     //    reference points directly into a cached sector.
     pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
-        let bytes_offset = cluster.0 * size_of::<FatEntry>() as u32;
-        let sector = self.fat_start_sector + (bytes_offset / self.bytes_per_sector as u32) as u64;
-        let offset = bytes_offset % self.bytes_per_sector as u32;
+        self.check_cluster(cluster)?;
+        let raw = self.read_fat_bytes(self.active_fat.unwrap_or(0), cluster)?;
+
+        // Widen 12 and 16-bit entries so that the reserved, bad and end of
+        // chain markers read the same as their 32-bit counterparts.
+        let value = match self.fat_type {
+            FatType::Fat12 => {
+                let value = if cluster.0 % 2 == 1 { raw >> 4 } else { raw & 0xFFF };
+                if value >= 0xFF0 { value | 0x0FFFF000 } else { value }
+            }
+            FatType::Fat16 => if raw >= 0xFFF0 { raw | 0x0FFF0000 } else { raw },
+            FatType::Fat32 => raw,
+        };
+
+        Ok(FatEntry::from(value))
+    }
+
+    //  * A method to set the FAT entry for `cluster` to `value` in every FAT,
+    //    or only in the active one if mirroring is disabled. The reserved
+    //    high 4 bits of FAT32 entries and the neighbouring half-byte of
+    //    FAT12 entries are preserved.
+    pub(crate) fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        self.check_cluster(cluster)?;
+        self.mark_dirty()?;
+        let fats = match self.active_fat {
+            Some(fat) => fat..fat + 1,
+            None => 0..self.num_fats,
+        };
 
-        let mut bytes: Vec<u8> = Vec::new();
-        self.device.read_all_sector(sector, &mut bytes)?;
+        for fat in fats {
+            let old = self.read_fat_bytes(fat, cluster)?;
+            let new = match self.fat_type {
+                FatType::Fat12 if cluster.0 % 2 == 1 => (old & 0x000F) | ((value & 0xFFF) << 4),
+                FatType::Fat12 => (old & 0xF000) | (value & 0xFFF),
+                FatType::Fat16 => value & 0xFFFF,
+                FatType::Fat32 => (old & 0xF0000000) | (value & 0x0FFFFFFF),
+            };
+            self.write_fat_bytes(fat, cluster, new)?;
+        }
+        Ok(())
+    }
 
-        let mut fat_entry_val: u32 = 0;
-        for i in 0..4 {
-            fat_entry_val += (bytes[(offset + i) as usize] as u32) << (8 * i);
+    //  * A method to read the little-endian bytes holding the entry for
+    //    `cluster` in FAT number `fat`. FAT12 entries share their bytes with
+    //    a neighbour and may straddle two sectors.
+    fn read_fat_bytes(&mut self, fat: u8, cluster: Cluster) -> io::Result<u32> {
+        let mut value = 0;
+        for i in 0..self.fat_entry_width() {
+            let (sector, offset) = self.fat_byte_location(fat, cluster, i);
+            value |= (self.device.get(sector)?[offset] as u32) << (8 * i);
         }
+        Ok(value)
+    }
 
-        Ok(FatEntry::from(fat_entry_val))
+    //  * A method to write back the bytes read by `read_fat_bytes`.
+    fn write_fat_bytes(&mut self, fat: u8, cluster: Cluster, value: u32) -> io::Result<()> {
+        for i in 0..self.fat_entry_width() {
+            let (sector, offset) = self.fat_byte_location(fat, cluster, i);
+            self.device.get_mut(sector)?[offset] = (value >> (8 * i)) as u8;
+        }
+        Ok(())
     }
 
-    fn cluster_raw_sector(&self, cluster: Cluster) -> u64 {
+    //  * A method to return the number of bytes to access for one FAT entry.
+    fn fat_entry_width(&self) -> usize {
+        match self.fat_type {
+            FatType::Fat12 | FatType::Fat16 => 2,
+            FatType::Fat32 => size_of::<FatEntry>(),
+        }
+    }
+
+    //  * A method to find the sector of FAT number `fat` holding byte `i` of
+    //    the entry for `cluster`, and the byte's offset inside that sector.
+    fn fat_byte_location(&self, fat: u8, cluster: Cluster, i: usize) -> (u64, usize) {
+        let cluster = cluster.0 as u64;
+        let entry_offset = match self.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * size_of::<FatEntry>() as u64,
+        };
+        let bytes_offset = entry_offset + i as u64;
+        let fat_start = self.fat_start_sector + fat as u64 * self.sectors_per_fat as u64;
+        let sector = fat_start + bytes_offset / self.bytes_per_sector as u64;
+        let offset = (bytes_offset % self.bytes_per_sector as u64) as usize;
+        (sector, offset)
+    }
+
+    fn cluster_raw_sector(&self, cluster: Cluster) -> io::Result<u64> {
+        self.check_cluster(cluster)?;
         // data sector starts with cluster 2
-        let offset = (cluster.0 - 2) * self.sectors_per_cluster as u32;
-        self.data_start_sector + offset as u64
+        let offset = (cluster.0 - 2) as u64 * self.sectors_per_cluster as u64;
+        Ok(self.data_start_sector + offset)
+    }
+
+    //  * A method to fail if `cluster` is not one of the volume's data
+    //    clusters, as when a corrupted FAT or directory entry points past the
+    //    end of the volume.
+    fn check_cluster(&self, cluster: Cluster) -> io::Result<()> {
+        if cluster.0 < 2 || cluster.0 >= self.num_clusters + 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster number is out of range"));
+        }
+        Ok(())
     }
 
-    fn bytes_per_cluster(&self) -> usize {
-        (self.sectors_per_cluster as u16 * self.bytes_per_sector) as usize
+    //  * A method to fail once a walk along a chain has visited `len`
+    //    clusters and that is more than the volume has, which only happens
+    //    when the chain loops back on itself.
+    pub(crate) fn check_chain_len(&self, len: usize) -> io::Result<()> {
+        if len > self.num_clusters as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster chain contains a cycle"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn bytes_per_cluster(&self) -> usize {
+        self.sectors_per_cluster as usize * self.bytes_per_sector as usize
+    }
+}
+
+/// The largest number of data clusters a FAT volume can have: higher cluster
+/// numbers are reserved for the bad cluster and end of chain markers.
+const MAX_CLUSTERS: u32 = 0x0FFFFFF5;
+
+/// MBR partition types of FAT12 (0x01), FAT16 (0x04, 0x06, 0x0E) and FAT32
+/// (0x0B, 0x0C) volumes.
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0E, 0x0B, 0x0C];
+
+/// Returns the first sector and the number of sectors of the first FAT
+/// partition of `device`. GPT partitions are recognized by their type GUID and
+/// MBR partitions, including logical ones, by their partition type.
+fn find_fat_partition<T: BlockDevice>(mut device: T) -> Result<(u64, u64), Error> {
+    let partitions = partition::partitions(&mut device)?;
+    partitions.iter()
+        .find(|partition| match partition.partition_type {
+            PartitionType::Mbr(partition_type) => FAT_PARTITION_TYPES.contains(&partition_type),
+            // Basic data partitions also hold NTFS or exFAT; only take one
+            // whose boot sector describes a FAT volume.
+            PartitionType::Gpt(type_guid) => {
+                (type_guid == BASIC_DATA_PARTITION || type_guid == EFI_SYSTEM_PARTITION)
+                    && match BiosParameterBlock::from(&mut device, partition.start) {
+                        Ok(ebpb) => ebpb.is_fat(),
+                        Err(_) => false,
+                    }
+            }
+        })
+        .map(|partition| (partition.start, partition.num_sectors))
+        .ok_or(Error::NotFound)
+}
+
+/// Opens the parent directory of `path` and returns it along with the last
+/// component of `path`.
+fn open_parent<'p, HANDLE: VFatHandle>(vfat: &HANDLE, path: &'p Path) -> io::Result<(Dir<HANDLE>, &'p OsStr)> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((vfat.open_dir(parent)?, name)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "path does not name an entry inside a directory",
+        )),
     }
 }
 
@@ -254,6 +927,7 @@ impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
                 cluster: Cluster::from(vfat.root_dir_cluster),
                 name: String::from(""),
                 metadata: Metadata::empty(),
+                entry: None,
             })
         })
     }
@@ -297,4 +971,37 @@ impl<'a, HANDLE: VFatHandle> FileSystem for &'a HANDLE {
 
         Ok(entry)
     }
+
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (dir, name) = open_parent(self, path.as_ref())?;
+        let entry = dir.create(name, Attributes::ARCHIVE)?;
+        self.lock(|vfat| vfat.flush())?;
+        match entry {
+            Entry::File(file) => Ok(file),
+            Entry::Dir(_) => unreachable!("created a directory instead of a file"),
+        }
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Dir> {
+        let (dir, name) = open_parent(self, path.as_ref())?;
+        let entry = dir.create(name, Attributes::DIRECTORY)?;
+        self.lock(|vfat| vfat.flush())?;
+        match entry {
+            Entry::Dir(dir) => Ok(dir),
+            Entry::File(_) => unreachable!("created a file instead of a directory"),
+        }
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P) -> io::Result<()> {
+        let (dir, name) = open_parent(self, path.as_ref())?;
+        dir.remove(name)?;
+        self.lock(|vfat| vfat.flush())
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, from: P, to: Q) -> io::Result<()> {
+        let (from_dir, from_name) = open_parent(self, from.as_ref())?;
+        let (to_dir, to_name) = open_parent(self, to.as_ref())?;
+        from_dir.rename(from_name, &to_dir, to_name)?;
+        self.lock(|vfat| vfat.flush())
+    }
 }
@@ -23,6 +23,7 @@ pub enum OsError {
     FileExists = 60,
     InvalidArgument = 70,
     ExpectedFileFoundDir = 80,
+    InvalidFileDescriptor = 90,
 
     IoError = 101,
     IoErrorEof = 102,
@@ -47,11 +48,13 @@ impl core::convert::From<u64> for OsError {
             60 => OsError::FileExists,
             70 => OsError::InvalidArgument,
             80 => OsError::ExpectedFileFoundDir,
+            90 => OsError::InvalidFileDescriptor,
 
             101 => OsError::IoError,
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            105 => OsError::IoErrorTimedOut,
 
             200 => OsError::InvalidSocket,
             201 => OsError::IllegalSocketOperation,
@@ -69,6 +72,8 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
             io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::PermissionDenied => OsError::NoAccess,
+            io::ErrorKind::AlreadyExists => OsError::FileExists,
             _ => OsError::IoError,
         }
     }
@@ -81,6 +86,45 @@ pub const NR_WRITE: usize = 4;
 pub const NR_GETPID: usize = 5;
 pub const NR_WRITE_STR: usize = 6;
 
+pub const NR_OPEN: usize = 10;
+pub const NR_READ: usize = 11;
+pub const NR_WRITE_FD: usize = 12;
+pub const NR_CLOSE: usize = 13;
+pub const NR_SEEK: usize = 14;
+pub const NR_STAT: usize = 15;
+
+/// `NR_OPEN` flag: creates the file if it does not exist.
+pub const O_CREATE: u64 = 1;
+
+/// `NR_SEEK` origins: the start of the file, the current position and the
+/// end of the file.
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+/// An open file of the current process. Descriptors 0, 1 and 2 are the
+/// console when a process starts.
+#[derive(Clone, Copy, Debug)]
+pub struct FileDescriptor(u64);
+
+impl FileDescriptor {
+    pub const STDIN: FileDescriptor = FileDescriptor(0);
+    pub const STDOUT: FileDescriptor = FileDescriptor(1);
+    pub const STDERR: FileDescriptor = FileDescriptor(2);
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The status of an entry, as returned by `NR_STAT`.
+#[derive(Debug)]
+pub struct Stat {
+    pub size: u64,
+    pub is_dir: bool,
+    pub read_only: bool,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct SocketDescriptor(u64);
 
@@ -2,6 +2,8 @@ use core::fmt;
 use core::fmt::Write;
 use core::time::Duration;
 
+use shim::io::SeekFrom;
+
 use crate::*;
 
 macro_rules! err_or {
@@ -97,6 +99,130 @@ pub fn getpid() -> u64 {
     pid
 }
 
+pub fn open(path: &str, flags: u64) -> OsResult<FileDescriptor> {
+    let mut ecode: u64;
+    let mut fd: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(fd), "=r"(ecode)
+             : "r"(path.as_ptr() as u64), "r"(path.len() as u64), "r"(flags), "i"(NR_OPEN)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, FileDescriptor(fd))
+}
+
+pub fn read(fd: FileDescriptor, buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut read: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(read), "=r"(ecode)
+             : "r"(fd.raw()), "r"(buf.as_mut_ptr() as u64), "r"(buf.len() as u64), "i"(NR_READ)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, read as usize)
+}
+
+pub fn write_fd(fd: FileDescriptor, buf: &[u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut written: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(written), "=r"(ecode)
+             : "r"(fd.raw()), "r"(buf.as_ptr() as u64), "r"(buf.len() as u64), "i"(NR_WRITE_FD)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, written as usize)
+}
+
+pub fn close(fd: FileDescriptor) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(fd.raw()), "i"(NR_CLOSE)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+pub fn seek(fd: FileDescriptor, pos: SeekFrom) -> OsResult<u64> {
+    let (offset, whence) = match pos {
+        SeekFrom::Start(offset) => (offset, SEEK_SET),
+        SeekFrom::Current(offset) => (offset as u64, SEEK_CUR),
+        SeekFrom::End(offset) => (offset as u64, SEEK_END),
+    };
+    let mut ecode: u64;
+    let mut new_pos: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(new_pos), "=r"(ecode)
+             : "r"(fd.raw()), "r"(offset), "r"(whence), "i"(NR_SEEK)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, new_pos)
+}
+
+pub fn stat(path: &str) -> OsResult<Stat> {
+    let mut ecode: u64;
+    let mut size: u64;
+    let mut is_dir: u64;
+    let mut read_only: u64;
+
+    unsafe {
+        asm!("mov x0, $4
+              mov x1, $5
+              svc $6
+              mov $0, x0
+              mov $1, x1
+              mov $2, x2
+              mov $3, x7"
+             : "=r"(size), "=r"(is_dir), "=r"(read_only), "=r"(ecode)
+             : "r"(path.as_ptr() as u64), "r"(path.len() as u64), "i"(NR_STAT)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, Stat { size, is_dir: is_dir != 0, read_only: read_only != 0 })
+}
+
 pub fn sock_create() -> SocketDescriptor {
     // Lab 5 2.D
     unimplemented!("sock_create")